@@ -0,0 +1,63 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use sailfish::runtime::{escape, Buffer, Render, RenderError};
+
+/// type which relies on the default `Render::render_escaped` implementation
+struct Text<'a>(&'a str);
+
+impl<'a> Render for Text<'a> {
+    #[inline]
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        b.push_str(self.0);
+        Ok(())
+    }
+}
+
+/// previous implementation of `Render::render_escaped`, which renders the value
+/// into the temporary buffer before escaping
+fn render_escaped_with_tmp<T: Render>(value: &T, b: &mut Buffer) {
+    let mut tmp = Buffer::new();
+    value.render(&mut tmp).unwrap();
+    escape::escape_to_buf(tmp.as_str(), b);
+}
+
+fn render_escaped(c: &mut Criterion) {
+    let inputs = [
+        ("short", "Hello, world!"),
+        ("short escaped", "<b>Hello</b>"),
+        (
+            "long",
+            "Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do \
+             eiusmod tempor incididunt ut labore et dolore magna aliqua.",
+        ),
+        (
+            "long escaped",
+            "<p>Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do \
+             eiusmod tempor incididunt ut labore & dolore magna aliqua.</p>",
+        ),
+    ];
+
+    let mut g = c.benchmark_group("Default render_escaped");
+    for &(name, input) in &inputs {
+        let mut buf = Buffer::with_capacity(1024);
+        g.bench_function(format!("{} (in place)", name), |b| {
+            b.iter(|| {
+                buf.clear();
+                for _ in 0..10 {
+                    Text(black_box(input)).render_escaped(&mut buf).unwrap();
+                }
+            })
+        });
+        g.bench_function(format!("{} (temporary buffer)", name), |b| {
+            b.iter(|| {
+                buf.clear();
+                for _ in 0..10 {
+                    render_escaped_with_tmp(&Text(black_box(input)), &mut buf);
+                }
+            })
+        });
+    }
+    g.finish();
+}
+
+criterion_group!(benches, render_escaped);
+criterion_main!(benches);
@@ -0,0 +1,233 @@
+//! Command line tool for sailfish templates
+//!
+//! ```text
+//! sailfish check [PATH]...
+//! sailfish expand <FILE>
+//! ```
+//!
+//! `check` compiles all templates under the given paths (the template
+//! directories in `sailfish.yml` by default) and reports the errors with the
+//! location in the template. `expand` prints the Rust code generated from the
+//! template, which is useful for debugging the code generation.
+//!
+//! The templates are only parsed and translated into Rust code. They are
+//! compiled without the template structs, so the type errors in the generated
+//! code are still reported by `cargo check`.
+//!
+//! Only the options in `sailfish.yml` and the front-matter of the templates
+//! are applied. The options in the derive attributes (e.g. `delimiter`,
+//! `escape` or `text_mode`) are not known to this tool, so the templates which
+//! depend on them may be reported with false errors. `delimiter`, `delimiters`
+//! and `escape` can be moved into the front-matter to avoid them.
+
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use sailfish_compiler::{block_artifact_path, Compiler, Config, Error};
+
+const USAGE: &str = "\
+Usage:
+    sailfish check [PATH]...    Compile the templates and report errors
+    sailfish expand <FILE>      Print the Rust code generated from the template
+    sailfish help               Print this message
+
+`check` searches the template directories in sailfish.yml, or `templates`
+directory if they are not configured. The options in the derive attributes
+are not applied, and the types are not checked.";
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Check(Vec<PathBuf>),
+    Expand(PathBuf),
+    Help,
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Command, String> {
+    let command = match args.next() {
+        Some(c) => c,
+        None => return Err("missing command".to_owned()),
+    };
+
+    match &*command {
+        "check" => Ok(Command::Check(args.map(PathBuf::from).collect())),
+        "expand" => match (args.next(), args.next()) {
+            (Some(file), None) => Ok(Command::Expand(PathBuf::from(file))),
+            (None, _) => Err("missing template file".to_owned()),
+            (Some(_), Some(arg)) => Err(format!("unexpected argument: {}", arg)),
+        },
+        "help" | "-h" | "--help" => Ok(Command::Help),
+        _ => Err(format!("unknown command: {}", command)),
+    }
+}
+
+/// Collect the template files under the path recursively
+fn find_templates(path: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    if path.is_file() {
+        files.push(path.to_owned());
+        return Ok(());
+    }
+
+    let mut entries = fs::read_dir(path)?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+    for entry in entries {
+        if entry.is_dir() {
+            find_templates(&entry, files)?;
+        } else if entry.extension() == Some(OsStr::new("stpl")) {
+            files.push(entry);
+        }
+    }
+    Ok(())
+}
+
+/// Compile the template into the temporary directory and returns the paths to
+/// the artifacts, with the names of the blocks except for the first one
+fn compile(
+    config: &Config,
+    input: &Path,
+) -> Result<Vec<(Option<String>, PathBuf)>, Error> {
+    let mut config = config.clone();
+    config.detect_escape_mode(input);
+
+    let output_dir = env::temp_dir().join("sailfish-cli");
+    fs::create_dir_all(&output_dir)?;
+    let output = output_dir.join(format!("artifact-{}", process::id()));
+
+    let report = Compiler::with_config(config).compile_file(input, &output)?;
+    let mut artifacts = vec![(None, output.clone())];
+    for name in report.blocks {
+        let path = block_artifact_path(&output, &name);
+        artifacts.push((Some(name), path));
+    }
+    Ok(artifacts)
+}
+
+fn check(config: &Config, paths: &[PathBuf]) -> Result<bool, String> {
+    let mut files = Vec::new();
+    for path in paths {
+        find_templates(path, &mut files)
+            .map_err(|e| format!("failed to read {:?}: {}", path, e))?;
+    }
+
+    let mut errors = 0;
+    for file in files.iter() {
+        match compile(config, file) {
+            Ok(artifacts) => {
+                for (_, artifact) in artifacts {
+                    let _ = fs::remove_file(artifact);
+                }
+            }
+            Err(e) => {
+                eprintln!("error: {}\n{}", file.display(), e);
+                errors += 1;
+            }
+        }
+    }
+
+    eprintln!(
+        "checked {} template(s), {} error(s) found",
+        files.len(),
+        errors
+    );
+    Ok(errors == 0)
+}
+
+fn expand(config: &Config, file: &Path) -> Result<bool, String> {
+    let artifacts = match compile(config, file) {
+        Ok(artifacts) => artifacts,
+        Err(e) => {
+            eprintln!("error: {}\n{}", file.display(), e);
+            return Ok(false);
+        }
+    };
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    for (name, artifact) in artifacts {
+        let code = fs::read_to_string(&artifact)
+            .map_err(|e| format!("failed to read {:?}: {}", artifact, e))?;
+        let _ = fs::remove_file(&artifact);
+        if let Some(name) = name {
+            let _ = writeln!(out, "\n// block `{}`", name);
+        }
+        let _ = write!(out, "{}", code);
+    }
+    Ok(true)
+}
+
+fn run(command: Command) -> Result<bool, String> {
+    let cwd = env::current_dir().map_err(|e| e.to_string())?;
+    // locations in the generated code and `crate_name::path` templates are
+    // resolved relative to the manifest directory, as in the derive macro
+    if env::var_os("CARGO_MANIFEST_DIR").is_none() {
+        env::set_var("CARGO_MANIFEST_DIR", &cwd);
+    }
+    let config = Config::search_file_and_read(&cwd).map_err(|e| e.to_string())?;
+
+    match command {
+        Command::Check(mut paths) => {
+            if paths.is_empty() {
+                paths = config.template_dirs.clone();
+            }
+            if paths.is_empty() {
+                paths.push(cwd.join("templates"));
+            }
+            check(&config, &paths)
+        }
+        Command::Expand(file) => expand(&config, &file),
+        Command::Help => {
+            println!("{}", USAGE);
+            Ok(true)
+        }
+    }
+}
+
+fn main() {
+    let command = parse_args(env::args().skip(1)).unwrap_or_else(|msg| {
+        eprintln!("error: {}\n\n{}", msg, USAGE);
+        process::exit(2);
+    });
+
+    match run(command) {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(msg) => {
+            eprintln!("error: {}", msg);
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(a: &[&str]) -> impl Iterator<Item = String> {
+        a.iter()
+            .map(|s| s.to_string())
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn arguments() {
+        assert_eq!(
+            parse_args(args(&["check", "a", "b"])),
+            Ok(Command::Check(vec![PathBuf::from("a"), PathBuf::from("b")]))
+        );
+        assert_eq!(
+            parse_args(args(&["expand", "index.stpl"])),
+            Ok(Command::Expand(PathBuf::from("index.stpl")))
+        );
+        assert_eq!(parse_args(args(&["--help"])), Ok(Command::Help));
+        assert!(parse_args(args(&["expand"])).is_err());
+        assert!(parse_args(args(&["expand", "a", "b"])).is_err());
+        assert!(parse_args(args(&["build"])).is_err());
+        assert!(parse_args(args(&[])).is_err());
+    }
+}
@@ -0,0 +1,480 @@
+//! Well-formedness checking of the static HTML parts of templates
+//!
+//! The checker does not validate the HTML against the specification. It only
+//! ensures that the tags are balanced, and that each control-flow branch
+//! (e.g. `if`, `for`, `block`) leaves the markup in the same state as it was
+//! when the branch began, so that broken markup is detected at compile time.
+
+use crate::error::*;
+use crate::parser::{ParseStream, TokenKind};
+use crate::translator::Directive;
+
+/// Elements which never have contents
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param",
+    "source", "track", "wbr",
+];
+
+/// Elements whose closing tags can be omitted
+const OPTIONAL_END_TAGS: &[&str] = &[
+    "body", "colgroup", "dd", "dt", "head", "html", "li", "optgroup", "option", "p",
+    "rp", "rt", "tbody", "td", "tfoot", "th", "thead", "tr",
+];
+
+/// Elements whose contents are not parsed as HTML
+const RAW_TEXT_ELEMENTS: &[&str] = &["script", "style", "textarea", "title"];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Tag {
+    name: String,
+    offset: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum State {
+    Data,
+    /// reading the tag name (`<div` or `</div`)
+    TagName {
+        tag: Tag,
+        closing: bool,
+    },
+    /// inside the start tag, after the tag name
+    StartTag(Tag),
+    /// inside the quoted attribute value
+    AttrValue(Tag, u8),
+    /// inside the closing tag, after the tag name
+    EndTag(Tag),
+    /// `<!-- -->`
+    Comment,
+    /// `<!DOCTYPE>` or `<?xml ?>`
+    Declaration,
+    /// contents of `<script>` or `<style>`
+    RawText(String),
+}
+
+/// State at the beginning of the control-flow branch
+struct Scope {
+    state: State,
+    depth: usize,
+}
+
+pub struct HtmlChecker {
+    state: State,
+    stack: Vec<Tag>,
+    scopes: Vec<Scope>,
+}
+
+impl HtmlChecker {
+    pub fn new() -> Self {
+        Self {
+            state: State::Data,
+            stack: Vec::new(),
+            scopes: Vec::new(),
+        }
+    }
+
+    /// Check the HTML structure of the template
+    ///
+    /// Unbalanced rust braces are not reported here since the translator
+    /// reports them.
+    pub fn check(mut self, token_iter: ParseStream) -> Result<(), Error> {
+        let end = token_iter.original_source.len();
+
+        for token in token_iter {
+            let token = token?;
+            match token.kind() {
+                TokenKind::Text => self.feed_text(token.as_str(), token.offset())?,
+                TokenKind::BufferedCode { .. } => {
+                    // tag names can be rendered dynamically (e.g. `<<%= tag %>>`)
+                    if let State::TagName { ref mut tag, .. } = self.state {
+                        tag.name.push('{');
+                        tag.name.push_str(token.as_str());
+                        tag.name.push('}');
+                    }
+                }
+                TokenKind::Code => self.feed_code(token.as_str(), token.offset())?,
+                TokenKind::Comment => {}
+            }
+        }
+
+        self.close_optional(0);
+        if let Some(tag) = self.stack.pop() {
+            return Err(unclosed_tag(&tag));
+        }
+
+        match self.state {
+            State::Data | State::RawText(_) => Ok(()),
+            State::Comment | State::Declaration => Err(make_error!(
+                ErrorKind::AnalyzeError("Unterminated HTML comment".to_owned()),
+                offset = end
+            )),
+            State::TagName { ref tag, .. }
+            | State::StartTag(ref tag)
+            | State::AttrValue(ref tag, _)
+            | State::EndTag(ref tag) => Err(make_error!(
+                ErrorKind::AnalyzeError(format!("Unterminated HTML tag `<{}`", tag.name)),
+                offset = tag.offset
+            )),
+        }
+    }
+
+    fn feed_code(&mut self, code: &str, offset: usize) -> Result<(), Error> {
+        match Directive::parse(code) {
+            Some(Directive::Block(_))
+            | Some(Directive::Macro(..))
+            | Some(Directive::For(_))
+            | Some(Directive::Capture(_))
+            | Some(Directive::Match(_))
+            | Some(Directive::If(_)) => self.open_scope(),
+            Some(Directive::Else)
+            | Some(Directive::When(_))
+            | Some(Directive::ElseIf(_)) => self.switch_branch(offset)?,
+            Some(Directive::EndBlock(_))
+            | Some(Directive::EndMacro)
+            | Some(Directive::EndFor)
+            | Some(Directive::EndCapture)
+            | Some(Directive::EndMatch)
+            | Some(Directive::EndIf) => self.close_scope(offset)?,
+            Some(Directive::Extends(_)) => {}
+            None => {
+                // `}`, `} else {`, `if cond {`, etc.
+                let code = code.trim();
+                let closes = code
+                    .bytes()
+                    .take_while(|&c| c == b'}' || c.is_ascii_whitespace())
+                    .filter(|&c| c == b'}')
+                    .count();
+                let opens = code.ends_with('{');
+
+                if closes > 0 && opens {
+                    for _ in 1..closes {
+                        self.close_scope(offset)?;
+                    }
+                    self.switch_branch(offset)?;
+                } else {
+                    for _ in 0..closes {
+                        self.close_scope(offset)?;
+                    }
+                    if opens {
+                        self.open_scope();
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn open_scope(&mut self) {
+        self.scopes.push(Scope {
+            state: self.state.clone(),
+            depth: self.stack.len(),
+        });
+    }
+
+    fn close_scope(&mut self, offset: usize) -> Result<(), Error> {
+        self.end_branch(offset)?;
+        self.scopes.pop();
+        Ok(())
+    }
+
+    fn switch_branch(&mut self, offset: usize) -> Result<(), Error> {
+        self.end_branch(offset)?;
+        if let Some(scope) = self.scopes.last() {
+            self.state = scope.state.clone();
+        }
+        Ok(())
+    }
+
+    /// Check that the branch did not change the markup outside of it
+    fn end_branch(&mut self, offset: usize) -> Result<(), Error> {
+        let (state, depth) = match self.scopes.last() {
+            Some(scope) => (scope.state.clone(), scope.depth),
+            None => return Ok(()),
+        };
+
+        self.close_optional(depth);
+        if let Some(tag) = self.stack.get(depth) {
+            return Err(unclosed_tag(tag));
+        }
+
+        if self.state != state {
+            return Err(make_error!(
+                ErrorKind::AnalyzeError(
+                    "HTML tag or attribute is split across control-flow branches"
+                        .to_owned()
+                ),
+                offset
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Implicitly close the elements whose closing tags can be omitted
+    fn close_optional(&mut self, depth: usize) {
+        while self.stack.len() > depth {
+            match self.stack.last() {
+                Some(tag) if OPTIONAL_END_TAGS.contains(&&*tag.name) => {
+                    self.stack.pop();
+                }
+                _ => break,
+            }
+        }
+    }
+
+    fn feed_text(&mut self, text: &str, offset: usize) -> Result<(), Error> {
+        let bytes = text.as_bytes();
+        let mut i = 0;
+
+        while i < bytes.len() {
+            let c = bytes[i];
+            match self.state {
+                State::Data if c == b'<' => {
+                    // the tag name may be rendered by the following `<%= %>` tag
+                    let rest = &text[i + 1..];
+                    if rest.is_empty() || starts_with_alpha(rest) {
+                        self.state = State::TagName {
+                            tag: Tag {
+                                name: String::new(),
+                                offset: offset + i,
+                            },
+                            closing: false,
+                        };
+                    } else if rest == "/"
+                        || rest.starts_with('/') && starts_with_alpha(&rest[1..])
+                    {
+                        self.state = State::TagName {
+                            tag: Tag {
+                                name: String::new(),
+                                offset: offset + i,
+                            },
+                            closing: true,
+                        };
+                        i += 1;
+                    } else if rest.starts_with("!--") {
+                        self.state = State::Comment;
+                        i += 3;
+                    } else if rest.starts_with('!') || rest.starts_with('?') {
+                        self.state = State::Declaration;
+                    }
+                }
+                State::Data => {}
+                State::TagName {
+                    ref mut tag,
+                    closing,
+                } => {
+                    let len = text[i..]
+                        .find(|c: char| c.is_whitespace() || c == '/' || c == '>')
+                        .unwrap_or(text.len() - i);
+                    tag.name.push_str(&text[i..i + len].to_ascii_lowercase());
+                    i += len;
+                    if i < bytes.len() {
+                        let tag = tag.clone();
+                        self.state = if closing {
+                            State::EndTag(tag)
+                        } else {
+                            State::StartTag(tag)
+                        };
+                    }
+                    continue;
+                }
+                State::StartTag(ref tag) => match c {
+                    b'"' | b'\'' => self.state = State::AttrValue(tag.clone(), c),
+                    b'>' => {
+                        let tag = tag.clone();
+                        let self_closing = i > 0 && bytes[i - 1] == b'/';
+                        self.open_tag(tag, self_closing);
+                    }
+                    _ => {}
+                },
+                State::AttrValue(ref tag, quote) => {
+                    if c == quote {
+                        self.state = State::StartTag(tag.clone());
+                    }
+                }
+                State::EndTag(ref tag) => {
+                    if c == b'>' {
+                        let tag = tag.clone();
+                        self.state = State::Data;
+                        self.close_tag(tag)?;
+                    }
+                }
+                State::Comment => {
+                    if text[i..].starts_with("-->") {
+                        self.state = State::Data;
+                        i += 3;
+                        continue;
+                    }
+                }
+                State::Declaration => {
+                    if c == b'>' {
+                        self.state = State::Data;
+                    }
+                }
+                State::RawText(ref name) => {
+                    let rest = &text[i + 1..];
+                    if c == b'<'
+                        && rest.starts_with('/')
+                        && rest.len() > name.len()
+                        && rest.is_char_boundary(name.len() + 1)
+                        && rest[1..name.len() + 1].eq_ignore_ascii_case(name)
+                    {
+                        self.state = State::TagName {
+                            tag: Tag {
+                                name: String::new(),
+                                offset: offset + i,
+                            },
+                            closing: true,
+                        };
+                        i += 1;
+                    }
+                }
+            }
+
+            i += 1;
+        }
+
+        Ok(())
+    }
+
+    fn open_tag(&mut self, tag: Tag, self_closing: bool) {
+        self.state = State::Data;
+        if self_closing || VOID_ELEMENTS.contains(&&*tag.name) {
+            return;
+        }
+
+        if RAW_TEXT_ELEMENTS.contains(&&*tag.name) {
+            self.state = State::RawText(tag.name.clone());
+        }
+        self.stack.push(tag);
+    }
+
+    fn close_tag(&mut self, tag: Tag) -> Result<(), Error> {
+        if VOID_ELEMENTS.contains(&&*tag.name) {
+            return Ok(());
+        }
+
+        let depth = self.scopes.last().map_or(0, |s| s.depth);
+        loop {
+            let open = match self.stack.last() {
+                Some(open) if self.stack.len() > depth => open,
+                _ => {
+                    let msg = if self.stack.is_empty() {
+                        format!("Unexpected closing tag `</{}>`", tag.name)
+                    } else {
+                        format!(
+                            "Closing tag `</{}>` does not match any tag opened in the same control-flow branch",
+                            tag.name
+                        )
+                    };
+                    return Err(make_error!(
+                        ErrorKind::AnalyzeError(msg),
+                        offset = tag.offset
+                    ));
+                }
+            };
+
+            if open.name == tag.name {
+                self.stack.pop();
+                return Ok(());
+            } else if OPTIONAL_END_TAGS.contains(&&*open.name) {
+                self.stack.pop();
+            } else {
+                return Err(make_error!(
+                    ErrorKind::AnalyzeError(format!(
+                        "Expected `</{}>`, found `</{}>`",
+                        open.name, tag.name
+                    )),
+                    offset = tag.offset
+                ));
+            }
+        }
+    }
+}
+
+impl Default for HtmlChecker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn starts_with_alpha(s: &str) -> bool {
+    matches!(s.as_bytes().first(), Some(c) if c.is_ascii_alphabetic())
+}
+
+fn unclosed_tag(tag: &Tag) -> Error {
+    make_error!(
+        ErrorKind::AnalyzeError(format!("Unclosed HTML tag `<{}>`", tag.name)),
+        offset = tag.offset
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn check(src: &str) -> Result<(), String> {
+        let parser = Parser::new();
+        HtmlChecker::new()
+            .check(parser.parse(src))
+            .map_err(|e| e.kind().to_string())
+    }
+
+    #[test]
+    fn balanced() {
+        check("<!DOCTYPE html><html><body><p>a<br>b<img src=\"x\" /></p></body></html>")
+            .unwrap();
+        check("<ul><% for x in xs { %><li><%= x %><% } %></ul>").unwrap();
+        check("<div class=\"<% if a { %>b<% } else { %>c<% } %>\"></div>").unwrap();
+        check("<input <% if checked { %>checked<% } %>>").unwrap();
+        check("<<%= tag %> id=\"a\">x</<%= tag %>>").unwrap();
+        check("<script>if (a < b && '</div>') {}</script><!-- <p> -->").unwrap();
+        check("<% block main %><main></main><% endblock %>").unwrap();
+        check("<% for x in xs %><p><%= x %></p><% else %><em>none</em><% endfor %>")
+            .unwrap();
+    }
+
+    #[test]
+    fn unbalanced() {
+        assert_eq!(
+            check("<div><span></div>").unwrap_err(),
+            "Analyzation error: Expected `</span>`, found `</div>`"
+        );
+        assert_eq!(
+            check("<section><p>a").unwrap_err(),
+            "Analyzation error: Unclosed HTML tag `<section>`"
+        );
+        assert_eq!(
+            check("</div>").unwrap_err(),
+            "Analyzation error: Unexpected closing tag `</div>`"
+        );
+        assert_eq!(
+            check("<a href=\"x>").unwrap_err(),
+            "Analyzation error: Unterminated HTML tag `<a`"
+        );
+    }
+
+    #[test]
+    fn control_flow() {
+        assert_eq!(
+            check("<% if a { %><div><% } %></div>").unwrap_err(),
+            "Analyzation error: Unclosed HTML tag `<div>`"
+        );
+        assert_eq!(
+            check("<div><% if a { %></div><% } %>").unwrap_err(),
+            "Analyzation error: Closing tag `</div>` does not match any tag opened \
+             in the same control-flow branch"
+        );
+        assert_eq!(
+            check("<a <% if a { %>href=\"x<% } else { %>href=\"y\"<% } %>>").unwrap_err(),
+            "Analyzation error: HTML tag or attribute is split across control-flow \
+             branches"
+        );
+        assert_eq!(
+            check("<% block main %><div><% endblock %>").unwrap_err(),
+            "Analyzation error: Unclosed HTML tag `<div>`"
+        );
+    }
+}
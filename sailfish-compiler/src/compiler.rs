@@ -1,25 +1,44 @@
 use quote::ToTokens;
+use std::collections::HashSet;
 use std::fs;
 use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use syn::Block;
+use syn::{Block, LitStr};
 
+use crate::checker::HtmlChecker;
 use crate::config::Config;
+use crate::depgraph::DependencyGraph;
 use crate::error::*;
-use crate::optimizer::Optimizer;
+use crate::optimizer::{static_output, static_size, Optimizer};
 use crate::parser::Parser;
 use crate::resolver::Resolver;
 use crate::translator::{TranslatedSource, Translator};
-use crate::util::{read_to_string, rustfmt_block};
+use crate::util::{collect_idents, fnv_hash, read_to_string, rustfmt_block};
 
 pub struct CompilationReport {
     pub deps: Vec<PathBuf>,
+    /// names of the blocks which were written into separate artifacts
+    pub blocks: Vec<String>,
+    /// initial capacity of the buffer specified in the front-matter
+    pub size_hint: Option<usize>,
+    /// number of bytes of the static texts which are always rendered
+    pub static_size: usize,
+    /// rendered contents if the template has no dynamic parts
+    pub static_output: Option<String>,
+    /// environment variables which are embedded into the template
+    pub env_vars: Vec<String>,
+    /// identifiers which appear in the template, including the included files
+    pub idents: HashSet<String>,
+    /// dependencies between the template and the files it refers to,
+    /// including the transitive ones
+    pub graph: DependencyGraph,
 }
 
 #[derive(Default)]
 pub struct Compiler {
     config: Config,
+    fields: Vec<String>,
 }
 
 impl Compiler {
@@ -28,16 +47,47 @@ impl Compiler {
     }
 
     pub fn with_config(config: Config) -> Self {
-        Self { config }
+        Self {
+            config,
+            fields: Vec::new(),
+        }
     }
 
-    fn translate_file_contents(&self, input: &Path) -> Result<TranslatedSource, Error> {
-        let parser = Parser::new().delimiter(self.config.delimiter);
-        let translator = Translator::new().escape(self.config.escape);
+    /// fields of the template struct
+    ///
+    /// The identifiers in the template which look like typos of these fields
+    /// are reported as errors.
+    pub fn fields(mut self, fields: &[String]) -> Self {
+        self.fields = fields.to_vec();
+        self
+    }
+
+    fn translate_file_contents(
+        &self,
+        input: &Path,
+        fields: &[String],
+    ) -> Result<TranslatedSource, Error> {
         let content = read_to_string(input)
             .chain_err(|| format!("Failed to open template file: {:?}", input))?;
 
-        let stream = parser.parse(&*content);
+        // front-matter overrides the options for this file only
+        let mut config = self.config.clone();
+        let front_matter_len = config.apply_front_matter(&content)?;
+
+        let parser = parser(&config);
+        let translator = Translator::new()
+            .escape(config.escape)
+            .escape_mode(config.escape_mode)
+            .escape_extra(&config.escape_extra)
+            .filters(&config.filters)
+            .nonce(config.nonce.as_deref())
+            .fields(fields)
+            .path(input);
+
+        let stream = parser.parse(&content).skip(front_matter_len);
+        if config.check_html {
+            HtmlChecker::new().check(stream.clone())?;
+        }
         translator.translate(stream)
     }
 
@@ -53,34 +103,85 @@ impl Compiler {
             .map_err(|_| format!("Template file not found: {:?}", input))?;
 
         let include_handler = Arc::new(|child_file: &Path| -> Result<_, Error> {
-            Ok(self.translate_file_contents(&*child_file)?.ast)
+            // the included files may define their own variables, so only the
+            // root template is checked against the fields
+            Ok(self.translate_file_contents(&*child_file, &[])?.ast)
         });
 
-        let resolver = Resolver::new().include_handler(include_handler);
-        let optimizer = Optimizer::new().rm_whitespace(self.config.rm_whitespace);
+        let asset_handler = Arc::new(|template: &Path, arg: &str| -> Result<_, Error> {
+            let file = match self.config.asset_dir {
+                Some(ref dir) => dir.join(arg.trim_start_matches('/')),
+                None => template.parent().unwrap().join(arg),
+            };
+            let content = fs::read(&file)
+                .chain_err(|| format!("Asset file not found: {:?}", file))?;
+            let hash = format!("{:08x}", fnv_hash(&content) as u32);
+            let url = self
+                .config
+                .asset_hash
+                .url(&self.config.asset_url, arg, &hash);
+            Ok((file, url))
+        });
+
+        let resolver = Resolver::new()
+            .template_dirs(&self.config.template_dirs)
+            .globals(&self.config.globals)
+            .include_handler(include_handler)
+            .asset_handler(asset_handler);
 
         let compile_file = |input: &Path,
                             output: &Path|
          -> Result<CompilationReport, Error> {
-            let mut tsource = self.translate_file_contents(input)?;
-            let mut report = CompilationReport { deps: Vec::new() };
+            let mut tsource = self.translate_file_contents(input, &self.fields)?;
+
+            // `rm_whitespace` and `size_hint` in the front-matter are applied to
+            // the whole template
+            let mut root_config = self.config.clone();
+            root_config.apply_front_matter(&read_to_string(input)?)?;
+            let optimizer = Optimizer::new()
+                .rm_whitespace(root_config.rm_whitespace)
+                .minify(root_config.minify);
+
+            let mut report = CompilationReport {
+                deps: Vec::new(),
+                blocks: Vec::new(),
+                size_hint: root_config.size_hint,
+                static_size: 0,
+                static_output: None,
+                env_vars: Vec::new(),
+                idents: HashSet::new(),
+                graph: DependencyGraph::new(),
+            };
 
             let r = resolver.resolve(&*input, &mut tsource.ast)?;
             report.deps = r.deps;
+            report.env_vars = r.env_vars;
+            report.graph = r.graph;
 
             optimizer.optimize(&mut tsource.ast);
+            report.static_size = static_size(&tsource.ast);
+            report.static_output = static_output(&tsource.ast);
+            collect_idents(tsource.ast.to_token_stream(), &mut report.idents);
+            for file in r.rust_files {
+                let tokens = fs::read_to_string(&file).ok().and_then(|s| s.parse().ok());
+                if let Some(tokens) = tokens {
+                    collect_idents(tokens, &mut report.idents);
+                }
+            }
 
             if let Some(parent) = output.parent() {
                 fs::create_dir_all(parent)
                     .chain_err(|| format!("Failed to save artifacts in {:?}", parent))?;
             }
 
-            let string = tsource.ast.into_token_stream().to_string();
+            write_artifact(tsource.ast, output)?;
+
+            for (name, mut blk) in r.blocks {
+                optimizer.optimize(&mut blk);
+                write_artifact(blk, &block_artifact_path(output, &name))?;
+                report.blocks.push(name);
+            }
 
-            let mut f = fs::File::create(output)
-                .chain_err(|| format!("Failed to create artifact: {:?}", output))?;
-            writeln!(f, "{}", rustfmt_block(&*string).unwrap_or(string))
-                .chain_err(|| format!("Failed to write artifact into {:?}", output))?;
             Ok(report)
         };
 
@@ -105,13 +206,26 @@ impl Compiler {
             ))
         });
 
-        let parser = Parser::new().delimiter(self.config.delimiter);
-        let translator = Translator::new().escape(self.config.escape);
-        let resolver = Resolver::new().include_handler(include_handler);
-        let optimizer = Optimizer::new().rm_whitespace(self.config.rm_whitespace);
+        let parser = parser(&self.config);
+        let translator = Translator::new()
+            .escape(self.config.escape)
+            .escape_mode(self.config.escape_mode)
+            .escape_extra(&self.config.escape_extra)
+            .filters(&self.config.filters)
+            .nonce(self.config.nonce.as_deref())
+            .fields(&self.fields);
+        let resolver = Resolver::new()
+            .globals(&self.config.globals)
+            .include_handler(include_handler);
+        let optimizer = Optimizer::new()
+            .rm_whitespace(self.config.rm_whitespace)
+            .minify(self.config.minify);
 
         let compile = || -> Result<String, Error> {
             let stream = parser.parse(input);
+            if self.config.check_html {
+                HtmlChecker::new().check(stream.clone())?;
+            }
             let mut tsource = translator.translate(stream)?;
             resolver.resolve(dummy_path, &mut tsource.ast)?;
 
@@ -127,3 +241,116 @@ impl Compiler {
             })
     }
 }
+
+/// Replace `__sf_loc!("path:line:column");` markers with comments at the
+/// beginning of the following statements
+///
+/// `rustc` shows the lines of the artifact in the error messages, so that the
+/// comments point to the location in the template file.
+fn replace_location_markers(code: &str) -> String {
+    let mut out = String::with_capacity(code.len());
+    let mut rest = code;
+    while let Some(p) = rest.find("__sf_loc") {
+        let marker = &rest[p..];
+        let lit_start = match marker.find('"') {
+            Some(start) => start,
+            None => break,
+        };
+        let lit_end = match find_string_end(&marker[lit_start..]) {
+            Some(len) => lit_start + len,
+            None => break,
+        };
+        let loc = match syn::parse_str::<LitStr>(&marker[lit_start..lit_end]) {
+            Ok(lit) => lit.value(),
+            Err(_) => break,
+        };
+        let after = marker[lit_end..].trim_start();
+        let after = if after.starts_with(')') {
+            after[1..].trim_start()
+        } else {
+            after
+        };
+        let after = if after.starts_with(';') {
+            &after[1..]
+        } else {
+            after
+        };
+
+        out.push_str(&rest[..p]);
+        out.push_str("/* ");
+        out.push_str(&loc.replace("*/", "* /"));
+        out.push_str(" */ ");
+        rest = after.trim_start();
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Returns the length of the string literal at the beginning of `s`
+fn find_string_end(s: &str) -> Option<usize> {
+    let mut chars = s.char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '"' => return Some(i + 1),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn parser(config: &Config) -> Parser {
+    let parser = Parser::new().trim_tag_lines(config.text_mode);
+    match config.delimiters {
+        Some((ref open, ref close)) => parser.delimiters(open, close),
+        None => parser.delimiter(config.delimiter),
+    }
+}
+
+/// Path to the artifact of the block named `name`
+pub fn block_artifact_path(output: &Path, name: &str) -> PathBuf {
+    let mut path = output.as_os_str().to_owned();
+    path.push(".");
+    path.push(name);
+    PathBuf::from(path)
+}
+
+fn write_artifact(ast: Block, output: &Path) -> Result<(), Error> {
+    let string = ast.into_token_stream().to_string();
+
+    let mut f = fs::File::create(output)
+        .chain_err(|| format!("Failed to create artifact: {:?}", output))?;
+    let code = rustfmt_block(&string).unwrap_or(string);
+    writeln!(f, "{}", replace_location_markers(&code))
+        .chain_err(|| format!("Failed to write artifact into {:?}", output))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn location_markers() {
+        let formatted =
+            "{\n    __sf_loc!(\"a.stpl:1:5\");\n    __sf_rt::render!(__sf_buf, a);\n}";
+        assert_eq!(
+            replace_location_markers(formatted),
+            "{\n    /* a.stpl:1:5 */ __sf_rt::render!(__sf_buf, a);\n}"
+        );
+
+        let unformatted = "{ f () ; __sf_loc ! (\"C:\\\\a.stpl:2:1\") ; g () ; }";
+        assert_eq!(
+            replace_location_markers(unformatted),
+            "{ f () ; /* C:\\a.stpl:2:1 */ g () ; }"
+        );
+
+        let wrapped = "{\n    __sf_loc!(\n        \"a.stpl:3:1\"\n    );\n    f();\n}";
+        assert_eq!(
+            replace_location_markers(wrapped),
+            "{\n    /* a.stpl:3:1 */ f();\n}"
+        );
+    }
+}
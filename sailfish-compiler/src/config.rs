@@ -1,11 +1,102 @@
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 
+use crate::error::{Error, ErrorKind};
+
+/// Escaping scheme applied to the output of `<%= %>` tags
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EscapeMode {
+    /// replace HTML special characters with entities
+    Html,
+    /// escape quotes, backslashes and control characters as in JSON strings
+    Json,
+    /// replace XML special characters with the predefined entities
+    Xml,
+}
+
+impl Default for EscapeMode {
+    fn default() -> Self {
+        EscapeMode::Html
+    }
+}
+
+/// How the content hash is embedded into the URL generated by `asset!` macro
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AssetHash {
+    /// `app.css?v=0123abcd`
+    Query,
+    /// `app.0123abcd.css`
+    Filename,
+}
+
+impl Default for AssetHash {
+    fn default() -> Self {
+        AssetHash::Query
+    }
+}
+
+impl AssetHash {
+    /// Build the URL of the asset from its path and content hash
+    pub fn url(self, prefix: &str, path: &str, hash: &str) -> String {
+        let path = path.trim_start_matches('/');
+        match self {
+            AssetHash::Query => format!("{}{}?v={}", prefix, path, hash),
+            AssetHash::Filename => {
+                let name_start = path.rfind('/').map_or(0, |p| p + 1);
+                match path[name_start..].rfind('.') {
+                    Some(p) if p > 0 => {
+                        let (stem, ext) = path.split_at(name_start + p);
+                        format!("{}{}.{}{}", prefix, stem, hash, ext)
+                    }
+                    _ => format!("{}{}.{}", prefix, path, hash),
+                }
+            }
+        }
+    }
+}
+
+/// Value of the global constant which is available in all templates
+#[derive(Clone, Debug, PartialEq)]
+pub enum GlobalValue {
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+}
+
 #[derive(Clone, Debug)]
 pub struct Config {
     pub delimiter: char,
+    /// opening and closing tags which take precedence over `delimiter`
+    pub delimiters: Option<(String, String)>,
     pub escape: bool,
+    pub escape_mode: EscapeMode,
+    pub escape_extra: String,
     pub rm_whitespace: bool,
+    /// minify the static HTML parts of templates
+    pub minify: bool,
+    /// render plain text (no escaping, and the lines which contain only code
+    /// blocks are removed)
+    pub text_mode: bool,
+    /// check if the HTML tags are balanced at compile time
+    pub check_html: bool,
+    /// expression of the CSP nonce which is added to `<script>` and `<style>`
+    /// tags
+    pub nonce: Option<String>,
+    /// directory where `asset!` macro looks up the files. If not specified, the
+    /// files are looked up relative to the template file.
+    pub asset_dir: Option<PathBuf>,
+    /// prefix of the URLs generated by `asset!` macro
+    pub asset_url: String,
+    pub asset_hash: AssetHash,
+    /// minimum initial capacity of the buffer in bytes. The size of the static
+    /// texts is reserved if it is larger.
+    pub size_hint: Option<usize>,
     pub template_dirs: Vec<PathBuf>,
+    /// custom filters which map the filter names to the paths to functions
+    pub filters: HashMap<String, String>,
+    /// constants which are available as `globals.<name>` in all templates
+    pub globals: HashMap<String, GlobalValue>,
     #[doc(hidden)]
     pub cache_dir: PathBuf,
     #[doc(hidden)]
@@ -17,17 +108,168 @@ impl Default for Config {
         Self {
             template_dirs: Vec::new(),
             delimiter: '%',
+            delimiters: None,
             escape: true,
+            escape_mode: EscapeMode::Html,
+            escape_extra: String::new(),
             cache_dir: Path::new(env!("OUT_DIR")).join("cache"),
             rm_whitespace: false,
+            minify: false,
+            text_mode: false,
+            check_html: false,
+            nonce: None,
+            asset_dir: None,
+            asset_url: String::new(),
+            asset_hash: AssetHash::Query,
+            size_hint: None,
+            filters: HashMap::new(),
+            globals: HashMap::new(),
             _non_exhaustive: (),
         }
     }
 }
 
+impl Config {
+    /// Select the escaping scheme from the extension which precedes `.stpl`
+    ///
+    /// `.html.stpl` and `.htm.stpl` templates are escaped as HTML, `.json.stpl`
+    /// templates as JSON strings, `.xml.stpl` templates as XML, and `.txt.stpl`
+    /// templates are rendered in the text mode. The settings are unchanged for
+    /// other templates.
+    pub fn detect_escape_mode(&mut self, template: &Path) {
+        let ext = template
+            .file_stem()
+            .map(Path::new)
+            .and_then(Path::extension)
+            .and_then(|ext| ext.to_str());
+
+        let mode = match ext {
+            Some("html") | Some("htm") => EscapeMode::Html,
+            Some("json") => EscapeMode::Json,
+            Some("xml") => EscapeMode::Xml,
+            Some("txt") => {
+                self.escape = false;
+                self.text_mode = true;
+                return;
+            }
+            _ => return,
+        };
+
+        self.escape = true;
+        self.escape_mode = mode;
+    }
+}
+
+/// Returns `true` if the string can be used as the opening or closing tag
+#[cfg(any(feature = "config", feature = "procmacro"))]
+pub(crate) fn is_valid_tag(tag: &str) -> bool {
+    !tag.is_empty() && !tag.contains(char::is_whitespace)
+}
+
+/// Returns the front-matter of the template and the length of the source
+/// which it occupies
+///
+/// The front-matter is a YAML block surrounded by `---` lines at the beginning
+/// of the template.
+pub(crate) fn front_matter(source: &str) -> Option<(&str, usize)> {
+    let start = if source.starts_with("---\n") {
+        4
+    } else if source.starts_with("---\r\n") {
+        5
+    } else {
+        return None;
+    };
+
+    let mut pos = start;
+    while pos < source.len() {
+        let end = source[pos..]
+            .find('\n')
+            .map_or(source.len(), |p| pos + p + 1);
+        if source[pos..end].trim_end_matches(&['\r', '\n'][..]) == "---" {
+            return Some((&source[start..pos], end));
+        }
+        pos = end;
+    }
+    None
+}
+
+impl Config {
+    /// Apply the options in the front-matter of the template
+    ///
+    /// Returns the length of the front-matter, which should be skipped when
+    /// parsing the template.
+    pub fn apply_front_matter(&mut self, source: &str) -> Result<usize, Error> {
+        let (content, len) = match front_matter(source) {
+            Some(f) => f,
+            None => return Ok(0),
+        };
+
+        #[cfg(feature = "config")]
+        {
+            self.apply_front_matter_content(content)?;
+            Ok(len)
+        }
+
+        #[cfg(not(feature = "config"))]
+        {
+            let _ = (content, len);
+            Err(make_error!(ErrorKind::ConfigError(
+                "`config` feature is required to use front-matter".to_owned()
+            )))
+        }
+    }
+}
+
+/// Split `crate_name::path/to/template.stpl` into the crate name and the path
+pub(crate) fn split_crate_path(path: &str) -> Option<(&str, &str)> {
+    let pos = path.find("::")?;
+    let (name, rest) = (&path[..pos], &path[pos + 2..]);
+    if !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        Some((name, rest))
+    } else {
+        None
+    }
+}
+
+/// Find the template file exported from the dependency crate
+pub(crate) fn resolve_crate_path(crate_name: &str, path: &str) -> Result<PathBuf, Error> {
+    #[cfg(feature = "config")]
+    {
+        let manifest_dir = std::env::var_os("CARGO_MANIFEST_DIR").ok_or_else(|| {
+            make_error!(ErrorKind::AnalyzeError(
+                "environmental variable `CARGO_MANIFEST_DIR` is not set".to_owned()
+            ))
+        })?;
+        let template_dirs =
+            Config::dependency_template_dirs(Path::new(&manifest_dir), crate_name)?;
+        template_dirs
+            .iter()
+            .rev()
+            .map(|dir| dir.join(path))
+            .find(|p| p.is_file())
+            .ok_or_else(|| {
+                make_error!(ErrorKind::AnalyzeError(format!(
+                    "Template file {:?} not found in crate `{}`",
+                    path, crate_name
+                )))
+            })
+    }
+
+    #[cfg(not(feature = "config"))]
+    {
+        let _ = path;
+        Err(make_error!(ErrorKind::AnalyzeError(format!(
+            "`config` feature is required to use templates in crate `{}`",
+            crate_name
+        ))))
+    }
+}
+
 #[cfg(feature = "config")]
 mod config {
+    use std::cell::RefCell;
     use std::fs;
+    use std::process::Command;
     use yaml_rust::yaml::{Yaml, YamlLoader};
 
     use super::*;
@@ -64,15 +306,60 @@ mod config {
 
                     if let Some(delimiter) = config_file.delimiter {
                         config.delimiter = delimiter;
+                        config.delimiters = None;
+                    }
+
+                    if let Some(delimiters) = config_file.delimiters {
+                        config.delimiters = Some(delimiters);
                     }
 
                     if let Some(escape) = config_file.escape {
                         config.escape = escape;
                     }
 
+                    if let Some(escape_mode) = config_file.escape_mode {
+                        config.escape_mode = escape_mode;
+                    }
+
+                    if let Some(escape_extra) = config_file.escape_extra {
+                        config.escape_extra = escape_extra;
+                    }
+
                     if let Some(rm_whitespace) = config_file.rm_whitespace {
                         config.rm_whitespace = rm_whitespace;
                     }
+
+                    if let Some(minify) = config_file.minify {
+                        config.minify = minify;
+                    }
+
+                    if let Some(text_mode) = config_file.text_mode {
+                        config.text_mode = text_mode;
+                    }
+
+                    if let Some(check_html) = config_file.check_html {
+                        config.check_html = check_html;
+                    }
+
+                    if let Some(asset_dir) = config_file.asset_dir {
+                        config.asset_dir = Some(path.parent().unwrap().join(asset_dir));
+                    }
+
+                    if let Some(asset_url) = config_file.asset_url {
+                        config.asset_url = asset_url;
+                    }
+
+                    if let Some(asset_hash) = config_file.asset_hash {
+                        config.asset_hash = asset_hash;
+                    }
+
+                    if let Some(filters) = config_file.filters {
+                        config.filters.extend(filters);
+                    }
+
+                    if let Some(globals) = config_file.globals {
+                        config.globals.extend(globals);
+                    }
                 }
 
                 path.pop();
@@ -82,12 +369,146 @@ mod config {
         }
     }
 
+    thread_local! {
+        /// Manifest paths of the packages listed by `cargo metadata`, keyed by
+        /// the directory where the command was executed
+        ///
+        /// The derive macro is invoked for each template in the same compiler
+        /// thread, so the command is executed only once per crate.
+        static PACKAGE_MANIFESTS: RefCell<HashMap<PathBuf, HashMap<String, PathBuf>>> =
+            RefCell::new(HashMap::new());
+    }
+
+    impl Config {
+        /// Returns the template directories exported from the dependency crate
+        ///
+        /// The crate is looked up with `cargo metadata`, and the directories are
+        /// read from `sailfish.yml` in the crate. `templates` directory in the
+        /// crate is searched last.
+        pub fn dependency_template_dirs(
+            manifest_dir: &Path,
+            crate_name: &str,
+        ) -> Result<Vec<PathBuf>, Error> {
+            let manifest_path = PACKAGE_MANIFESTS.with(|cache| {
+                let mut cache = cache.borrow_mut();
+                if !cache.contains_key(manifest_dir) {
+                    let manifests = package_manifests(manifest_dir)?;
+                    cache.insert(manifest_dir.to_owned(), manifests);
+                }
+                cache[manifest_dir].get(crate_name).cloned().ok_or_else(|| {
+                    ConfigFile::error(format!(
+                        "Dependency crate `{}` not found",
+                        crate_name
+                    ))
+                })
+            })?;
+
+            let crate_dir = manifest_path.parent().unwrap();
+            let mut template_dirs =
+                Config::search_file_and_read(crate_dir)?.template_dirs;
+            template_dirs.insert(0, crate_dir.join("templates"));
+            Ok(template_dirs)
+        }
+    }
+
+    /// List the manifest paths of the packages in the dependency graph
+    ///
+    /// The package names are normalized as crate names. `--offline` is passed
+    /// since the dependencies are already fetched when the crate is compiled.
+    fn package_manifests(manifest_dir: &Path) -> Result<HashMap<String, PathBuf>, Error> {
+        let cargo = std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+        let output = Command::new(cargo)
+            .args(&["metadata", "--format-version", "1", "--offline"])
+            .current_dir(manifest_dir)
+            .output()
+            .chain_err(|| "Failed to execute `cargo metadata`")?;
+        if !output.status.success() {
+            return Err(ConfigFile::error(format!(
+                "`cargo metadata` failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+
+        // JSON is a subset of YAML
+        let metadata = String::from_utf8_lossy(&output.stdout);
+        let docs = YamlLoader::load_from_str(&metadata)
+            .map_err(|e| ErrorKind::ConfigError(e.to_string()))?;
+        let packages = docs
+            .first()
+            .and_then(|doc| doc["packages"].as_vec())
+            .map_or(&[][..], |packages| packages.as_slice());
+
+        let mut manifests = HashMap::new();
+        for package in packages {
+            if let (Some(name), Some(path)) =
+                (package["name"].as_str(), package["manifest_path"].as_str())
+            {
+                manifests.insert(name.replace('-', "_"), PathBuf::from(path));
+            }
+        }
+        Ok(manifests)
+    }
+
+    impl Config {
+        pub(super) fn apply_front_matter_content(
+            &mut self,
+            content: &str,
+        ) -> Result<(), Error> {
+            let entries = YamlLoader::load_from_str(content)
+                .map_err(|e| ErrorKind::ConfigError(e.to_string()))?;
+
+            let mut front_matter = ConfigFile::default();
+            for entry in entries {
+                front_matter.visit_front_matter(entry)?;
+            }
+
+            if let Some(delimiter) = front_matter.delimiter {
+                self.delimiter = delimiter;
+                self.delimiters = None;
+            }
+
+            if let Some(delimiters) = front_matter.delimiters {
+                self.delimiters = Some(delimiters);
+            }
+
+            if let Some(escape) = front_matter.escape {
+                self.escape = escape;
+            }
+
+            if let Some(escape_mode) = front_matter.escape_mode {
+                self.escape_mode = escape_mode;
+            }
+
+            if let Some(rm_whitespace) = front_matter.rm_whitespace {
+                self.rm_whitespace = rm_whitespace;
+            }
+
+            if let Some(size_hint) = front_matter.size_hint {
+                self.size_hint = Some(size_hint);
+            }
+
+            Ok(())
+        }
+    }
+
     #[derive(Default)]
     struct ConfigFile {
         template_dirs: Option<Vec<PathBuf>>,
         delimiter: Option<char>,
+        delimiters: Option<(String, String)>,
         escape: Option<bool>,
+        escape_mode: Option<EscapeMode>,
+        escape_extra: Option<String>,
         rm_whitespace: Option<bool>,
+        minify: Option<bool>,
+        text_mode: Option<bool>,
+        check_html: Option<bool>,
+        asset_dir: Option<PathBuf>,
+        asset_url: Option<String>,
+        asset_hash: Option<AssetHash>,
+        size_hint: Option<usize>,
+        filters: Option<Vec<(String, String)>>,
+        globals: Option<Vec<(String, GlobalValue)>>,
     }
 
     impl ConfigFile {
@@ -117,8 +538,15 @@ mod config {
                     Yaml::String(ref s) => match &**s {
                         "template_dir" => self.visit_template_dir(v)?,
                         "delimiter" => self.visit_delimiter(v)?,
+                        "delimiters" => self.visit_delimiters(v)?,
                         "escape" => self.visit_escape(v)?,
+                        "escape_extra" => self.visit_escape_extra(v)?,
+                        "text_mode" => self.visit_text_mode(v)?,
+                        "check_html" => self.visit_check_html(v)?,
                         "optimization" => self.visit_optimization(v)?,
+                        "filters" => self.visit_filters(v)?,
+                        "globals" => self.visit_globals(v)?,
+                        "assets" => self.visit_assets(v)?,
                         _ => return Err(Self::error(format!("Unknown key ({})", s))),
                     },
                     _ => {
@@ -130,18 +558,55 @@ mod config {
             Ok(())
         }
 
+        /// Only the options which affect a single template are allowed in the
+        /// front-matter
+        fn visit_front_matter(&mut self, entry: Yaml) -> Result<(), Error> {
+            if entry.is_null() {
+                return Ok(());
+            }
+
+            let hash = entry.into_hash().ok_or_else(|| {
+                ErrorKind::ConfigError("Invalid front-matter format".to_owned())
+            })?;
+
+            for (k, v) in hash {
+                match k {
+                    Yaml::String(ref s) => match &**s {
+                        "delimiter" => self.visit_delimiter(v)?,
+                        "delimiters" => self.visit_delimiters(v)?,
+                        "escape" => self.visit_escape(v)?,
+                        "rm_whitespace" => self.visit_rm_whitespace(v)?,
+                        "size_hint" => self.visit_size_hint(v)?,
+                        _ => {
+                            return Err(Self::error(format!(
+                                "Unknown key in front-matter ({})",
+                                s
+                            )))
+                        }
+                    },
+                    _ => {
+                        return Err(Self::error("Invalid front-matter format"));
+                    }
+                }
+            }
+
+            Ok(())
+        }
+
         fn visit_template_dir(&mut self, value: Yaml) -> Result<(), Error> {
             if self.template_dirs.is_some() {
                 return Err(Self::error("Duplicate key (template_dir)"));
             }
 
             match value {
-                Yaml::String(s) => self.template_dirs = Some(vec![PathBuf::from(s)]),
+                Yaml::String(s) => {
+                    self.template_dirs = Some(vec![PathBuf::from(expand_env(&s)?)])
+                }
                 Yaml::Array(v) => {
                     let mut template_dirs = Vec::new();
                     for e in v {
                         if let Yaml::String(s) = e {
-                            template_dirs.push(PathBuf::from(s));
+                            template_dirs.push(PathBuf::from(expand_env(&s)?));
                         } else {
                             return Err(Self::error(
                                 "Arguments of `template_dir` must be string",
@@ -177,16 +642,102 @@ mod config {
             }
         }
 
+        fn visit_delimiters(&mut self, value: Yaml) -> Result<(), Error> {
+            if self.delimiters.is_some() {
+                return Err(Self::error("Duplicate key (delimiters)"));
+            }
+
+            if let Yaml::Array(v) = value {
+                if let [Yaml::String(open), Yaml::String(close)] = &v[..] {
+                    if is_valid_tag(open) && is_valid_tag(close) {
+                        self.delimiters = Some((open.clone(), close.clone()));
+                        return Ok(());
+                    }
+                }
+            }
+
+            Err(Self::error(
+                "`delimiters` must be a pair of non-empty strings without whitespaces",
+            ))
+        }
+
         fn visit_escape(&mut self, value: Yaml) -> Result<(), Error> {
             if self.escape.is_some() {
                 return Err(Self::error("Duplicate key (escape)"));
             }
 
-            if let Yaml::Boolean(b) = value {
-                self.escape = Some(b);
-                Ok(())
-            } else {
-                Err(Self::error("`escape` must be boolean"))
+            match value {
+                Yaml::Boolean(b) => {
+                    self.escape = Some(b);
+                    Ok(())
+                }
+                Yaml::String(ref s) => match &**s {
+                    "html" => {
+                        self.escape = Some(true);
+                        self.escape_mode = Some(EscapeMode::Html);
+                        Ok(())
+                    }
+                    "json" => {
+                        self.escape = Some(true);
+                        self.escape_mode = Some(EscapeMode::Json);
+                        Ok(())
+                    }
+                    "xml" => {
+                        self.escape = Some(true);
+                        self.escape_mode = Some(EscapeMode::Xml);
+                        Ok(())
+                    }
+                    "none" => {
+                        self.escape = Some(false);
+                        Ok(())
+                    }
+                    _ => Err(Self::error(format!("Unknown escape mode ({})", s))),
+                },
+                _ => Err(Self::error(
+                    "`escape` must be boolean or one of \"html\", \"json\", \"xml\" or \"none\"",
+                )),
+            }
+        }
+
+        fn visit_escape_extra(&mut self, value: Yaml) -> Result<(), Error> {
+            if self.escape_extra.is_some() {
+                return Err(Self::error("Duplicate key (escape_extra)"));
+            }
+
+            match value {
+                Yaml::String(s) if s.is_ascii() => {
+                    self.escape_extra = Some(s);
+                    Ok(())
+                }
+                _ => Err(Self::error("`escape_extra` must be ASCII string")),
+            }
+        }
+
+        fn visit_text_mode(&mut self, value: Yaml) -> Result<(), Error> {
+            if self.text_mode.is_some() {
+                return Err(Self::error("Duplicate key (text_mode)"));
+            }
+
+            match value {
+                Yaml::Boolean(b) => {
+                    self.text_mode = Some(b);
+                    Ok(())
+                }
+                _ => Err(Self::error("`text_mode` must be boolean")),
+            }
+        }
+
+        fn visit_check_html(&mut self, value: Yaml) -> Result<(), Error> {
+            if self.check_html.is_some() {
+                return Err(Self::error("Duplicate key (check_html)"));
+            }
+
+            match value {
+                Yaml::Boolean(b) => {
+                    self.check_html = Some(b);
+                    Ok(())
+                }
+                _ => Err(Self::error("`check_html` must be boolean")),
             }
         }
 
@@ -199,6 +750,7 @@ mod config {
                 match k {
                     Yaml::String(ref s) => match &**s {
                         "rm_whitespace" => self.visit_rm_whitespace(v)?,
+                        "minify" => self.visit_minify(v)?,
                         _ => {
                             return Err(Self::error(format!(
                                 "Unknown key (optimization.{})",
@@ -215,6 +767,137 @@ mod config {
             Ok(())
         }
 
+        fn visit_assets(&mut self, entry: Yaml) -> Result<(), Error> {
+            let hash = entry.into_hash().ok_or_else(|| {
+                ErrorKind::ConfigError("`assets` must be a mapping".to_owned())
+            })?;
+
+            for (k, v) in hash {
+                match (k, v) {
+                    (Yaml::String(ref k), v) if k == "dir" => {
+                        if self.asset_dir.is_some() {
+                            return Err(Self::error("Duplicate key (assets.dir)"));
+                        }
+                        match v {
+                            Yaml::String(s) => self.asset_dir = Some(PathBuf::from(s)),
+                            _ => return Err(Self::error("`assets.dir` must be string")),
+                        }
+                    }
+                    (Yaml::String(ref k), v) if k == "url" => {
+                        if self.asset_url.is_some() {
+                            return Err(Self::error("Duplicate key (assets.url)"));
+                        }
+                        match v {
+                            Yaml::String(s) => self.asset_url = Some(s),
+                            _ => return Err(Self::error("`assets.url` must be string")),
+                        }
+                    }
+                    (Yaml::String(ref k), v) if k == "hash" => {
+                        if self.asset_hash.is_some() {
+                            return Err(Self::error("Duplicate key (assets.hash)"));
+                        }
+                        self.asset_hash = match v.as_str() {
+                            Some("query") => Some(AssetHash::Query),
+                            Some("filename") => Some(AssetHash::Filename),
+                            _ => {
+                                return Err(Self::error(
+                                    "`assets.hash` must be \"query\" or \"filename\"",
+                                ))
+                            }
+                        };
+                    }
+                    (Yaml::String(k), _) => {
+                        return Err(Self::error(format!("Unknown key (assets.{})", k)));
+                    }
+                    _ => return Err(Self::error("Invalid configuration format")),
+                }
+            }
+
+            Ok(())
+        }
+
+        fn visit_filters(&mut self, entry: Yaml) -> Result<(), Error> {
+            if self.filters.is_some() {
+                return Err(Self::error("Duplicate key (filters)"));
+            }
+
+            let hash = entry.into_hash().ok_or_else(|| {
+                ErrorKind::ConfigError("`filters` must be a mapping".to_owned())
+            })?;
+
+            let mut filters = Vec::new();
+            for (k, v) in hash {
+                match (k, v) {
+                    (Yaml::String(name), Yaml::String(path)) => {
+                        if syn::parse_str::<syn::Ident>(&name).is_err() {
+                            return Err(Self::error(format!(
+                                "Invalid filter name ({})",
+                                name
+                            )));
+                        }
+                        if syn::parse_str::<syn::Path>(&path).is_err() {
+                            return Err(Self::error(format!(
+                                "Invalid path to the filter function ({})",
+                                path
+                            )));
+                        }
+                        filters.push((name, path));
+                    }
+                    _ => {
+                        return Err(Self::error("Filter names and paths must be string"));
+                    }
+                }
+            }
+
+            self.filters = Some(filters);
+            Ok(())
+        }
+
+        fn visit_globals(&mut self, entry: Yaml) -> Result<(), Error> {
+            if self.globals.is_some() {
+                return Err(Self::error("Duplicate key (globals)"));
+            }
+
+            let hash = entry.into_hash().ok_or_else(|| {
+                ErrorKind::ConfigError("`globals` must be a mapping".to_owned())
+            })?;
+
+            let mut globals = Vec::new();
+            for (k, v) in hash {
+                let name = match k {
+                    Yaml::String(name) if syn::parse_str::<syn::Ident>(&name).is_ok() => {
+                        name
+                    }
+                    _ => return Err(Self::error("Global names must be identifiers")),
+                };
+
+                let value = match v {
+                    Yaml::Boolean(b) => GlobalValue::Bool(b),
+                    Yaml::Integer(n) => GlobalValue::Integer(n),
+                    Yaml::Real(ref r) => match v.as_f64() {
+                        Some(f) if f.is_finite() => GlobalValue::Float(f),
+                        _ => {
+                            return Err(Self::error(format!(
+                                "Invalid value of global `{}` ({})",
+                                name, r
+                            )))
+                        }
+                    },
+                    Yaml::String(s) => GlobalValue::String(s),
+                    _ => {
+                        return Err(Self::error(format!(
+                            "Global `{}` must be boolean, number or string",
+                            name
+                        )))
+                    }
+                };
+                globals.push((name, value));
+            }
+
+            self.globals = Some(globals);
+            Ok(())
+        }
+
         fn visit_rm_whitespace(&mut self, value: Yaml) -> Result<(), Error> {
             if self.rm_whitespace.is_some() {
                 return Err(Self::error("Duplicate key (rm_whitespace)"));
@@ -228,8 +911,72 @@ mod config {
             }
         }
 
+        fn visit_minify(&mut self, value: Yaml) -> Result<(), Error> {
+            if self.minify.is_some() {
+                return Err(Self::error("Duplicate key (minify)"));
+            }
+
+            if let Yaml::Boolean(b) = value {
+                self.minify = Some(b);
+                Ok(())
+            } else {
+                Err(Self::error("`minify` must be boolean"))
+            }
+        }
+
+        fn visit_size_hint(&mut self, value: Yaml) -> Result<(), Error> {
+            if self.size_hint.is_some() {
+                return Err(Self::error("Duplicate key (size_hint)"));
+            }
+
+            match value {
+                Yaml::Integer(n) if n >= 0 => {
+                    self.size_hint = Some(n as usize);
+                    Ok(())
+                }
+                _ => Err(Self::error("`size_hint` must be non-negative integer")),
+            }
+        }
+
         fn error<T: Into<String>>(msg: T) -> Error {
             make_error!(ErrorKind::ConfigError(msg.into()))
         }
     }
+
+    /// Replace `$VAR` and `${VAR}` with the value of the environment variable
+    fn expand_env(s: &str) -> Result<String, Error> {
+        let mut expanded = String::with_capacity(s.len());
+        let mut rest = s;
+        while let Some(p) = rest.find('$') {
+            expanded.push_str(&rest[..p]);
+            rest = &rest[p + 1..];
+
+            let (name, len) = if rest.starts_with('{') {
+                let braced = &rest[1..];
+                let end = braced.find('}').ok_or_else(|| {
+                    ConfigFile::error(format!("Unclosed `${{` in {:?}", s))
+                })?;
+                (&braced[..end], end + 2)
+            } else {
+                let end = rest
+                    .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                    .unwrap_or(rest.len());
+                (&rest[..end], end)
+            };
+
+            if name.is_empty() {
+                return Err(ConfigFile::error(format!(
+                    "Expected environment variable name after `$` in {:?}",
+                    s
+                )));
+            }
+            let value = std::env::var(name).map_err(|_| {
+                ConfigFile::error(format!("Environment variable `{}` is not set", name))
+            })?;
+            expanded.push_str(&value);
+            rest = &rest[len..];
+        }
+        expanded.push_str(rest);
+        Ok(expanded)
+    }
 }
@@ -0,0 +1,121 @@
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+/// Graph of the files which the templates depend on
+///
+/// Each edge represents that a template includes, extends or embeds another
+/// file (e.g. with `include!`, `extends` or `asset!`).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DependencyGraph {
+    edges: Vec<(PathBuf, PathBuf)>,
+}
+
+impl DependencyGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that `from` depends on `to`
+    pub fn add_edge(&mut self, from: &Path, to: &Path) {
+        if !self.edges.iter().any(|(f, t)| f == from && t == to) {
+            self.edges.push((from.to_owned(), to.to_owned()));
+        }
+    }
+
+    /// Iterate over the edges in the order of insertion
+    pub fn edges(&self) -> impl Iterator<Item = (&Path, &Path)> {
+        self.edges.iter().map(|(f, t)| (f.as_path(), t.as_path()))
+    }
+
+    /// Files which `file` refers to directly
+    pub fn dependencies(&self, file: &Path) -> Vec<&Path> {
+        self.edges()
+            .filter(|&(f, _)| f == file)
+            .map(|(_, t)| t)
+            .collect()
+    }
+
+    /// Files which `file` depends on directly or indirectly
+    pub fn transitive_dependencies(&self, file: &Path) -> Vec<&Path> {
+        self.walk(file, |(f, t)| (f, t))
+    }
+
+    /// Files which depend on `file` directly or indirectly
+    ///
+    /// These files must be recompiled when `file` is modified.
+    pub fn dependents(&self, file: &Path) -> Vec<&Path> {
+        self.walk(file, |(f, t)| (t, f))
+    }
+
+    /// Render the graph in the Makefile format, as well as `.d` files emitted
+    /// by rustc
+    pub fn to_dep_info(&self, target: &Path) -> String {
+        let mut out = String::new();
+        let _ = write!(out, "{}:", target.display());
+        for dep in self.transitive_dependencies(target) {
+            let _ = write!(out, " {}", dep.display());
+        }
+        out.push('\n');
+        out
+    }
+
+    /// collect the files reachable from `start`, following the edges in the
+    /// direction given by `orient`
+    fn walk<'a, F>(&'a self, start: &Path, orient: F) -> Vec<&'a Path>
+    where
+        F: Fn((&'a Path, &'a Path)) -> (&'a Path, &'a Path),
+    {
+        let mut found: Vec<&'a Path> = Vec::new();
+        let mut pending: Vec<&Path> = vec![start];
+        while let Some(current) = pending.pop() {
+            for (from, to) in self.edges().map(&orient) {
+                if from == current && to != start && !found.contains(&to) {
+                    found.push(to);
+                    pending.push(to);
+                }
+            }
+        }
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transitive() {
+        let mut graph = DependencyGraph::new();
+        graph.add_edge(Path::new("index.stpl"), Path::new("header.stpl"));
+        graph.add_edge(Path::new("header.stpl"), Path::new("nav.stpl"));
+        graph.add_edge(Path::new("index.stpl"), Path::new("layout.stpl"));
+        graph.add_edge(Path::new("layout.stpl"), Path::new("nav.stpl"));
+        graph.add_edge(Path::new("index.stpl"), Path::new("header.stpl"));
+        assert_eq!(graph.edges().count(), 4);
+
+        assert_eq!(
+            graph.dependencies(Path::new("index.stpl")),
+            vec![Path::new("header.stpl"), Path::new("layout.stpl")]
+        );
+        assert_eq!(
+            graph.transitive_dependencies(Path::new("index.stpl")),
+            vec![
+                Path::new("header.stpl"),
+                Path::new("layout.stpl"),
+                Path::new("nav.stpl")
+            ]
+        );
+        assert_eq!(
+            graph.dependents(Path::new("nav.stpl")),
+            vec![
+                Path::new("header.stpl"),
+                Path::new("layout.stpl"),
+                Path::new("index.stpl")
+            ]
+        );
+        assert_eq!(
+            graph.to_dep_info(Path::new("index.stpl")),
+            "index.stpl: header.stpl layout.stpl nav.stpl\n"
+        );
+    }
+}
@@ -68,6 +68,14 @@ impl Error {
         }
     }
 
+    /// Attach the template source and the position of the error, which are
+    /// displayed with the error message
+    pub fn with_source(mut self, source: &str, offset: usize) -> Self {
+        self.source = Some(source.to_owned());
+        self.offset = Some(offset);
+        self
+    }
+
     pub fn kind(&self) -> &ErrorKind {
         self.chains.last().unwrap()
     }
@@ -1,17 +1,21 @@
 #[macro_use]
 mod error;
 
+mod checker;
 mod compiler;
 mod config;
+mod depgraph;
 mod optimizer;
 mod parser;
 mod resolver;
 mod translator;
 mod util;
 
-pub use compiler::Compiler;
-pub use config::Config;
+pub use compiler::{block_artifact_path, CompilationReport, Compiler};
+pub use config::{AssetHash, Config, EscapeMode, GlobalValue};
+pub use depgraph::DependencyGraph;
 pub use error::{Error, ErrorKind};
+pub use parser::{ParseStream, Parser, Token, TokenKind};
 
 #[cfg(feature = "procmacro")]
 #[doc(hidden)]
@@ -1,7 +1,10 @@
 use quote::quote;
 use syn::parse::{Parse, ParseStream, Result as ParseResult};
+use syn::punctuated::Punctuated;
 use syn::visit_mut::VisitMut;
-use syn::{Block, Expr, ExprMacro, Ident, LitStr, Stmt, Token};
+use syn::{Block, Expr, ExprCall, ExprIf, ExprMacro, Ident, Lit, LitStr, Stmt, Token};
+
+use crate::util::escape_html;
 
 struct RenderTextMacroArgument {
     #[allow(dead_code)]
@@ -35,6 +38,236 @@ fn get_rendertext_value(i: &ExprMacro) -> Option<String> {
     None
 }
 
+/// Returns the name and parameters if the block starts with
+/// `__sf_macro!(name(args));`
+fn get_macro_signature(blk: &Block) -> Option<(Ident, Vec<Ident>)> {
+    let em = match blk.stmts.first() {
+        Some(Stmt::Semi(Expr::Macro(ref em), _))
+            if em.mac.path.is_ident("__sf_macro") =>
+        {
+            em
+        }
+        _ => return None,
+    };
+
+    let call = syn::parse2::<ExprCall>(em.mac.tokens.clone()).ok()?;
+    let ident = |e: &Expr| match *e {
+        Expr::Path(ref p) => p.path.get_ident().cloned(),
+        _ => None,
+    };
+    let name = ident(&call.func)?;
+    let params = call.args.iter().map(ident).collect::<Option<Vec<_>>>()?;
+    Some((name, params))
+}
+
+/// Lower the macros defined in templates into `macro_rules!`
+///
+/// This must be done after all other transformations since the contents of
+/// `macro_rules!` are opaque to the visitors.
+struct MacroLowering;
+
+impl VisitMut for MacroLowering {
+    fn visit_block_mut(&mut self, i: &mut Block) {
+        syn::visit_mut::visit_block_mut(self, i);
+
+        for stmt in i.stmts.iter_mut() {
+            let lowered = match *stmt {
+                Stmt::Expr(Expr::Block(ref eb)) => {
+                    get_macro_signature(&eb.block).map(|(name, params)| {
+                        let body = &eb.block.stmts[1..];
+                        quote! {
+                            macro_rules! #name {
+                                (#($#params:expr),* $(,)?) => {{
+                                    let (#(#params,)*) = (#($#params,)*);
+                                    #(#body)*
+                                }};
+                            }
+                        }
+                    })
+                }
+                _ => None,
+            };
+
+            if let Some(tokens) = lowered {
+                *stmt = syn::parse2(tokens).unwrap();
+            }
+        }
+    }
+}
+
+/// Elements whose contents are kept as-is by the minifier
+const PRESERVED_ELEMENTS: &[&str] = &["pre", "script", "style", "textarea"];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum MinifyState {
+    Text,
+    /// inside the tag. `preserve` is the name of the element whose contents
+    /// must be preserved
+    Tag {
+        quote: Option<char>,
+        preserve: Option<String>,
+    },
+    /// inside the comment which contains the dynamic contents
+    Comment,
+    /// inside `<pre>`, `<script>`, etc.
+    Preserved(String),
+}
+
+/// Minify the static HTML parts of templates
+///
+/// Texts are visited in the source order, so that the state (e.g. inside
+/// `<pre>` tag) is carried over the dynamic contents.
+struct Minifier {
+    state: MinifyState,
+}
+
+impl Minifier {
+    fn new() -> Self {
+        Self {
+            state: MinifyState::Text,
+        }
+    }
+
+    fn minify(&mut self, text: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        let mut rest = text;
+
+        while let Some(c) = rest.chars().next() {
+            match self.state {
+                MinifyState::Text => {
+                    if c.is_whitespace() {
+                        let next = rest.trim_start();
+                        let ws = &rest[..rest.len() - next.len()];
+                        // newlines between tags or code blocks are not significant
+                        if !(ws.contains('\n')
+                            && (out.is_empty() || out.ends_with('>'))
+                            && (next.is_empty() || next.starts_with('<')))
+                        {
+                            out.push(' ');
+                        }
+                        rest = next;
+                        continue;
+                    }
+
+                    if rest.starts_with("<!--") && !rest.starts_with("<!--[if") {
+                        match rest.find("-->") {
+                            Some(p) => rest = &rest[p + 3..],
+                            None => {
+                                // the comment contains the dynamic contents
+                                out.push_str("<!--");
+                                rest = &rest[4..];
+                                self.state = MinifyState::Comment;
+                            }
+                        }
+                        continue;
+                    }
+
+                    if c == '<'
+                        && rest[1..].starts_with(|c: char| {
+                            c.is_ascii_alphabetic() || c == '/' || c == '!'
+                        })
+                    {
+                        let name = rest[1..]
+                            .chars()
+                            .take_while(char::is_ascii_alphanumeric)
+                            .collect::<String>()
+                            .to_ascii_lowercase();
+                        let preserve = if PRESERVED_ELEMENTS.contains(&&*name) {
+                            Some(name)
+                        } else {
+                            None
+                        };
+                        self.state = MinifyState::Tag {
+                            quote: None,
+                            preserve,
+                        };
+                    }
+                    out.push(c);
+                }
+                MinifyState::Tag {
+                    ref mut quote,
+                    ref mut preserve,
+                } => match *quote {
+                    Some(q) => {
+                        if c == q {
+                            *quote = None;
+                        }
+                        out.push(c);
+                    }
+                    None if c.is_whitespace() => {
+                        let next = rest.trim_start();
+                        if !(next.starts_with('>')
+                            || next.starts_with("/>")
+                            || next.starts_with('=')
+                            || out.ends_with('=')
+                            || out.ends_with(char::is_whitespace))
+                        {
+                            out.push(' ');
+                        }
+                        rest = next;
+                        continue;
+                    }
+                    None if c == '"' || c == '\'' => {
+                        *quote = Some(c);
+                        out.push(c);
+                    }
+                    None if c == '>' => {
+                        out.push(c);
+                        self.state = match preserve.take() {
+                            Some(name) => MinifyState::Preserved(name),
+                            None => MinifyState::Text,
+                        };
+                    }
+                    None => out.push(c),
+                },
+                MinifyState::Comment => match rest.find("-->") {
+                    Some(p) => {
+                        out.push_str(&rest[..p + 3]);
+                        rest = &rest[p + 3..];
+                        self.state = MinifyState::Text;
+                        continue;
+                    }
+                    None => {
+                        out.push_str(rest);
+                        break;
+                    }
+                },
+                MinifyState::Preserved(ref name) => {
+                    let end_tag = format!("</{}", name);
+                    match rest.to_ascii_lowercase().find(&*end_tag) {
+                        Some(p) => {
+                            out.push_str(&rest[..p]);
+                            rest = &rest[p..];
+                            self.state = MinifyState::Text;
+                            continue;
+                        }
+                        None => {
+                            out.push_str(rest);
+                            break;
+                        }
+                    }
+                }
+            }
+
+            rest = &rest[c.len_utf8()..];
+        }
+
+        out
+    }
+}
+
+impl VisitMut for Minifier {
+    fn visit_expr_macro_mut(&mut self, i: &mut ExprMacro) {
+        if let Some(v) = get_rendertext_value(i) {
+            let minified = self.minify(&v);
+            i.mac.tokens = quote! { __sf_buf, #minified };
+            return;
+        }
+
+        syn::visit_mut::visit_expr_macro_mut(self, i);
+    }
+}
+
 struct OptmizerImpl {
     rm_whitespace: bool,
 }
@@ -81,7 +314,7 @@ impl VisitMut for OptmizerImpl {
         let new_expr = syn::parse2(quote! {{
             __sf_rt::render_text!(__sf_buf, #sf);
             #fl;
-            unsafe { __sf_buf._set_len(__sf_buf.len() - #sf_len); }
+            __sf_buf.truncate(__sf_buf.len() - #sf_len);
         }})
         .unwrap();
 
@@ -125,8 +358,303 @@ impl VisitMut for OptmizerImpl {
     }
 }
 
+fn get_text_stmt(stmt: &Stmt) -> Option<String> {
+    match *stmt {
+        Stmt::Semi(Expr::Macro(ref em), _) => get_rendertext_value(em),
+        _ => None,
+    }
+}
+
+struct RenderMacroArguments {
+    args: Punctuated<Expr, Token![,]>,
+}
+
+impl Parse for RenderMacroArguments {
+    fn parse(s: ParseStream) -> ParseResult<Self> {
+        Ok(Self {
+            args: Punctuated::parse_terminated(s)?,
+        })
+    }
+}
+
+/// Returns the rendered contents if the statement renders the static text or
+/// the literal
+fn get_const_text(stmt: &Stmt) -> Option<String> {
+    let em = match *stmt {
+        Stmt::Semi(Expr::Macro(ref em), _) => em,
+        _ => return None,
+    };
+    if let Some(text) = get_rendertext_value(em) {
+        return Some(text);
+    }
+
+    let mut it = em.mac.path.segments.iter().map(|s| s.ident.to_string());
+    let escape = match (it.next().as_deref(), it.next().as_deref(), it.next()) {
+        (Some("__sf_rt"), Some("render"), None)
+        | (Some("__sf_rt"), Some("render_once"), None) => false,
+        (Some("__sf_rt"), Some("render_escaped"), None)
+        | (Some("__sf_rt"), Some("render_once_escaped"), None) => true,
+        _ => return None,
+    };
+
+    let macro_args = syn::parse2::<RenderMacroArguments>(em.mac.tokens.clone()).ok()?;
+    let value = match macro_args.args.iter().nth(1) {
+        Some(Expr::Lit(ref el)) => match el.lit {
+            Lit::Str(ref l) => l.value(),
+            Lit::Char(ref l) => l.value().to_string(),
+            Lit::Int(ref l) => l.base10_digits().to_owned(),
+            Lit::Bool(ref l) => l.value.to_string(),
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    if escape {
+        Some(escape_html(&value))
+    } else {
+        Some(value)
+    }
+}
+
+/// Returns `true` if the statement is `__sf_loc!("path:line:column");`
+/// marker, which describes the location of the following statement
+fn is_loc_marker(stmt: &Stmt) -> bool {
+    match *stmt {
+        Stmt::Semi(Expr::Macro(ref em), _) => em.mac.path.is_ident("__sf_loc"),
+        _ => false,
+    }
+}
+
+fn text_stmt(text: &str) -> Stmt {
+    syn::parse2(quote! { __sf_rt::render_text!(__sf_buf, #text); }).unwrap()
+}
+
+/// Append the text to the statements, concatenating it with the last
+/// statement if it also renders the static text
+fn push_text(stmts: &mut Vec<Stmt>, text: &str) {
+    if text.is_empty() {
+        return;
+    }
+
+    if let Some(last) = stmts.last_mut() {
+        if let Some(prev) = get_text_stmt(last) {
+            *last = text_stmt(&(prev + text));
+            return;
+        }
+    }
+    stmts.push(text_stmt(text));
+}
+
+/// Returns the texts if the block only renders the static texts
+fn get_static_texts(blk: &Block) -> Option<Vec<String>> {
+    blk.stmts
+        .iter()
+        .filter(|stmt| !is_loc_marker(stmt))
+        .map(get_text_stmt)
+        .collect()
+}
+
+/// Returns the blocks of all branches of the `if` expression, or `None` if
+/// the expression has no `else` clause in the end
+fn get_branches(ei: &mut ExprIf) -> Option<Vec<&mut Block>> {
+    let mut branches = Vec::new();
+    let mut current = ei;
+    loop {
+        branches.push(&mut current.then_branch);
+        match current.else_branch {
+            Some((_, ref mut e)) => match **e {
+                Expr::If(ref mut next) => current = next,
+                Expr::Block(ref mut eb) if eb.label.is_none() => {
+                    branches.push(&mut eb.block);
+                    return Some(branches);
+                }
+                _ => return None,
+            },
+            None => return None,
+        }
+    }
+}
+
+fn common_prefix_len(a: &str, b: &str) -> usize {
+    a.char_indices()
+        .zip(b.chars())
+        .find(|&((_, c1), c2)| c1 != c2)
+        .map_or_else(|| a.len().min(b.len()), |((i, _), _)| i)
+}
+
+fn common_suffix_len(a: &str, b: &str) -> usize {
+    a.char_indices()
+        .rev()
+        .zip(b.chars().rev())
+        .find(|&((_, c1), c2)| c1 != c2)
+        .map_or_else(
+            || a.len().min(b.len()),
+            |((i, c), _)| a.len() - i - c.len_utf8(),
+        )
+}
+
+/// Move the static texts which all branches start or end with out of the
+/// `if` expression, and returns the prefix and suffix
+fn hoist_common_texts(ei: &mut ExprIf) -> (String, String) {
+    let mut branches = match get_branches(ei) {
+        Some(branches) => branches,
+        None => return (String::new(), String::new()),
+    };
+
+    let firsts = branches
+        .iter()
+        .map(|b| b.stmts.first().and_then(get_text_stmt))
+        .collect::<Option<Vec<_>>>();
+    let prefix = match firsts {
+        Some(firsts) => {
+            let len = firsts[1..].iter().fold(firsts[0].len(), |len, t| {
+                common_prefix_len(&firsts[0][..len], t)
+            });
+            firsts[0][..len].to_owned()
+        }
+        None => String::new(),
+    };
+
+    let lasts = branches
+        .iter()
+        .map(|b| {
+            let text = b.stmts.last().and_then(get_text_stmt)?;
+            if b.stmts.len() == 1 {
+                // the prefix is already taken from this text
+                Some(text[prefix.len()..].to_owned())
+            } else {
+                Some(text)
+            }
+        })
+        .collect::<Option<Vec<_>>>();
+    let suffix = match lasts {
+        Some(lasts) => {
+            let len = lasts[1..].iter().fold(lasts[0].len(), |len, t| {
+                common_suffix_len(&lasts[0][lasts[0].len() - len..], t)
+            });
+            lasts[0][lasts[0].len() - len..].to_owned()
+        }
+        None => String::new(),
+    };
+
+    for blk in branches.iter_mut() {
+        if !prefix.is_empty() {
+            let text = get_text_stmt(&blk.stmts[0]).unwrap();
+            blk.stmts.remove(0);
+            if text.len() > prefix.len() {
+                blk.stmts.insert(0, text_stmt(&text[prefix.len()..]));
+            }
+        }
+        if !suffix.is_empty() {
+            let text = get_text_stmt(blk.stmts.last().unwrap()).unwrap();
+            blk.stmts.pop();
+            if text.len() > suffix.len() {
+                blk.stmts
+                    .push(text_stmt(&text[..text.len() - suffix.len()]));
+            }
+        }
+    }
+
+    (prefix, suffix)
+}
+
+/// Merge the static texts across the control flow
+///
+/// Literals in the code blocks are rendered at compile time. Blocks which only render the static texts are flattened, and the texts
+/// which all branches of `if` expressions start or end with are moved out of
+/// the branches, so that the adjacent texts are concatenated into a single
+/// `render_text!()` call.
+///
+/// This must be done after removing whitespaces since it changes the
+/// boundaries of the texts.
+struct StaticTextMerger;
+
+impl VisitMut for StaticTextMerger {
+    fn visit_block_mut(&mut self, i: &mut Block) {
+        syn::visit_mut::visit_block_mut(self, i);
+
+        let mut stmts = Vec::with_capacity(i.stmts.len());
+        for mut stmt in i.stmts.drain(..) {
+            if let Some(text) = get_const_text(&stmt) {
+                // the rendered literal does not need the location any more
+                if matches!(stmts.last(), Some(s) if is_loc_marker(s)) {
+                    stmts.pop();
+                }
+                push_text(&mut stmts, &text);
+                continue;
+            }
+
+            match stmt {
+                Stmt::Expr(Expr::Block(ref eb)) | Stmt::Semi(Expr::Block(ref eb), _)
+                    if eb.attrs.is_empty() && eb.label.is_none() =>
+                {
+                    if let Some(texts) = get_static_texts(&eb.block) {
+                        for text in texts {
+                            push_text(&mut stmts, &text);
+                        }
+                        continue;
+                    }
+                }
+                Stmt::Expr(Expr::If(ref mut ei))
+                | Stmt::Semi(Expr::If(ref mut ei), _) => {
+                    let (prefix, suffix) = hoist_common_texts(ei);
+                    push_text(&mut stmts, &prefix);
+                    stmts.push(stmt);
+                    push_text(&mut stmts, &suffix);
+                    continue;
+                }
+                _ => {}
+            }
+
+            stmts.push(stmt);
+        }
+        i.stmts = stmts;
+    }
+}
+
+/// Returns `true` if the statement is `__sf_buf.truncate(..);` inserted by
+/// the loop optimization
+fn is_truncate(stmt: &Stmt) -> bool {
+    match *stmt {
+        Stmt::Semi(Expr::MethodCall(ref mc), _) => {
+            mc.method == "truncate"
+                && matches!(*mc.receiver, Expr::Path(ref p) if p.path.is_ident("__sf_buf"))
+        }
+        _ => false,
+    }
+}
+
+/// Number of bytes of the static texts which are always rendered
+///
+/// Texts inside the branches and loops are not counted since they may not be
+/// rendered, so the result is the lower bound of the output size.
+pub fn static_size(blk: &Block) -> usize {
+    blk.stmts
+        .iter()
+        .map(|stmt| match *stmt {
+            Stmt::Semi(Expr::Macro(ref em), _) => {
+                get_rendertext_value(em).map_or(0, |s| s.len())
+            }
+            // the text rendered before the loop is removed after the loop
+            Stmt::Expr(Expr::Block(ref eb)) | Stmt::Semi(Expr::Block(ref eb), _)
+                if eb.label.is_none()
+                    && !matches!(eb.block.stmts.last(), Some(s) if is_truncate(s)) =>
+            {
+                static_size(&eb.block)
+            }
+            _ => 0,
+        })
+        .sum()
+}
+
+/// Returns the output if the block only renders the static texts
+pub fn static_output(blk: &Block) -> Option<String> {
+    get_static_texts(blk).map(|texts| texts.concat())
+}
+
 pub struct Optimizer {
     rm_whitespace: bool,
+    minify: bool,
 }
 
 impl Optimizer {
@@ -134,6 +662,7 @@ impl Optimizer {
     pub fn new() -> Self {
         Self {
             rm_whitespace: false,
+            minify: false,
         }
     }
 
@@ -143,11 +672,175 @@ impl Optimizer {
         self
     }
 
+    /// collapse whitespaces, strip HTML comments and trim whitespaces inside
+    /// tags, preserving the contents of `<pre>`, `<textarea>`, `<script>` and
+    /// `<style>`
+    #[inline]
+    pub fn minify(mut self, new: bool) -> Self {
+        self.minify = new;
+        self
+    }
+
     #[inline]
     pub fn optimize(&self, i: &mut Block) {
+        if self.minify {
+            Minifier::new().visit_block_mut(i);
+        }
         OptmizerImpl {
             rm_whitespace: self.rm_whitespace,
         }
         .visit_block_mut(i);
+        StaticTextMerger.visit_block_mut(i);
+        MacroLowering.visit_block_mut(i);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn minify() {
+        let mut minifier = Minifier::new();
+        assert_eq!(
+            minifier.minify(
+                "<ul  class = \"a  b\"\n    id=\"x\" >\n  <li>a  b</li> <li>c</li>\n<!-- comment -->\n</ul>\n"
+            ),
+            "<ul class=\"a  b\" id=\"x\"><li>a b</li> <li>c</li></ul>"
+        );
+        assert_eq!(
+            minifier.minify("<pre>\n  a  <b>b</b>\n</pre>\n<script>\nlet a  = 1;\n"),
+            "<pre>\n  a  <b>b</b>\n</pre><script>\nlet a  = 1;\n"
+        );
+        assert_eq!(minifier.minify("</script>  <br />"), "</script> <br/>");
+        assert_eq!(
+            minifier.minify("<!--[if IE]><p>IE</p><![endif]--><!-- a "),
+            "<!--[if IE]><p>IE</p><![endif]--><!-- a "
+        );
+        assert_eq!(minifier.minify(" b -->  c"), " b --> c");
+    }
+
+    #[test]
+    fn static_texts_size() {
+        let mut blk: Block = syn::parse_str(
+            r#"{
+                __sf_rt::render_text!(__sf_buf, "<ul>");
+                for item in items {
+                    __sf_rt::render_text!(__sf_buf, "<li>");
+                    __sf_rt::render!(__sf_buf, item);
+                    __sf_rt::render_text!(__sf_buf, "</li>");
+                }
+                if items.is_empty() {
+                    __sf_rt::render_text!(__sf_buf, "empty");
+                }
+                {
+                    let title = "é";
+                    __sf_rt::render_text!(__sf_buf, "<p>é</p>");
+                }
+                __sf_rt::render_text!(__sf_buf, "</ul>");
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(static_size(&blk), 18);
+
+        // the loop optimization does not change the size
+        Optimizer::new().optimize(&mut blk);
+        assert_eq!(static_size(&blk), 18);
+    }
+
+    fn merge(src: &str) -> String {
+        let mut blk: Block = syn::parse_str(src).unwrap();
+        StaticTextMerger.visit_block_mut(&mut blk);
+        let blk = blk.stmts;
+        quote!(#(#blk)*).to_string()
+    }
+
+    #[test]
+    fn merge_static_texts() {
+        let expected = quote! {
+            __sf_rt::render_text!(__sf_buf, "<ul><li>");
+            if a {
+                __sf_rt::render_text!(__sf_buf, "a");
+            } else if b {
+                __sf_rt::render!(__sf_buf, b);
+            } else {
+                __sf_rt::render_text!(__sf_buf, "c");
+            }
+            __sf_rt::render_text!(__sf_buf, "</li><li>x</li></ul>");
+        };
+        assert_eq!(
+            merge(
+                r#"{
+                    __sf_rt::render_text!(__sf_buf, "<ul>");
+                    if a {
+                        __sf_rt::render_text!(__sf_buf, "<li>a</li>");
+                    } else if b {
+                        __sf_rt::render_text!(__sf_buf, "<li>");
+                        __sf_rt::render!(__sf_buf, b);
+                        __sf_rt::render_text!(__sf_buf, "</li>");
+                    } else {
+                        __sf_rt::render_text!(__sf_buf, "<li>c</li>");
+                    }
+                    {
+                        __sf_rt::render_text!(__sf_buf, "<li>");
+                        __sf_rt::render_text!(__sf_buf, "x</li>");
+                    }
+                    __sf_rt::render_text!(__sf_buf, "</ul>");
+                }"#
+            ),
+            expected.to_string()
+        );
+
+        // literals are rendered at compile time
+        let expected = quote! {
+            __sf_rt::render_text!(__sf_buf, "<p>&lt;a&gt;1</p>");
+            __sf_loc!("a.stpl:1:20");
+            __sf_rt::render_escaped!(__sf_buf, a, "ctx");
+        };
+        assert_eq!(
+            merge(
+                r#"{
+                    __sf_rt::render_text!(__sf_buf, "<p>");
+                    __sf_loc!("a.stpl:1:4");
+                    __sf_rt::render_escaped!(__sf_buf, "<a>", "ctx");
+                    __sf_loc!("a.stpl:1:14");
+                    __sf_rt::render!(__sf_buf, 1, "ctx");
+                    __sf_rt::render_text!(__sf_buf, "</p>");
+                    __sf_loc!("a.stpl:1:20");
+                    __sf_rt::render_escaped!(__sf_buf, a, "ctx");
+                }"#
+            ),
+            expected.to_string()
+        );
+
+        // the texts are not moved out of `if` without `else`
+        let expected = quote! {
+            if a {
+                __sf_rt::render_text!(__sf_buf, "<li>a</li>");
+            }
+        };
+        assert_eq!(
+            merge(r#"{ if a { __sf_rt::render_text!(__sf_buf, "<li>a</li>"); } }"#),
+            expected.to_string()
+        );
+
+        let expected = quote! {
+            __sf_rt::render_text!(__sf_buf, "ab");
+            if a {} else {
+                __sf_rt::render_text!(__sf_buf, "é");
+            }
+        };
+        assert_eq!(
+            merge(
+                r#"{
+                    if a {
+                        __sf_rt::render_text!(__sf_buf, "ab");
+                    } else {
+                        __sf_rt::render_text!(__sf_buf, "abé");
+                    }
+                }"#
+            ),
+            expected.to_string()
+        );
     }
 }
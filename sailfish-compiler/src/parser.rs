@@ -18,7 +18,8 @@ macro_rules! unwrap_or_break {
 
 #[derive(Clone, Debug)]
 pub struct Parser {
-    delimiter: char,
+    block_delimiter: Rc<(String, String)>,
+    trim_tag_lines: bool,
 }
 
 impl Parser {
@@ -28,32 +29,62 @@ impl Parser {
 
     /// change delimiter
     pub fn delimiter(mut self, new: char) -> Self {
-        self.delimiter = new;
+        self.block_delimiter = Rc::new((format!("<{}", new), format!("{}>", new)));
+        self
+    }
+
+    /// change the opening and closing tags (e.g. `{%` and `%}`)
+    ///
+    /// Both tags must not be empty.
+    pub fn delimiters(mut self, open: &str, close: &str) -> Self {
+        debug_assert!(!open.is_empty() && !close.is_empty());
+        self.block_delimiter = Rc::new((open.to_owned(), close.to_owned()));
+        self
+    }
+
+    /// remove the lines which contain only code blocks or comments
+    ///
+    /// The indentation before the tag and the newline after the tag are
+    /// stripped, so that the control flow does not leave blank lines in the
+    /// plain-text output.
+    pub fn trim_tag_lines(mut self, new: bool) -> Self {
+        self.trim_tag_lines = new;
         self
     }
 
     /// parse source string
     pub fn parse<'a>(&self, source: &'a str) -> ParseStream<'a> {
-        let block_delimiter = Rc::new((
-            format!("<{}", self.delimiter),
-            format!("{}>", self.delimiter),
-        ));
+        // `<%%` is converted into `<%`
+        let delimiter = self.block_delimiter.0.chars().next_back().unwrap();
 
         ParseStream {
-            block_delimiter,
+            block_delimiter: Rc::clone(&self.block_delimiter),
             original_source: source,
             source,
-            delimiter: self.delimiter,
+            delimiter,
+            trim_tag_lines: self.trim_tag_lines,
         }
     }
 }
 
 impl Default for Parser {
     fn default() -> Self {
-        Self { delimiter: '%' }
+        Self {
+            block_delimiter: Rc::new(("<%".to_owned(), "%>".to_owned())),
+            trim_tag_lines: false,
+        }
     }
 }
 
+/// Whitespace trimming requested by the closing tag
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Trim {
+    /// `-%>`: strip the newline right after the tag
+    Newline,
+    /// `_%>`: strip all whitespaces after the tag
+    All,
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum TokenKind {
     BufferedCode { escape: bool },
@@ -101,6 +132,7 @@ pub struct ParseStream<'a> {
     pub(crate) original_source: &'a str,
     source: &'a str,
     delimiter: char,
+    trim_tag_lines: bool,
 }
 
 impl<'a> ParseStream<'a> {
@@ -122,6 +154,14 @@ impl<'a> ParseStream<'a> {
         Ok(vec)
     }
 
+    /// Skip the first `len` bytes of the source (e.g. front-matter)
+    ///
+    /// The offsets in the error messages still point to the original source.
+    pub(crate) fn skip(mut self, len: usize) -> Self {
+        self.source = &self.source[len..];
+        self
+    }
+
     fn error(&self, msg: &str) -> Error {
         let offset = self.original_source.len() - self.source.len();
         make_error!(
@@ -141,12 +181,89 @@ impl<'a> ParseStream<'a> {
         l
     }
 
+    /// Returns `true` if the source starts with the opening tag which strips the
+    /// preceding whitespaces (`<%_`)
+    fn starts_with_trim_tag(&self, source: &str) -> bool {
+        let open = &*self.block_delimiter.0;
+        if !source.starts_with(open) {
+            return false;
+        }
+
+        let mut bytes = source[open.len()..].bytes();
+        bytes.next() == Some(b'_')
+            && matches!(
+                bytes.next(),
+                Some(b'#' | b'=' | b'-' | b' ' | b'\t' | b'\n'..=b'\r')
+            )
+    }
+
+    /// If the source starts with a code block or comment which occupies the
+    /// whole line, returns the length of the whitespaces and the newline after
+    /// the closing tag
+    fn standalone_tag(&self) -> Option<usize> {
+        if !self.trim_tag_lines
+            || !self.source.starts_with(&*self.block_delimiter.0)
+            || self.source[self.block_delimiter.0.len()..].starts_with(self.delimiter)
+        {
+            return None;
+        }
+
+        let before = &self.original_source[..self.offset()];
+        let line_start = before.rfind('\n').map_or(0, |p| p + 1);
+        if !before[line_start..]
+            .bytes()
+            .all(|b| b == b' ' || b == b'\t')
+        {
+            return None;
+        }
+
+        let mut rest = self.clone();
+        match rest.tokenize_code() {
+            Ok(ref t) if matches!(t.kind, TokenKind::Code | TokenKind::Comment) => {}
+            _ => return None,
+        }
+
+        let after = rest.source.trim_start_matches(&[' ', '\t'][..]);
+        let newline = if after.is_empty() || after.starts_with('\n') {
+            after.len().min(1)
+        } else if after.starts_with("\r\n") {
+            2
+        } else {
+            return None;
+        };
+
+        Some(rest.source.len() - after.len() + newline)
+    }
+
+    /// Skip the whitespaces after the closing tag
+    fn skip_trimmed(&mut self, trim: Option<Trim>) {
+        let len = match trim {
+            Some(Trim::Newline) => {
+                if self.source.starts_with('\n') {
+                    1
+                } else if self.source.starts_with("\r\n") {
+                    2
+                } else {
+                    0
+                }
+            }
+            Some(Trim::All) => self.source.len() - self.source.trim_start().len(),
+            None => 0,
+        };
+        self.take_n(len);
+    }
+
     fn tokenize_code(&mut self) -> Result<Token<'a>, Error> {
         debug_assert!(self.source.starts_with(&*self.block_delimiter.0));
 
         let mut start = self.block_delimiter.0.len();
         let mut token_kind = TokenKind::Code;
 
+        // preceding whitespaces were already stripped from the text token
+        if self.starts_with_trim_tag(self.source) {
+            start += 1;
+        }
+
         // read flags
         match self.source.as_bytes().get(start).copied() {
             Some(b'#') => {
@@ -180,13 +297,16 @@ impl<'a> ParseStream<'a> {
                 .ok_or_else(|| self.error("Unterminated comment block"))?;
 
             self.take_n(start);
+            let (content, trim) = split_trim_marker(&self.source[..pos]);
+            let content = content.trim_end();
             let token = Token {
-                content: self.source[..pos].trim_end(),
+                content,
                 offset: self.offset(),
                 kind: token_kind,
             };
 
             self.take_n(pos + self.block_delimiter.1.len());
+            self.skip_trimmed(trim);
             return Ok(token);
         }
 
@@ -195,21 +315,71 @@ impl<'a> ParseStream<'a> {
         {
             // closing bracket was found
             self.take_n(start);
-            let s = &self.source[..pos - self.block_delimiter.1.len()].trim_end_matches(
-                |c| matches!(c, ' ' | '\t' | '\r' | '\u{000B}' | '\u{000C}'),
-            );
+            let (content, trim) =
+                split_trim_marker(&self.source[..pos - self.block_delimiter.1.len()]);
+            let content = content.trim_end_matches(|c| {
+                matches!(c, ' ' | '\t' | '\r' | '\u{000B}' | '\u{000C}')
+            });
             let token = Token {
-                content: s,
+                content,
                 offset: self.offset(),
                 kind: token_kind,
             };
             self.take_n(pos);
+            self.skip_trimmed(trim);
             Ok(token)
         } else {
             Err(self.error("Unterminated code block"))
         }
     }
 
+    /// Read the contents of `<% raw %>` block as a text token
+    fn tokenize_raw(&mut self) -> Result<Token<'a>, Error> {
+        let offset = self.offset();
+        let (open, close) = (&*self.block_delimiter.0, &*self.block_delimiter.1);
+
+        let mut searched = 0;
+        while let Some(pos) = self.source[searched..].find(open) {
+            let start = searched + pos;
+            searched = start + open.len();
+
+            // <%_ endraw -%>
+            let rest = &self.source[searched..];
+            let trim_before = rest.starts_with('_');
+            let rest = if trim_before { &rest[1..] } else { rest }.trim_start();
+            if !rest.starts_with("endraw") {
+                continue;
+            }
+            let rest = rest["endraw".len()..].trim_start();
+            let (rest, trim) = if rest.starts_with('-') {
+                (rest[1..].trim_start(), Some(Trim::Newline))
+            } else if rest.starts_with('_') {
+                (rest[1..].trim_start(), Some(Trim::All))
+            } else {
+                (rest, None)
+            };
+            if !rest.starts_with(close) {
+                continue;
+            }
+
+            let end = self.source.len() - rest.len() + close.len();
+            let mut content = &self.source[..start];
+            if trim_before {
+                content = content.trim_end();
+            }
+            self.take_n(end);
+            self.skip_trimmed(trim);
+
+            return Ok(Token {
+                content,
+                offset,
+                kind: TokenKind::Text,
+            });
+        }
+
+        Err(self.error("Unterminated raw block"))
+    }
+
     fn tokenize_text(&mut self) -> Result<Token<'a>, Error> {
         // TODO: allow buffer block inside code block
         let offset = self.offset();
@@ -217,8 +387,14 @@ impl<'a> ParseStream<'a> {
             .source
             .find(&*self.block_delimiter.0)
             .unwrap_or_else(|| self.source.len());
+        let mut content = self.take_n(end);
+        if self.starts_with_trim_tag(self.source) {
+            content = content.trim_end();
+        } else if self.standalone_tag().is_some() {
+            content = content.trim_end_matches(&[' ', '\t'][..]);
+        }
         let token = Token {
-            content: self.take_n(end),
+            content,
             offset,
             kind: TokenKind::Text,
         };
@@ -233,6 +409,7 @@ impl<'a> Default for ParseStream<'a> {
             original_source: "",
             source: "",
             delimiter: '%',
+            trim_tag_lines: false,
         }
     }
 }
@@ -241,33 +418,53 @@ impl<'a> Iterator for ParseStream<'a> {
     type Item = Result<Token<'a>, Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.source.is_empty() {
-            return None;
+        loop {
+            if self.source.is_empty() {
+                return None;
+            }
+
+            let token = self.next_token();
+
+            // text tokens may become empty after whitespaces are stripped
+            match token {
+                Ok(ref t) if t.kind == TokenKind::Text && t.content.is_empty() => {}
+                _ => return Some(token),
+            }
         }
+    }
+}
 
-        let token = if self.source.starts_with(&*self.block_delimiter.0) {
+impl<'a> ParseStream<'a> {
+    fn next_token(&mut self) -> Result<Token<'a>, Error> {
+        if self.source.starts_with(&*self.block_delimiter.0) {
             if !self.source[self.block_delimiter.0.len()..].starts_with(self.delimiter) {
-                self.tokenize_code()
+                let standalone = self.standalone_tag();
+                let token = self.tokenize_code()?;
+                if let Some(len) = standalone {
+                    self.take_n(len);
+                }
+                match (token.kind, token.content) {
+                    (TokenKind::Code, "raw") => self.tokenize_raw(),
+                    (TokenKind::Code, "endraw") => Err(make_error!(
+                        ErrorKind::ParseError("Unexpected `endraw` tag".to_owned()),
+                        source = self.original_source.to_owned(),
+                        offset = token.offset
+                    )),
+                    _ => Ok(token),
+                }
             } else {
-                debug_assert_eq!(
-                    &self.source[..self.delimiter.len_utf8() * 2 + 1],
-                    format!("<{0}{0}", self.delimiter)
-                );
-
                 // Escape '<%%' token
                 let token = Token {
                     content: &self.source[..self.block_delimiter.0.len()],
                     offset: self.offset(),
                     kind: TokenKind::Text,
                 };
-                self.take_n(self.block_delimiter.0.len() * 2 - 1);
+                self.take_n(self.block_delimiter.0.len() + self.delimiter.len_utf8());
                 Ok(token)
             }
         } else {
             self.tokenize_text()
-        };
-
-        Some(token)
+        }
     }
 }
 
@@ -279,6 +476,43 @@ impl<'a> TryInto<Vec<Token<'a>>> for ParseStream<'a> {
     }
 }
 
+/// Split off the trailing `-` or `_` which requests trimming the whitespaces
+/// after the closing tag
+///
+/// `_` is treated as the modifier only when it touches the closing delimiter
+/// and does not complete the Rust code, such as `foo_` or `when _`.
+fn split_trim_marker(content: &str) -> (&str, Option<Trim>) {
+    let trimmed = content.trim_end();
+    if trimmed.ends_with('-') {
+        (trimmed[..trimmed.len() - 1].trim_end(), Some(Trim::Newline))
+    } else if content.ends_with('_') {
+        let rest = &content[..content.len() - 1];
+        if completed_by_underscore(rest) {
+            (content, None)
+        } else {
+            (rest.trim_end(), Some(Trim::All))
+        }
+    } else {
+        (content, None)
+    }
+}
+
+/// Returns `true` if the `_` after `code` is a part of the Rust code
+fn completed_by_underscore(code: &str) -> bool {
+    let is_ident_char = |c: char| c.is_alphanumeric() || c == '_';
+
+    // `foo_` is an identifier
+    if matches!(code.chars().next_back(), Some(c) if is_ident_char(c)) {
+        return true;
+    }
+
+    // wildcard pattern in `when _` or `when A | _`
+    let code = code.trim_end();
+    code.ends_with('|')
+        || (code.ends_with("when")
+            && !code[..code.len() - "when".len()].ends_with(is_ident_char))
+}
+
 fn find_block_end(haystack: &str, delimiter: &str) -> Option<usize> {
     let mut remain = haystack;
 
@@ -447,6 +681,68 @@ mod tests {
         );
     }
 
+    #[test]
+    fn custom_delimiters() {
+        let src = "<%= a %>{%= b %}{%% {%- \"%}\" -%}\n";
+        let parser = Parser::new().delimiters("{%", "%}");
+        let tokens = parser.parse(src).into_vec().unwrap();
+        assert_eq!(
+            &tokens,
+            &[
+                Token {
+                    content: "<%= a %>",
+                    offset: 0,
+                    kind: TokenKind::Text
+                },
+                Token {
+                    content: "b",
+                    offset: 12,
+                    kind: TokenKind::BufferedCode { escape: true }
+                },
+                Token {
+                    content: "{%",
+                    offset: 16,
+                    kind: TokenKind::Text
+                },
+                Token {
+                    content: " ",
+                    offset: 19,
+                    kind: TokenKind::Text
+                },
+                Token {
+                    content: "\"%}\"",
+                    offset: 24,
+                    kind: TokenKind::BufferedCode { escape: false }
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn raw_block() {
+        let src = "<% raw -%>\n<%= a %><% endblock %>\n<%_ endraw %>b";
+        let parser = Parser::new();
+        let tokens = parser.parse(src).into_vec().unwrap();
+        assert_eq!(
+            &tokens,
+            &[
+                Token {
+                    content: "<%= a %><% endblock %>",
+                    offset: 11,
+                    kind: TokenKind::Text
+                },
+                Token {
+                    content: "b",
+                    offset: 47,
+                    kind: TokenKind::Text
+                },
+            ]
+        );
+
+        assert!(parser.parse("<% raw %><%= a %>").into_vec().is_err());
+        assert!(parser.parse("<% endraw %>").into_vec().is_err());
+    }
+
     #[test]
     fn comment_inside_block() {
         let src = "<% // %>\n %><%= /* %%>*/ 1 %>";
@@ -468,4 +764,107 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn trim_whitespace() {
+        let src = "a  \n <%_ if x { -%>\n  b\n<%= foo_ %>\n<%_# c _%>  \n d";
+        let parser = Parser::new();
+        let tokens = parser.parse(src).into_vec().unwrap();
+        assert_eq!(
+            &tokens,
+            &[
+                Token {
+                    content: "a",
+                    offset: 0,
+                    kind: TokenKind::Text
+                },
+                Token {
+                    content: "if x {",
+                    offset: 9,
+                    kind: TokenKind::Code
+                },
+                Token {
+                    content: "  b\n",
+                    offset: 20,
+                    kind: TokenKind::Text
+                },
+                Token {
+                    content: "foo_",
+                    offset: 28,
+                    kind: TokenKind::BufferedCode { escape: true }
+                },
+                Token {
+                    content: "c",
+                    offset: 41,
+                    kind: TokenKind::Comment
+                },
+                Token {
+                    content: "d",
+                    offset: 50,
+                    kind: TokenKind::Text
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn trim_marker_wildcard() {
+        let src = "<% when _ %><% when _%><% when A | _%><%= a _%> b";
+        let parser = Parser::new();
+        let tokens = parser.parse(src).into_vec().unwrap();
+        let contents: Vec<_> = tokens.iter().map(|t| t.as_str()).collect();
+        assert_eq!(contents, ["when _", "when _", "when A | _", "a", "b"]);
+    }
+
+    #[test]
+    fn trim_tag_lines() {
+        let src = "<% if x { %>\n  a <% y %>\n  <%# c %>  \n<%= b %>\n<% } %>";
+        let parser = Parser::new().trim_tag_lines(true);
+        let tokens = parser.parse(src).into_vec().unwrap();
+        assert_eq!(
+            &tokens,
+            &[
+                Token {
+                    content: "if x {",
+                    offset: 3,
+                    kind: TokenKind::Code
+                },
+                Token {
+                    content: "  a ",
+                    offset: 13,
+                    kind: TokenKind::Text
+                },
+                Token {
+                    content: "y",
+                    offset: 20,
+                    kind: TokenKind::Code
+                },
+                Token {
+                    content: "\n",
+                    offset: 24,
+                    kind: TokenKind::Text
+                },
+                Token {
+                    content: "c",
+                    offset: 31,
+                    kind: TokenKind::Comment
+                },
+                Token {
+                    content: "b",
+                    offset: 42,
+                    kind: TokenKind::BufferedCode { escape: true }
+                },
+                Token {
+                    content: "\n",
+                    offset: 46,
+                    kind: TokenKind::Text
+                },
+                Token {
+                    content: "}",
+                    offset: 50,
+                    kind: TokenKind::Code
+                },
+            ]
+        );
+    }
 }
@@ -1,25 +1,75 @@
 use proc_macro2::{Span, TokenStream};
-use quote::{quote, ToTokens};
+use quote::{quote, quote_spanned, ToTokens};
 use std::env;
 use std::path::{Path, PathBuf};
 use syn::parse::{Parse, ParseStream, Result as ParseResult};
 use syn::punctuated::Punctuated;
-use syn::{Fields, Ident, ItemStruct, LitBool, LitChar, LitStr, Token};
-
-use crate::compiler::{CompilationReport, Compiler};
-use crate::config::Config;
+use syn::{
+    Fields, Ident, Index, ItemStruct, Lit, LitBool, LitChar, LitStr, Member, Token,
+};
+
+use crate::compiler::{block_artifact_path, CompilationReport, Compiler};
+use crate::config::{
+    is_valid_tag, resolve_crate_path, split_crate_path, Config, EscapeMode,
+};
 use crate::error::*;
+use crate::util::fnv_hash;
 
 // options for `template` attributes
 #[derive(Default)]
 struct DeriveTemplateOptions {
     path: Option<LitStr>,
     delimiter: Option<LitChar>,
-    escape: Option<LitBool>,
+    delimiters: Option<Delimiters>,
+    escape: Option<Lit>,
+    escape_extra: Option<LitStr>,
     rm_whitespace: Option<LitBool>,
+    minify: Option<LitBool>,
+    text_mode: Option<LitBool>,
+    check_html: Option<LitBool>,
+    nonce: Option<LitStr>,
+    allow_unused_fields: Option<LitBool>,
+    hot_reload: Option<LitBool>,
     type_: Option<LitStr>,
 }
 
+// `delimiters = ("{%", "%}")`
+struct Delimiters {
+    open: LitStr,
+    close: LitStr,
+}
+
+impl Parse for Delimiters {
+    fn parse(outer: ParseStream) -> ParseResult<Self> {
+        let s;
+        syn::parenthesized!(s in outer);
+        let open = s.parse::<LitStr>()?;
+        s.parse::<Token![,]>()?;
+        let close = s.parse::<LitStr>()?;
+        if !s.is_empty() {
+            s.parse::<Token![,]>()?;
+        }
+
+        for lit in [&open, &close].iter() {
+            if !is_valid_tag(&lit.value()) {
+                return Err(syn::Error::new_spanned(
+                    lit,
+                    "tags must not be empty or contain whitespaces",
+                ));
+            }
+        }
+
+        Ok(Self { open, close })
+    }
+}
+
+impl ToTokens for Delimiters {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        self.open.to_tokens(tokens);
+        self.close.to_tokens(tokens);
+    }
+}
+
 impl Parse for DeriveTemplateOptions {
     fn parse(outer: ParseStream) -> ParseResult<Self> {
         let s;
@@ -44,10 +94,52 @@ impl Parse for DeriveTemplateOptions {
                 options.path = Some(s.parse::<LitStr>()?);
             } else if key == "delimiter" {
                 options.delimiter = Some(s.parse::<LitChar>()?);
+            } else if key == "delimiters" {
+                options.delimiters = Some(s.parse::<Delimiters>()?);
             } else if key == "escape" {
-                options.escape = Some(s.parse::<LitBool>()?);
+                let lit = s.parse::<Lit>()?;
+                match lit {
+                    Lit::Bool(_) => {}
+                    Lit::Str(ref l)
+                        if ["html", "json", "xml", "none"].contains(&&*l.value()) => {}
+                    _ => {
+                        return Err(syn::Error::new_spanned(
+                            lit,
+                            "`escape` must be a boolean or one of \"html\", \"json\", \"xml\" or \"none\"",
+                        ));
+                    }
+                }
+                options.escape = Some(lit);
+            } else if key == "escape_extra" {
+                let lit = s.parse::<LitStr>()?;
+                if !lit.value().is_ascii() {
+                    return Err(syn::Error::new_spanned(
+                        lit,
+                        "`escape_extra` must contain ASCII characters only",
+                    ));
+                }
+                options.escape_extra = Some(lit);
             } else if key == "rm_whitespace" {
                 options.rm_whitespace = Some(s.parse::<LitBool>()?);
+            } else if key == "minify" {
+                options.minify = Some(s.parse::<LitBool>()?);
+            } else if key == "text_mode" {
+                options.text_mode = Some(s.parse::<LitBool>()?);
+            } else if key == "check_html" {
+                options.check_html = Some(s.parse::<LitBool>()?);
+            } else if key == "nonce" {
+                let lit = s.parse::<LitStr>()?;
+                if let Err(e) = syn::parse_str::<syn::Expr>(&lit.value()) {
+                    return Err(syn::Error::new_spanned(
+                        lit,
+                        format!("`nonce` must be a Rust expression: {}", e),
+                    ));
+                }
+                options.nonce = Some(lit);
+            } else if key == "allow_unused_fields" {
+                options.allow_unused_fields = Some(s.parse::<LitBool>()?);
+            } else if key == "hot_reload" {
+                options.hot_reload = Some(s.parse::<LitBool>()?);
             } else if key == "type" {
                 options.type_ = Some(s.parse::<LitStr>()?);
             } else {
@@ -91,8 +183,16 @@ impl DeriveTemplateOptions {
 
         merge_single(&mut self.path, other.path)?;
         merge_single(&mut self.delimiter, other.delimiter)?;
+        merge_single(&mut self.delimiters, other.delimiters)?;
         merge_single(&mut self.escape, other.escape)?;
+        merge_single(&mut self.escape_extra, other.escape_extra)?;
         merge_single(&mut self.rm_whitespace, other.rm_whitespace)?;
+        merge_single(&mut self.minify, other.minify)?;
+        merge_single(&mut self.text_mode, other.text_mode)?;
+        merge_single(&mut self.check_html, other.check_html)?;
+        merge_single(&mut self.nonce, other.nonce)?;
+        merge_single(&mut self.allow_unused_fields, other.allow_unused_fields)?;
+        merge_single(&mut self.hot_reload, other.hot_reload)?;
         merge_single(&mut self.type_, other.type_)?;
         Ok(())
     }
@@ -101,13 +201,54 @@ impl DeriveTemplateOptions {
 fn merge_config_options(config: &mut Config, options: &DeriveTemplateOptions) {
     if let Some(ref delimiter) = options.delimiter {
         config.delimiter = delimiter.value();
+        config.delimiters = None;
+    }
+    if let Some(ref delimiters) = options.delimiters {
+        config.delimiters = Some((delimiters.open.value(), delimiters.close.value()));
     }
-    if let Some(ref escape) = options.escape {
-        config.escape = escape.value;
+    if let Some(ref text_mode) = options.text_mode {
+        config.text_mode = text_mode.value;
+    }
+    // HTML-oriented options can be enabled again with the derive options
+    if config.text_mode {
+        config.escape = false;
+        config.rm_whitespace = false;
+        config.minify = false;
+    }
+    match options.escape {
+        Some(Lit::Bool(ref escape)) => config.escape = escape.value,
+        Some(Lit::Str(ref escape)) => match &*escape.value() {
+            "html" => {
+                config.escape = true;
+                config.escape_mode = EscapeMode::Html;
+            }
+            "json" => {
+                config.escape = true;
+                config.escape_mode = EscapeMode::Json;
+            }
+            "xml" => {
+                config.escape = true;
+                config.escape_mode = EscapeMode::Xml;
+            }
+            _ => config.escape = false,
+        },
+        _ => {}
+    }
+    if let Some(ref escape_extra) = options.escape_extra {
+        config.escape_extra = escape_extra.value();
     }
     if let Some(ref rm_whitespace) = options.rm_whitespace {
         config.rm_whitespace = rm_whitespace.value;
     }
+    if let Some(ref minify) = options.minify {
+        config.minify = minify.value;
+    }
+    if let Some(ref check_html) = options.check_html {
+        config.check_html = check_html.value;
+    }
+    if let Some(ref nonce) = options.nonce {
+        config.nonce = Some(nonce.value());
+    }
 }
 
 fn resolve_template_file(path: &str, template_dirs: &[PathBuf]) -> Option<PathBuf> {
@@ -134,9 +275,6 @@ fn resolve_template_file(path: &str, template_dirs: &[PathBuf]) -> Option<PathBu
 fn filename_hash(path: &Path) -> String {
     use std::fmt::Write;
 
-    const FNV_PRIME: u64 = 1_099_511_628_211;
-    const FNV_OFFSET_BASIS: u64 = 14_695_981_039_346_656_037;
-
     let mut hash = String::with_capacity(16);
 
     if let Some(n) = path.file_name() {
@@ -149,11 +287,7 @@ fn filename_hash(path: &Path) -> String {
     }
 
     // calculate 64bit hash
-    let mut h = FNV_OFFSET_BASIS;
-    for b in (&*path.to_string_lossy()).bytes() {
-        h = h.wrapping_mul(FNV_PRIME);
-        h ^= b as u64;
-    }
+    let h = fnv_hash(path.to_string_lossy().as_bytes());
 
     // convert 64bit hash into ascii
     let _ = write!(hash, "{:016x}", h);
@@ -165,11 +299,27 @@ fn compile(
     input_file: &Path,
     output_file: &Path,
     config: Config,
+    fields: &[String],
 ) -> Result<CompilationReport, Error> {
-    let compiler = Compiler::with_config(config);
+    let compiler = Compiler::with_config(config).fields(fields);
     compiler.compile_file(input_file, &*output_file)
 }
 
+/// Switches `proc_macro2` back from the fallback mode when dropped
+///
+/// The compiler switches `proc_macro2` into the fallback mode to get the
+/// locations of the tokens. It must be switched back even if the compilation
+/// fails, so that the generated code keeps the spans of the input (e.g. to
+/// point the unused fields) and the later derives in the crate do not mix the
+/// compiler and fallback tokens.
+struct UnforceFallback;
+
+impl Drop for UnforceFallback {
+    fn drop(&mut self) {
+        proc_macro2::fallback::unforce();
+    }
+}
+
 fn derive_template_impl(tokens: TokenStream) -> Result<TokenStream, syn::Error> {
     let strct = syn::parse2::<ItemStruct>(tokens)?;
 
@@ -212,14 +362,19 @@ fn derive_template_impl(tokens: TokenStream) -> Result<TokenStream, syn::Error>
         let path = all_options.path.as_ref().ok_or_else(|| {
             syn::Error::new(Span::call_site(), "`path` option must be specified.")
         })?;
-        resolve_template_file(&*path.value(), &*config.template_dirs).ok_or_else(
-            || {
-                syn::Error::new(
-                    path.span(),
-                    format!("Template file {:?} not found", path.value()),
-                )
-            },
-        )?
+        if let Some((crate_name, p)) = split_crate_path(&path.value()) {
+            resolve_crate_path(crate_name, p)
+                .map_err(|e| syn::Error::new(path.span(), e))?
+        } else {
+            resolve_template_file(&*path.value(), &*config.template_dirs).ok_or_else(
+                || {
+                    syn::Error::new(
+                        path.span(),
+                        format!("Template file {:?} not found", path.value()),
+                    )
+                },
+            )?
+        }
     };
 
     let out_dir = PathBuf::from(env!("OUT_DIR"));
@@ -227,9 +382,25 @@ fn derive_template_impl(tokens: TokenStream) -> Result<TokenStream, syn::Error>
     output_file.push("templates");
     output_file.push(filename_hash(&*input_file));
 
+    // options in the derive attribute take precedence over the file extension,
+    // and the front-matter of the template takes precedence over both of them
+    config.detect_escape_mode(&input_file);
     merge_config_options(&mut config, &all_options);
-    let report = compile(&*input_file, &*output_file, config)
-        .map_err(|e| syn::Error::new(Span::call_site(), e))?;
+    let hot_reload = match all_options.hot_reload {
+        Some(ref b) if b.value => hot_reload(&input_file, &config)
+            .map_err(|msg| syn::Error::new(b.span(), msg))?,
+        _ => TokenStream::new(),
+    };
+    let fields: Vec<String> = strct
+        .fields
+        .iter()
+        .filter_map(|f| f.ident.as_ref().map(|i| i.to_string()))
+        .collect();
+    let report = {
+        let _guard = UnforceFallback;
+        compile(&*input_file, &*output_file, config, &fields)
+    }
+    .map_err(|e| syn::Error::new(Span::call_site(), e))?;
 
     let input_file_string = input_file.to_string_lossy();
     let output_file_string = output_file.to_string_lossy();
@@ -239,6 +410,10 @@ fn derive_template_impl(tokens: TokenStream) -> Result<TokenStream, syn::Error>
         let dep_string = dep.to_string_lossy();
         include_bytes_seq.extend(quote! { include_bytes!(#dep_string); });
     }
+    // the crate is rebuilt when the environment variables are changed
+    for var in report.env_vars {
+        include_bytes_seq.extend(quote! { option_env!(#var); });
+    }
 
     // Generate tokens
 
@@ -263,35 +438,311 @@ fn derive_template_impl(tokens: TokenStream) -> Result<TokenStream, syn::Error>
         }
     };
 
+    // fields starting with `_` are allowed to be unused as well as variables
+    let allow_unused_fields =
+        matches!(all_options.allow_unused_fields, Some(ref b) if b.value);
+    let mut unused_field_warnings = TokenStream::new();
+    if !allow_unused_fields {
+        for field in field_names.iter() {
+            let name = field.to_string();
+            if !name.starts_with('_') && !report.idents.contains(&name) {
+                unused_field_warnings.extend(unused_field_warning(field, &input_file));
+            }
+        }
+    }
+
     let (impl_generics, ty_generics, where_clause) = strct.generics.split_for_impl();
 
+    // the static texts are reserved at once even in the first rendering, when
+    // `SIZE_HINT` is not updated yet
+    let initial_size = std::cmp::max(report.size_hint.unwrap_or(0), report.static_size);
+    let size_hint = if initial_size > 0 {
+        quote! { core::cmp::max(SIZE_HINT.get(), #initial_size) }
+    } else {
+        quote! { SIZE_HINT.get() }
+    };
+
+    let render_block_once_to_buffer = if report.blocks.is_empty() {
+        quote! {}
+    } else {
+        let block_names = &report.blocks;
+        let block_files = report.blocks.iter().map(|name| {
+            block_artifact_path(&output_file, name)
+                .to_string_lossy()
+                .into_owned()
+        });
+
+        // the code blocks evaluated before the block may declare the variables
+        // which are not used inside the block
+        quote! {
+            #[allow(unused_variables)]
+            fn render_block_once_to_buffer(self, __sf_block_name: &str, buf: &mut sailfish::runtime::Buffer) -> Result<(), sailfish::runtime::RenderError> {
+                use sailfish::runtime as __sf_rt;
+
+                #[allow(unused_mut)]
+                let mut __sf_buf = buf;
+
+                let #name { #field_names } = self;
+                match __sf_block_name {
+                    #(#block_names => { include!(#block_files); })*
+                    _ => {
+                        return Err(__sf_rt::RenderError::new(&sailfish::alloc::format!(
+                            "block `{}` is not defined",
+                            __sf_block_name
+                        )));
+                    }
+                }
+
+                __sf_buf.check_limit()?;
+                Ok(())
+            }
+        }
+    };
+
+    // templates without dynamic parts are rendered at compile time. The
+    // hot reloaded templates must be read from the file on each rendering.
+    let (static_output, render_once) = match report.static_output {
+        Some(ref output) => {
+            let render_once = if hot_reload.is_empty() {
+                quote! {
+                    #[inline]
+                    fn render_once(self) -> sailfish::RenderResult {
+                        // `render_once_static` returns the contents without allocation
+                        Ok(sailfish::alloc::string::String::from(#output))
+                    }
+                }
+            } else {
+                quote! {}
+            };
+            // trait bounds on `const fn` require Rust 1.61
+            let constness = if strct.generics.type_params().next().is_none() {
+                quote! { const }
+            } else {
+                quote! {}
+            };
+            let static_output = quote! {
+                impl #impl_generics #name #ty_generics #where_clause {
+                    /// Returns the rendered contents, which are computed at compile time
+                    /// since the template has no dynamic parts
+                    #[allow(dead_code)]
+                    pub #constness fn rendered() -> &'static str {
+                        #output
+                    }
+
+                    /// Render the template without allocation
+                    ///
+                    /// This returns the same contents as `rendered()`.
+                    #[allow(dead_code)]
+                    pub fn render_once_static(self) -> &'static str {
+                        #output
+                    }
+                }
+            };
+            (static_output, render_once)
+        }
+        None => (quote! {}, quote! {}),
+    };
+
+    // the static texts are appended by reference through `push_static`. The
+    // hot reloaded templates use the default implementation, which renders
+    // through `render_once_to_buffer`.
+    let render_once_to_chunked = if hot_reload.is_empty() {
+        quote! {
+            fn render_once_to_chunked(self, buf: &mut sailfish::runtime::ChunkedBuffer) -> Result<(), sailfish::runtime::RenderError> {
+                use sailfish::runtime as __sf_rt;
+
+                #[allow(unused_mut)]
+                let mut __sf_buf = buf;
+
+                #[allow(unused_variables)]
+                let #name { #field_names } = self;
+                include!(#output_file_string);
+
+                __sf_buf.check_limit()?;
+                Ok(())
+            }
+        }
+    } else {
+        quote! {}
+    };
+
     let tokens = quote! {
         impl #impl_generics sailfish::TemplateOnce for #name #ty_generics #where_clause {
-            fn render_once_to_string(self, buf: &mut String) -> Result<(), sailfish::runtime::RenderError> {
+            #render_once
+
+            fn render_once_to_buffer(self, buf: &mut sailfish::runtime::Buffer) -> Result<(), sailfish::runtime::RenderError> {
                 #include_bytes_seq;
 
                 use sailfish::runtime as __sf_rt;
 
                 static SIZE_HINT: __sf_rt::SizeHint = __sf_rt::SizeHint::new();
 
-                let mut __sf_buf = __sf_rt::Buffer::from(buf.as_str());
-                __sf_buf.reserve(SIZE_HINT.get());
+                #[allow(unused_mut)]
+                let mut __sf_buf = buf;
+                #hot_reload
+                __sf_buf.reserve(core::cmp::min(
+                    #size_hint,
+                    __sf_buf.limit().saturating_sub(__sf_buf.len()),
+                ));
 
                 let __sf_old_len = __sf_buf.len();
 
+                // unused fields are reported by `unused_field_warning`
+                #[allow(unused_variables)]
                 let #name { #field_names } = self;
                 include!(#output_file_string);
 
+                __sf_buf.check_limit()?;
                 SIZE_HINT.update(__sf_buf.len() - __sf_old_len);
+                Ok(())
+            }
+
+            fn render_once_to_string(self, buf: &mut sailfish::alloc::string::String) -> Result<(), sailfish::runtime::RenderError> {
+                let mut __sf_buf = sailfish::runtime::Buffer::from(buf.as_str());
+                self.render_once_to_buffer(&mut __sf_buf)?;
                 *buf = __sf_buf.into_string();
                 Ok(())
             }
+
+            #render_once_to_chunked
+
+            #render_block_once_to_buffer
         }
+
+        #static_output
+
+        #unused_field_warnings
     };
 
     Ok(tokens)
 }
 
+/// Render the template with `sailfish-dyn` in debug builds, so that the
+/// modifications of the template are reflected without recompilation
+fn hot_reload(input_file: &Path, config: &Config) -> Result<TokenStream, String> {
+    let unsupported = if config.text_mode {
+        Some("text_mode")
+    } else if config.rm_whitespace {
+        Some("rm_whitespace")
+    } else if config.minify {
+        Some("minify")
+    } else if config.escape && config.escape_mode != EscapeMode::Html {
+        Some("escape")
+    } else if !config.escape_extra.is_empty() {
+        Some("escape_extra")
+    } else if config.nonce.is_some() {
+        Some("nonce")
+    } else {
+        None
+    };
+    if let Some(option) = unsupported {
+        return Err(format!(
+            "`hot_reload` cannot be used with `{}` option",
+            option
+        ));
+    }
+
+    let path = input_file.to_string_lossy();
+    let (open, close) = match config.delimiters {
+        Some((ref open, ref close)) => (open.clone(), close.clone()),
+        None => (
+            format!("<{}", config.delimiter),
+            format!("{}>", config.delimiter),
+        ),
+    };
+    let escape = config.escape;
+    Ok(quote! {
+        #[cfg(debug_assertions)]
+        {
+            if let Some(result) = sailfish_dyn::__hot_reload(#path, (#open, #close), #escape, &self, __sf_buf) {
+                return result.and_then(|_| __sf_buf.check_limit());
+            }
+        }
+    })
+}
+
+/// Emit a warning which points to the field unused in the template
+///
+/// Procedural macros cannot emit warnings on stable Rust, so this uses the
+/// deprecation warning of a dummy constant instead.
+fn unused_field_warning(field: &Ident, template: &Path) -> TokenStream {
+    let note = format!(
+        "field `{}` is not used in the template {:?}. Add `#[template(allow_unused_fields = true)]` to the struct to silence this warning",
+        field,
+        template.file_name().unwrap_or_default()
+    );
+    let use_const = quote_spanned! {field.span()=> unused_template_field };
+    quote! {
+        const _: () = {
+            #[deprecated(note = #note)]
+            #[allow(non_upper_case_globals)]
+            const unused_template_field: () = ();
+            #use_const
+        };
+    }
+}
+
 pub fn derive_template(tokens: TokenStream) -> TokenStream {
     derive_template_impl(tokens).unwrap_or_else(|e| e.to_compile_error())
 }
+
+fn derive_render_impl(tokens: TokenStream) -> Result<TokenStream, syn::Error> {
+    let mut strct = syn::parse2::<ItemStruct>(tokens)?;
+
+    let (member, ty) = {
+        let mut it = strct.fields.iter();
+        match (it.next(), it.next()) {
+            (Some(field), None) => {
+                let member = match field.ident {
+                    Some(ref i) => Member::Named(i.clone()),
+                    None => Member::Unnamed(Index::from(0)),
+                };
+                (member, field.ty.clone())
+            }
+            _ => {
+                return Err(syn::Error::new(
+                    Span::call_site(),
+                    "You can derive `Render` only for the struct with a single field",
+                ));
+            }
+        }
+    };
+
+    strct
+        .generics
+        .make_where_clause()
+        .predicates
+        .push(syn::parse2(quote! { #ty: sailfish::runtime::Render }).unwrap());
+
+    let name = strct.ident;
+    let (impl_generics, ty_generics, where_clause) = strct.generics.split_for_impl();
+
+    let tokens = quote! {
+        impl #impl_generics sailfish::runtime::Render for #name #ty_generics #where_clause {
+            #[inline]
+            fn render(&self, b: &mut sailfish::runtime::Buffer) -> Result<(), sailfish::runtime::RenderError> {
+                sailfish::runtime::Render::render(&self.#member, b)
+            }
+
+            #[inline]
+            fn render_escaped(&self, b: &mut sailfish::runtime::Buffer) -> Result<(), sailfish::runtime::RenderError> {
+                sailfish::runtime::Render::render_escaped(&self.#member, b)
+            }
+
+            #[inline]
+            fn render_escaped_with(
+                &self,
+                b: &mut sailfish::runtime::Buffer,
+                escaper: &dyn sailfish::runtime::escape::Escaper,
+            ) -> Result<(), sailfish::runtime::RenderError> {
+                sailfish::runtime::Render::render_escaped_with(&self.#member, b, escaper)
+            }
+        }
+    };
+
+    Ok(tokens)
+}
+
+pub fn derive_render(tokens: TokenStream) -> TokenStream {
+    derive_render_impl(tokens).unwrap_or_else(|e| e.to_compile_error())
+}
@@ -1,10 +1,20 @@
-use quote::quote;
+use quote::{quote, ToTokens};
+use std::collections::HashMap;
+use std::fs;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use syn::parse::{Parse, ParseStream, Parser, Result as ParseResult};
+use syn::punctuated::Punctuated;
 use syn::visit_mut::VisitMut;
-use syn::{Block, Expr, ExprBlock, ExprMacro, LitStr};
+use syn::{
+    Block, Expr, ExprBlock, ExprField, ExprMacro, FieldValue, Ident, LitStr, Member,
+    Stmt, Token,
+};
 
+use crate::config::{resolve_crate_path, split_crate_path, GlobalValue};
+use crate::depgraph::DependencyGraph;
 use crate::error::*;
+use crate::util::{base64_encode, escape_html};
 
 macro_rules! matches_or_else {
     ($val:expr, $p:pat, $ok:expr, $else:expr) => {
@@ -23,21 +33,353 @@ macro_rules! return_if_some {
     };
 }
 
+/// Arguments for `include!` macro
+///
+/// e.g. `include!("card.stpl", { title: &item.title, count: 3 })`
+struct IncludeArgs {
+    path: LitStr,
+    bindings: Vec<(Ident, Expr)>,
+}
+
+impl Parse for IncludeArgs {
+    fn parse(s: ParseStream) -> ParseResult<Self> {
+        let path = s.parse()?;
+        let mut bindings = Vec::new();
+
+        if s.parse::<Option<Token![,]>>()?.is_some() && !s.is_empty() {
+            let content;
+            syn::braced!(content in s);
+            let fields = Punctuated::<FieldValue, Token![,]>::parse_terminated(&content)?;
+            for field in fields {
+                match field.member {
+                    Member::Named(ident) => bindings.push((ident, field.expr)),
+                    Member::Unnamed(_) => {
+                        return Err(syn::Error::new_spanned(
+                            field,
+                            "expected the argument name",
+                        ))
+                    }
+                }
+            }
+            s.parse::<Option<Token![,]>>()?;
+        }
+
+        Ok(Self { path, bindings })
+    }
+}
+
+/// Arguments for `include_static!` macro
+struct IncludeStaticArgs {
+    path: LitStr,
+    encoding: Option<Ident>,
+}
+
+impl Parse for IncludeStaticArgs {
+    fn parse(s: ParseStream) -> ParseResult<Self> {
+        let path = s.parse()?;
+        let mut encoding = None;
+
+        if s.parse::<Option<Token![,]>>()?.is_some() && !s.is_empty() {
+            let ident = s.parse::<Ident>()?;
+            if ident != "escape" && ident != "base64" {
+                return Err(syn::Error::new_spanned(
+                    ident,
+                    "expected `escape` or `base64`",
+                ));
+            }
+            encoding = Some(ident);
+            s.parse::<Option<Token![,]>>()?;
+        }
+
+        Ok(Self { path, encoding })
+    }
+}
+
 pub struct ResolveReport {
     pub deps: Vec<PathBuf>,
+    /// named blocks which can be rendered separately
+    pub blocks: Vec<(String, Block)>,
+    /// environment variables read by `env!` macros
+    pub env_vars: Vec<String>,
+    /// Rust files included with `include!` macro, which are compiled by rustc
+    pub rust_files: Vec<PathBuf>,
+    /// which files include, extend or embed which files
+    pub graph: DependencyGraph,
 }
 
+type AssetHandler<'h> = Arc<dyn 'h + Fn(&Path, &str) -> Result<(PathBuf, String), Error>>;
+
 struct ResolverImpl<'h> {
     path_stack: Vec<PathBuf>,
+    template_dirs: Vec<PathBuf>,
     deps: Vec<PathBuf>,
+    env_vars: Vec<String>,
+    rust_files: Vec<PathBuf>,
+    graph: DependencyGraph,
     error: Option<Error>,
     include_handler: Arc<dyn 'h + Fn(&Path) -> Result<Block, Error>>,
+    asset_handler: AssetHandler<'h>,
+    globals: Arc<HashMap<String, GlobalValue>>,
 }
 
 impl<'h> ResolverImpl<'h> {
+    /// Record that the current template depends on the file
+    ///
+    /// The paths are canonicalized so that the same file included with
+    /// different relative paths (e.g. `../a/b.stpl` and `b.stpl`) is recorded
+    /// only once.
+    fn add_dep(&mut self, file: &Path) {
+        let from = canonicalize(self.path_stack.last().unwrap());
+        let file = canonicalize(file);
+        self.graph.add_edge(&from, &file);
+        if self.deps.iter().all(|p| p != &file) {
+            self.deps.push(file);
+        }
+    }
+
+    /// resolve the path relative to the current template file
+    fn resolve_path(&self, arg: &str) -> Result<PathBuf, Error> {
+        // `crate_name::path` refers to the template in the dependency crate
+        if let Some((crate_name, path)) = split_crate_path(arg) {
+            return resolve_crate_path(crate_name, path);
+        }
+
+        // TODO: How should arguments be interpreted on Windows?
+        let (path, rel) = if Path::new(arg).is_absolute() {
+            // absolute imclude
+            (PathBuf::from(&arg[1..]), &arg[1..])
+        } else {
+            // relative include
+            (
+                self.path_stack.last().unwrap().parent().unwrap().join(arg),
+                arg,
+            )
+        };
+
+        if path.exists() {
+            return Ok(path);
+        }
+
+        // search the template directories in order of precedence
+        Ok(self
+            .template_dirs
+            .iter()
+            .rev()
+            .map(|dir| dir.join(rel))
+            .find(|p| p.exists())
+            .unwrap_or(path))
+    }
+
+    /// Resolve `include!` macros in the template, and then replace the template
+    /// with its parent layouts if the template extends another template.
+    ///
+    /// The top of `path_stack` must be the path to the template.
+    fn resolve_template(&mut self, ast: &mut Block) -> Result<(), Error> {
+        let depth = self.path_stack.len();
+        let mut blocks = HashMap::new();
+        let mut prelude = Vec::new();
+
+        loop {
+            let parent = take_extends(ast)?;
+            self.visit_block_mut(ast);
+            if let Some(e) = self.error.take() {
+                return Err(e);
+            }
+
+            let parent = match parent {
+                Some(parent) => parent,
+                None => break,
+            };
+
+            // blocks defined in the child templates take precedence
+            let mut collector = BlockCollector {
+                blocks: HashMap::new(),
+                error: None,
+            };
+            collector.visit_block_mut(ast);
+            if let Some(e) = collector.error {
+                return Err(e);
+            }
+            for (name, blk) in collector.blocks {
+                blocks.entry(name).or_insert(blk);
+            }
+
+            // contents outside the blocks are discarded, but the code blocks
+            // are evaluated before rendering the parent template
+            prelude.extend(ast.stmts.drain(..).filter(|stmt| !is_rendering_stmt(stmt)));
+
+            let parent_file = self.resolve_path(&parent)?;
+            if self.path_stack.contains(&parent_file) {
+                return Err(make_error!(ErrorKind::AnalyzeError(format!(
+                    "Cyclic template inheritance detected: {:?}",
+                    parent_file
+                ))));
+            }
+
+            *ast = (*self.include_handler)(&parent_file)
+                .chain_err(|| format!("Failed to extend {:?}", parent_file))?;
+
+            self.add_dep(&parent_file);
+            self.path_stack.push(parent_file);
+        }
+
+        self.path_stack.truncate(depth);
+
+        if !prelude.is_empty() {
+            prelude.append(&mut ast.stmts);
+            ast.stmts = prelude;
+        }
+
+        let mut expander = BlockExpander {
+            blocks: &blocks,
+            expanding: Vec::new(),
+            error: None,
+        };
+        expander.visit_block_mut(ast);
+        match expander.error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// Extract the named blocks from the resolved template, and then remove the
+    /// block markers
+    ///
+    /// Each block is preceded by the top-level statements which do not render
+    /// anything (e.g. `let` statements or macro definitions), so that the block
+    /// can be rendered without the rest of the template.
+    fn extract_blocks(ast: &mut Block) -> Vec<(String, Block)> {
+        let mut fragments: Vec<(String, Block)> = Vec::new();
+        let mut prelude = Vec::new();
+
+        for stmt in ast.stmts.iter() {
+            let mut collector = BlockCollector {
+                blocks: HashMap::new(),
+                error: None,
+            };
+            collector.visit_stmt_mut(&mut stmt.clone());
+
+            let mut names = collector.blocks.into_iter().collect::<Vec<_>>();
+            names.sort_by(|a, b| a.0.cmp(&b.0));
+            for (name, mut blk) in names {
+                if fragments.iter().any(|(n, _)| *n == name) {
+                    continue;
+                }
+                MarkerRemover.visit_block_mut(&mut blk);
+                let mut stmts = prelude.clone();
+                stmts.append(&mut blk.stmts);
+                blk.stmts = stmts;
+                fragments.push((name, blk));
+            }
+
+            if !renders_contents(stmt) {
+                prelude.push(stmt.clone());
+            }
+        }
+
+        MarkerRemover.visit_block_mut(ast);
+        fragments
+    }
+
+    /// Replace `asset!("path")` with the URL of the asset
+    fn resolve_asset(&mut self, i: &ExprMacro) -> Result<Expr, Error> {
+        let arg = syn::parse2::<LitStr>(i.mac.tokens.clone()).map_err(|e| {
+            let mut e = Error::from(e);
+            e.chains.push(ErrorKind::AnalyzeError(
+                "invalid arguments for `asset` macro".to_owned(),
+            ));
+            e
+        })?;
+
+        let template = self.path_stack.last().unwrap();
+        let (file, url) = (*self.asset_handler)(template, &arg.value())?;
+        self.add_dep(&file);
+
+        let url = LitStr::new(&url, arg.span());
+        Ok(syn::parse2(quote! { #url }).unwrap())
+    }
+
+    /// Replace `env!("NAME")` with the value of the environment variable
+    ///
+    /// As well as `std::env!`, the optional second argument is used as the
+    /// error message if the variable is not set.
+    fn resolve_env(&mut self, i: &ExprMacro) -> Result<Expr, Error> {
+        let parser = Punctuated::<LitStr, Token![,]>::parse_terminated;
+        let args = match parser.parse2(i.mac.tokens.clone()) {
+            Ok(args) if !args.is_empty() && args.len() <= 2 => args,
+            _ => {
+                return Err(make_error!(ErrorKind::AnalyzeError(
+                    "invalid arguments for `env` macro".to_owned()
+                )));
+            }
+        };
+
+        let name = args[0].value();
+        let value = std::env::var(&name).map_err(|_| {
+            let msg = match args.iter().nth(1) {
+                Some(msg) => msg.value(),
+                None => format!("environment variable `{}` not defined", name),
+            };
+            make_error!(ErrorKind::AnalyzeError(msg))
+        })?;
+
+        if !self.env_vars.contains(&name) {
+            self.env_vars.push(name);
+        }
+
+        let value = LitStr::new(&value, args[0].span());
+        Ok(syn::parse2(quote! { #value }).unwrap())
+    }
+
+    /// Replace `include_static!("path")` with the contents of the file
+    fn resolve_include_static(&mut self, i: &ExprMacro) -> Result<Expr, Error> {
+        let args =
+            syn::parse2::<IncludeStaticArgs>(i.mac.tokens.clone()).map_err(|e| {
+                let mut e = Error::from(e);
+                e.chains.push(ErrorKind::AnalyzeError(
+                    "invalid arguments for `include_static` macro".to_owned(),
+                ));
+                e
+            })?;
+
+        let file = self.resolve_path(&args.path.value())?;
+        let content = fs::read(&file)
+            .chain_err(|| format!("Failed to include {:?}", file.clone()))?;
+
+        let text = match args.encoding {
+            Some(ref e) if e == "base64" => base64_encode(&content),
+            _ => {
+                let mut text = String::from_utf8(content).map_err(|_| {
+                    make_error!(ErrorKind::AnalyzeError(format!(
+                        "{:?} is not a valid UTF-8 file. Use `base64` encoding \
+                         to include binary files.",
+                        file
+                    )))
+                })?;
+                // strip break line at file end as well as template files
+                if text.ends_with('\n') {
+                    text.pop();
+                    if text.ends_with('\r') {
+                        text.pop();
+                    }
+                }
+                if args.encoding.is_some() {
+                    escape_html(&text)
+                } else {
+                    text
+                }
+            }
+        };
+
+        self.add_dep(&file);
+
+        let text = LitStr::new(&text, args.path.span());
+        Ok(syn::parse2(quote! { { __sf_rt::render_text!(__sf_buf, #text); } }).unwrap())
+    }
+
     fn resolve_include(&mut self, i: &ExprMacro) -> Result<Expr, Error> {
-        let arg = match syn::parse2::<LitStr>(i.mac.tokens.clone()) {
-            Ok(l) => l.value(),
+        let (arg, bindings) = match syn::parse2::<IncludeArgs>(i.mac.tokens.clone()) {
+            Ok(args) => (args.path.value(), args.bindings),
             Err(e) => {
                 let mut e = Error::from(e);
                 e.chains.push(ErrorKind::AnalyzeError(
@@ -49,29 +391,28 @@ impl<'h> ResolverImpl<'h> {
 
         // resolve include! for rust file
         if arg.ends_with(".rs") {
+            if !bindings.is_empty() {
+                return Err(make_error!(ErrorKind::AnalyzeError(
+                    "arguments cannot be passed to the included rust file".to_owned()
+                )));
+            }
+
             let absolute_path = if Path::new(&*arg).is_absolute() {
                 PathBuf::from(&arg[1..])
             } else {
                 self.path_stack.last().unwrap().parent().unwrap().join(arg)
             };
-            let absolute_path_str = absolute_path.to_string_lossy();
+            let absolute_path_str = absolute_path.to_string_lossy().into_owned();
+            let from = canonicalize(self.path_stack.last().unwrap());
+            self.graph.add_edge(&from, &canonicalize(&absolute_path));
+            if !self.rust_files.contains(&absolute_path) {
+                self.rust_files.push(absolute_path);
+            }
             return Ok(syn::parse2(quote! { include!(#absolute_path_str) }).unwrap());
         }
 
         // resolve the template file path
-        // TODO: How should arguments be interpreted on Windows?
-        let child_template_file = if Path::new(&*arg).is_absolute() {
-            // absolute imclude
-            PathBuf::from(&arg[1..])
-        } else {
-            // relative include
-            self.path_stack
-                .last()
-                .unwrap()
-                .parent()
-                .unwrap()
-                .join(arg.clone())
-        };
+        let child_template_file = self.resolve_path(&arg)?;
 
         // parse and translate the child template
         let mut blk = (*self.include_handler)(&*child_template_file).chain_err(|| {
@@ -79,11 +420,22 @@ impl<'h> ResolverImpl<'h> {
         })?;
 
         self.path_stack.push(child_template_file);
-        syn::visit_mut::visit_block_mut(self, &mut blk);
+        let ret = self.resolve_template(&mut blk);
 
         let child_template_file = self.path_stack.pop().unwrap();
-        if self.deps.iter().all(|p| p != &child_template_file) {
-            self.deps.push(child_template_file);
+        ret?;
+        // blocks inside the included template cannot be overridden
+        MarkerRemover.visit_block_mut(&mut blk);
+        self.add_dep(&child_template_file);
+
+        // bind the arguments inside the scope of the child template. All
+        // arguments are evaluated before binding, so that the argument cannot
+        // refer to other arguments.
+        if !bindings.is_empty() {
+            let (names, exprs): (Vec<_>, Vec<_>) = bindings.into_iter().unzip();
+            let stmt =
+                syn::parse2(quote! { let (#(#names,)*) = (#(#exprs,)*); }).unwrap();
+            blk.stmts.insert(0, stmt);
         }
 
         Ok(Expr::Block(ExprBlock {
@@ -97,6 +449,13 @@ impl<'h> ResolverImpl<'h> {
 impl<'h> VisitMut for ResolverImpl<'h> {
     fn visit_expr_mut(&mut self, i: &mut Expr) {
         return_if_some!(self.error);
+        if let Expr::Field(ref ef) = *i {
+            if let Some(value) = global_value(ef, &self.globals) {
+                *i = value;
+                return;
+            }
+        }
+
         let em = matches_or_else!(*i, Expr::Macro(ref mut em), em, {
             syn::visit_mut::visit_expr_mut(self, i);
             return;
@@ -111,23 +470,257 @@ impl<'h> VisitMut for ResolverImpl<'h> {
                     return;
                 }
             }
+        } else if em.mac.path.is_ident("include_static") {
+            match self.resolve_include_static(em) {
+                Ok(e) => *i = e,
+                Err(e) => self.error = Some(e),
+            }
+        } else if em.mac.path.is_ident("asset") {
+            match self.resolve_asset(em) {
+                Ok(e) => *i = e,
+                Err(e) => self.error = Some(e),
+            }
+        } else if em.mac.path.is_ident("env") {
+            match self.resolve_env(em) {
+                Ok(e) => *i = e,
+                Err(e) => self.error = Some(e),
+            }
+        } else if is_render_macro(em) {
+            // `asset!` and `env!` can be used inside `<%= %>` tag
+            let parser = Punctuated::<Expr, Token![,]>::parse_terminated;
+            if let Ok(mut args) = parser.parse2(em.mac.tokens.clone()) {
+                for arg in args.iter_mut() {
+                    self.visit_expr_mut(arg);
+                }
+                em.mac.tokens = args.into_token_stream();
+            }
+        } else if em.mac.path.is_ident("__sf_extends") {
+            self.error = Some(make_error!(ErrorKind::AnalyzeError(
+                "`extends` must be placed at the top level of the template only once"
+                    .to_owned()
+            )));
+        }
+    }
+}
+
+/// Returns the literal of the global constant if the expression is
+/// `globals.<name>`
+///
+/// `globals` which are not defined in the configuration are left unchanged, so
+/// that the templates can still use the variables named `globals`.
+fn global_value(ef: &ExprField, globals: &HashMap<String, GlobalValue>) -> Option<Expr> {
+    let name = match (&*ef.base, &ef.member) {
+        (Expr::Path(ref base), Member::Named(ref name))
+            if base.qself.is_none() && base.path.is_ident("globals") =>
+        {
+            name
+        }
+        _ => return None,
+    };
+
+    let span = name.span();
+    let lit = match globals.get(&name.to_string())? {
+        GlobalValue::Bool(b) => syn::LitBool { value: *b, span }.into(),
+        GlobalValue::Integer(n) => syn::LitInt::new(&n.to_string(), span).into(),
+        GlobalValue::Float(f) => syn::LitFloat::new(&format!("{:?}", f), span).into(),
+        GlobalValue::String(s) => LitStr::new(s, span).into(),
+    };
+
+    Some(Expr::Lit(syn::ExprLit {
+        attrs: Vec::new(),
+        lit,
+    }))
+}
+
+fn canonicalize(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_owned())
+}
+
+/// Remove `__sf_extends!` macro from the top level of the template and returns
+/// its argument
+fn take_extends(ast: &mut Block) -> Result<Option<String>, Error> {
+    let pos = ast.stmts.iter().position(|stmt| match *stmt {
+        Stmt::Semi(Expr::Macro(ref em), _) => em.mac.path.is_ident("__sf_extends"),
+        _ => false,
+    });
+
+    if let Some(pos) = pos {
+        if let Stmt::Semi(Expr::Macro(em), _) = ast.stmts.remove(pos) {
+            let path = syn::parse2::<LitStr>(em.mac.tokens)
+                .map_err(|e| {
+                    let mut e = Error::from(e);
+                    e.chains.push(ErrorKind::AnalyzeError(
+                        "invalid arguments for `extends`".to_owned(),
+                    ));
+                    e
+                })?
+                .value();
+            return Ok(Some(path));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Returns the name of the template block if the block starts with
+/// `__sf_block!(name);`
+fn block_name(blk: &Block) -> Option<String> {
+    match blk.stmts.first() {
+        Some(Stmt::Semi(Expr::Macro(ref em), _))
+            if em.mac.path.is_ident("__sf_block") =>
+        {
+            syn::parse2::<Ident>(em.mac.tokens.clone())
+                .ok()
+                .map(|i| i.to_string())
+        }
+        _ => None,
+    }
+}
+
+/// check if the statement renders the contents or defines the template block
+fn is_rendering_stmt(stmt: &Stmt) -> bool {
+    match *stmt {
+        Stmt::Semi(Expr::Macro(ref em), _) | Stmt::Expr(Expr::Macro(ref em)) => {
+            match em.mac.path.segments.first() {
+                Some(s) => s.ident == "__sf_rt",
+                None => false,
+            }
+        }
+        Stmt::Expr(Expr::Block(ref eb)) => block_name(&eb.block).is_some(),
+        _ => false,
+    }
+}
+
+/// Collect the template blocks defined in a template
+struct BlockCollector {
+    blocks: HashMap<String, Block>,
+    error: Option<Error>,
+}
+
+impl VisitMut for BlockCollector {
+    fn visit_expr_block_mut(&mut self, i: &mut ExprBlock) {
+        return_if_some!(self.error);
+        if let Some(name) = block_name(&i.block) {
+            if self.blocks.contains_key(&name) {
+                self.error = Some(make_error!(ErrorKind::AnalyzeError(format!(
+                    "block `{}` is defined multiple times",
+                    name
+                ))));
+                return;
+            }
+            self.blocks.insert(name, i.block.clone());
+        }
+
+        syn::visit_mut::visit_expr_block_mut(self, i);
+    }
+}
+
+/// Replace the template blocks with the blocks defined in child templates
+struct BlockExpander<'a> {
+    blocks: &'a HashMap<String, Block>,
+    expanding: Vec<String>,
+    error: Option<Error>,
+}
+
+impl<'a> VisitMut for BlockExpander<'a> {
+    fn visit_expr_block_mut(&mut self, i: &mut ExprBlock) {
+        return_if_some!(self.error);
+        let name = matches_or_else!(block_name(&i.block), Some(name), name, {
+            syn::visit_mut::visit_expr_block_mut(self, i);
+            return;
+        });
+
+        if self.expanding.contains(&name) {
+            self.error = Some(make_error!(ErrorKind::AnalyzeError(format!(
+                "block `{}` is defined inside itself",
+                name
+            ))));
+            return;
+        }
+
+        // block markers are kept until the blocks are extracted
+        if let Some(blk) = self.blocks.get(&name) {
+            i.block = blk.clone();
+        }
+
+        self.expanding.push(name);
+        syn::visit_mut::visit_expr_block_mut(self, i);
+        self.expanding.pop();
+    }
+}
+
+/// Returns `true` if the macro renders the contents (e.g. `__sf_rt::render!`)
+fn is_render_macro(em: &ExprMacro) -> bool {
+    matches!(em.mac.path.segments.first(), Some(s) if s.ident == "__sf_rt")
+}
+
+/// Remove `__sf_block!(name);` markers
+struct MarkerRemover;
+
+impl VisitMut for MarkerRemover {
+    fn visit_block_mut(&mut self, i: &mut Block) {
+        if block_name(i).is_some() {
+            i.stmts.remove(0);
+        }
+        syn::visit_mut::visit_block_mut(self, i);
+    }
+}
+
+/// Check if the statement renders the contents into the output buffer
+fn renders_contents(stmt: &Stmt) -> bool {
+    struct Finder(bool);
+
+    impl VisitMut for Finder {
+        fn visit_expr_block_mut(&mut self, i: &mut ExprBlock) {
+            // macro definitions do not render anything by themselves
+            match i.block.stmts.first() {
+                Some(Stmt::Semi(Expr::Macro(ref em), _))
+                    if em.mac.path.is_ident("__sf_macro") => {}
+                _ => syn::visit_mut::visit_expr_block_mut(self, i),
+            }
+        }
+
+        fn visit_macro_mut(&mut self, i: &mut syn::Macro) {
+            if let Some(s) = i.path.segments.first() {
+                self.0 |= s.ident == "__sf_rt";
+            }
+        }
+    }
+
+    match *stmt {
+        // captures render the contents into the local buffer
+        Stmt::Local(_) | Stmt::Item(_) => false,
+        _ => {
+            let mut finder = Finder(false);
+            finder.visit_stmt_mut(&mut stmt.clone());
+            finder.0
         }
     }
 }
 
 #[derive(Clone)]
 pub struct Resolver<'h> {
+    template_dirs: Vec<PathBuf>,
     include_handler: Arc<dyn 'h + Fn(&Path) -> Result<Block, Error>>,
+    asset_handler: AssetHandler<'h>,
+    globals: Arc<HashMap<String, GlobalValue>>,
 }
 
 impl<'h> Resolver<'h> {
     pub fn new() -> Self {
         Self {
+            template_dirs: Vec::new(),
             include_handler: Arc::new(|_| {
                 Err(make_error!(ErrorKind::AnalyzeError(
                     "You cannot use `include` macro inside templates".to_owned()
                 )))
             }),
+            asset_handler: Arc::new(|_, _| {
+                Err(make_error!(ErrorKind::AnalyzeError(
+                    "You cannot use `asset` macro inside templates".to_owned()
+                )))
+            }),
+            globals: Arc::default(),
         }
     }
 
@@ -140,6 +733,33 @@ impl<'h> Resolver<'h> {
         self
     }
 
+    /// Directories which are searched if the included file is not found
+    /// relative to the current template
+    ///
+    /// The directories at the end take precedence, as in `Config::template_dirs`.
+    #[inline]
+    pub fn template_dirs(mut self, new: &[PathBuf]) -> Resolver<'h> {
+        self.template_dirs = new.to_vec();
+        self
+    }
+
+    /// Set the function which returns the path to the asset file and its URL
+    ///
+    /// The function receives the path to the current template file and the
+    /// argument of `asset!` macro.
+    #[inline]
+    pub fn asset_handler(mut self, new: AssetHandler<'h>) -> Resolver<'h> {
+        self.asset_handler = new;
+        self
+    }
+
+    /// Constants which replace `globals.<name>` expressions in the templates
+    #[inline]
+    pub fn globals(mut self, new: &HashMap<String, GlobalValue>) -> Resolver<'h> {
+        self.globals = Arc::new(new.clone());
+        self
+    }
+
     #[inline]
     pub fn resolve(
         &self,
@@ -148,16 +768,25 @@ impl<'h> Resolver<'h> {
     ) -> Result<ResolveReport, Error> {
         let mut child = ResolverImpl {
             path_stack: vec![input_file.to_owned()],
+            template_dirs: self.template_dirs.clone(),
             deps: Vec::new(),
+            env_vars: Vec::new(),
+            rust_files: Vec::new(),
+            graph: DependencyGraph::new(),
             error: None,
             include_handler: Arc::clone(&self.include_handler),
+            asset_handler: Arc::clone(&self.asset_handler),
+            globals: Arc::clone(&self.globals),
         };
-        child.visit_block_mut(ast);
+        child.resolve_template(ast)?;
+        let blocks = ResolverImpl::extract_blocks(ast);
 
-        if let Some(e) = child.error {
-            Err(e)
-        } else {
-            Ok(ResolveReport { deps: child.deps })
-        }
+        Ok(ResolveReport {
+            deps: child.deps,
+            blocks,
+            env_vars: child.env_vars,
+            rust_files: child.rust_files,
+            graph: child.graph,
+        })
     }
 }
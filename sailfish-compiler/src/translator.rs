@@ -1,11 +1,25 @@
 use proc_macro2::Span;
 use quote::ToTokens;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::path::Path;
 use syn::parse::{Parse, ParseStream as SynParseStream, Result as ParseResult};
 use syn::spanned::Spanned;
-use syn::{BinOp, Block, Expr};
+use syn::visit_mut::VisitMut;
+use syn::{BinOp, Block, Expr, ExprCall, ExprPath, Ident, LitStr, PatIdent};
 
+use crate::config::EscapeMode;
 use crate::error::*;
 use crate::parser::{ParseStream, Token, TokenKind};
+use crate::util::edit_distance;
+
+/// filters defined in `sailfish::runtime::filter` module
+const HELPER_MACROS: &[&str] = &["classes"];
+
+const BUILTIN_FILTERS: &[&str] = &[
+    "attr_if", "dbg", "default", "disp", "json", "lower", "or_else", "safe", "trim",
+    "truncate", "upper",
+];
 
 enum Filter {
     Ident(syn::Ident),
@@ -22,65 +36,253 @@ impl Spanned for Filter {
 }
 
 struct CodeBlock {
-    #[allow(dead_code)]
     expr: Box<Expr>,
-    filter: Option<Filter>,
+    /// filters applied to the expression, from the outermost one
+    filters: Vec<Filter>,
+}
+
+impl CodeBlock {
+    /// split off the filter from the right side of `|` operator
+    ///
+    /// Returns `Ok(None)` if the right side is not a filter.
+    fn split_filter(b: &syn::ExprBinary) -> ParseResult<Option<Filter>> {
+        match *b.right {
+            Expr::Call(ref c) => {
+                if let Expr::Path(ref p) = *c.func {
+                    if p.path.get_ident().is_some() {
+                        Ok(Some(Filter::Call(c.clone())))
+                    } else {
+                        Err(syn::Error::new_spanned(p, "Invalid filter name"))
+                    }
+                } else {
+                    // if function in right side is not a path, fallback to
+                    // normal evaluation block
+                    Ok(None)
+                }
+            }
+            Expr::Path(ref p) => {
+                if let Some(i) = p.path.get_ident() {
+                    Ok(Some(Filter::Ident(i.clone())))
+                } else {
+                    Err(syn::Error::new_spanned(p, "Invalid filter name"))
+                }
+            }
+            _ => Ok(None),
+        }
+    }
 }
 
 impl Parse for CodeBlock {
     fn parse(s: SynParseStream) -> ParseResult<Self> {
-        let main = s.parse::<Expr>()?;
-
-        let code_block = match main {
-            Expr::Binary(b) if matches!(b.op, BinOp::BitOr(_)) => {
-                match *b.right {
-                    Expr::Call(c) => {
-                        if let Expr::Path(ref p) = *c.func {
-                            if p.path.get_ident().is_some() {
-                                CodeBlock {
-                                    expr: b.left,
-                                    filter: Some(Filter::Call(c)),
-                                }
-                            } else {
-                                return Err(syn::Error::new_spanned(
-                                    p,
-                                    "Invalid filter name",
-                                ));
-                            }
-                        } else {
-                            // if function in right side is not a path, fallback to
-                            // normal evaluation block
-                            CodeBlock {
-                                expr: b.left,
-                                filter: None,
-                            }
-                        }
-                    }
-                    Expr::Path(p) => {
-                        if let Some(i) = p.path.get_ident() {
-                            CodeBlock {
-                                expr: b.left,
-                                filter: Some(Filter::Ident(i.clone())),
-                            }
-                        } else {
-                            return Err(syn::Error::new_spanned(
-                                p,
-                                "Invalid filter name",
-                            ));
-                        }
-                    }
-                    _ => {
-                        return Err(syn::Error::new_spanned(b, "Expected filter"));
-                    }
+        let mut expr = s.parse::<Expr>()?;
+        let mut filters = Vec::new();
+
+        // `a | f | g(1)` is parsed as `(a | f) | g(1)`, so that the filters are
+        // split off from the outermost one
+        loop {
+            let b = match expr {
+                Expr::Binary(ref b) if matches!(b.op, BinOp::BitOr(_)) => b,
+                _ => break,
+            };
+
+            match CodeBlock::split_filter(b)? {
+                Some(filter) => filters.push(filter),
+                None if filters.is_empty() && !matches!(*b.right, Expr::Call(_)) => {
+                    return Err(syn::Error::new_spanned(b, "Expected filter"));
                 }
+                None => break,
             }
-            _ => CodeBlock {
-                expr: Box::new(main),
-                filter: None,
-            },
+
+            expr = match expr {
+                Expr::Binary(b) => *b.left,
+                _ => unreachable!(),
+            };
+        }
+
+        Ok(CodeBlock {
+            expr: Box::new(expr),
+            filters,
+        })
+    }
+}
+
+/// Directives for template inheritance and macros written inside code blocks
+pub(crate) enum Directive<'a> {
+    /// `<% extends "layout.stpl" %>`
+    Extends(&'a str),
+    /// `<% block name %>`
+    Block(&'a str),
+    /// `<% endblock %>` or `<% endblock name %>`
+    EndBlock(Option<&'a str>),
+    /// `<% macro name(arg1, arg2) %>`
+    Macro(String, Vec<String>),
+    /// `<% endmacro %>`
+    EndMacro,
+    /// `<% for item in items %>`
+    For(ForLoop<'a>),
+    /// `<% else %>` inside `for` loop or `if`
+    Else,
+    /// `<% endfor %>`
+    EndFor,
+    /// `<% capture name %>`
+    Capture(&'a str),
+    /// `<% endcapture %>`
+    EndCapture,
+    /// `<% match expr %>`
+    Match(&'a str),
+    /// `<% when pattern %>` or `<% when pattern if guard %>` inside `match`
+    When(&'a str),
+    /// `<% endmatch %>`
+    EndMatch,
+    /// `<% if cond %>` or `<% if let pattern = expr %>`
+    If(&'a str),
+    /// `<% else if cond %>`
+    ElseIf(&'a str),
+    /// `<% endif %>`
+    EndIf,
+}
+
+impl<'a> Directive<'a> {
+    pub(crate) fn parse(code: &'a str) -> Option<Directive<'a>> {
+        let code = code.trim();
+        let (keyword, rest) = match code.find(char::is_whitespace) {
+            Some(p) => (&code[..p], code[p..].trim_start()),
+            None => (code, ""),
         };
 
-        Ok(code_block)
+        match keyword {
+            "extends" if syn::parse_str::<LitStr>(rest).is_ok() => {
+                Some(Directive::Extends(rest))
+            }
+            "block" if syn::parse_str::<Ident>(rest).is_ok() => {
+                Some(Directive::Block(rest))
+            }
+            "endblock" if rest.is_empty() => Some(Directive::EndBlock(None)),
+            "endblock" if syn::parse_str::<Ident>(rest).is_ok() => {
+                Some(Directive::EndBlock(Some(rest)))
+            }
+            "macro" => {
+                let call = syn::parse_str::<syn::ExprCall>(rest).ok()?;
+                let name = path_ident(&call.func)?;
+                let params = call
+                    .args
+                    .iter()
+                    .map(path_ident)
+                    .collect::<Option<Vec<_>>>()?;
+                Some(Directive::Macro(name, params))
+            }
+            "endmacro" if rest.is_empty() => Some(Directive::EndMacro),
+            "for" if !rest.ends_with('{') => ForLoop::parse(rest).map(Directive::For),
+            "else" if rest.is_empty() => Some(Directive::Else),
+            "else" if rest.starts_with("if") => {
+                let cond = &rest["if".len()..];
+                if cond.starts_with(char::is_whitespace) && is_if_condition(cond) {
+                    Some(Directive::ElseIf(cond.trim_start()))
+                } else {
+                    None
+                }
+            }
+            "if" if is_if_condition(rest) => Some(Directive::If(rest)),
+            "endif" if rest.is_empty() => Some(Directive::EndIf),
+            "endfor" if rest.is_empty() => Some(Directive::EndFor),
+            "capture" if syn::parse_str::<Ident>(rest).is_ok() => {
+                Some(Directive::Capture(rest))
+            }
+            "endcapture" if rest.is_empty() => Some(Directive::EndCapture),
+            "match" if !rest.ends_with('{') && syn::parse_str::<Expr>(rest).is_ok() => {
+                Some(Directive::Match(rest))
+            }
+            "when"
+                if syn::parse_str::<syn::Arm>(&format!("{} => {{}}", rest)).is_ok() =>
+            {
+                Some(Directive::When(rest))
+            }
+            "endmatch" if rest.is_empty() => Some(Directive::EndMatch),
+            _ => None,
+        }
+    }
+}
+
+/// Header of `for` loop (`pat in expr [with name]`)
+#[derive(Debug, PartialEq)]
+pub(crate) struct ForLoop<'a> {
+    pat: &'a str,
+    expr: &'a str,
+    /// name of the loop metadata
+    name: Option<&'a str>,
+}
+
+impl<'a> ForLoop<'a> {
+    fn parse(header: &'a str) -> Option<ForLoop<'a>> {
+        let mut rest = header.trim();
+        let mut name = None;
+        if let Some(pos) = rest.rfind(char::is_whitespace) {
+            let (head, ident) = (rest[..pos].trim_end(), rest[pos..].trim_start());
+            if head.ends_with("with") {
+                let head = &head[..head.len() - "with".len()];
+                if head.ends_with(char::is_whitespace)
+                    && syn::parse_str::<Ident>(ident).is_ok()
+                {
+                    rest = head;
+                    name = Some(ident);
+                }
+            }
+        }
+
+        let pos = rest.find(" in ")?;
+        let (pat, expr) = (rest[..pos].trim(), rest[pos + 4..].trim());
+        if syn::parse_str::<syn::Pat>(pat).is_err()
+            || syn::parse_str::<Expr>(expr).is_err()
+        {
+            return None;
+        }
+
+        Some(ForLoop { pat, expr, name })
+    }
+}
+
+/// Parse `for pat in expr with name {`
+fn loop_with(code: &str) -> Option<ForLoop<'_>> {
+    let code = code.trim();
+    if !code.starts_with("for") || !code.ends_with('{') {
+        return None;
+    }
+
+    let rest = &code["for".len()..code.len() - 1];
+    if !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+
+    let header = ForLoop::parse(rest)?;
+    header.name?;
+    Some(header)
+}
+
+/// Returns `true` if the code is a condition of `if` expression (e.g. `a > 0`
+/// or `let Some(a) = b`) without the opening brace
+fn is_if_condition(cond: &str) -> bool {
+    !cond.ends_with('{')
+        && syn::parse_str::<syn::ExprIf>(&format!("if {} {{}}", cond)).is_ok()
+}
+
+/// Returns the token of the part of the code, so that the errors inside it
+/// point to the original location
+fn subtoken<'a>(token: &Token<'a>, part: &'a str) -> Token<'a> {
+    let base = token.as_str().as_ptr() as usize;
+    Token::new(
+        part,
+        token.offset() + (part.as_ptr() as usize - base),
+        TokenKind::Code,
+    )
+}
+
+/// Returns the identifier if the expression is a single identifier
+fn path_ident(expr: &Expr) -> Option<String> {
+    match *expr {
+        Expr::Path(ref p) if p.qself.is_none() => {
+            p.path.get_ident().map(|i| i.to_string())
+        }
+        _ => None,
     }
 }
 
@@ -119,16 +321,39 @@ impl SourceMap {
 
 struct SourceBuilder {
     escape: bool,
+    escape_mode: EscapeMode,
+    escape_extra: String,
+    filters: HashMap<String, String>,
+    path: Option<String>,
+    /// expression of the CSP nonce added to `<script>` and `<style>` tags
+    nonce: Option<String>,
     source: String,
     source_map: SourceMap,
+    /// kinds, names and offsets of the blocks and macros which are not closed yet
+    block_stack: Vec<(&'static str, String, usize)>,
+    /// names of the macros defined in the template
+    macros: Vec<String>,
+    /// fields of the template struct
+    fields: Vec<String>,
+    /// identifiers which appear in the code blocks
+    code_idents: HashSet<String>,
 }
 
 impl SourceBuilder {
     fn new(escape: bool) -> SourceBuilder {
         SourceBuilder {
             escape,
+            escape_mode: EscapeMode::Html,
+            escape_extra: String::new(),
+            filters: HashMap::new(),
+            path: None,
+            nonce: None,
             source: String::from("{\n"),
             source_map: SourceMap::default(),
+            block_stack: Vec::new(),
+            macros: Vec::new(),
+            fields: Vec::new(),
+            code_idents: HashSet::new(),
         }
     }
 
@@ -147,26 +372,321 @@ impl SourceBuilder {
     }
 
     fn write_code<'a>(&mut self, token: &Token<'a>) -> Result<(), Error> {
+        if let Some(directive) = Directive::parse(token.as_str()) {
+            return self.write_directive(token, directive);
+        }
+
+        if let Some(header) = loop_with(token.as_str()) {
+            self.write_loop(token, &header);
+            return Ok(());
+        }
+
         // TODO: automatically add missing tokens (e.g. ';', '{')
         self.write_token(token);
         self.source.push_str("\n");
         Ok(())
     }
 
+    /// `for pat in expr with name {` is translated into
+    /// `for (name, pat) in sailfish::runtime::LoopIter::new(expr) {`
+    fn write_loop<'a>(&mut self, token: &Token<'a>, header: &ForLoop<'a>) {
+        let expr = subtoken(token, header.expr);
+        match header.name {
+            Some(name) => {
+                write!(
+                    self.source,
+                    "for ({}, {}) in sailfish::runtime::LoopIter::new(",
+                    name, header.pat
+                )
+                .unwrap();
+                self.write_token(&expr);
+                self.source.push_str(") {\n");
+            }
+            None => {
+                write!(self.source, "for {} in ", header.pat).unwrap();
+                self.write_token(&expr);
+                self.source.push_str(" {\n");
+            }
+        }
+    }
+
+    /// Blocks are translated into the Rust blocks starting with
+    /// `__sf_block!(name);`, and `extends` into `__sf_extends!("path");`.
+    /// These markers are expanded by the resolver.
+    ///
+    /// Macros are translated into the Rust blocks starting with
+    /// `__sf_macro!(name(args));`, which are lowered into `macro_rules!` by
+    /// the optimizer.
+    ///
+    /// Captures are translated into the blocks which render the contents into
+    /// a new buffer and evaluate to it.
+    ///
+    /// `match` and `when` are translated into the Rust `match` expression
+    /// whose arms are blocks.
+    fn write_directive<'a>(
+        &mut self,
+        token: &Token<'a>,
+        directive: Directive<'a>,
+    ) -> Result<(), Error> {
+        let offset = token.offset();
+        match directive {
+            Directive::Extends(path) => {
+                if !self.block_stack.is_empty() {
+                    return Err(make_error!(
+                        ErrorKind::ParseError(
+                            "`extends` cannot be used inside blocks or macros".to_owned()
+                        ),
+                        offset
+                    ));
+                }
+                writeln!(self.source, "__sf_extends!({});", path).unwrap();
+            }
+            Directive::Block(name) => {
+                write!(self.source, "{{\n__sf_block!({});\n", name).unwrap();
+                self.block_stack.push(("block", name.to_owned(), offset));
+            }
+            Directive::EndBlock(name) => {
+                let open = self.close_scope("block", offset)?;
+                if let Some(name) = name {
+                    if name != open {
+                        return Err(make_error!(
+                            ErrorKind::ParseError(format!(
+                                "Expected `endblock {}`, found `endblock {}`",
+                                open, name
+                            )),
+                            offset
+                        ));
+                    }
+                }
+                self.source.push_str("}\n");
+            }
+            Directive::Macro(name, params) => {
+                if self.block_stack.iter().any(|&(kind, ..)| kind == "macro") {
+                    return Err(make_error!(
+                        ErrorKind::ParseError(
+                            "macros cannot be defined inside macros".to_owned()
+                        ),
+                        offset
+                    ));
+                }
+                write!(
+                    self.source,
+                    "{{\n__sf_macro!({}({}));\n",
+                    name,
+                    params.join(", ")
+                )
+                .unwrap();
+                self.block_stack.push(("macro", name.clone(), offset));
+                self.macros.push(name);
+            }
+            Directive::EndMacro => {
+                self.close_scope("macro", offset)?;
+                self.source.push_str("}\n");
+            }
+            Directive::For(header) => {
+                self.source.push_str("{\nlet mut __sf_empty = true;\n");
+                self.write_loop(token, &header);
+                self.source.push_str("__sf_empty = false;\n");
+                let name = format!("{} in {}", header.pat, header.expr);
+                self.block_stack.push(("for", name, offset));
+            }
+            Directive::Else => match self.block_stack.last_mut() {
+                Some(&mut (ref mut kind, ..)) if *kind == "for" => {
+                    *kind = "for-else";
+                    self.source.push_str("}\nif __sf_empty {\n");
+                }
+                Some(&mut (ref mut kind, ..)) if *kind == "if" => {
+                    *kind = "if-else";
+                    self.source.push_str("} else {\n");
+                }
+                _ => {
+                    return Err(make_error!(
+                        ErrorKind::ParseError("Unexpected `else`".to_owned()),
+                        offset
+                    ));
+                }
+            },
+            Directive::EndFor => {
+                if let Some(&mut (ref mut kind, ..)) = self.block_stack.last_mut() {
+                    if *kind == "for-else" {
+                        *kind = "for";
+                    }
+                }
+                self.close_scope("for", offset)?;
+                self.source.push_str("}\n}\n");
+            }
+            Directive::Capture(name) => {
+                write!(
+                    self.source,
+                    "let {} = {{\n\
+                     let mut __sf_capture = __sf_rt::Buffer::new();\n\
+                     {{\n\
+                     #[allow(unused_mut)]\n\
+                     let mut __sf_buf = &mut __sf_capture;\n",
+                    name
+                )
+                .unwrap();
+                self.block_stack.push(("capture", name.to_owned(), offset));
+            }
+            Directive::EndCapture => {
+                self.close_scope("capture", offset)?;
+                self.source.push_str("}\n__sf_capture\n};\n");
+            }
+            Directive::If(cond) => {
+                self.source.push_str("if ");
+                self.write_token(&subtoken(token, cond));
+                self.source.push_str(" {\n");
+                self.block_stack.push(("if", cond.to_owned(), offset));
+            }
+            Directive::ElseIf(cond) => {
+                if !matches!(self.block_stack.last(), Some(&("if", ..))) {
+                    return Err(make_error!(
+                        ErrorKind::ParseError("Unexpected `else if`".to_owned()),
+                        offset
+                    ));
+                }
+                self.source.push_str("} else if ");
+                self.write_token(&subtoken(token, cond));
+                self.source.push_str(" {\n");
+            }
+            Directive::EndIf => {
+                if let Some(&mut (ref mut kind, ..)) = self.block_stack.last_mut() {
+                    if *kind == "if-else" {
+                        *kind = "if";
+                    }
+                }
+                self.close_scope("if", offset)?;
+                self.source.push_str("}\n");
+            }
+            Directive::Match(expr) => {
+                self.source.push_str("match ");
+                self.write_token(&subtoken(token, expr));
+                self.source.push_str(" {\n");
+                self.block_stack.push(("match", expr.to_owned(), offset));
+            }
+            Directive::When(pat) => {
+                match self.block_stack.last_mut() {
+                    Some(&mut (ref mut kind, ..)) if *kind == "match" => {
+                        *kind = "match-arm";
+                    }
+                    Some(&mut ("match-arm", ..)) => {
+                        self.source.push_str("}\n");
+                    }
+                    _ => {
+                        return Err(make_error!(
+                            ErrorKind::ParseError("Unexpected `when`".to_owned()),
+                            offset
+                        ));
+                    }
+                }
+                self.write_token(&subtoken(token, pat));
+                self.source.push_str(" => {\n");
+            }
+            Directive::EndMatch => {
+                if let Some(&mut (ref mut kind, ..)) = self.block_stack.last_mut() {
+                    if *kind == "match-arm" {
+                        *kind = "match";
+                        self.source.push_str("}\n");
+                    }
+                }
+                self.close_scope("match", offset)?;
+                self.source.push_str("}\n");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns an error if the contents are placed before the first `when`
+    fn check_match_arm(&self, offset: usize) -> Result<(), Error> {
+        if matches!(self.block_stack.last(), Some(&("match", ..))) {
+            return Err(make_error!(
+                ErrorKind::ParseError("Expected `when` after `match`".to_owned()),
+                offset
+            ));
+        }
+        Ok(())
+    }
+
+    /// Pop the innermost block or macro and returns its name
+    fn close_scope(&mut self, kind: &str, offset: usize) -> Result<String, Error> {
+        match self.block_stack.pop() {
+            Some((open, name, _)) if open == kind => Ok(name),
+            Some((open, ..)) => Err(make_error!(
+                ErrorKind::ParseError(format!(
+                    "Expected `end{}`, found `end{}`",
+                    // `for-else` and `match-arm` are closed by `endfor` and `endmatch`
+                    open.split('-').next().unwrap(),
+                    kind
+                )),
+                offset
+            )),
+            None => Err(make_error!(
+                ErrorKind::ParseError(format!("Unexpected `end{}`", kind)),
+                offset
+            )),
+        }
+    }
+
+    /// Returns `true` if the expression calls the helper macro exported from
+    /// sailfish (e.g. `classes!`) which is not shadowed by the template macros
+    fn helper_macro(&self, expr: &Expr) -> bool {
+        match *expr {
+            Expr::Macro(ref m) => match m.mac.path.get_ident() {
+                Some(ident) => {
+                    let name = ident.to_string();
+                    HELPER_MACROS.contains(&&*name) && !self.macros.contains(&name)
+                }
+                None => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// Returns the arguments if the expression calls the macro defined in the
+    /// template
+    fn macro_call(&self, expr: &Expr) -> Option<(String, String)> {
+        if let Expr::Call(ref call) = *expr {
+            let name = path_ident(&call.func)?;
+            if self.macros.contains(&name) {
+                return Some((name, call.args.to_token_stream().to_string()));
+            }
+        }
+
+        None
+    }
+
     fn write_text<'a>(&mut self, token: &Token<'a>) -> Result<(), Error> {
-        use std::fmt::Write;
+        let mut text = token.as_str();
+        if let Some(nonce) = self.nonce.clone() {
+            // `<script>` is translated into `<script{nonce_attr}>`
+            while let Some(p) = find_nonce_position(text) {
+                self.write_text_str(&text[..p]);
+                self.source.push_str("__sf_rt::render!(__sf_buf, (");
+                self.source.push_str(&nonce);
+                self.source.push_str(").nonce_attr());\n");
+                text = &text[p..];
+            }
+        }
+        self.write_text_str(text);
+        Ok(())
+    }
 
+    fn write_text_str(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
         self.source.push_str("__sf_rt::render_text!(__sf_buf, ");
         // write text token with Debug::fmt
-        write!(self.source, "{:?}", token.as_str()).unwrap();
+        write!(self.source, "{:?}", text).unwrap();
         self.source.push_str(");\n");
-        Ok(())
     }
 
     fn write_buffered_code<'a>(
         &mut self,
         token: &Token<'a>,
         escape: bool,
+        original_source: &str,
     ) -> Result<(), Error> {
         // parse and split off filter
         let code_block = syn::parse_str::<CodeBlock>(token.as_str()).map_err(|e| {
@@ -175,67 +695,236 @@ impl SourceBuilder {
             err.offset = into_offset(token.as_str(), span).map(|p| token.offset() + p);
             err
         })?;
-        let method = if self.escape && escape {
-            "render_escaped"
+        self.check_undefined_idents(token, &code_block.expr)?;
+
+        // macros render the contents by themselves
+        if code_block.filters.is_empty() {
+            if let Some((name, args)) = self.macro_call(&code_block.expr) {
+                writeln!(self.source, "{}!({});", name, args).unwrap();
+                return Ok(());
+            }
+        }
+
+        // move owned temporary values into the buffer
+        let consume =
+            code_block.filters.is_empty() && is_owned_temporary(&code_block.expr);
+        let escaper = if self.escape && escape {
+            self.escaper()
         } else {
-            "render"
+            None
+        };
+        let method = match (self.escape && escape, escaper.is_some(), consume) {
+            (true, true, _) => "render_escaped_with",
+            (true, false, false) => "render_escaped",
+            (false, _, false) => "render",
+            (true, false, true) => "render_once_escaped",
+            (false, _, true) => "render_once",
         };
 
         self.source.push_str("__sf_rt::");
         self.source.push_str(method);
         self.source.push_str("!(__sf_buf, ");
 
-        if let Some(filter) = code_block.filter {
+        // helper macros exported from sailfish can be used without importing
+        let helper = self.helper_macro(&code_block.expr);
+
+        if !code_block.filters.is_empty() {
             let expr_str = code_block.expr.into_token_stream().to_string();
-            let (name, extra_args) = match filter {
-                Filter::Ident(i) => (i.to_string(), None),
-                Filter::Call(c) => (
-                    c.func.into_token_stream().to_string(),
-                    Some(c.args.into_token_stream().to_string()),
-                ),
-            };
+            let mut filters = Vec::with_capacity(code_block.filters.len());
+            for filter in code_block.filters {
+                let (name, extra_args) = match filter {
+                    Filter::Ident(i) => (i.to_string(), None),
+                    Filter::Call(c) => (
+                        c.func.into_token_stream().to_string(),
+                        Some(c.args.into_token_stream().to_string()),
+                    ),
+                };
+                let path = self.filter_path(&name).ok_or_else(|| {
+                    make_error!(
+                        ErrorKind::AnalyzeError(format!("Unknown filter: `{}`", name)),
+                        offset = token.offset()
+                    )
+                })?;
+                filters.push((path, extra_args));
+            }
 
-            self.source.push_str("sailfish::runtime::filter::");
-            self.source.push_str(&*name);
-            self.source.push_str("(");
+            // `a | f | g(1)` is translated into `g(&(f(&(a))), 1)`
+            for (path, _) in filters.iter() {
+                self.source.push_str(path);
+                self.source.push_str("(&(");
+            }
 
-            // arguments to filter function
-            {
-                self.source.push_str("&(");
-                let entry = SourceMapEntry {
-                    original: token.offset(),
-                    new: self.source.len(),
-                    length: expr_str.len(),
-                };
-                self.source_map.entries.push(entry);
-                self.source.push_str(&expr_str);
-                self.source.push_str(")");
+            if helper {
+                self.source.push_str("sailfish::");
+            }
+            let entry = SourceMapEntry {
+                original: token.offset(),
+                new: self.source.len(),
+                length: expr_str.len(),
+            };
+            self.source_map.entries.push(entry);
+            self.source.push_str(&expr_str);
 
+            // arguments to filter functions
+            for (_, extra_args) in filters.iter().rev() {
+                self.source.push_str(")");
                 if let Some(extra_args) = extra_args {
                     self.source.push_str(", ");
-                    self.source.push_str(&*extra_args);
+                    self.source.push_str(extra_args);
                 }
+                self.source.push_str(")");
             }
-
-            self.source.push_str(")");
         } else {
+            if helper {
+                self.source.push_str("sailfish::");
+            }
             self.write_token(token);
         }
 
+        if let Some(escaper) = escaper {
+            self.source.push_str(", ");
+            self.source.push_str(&escaper);
+        }
+
+        // describe the location of the code block in error message
+        self.source.push_str(", ");
+        let context = self.error_context(token, original_source);
+        write!(self.source, "{:?}", context).unwrap();
+
         self.source.push_str(");\n");
 
         Ok(())
     }
 
+    /// Reject the identifiers which are not defined in the template but similar
+    /// to the fields of the template struct, since they are likely to be typos
+    ///
+    /// Other undefined identifiers are left to rustc, because they may refer
+    /// to the items defined outside the template.
+    fn check_undefined_idents<'a>(
+        &self,
+        token: &Token<'a>,
+        expr: &Expr,
+    ) -> Result<(), Error> {
+        if self.fields.is_empty() {
+            return Ok(());
+        }
+
+        let mut finder = FreeIdentFinder::default();
+        finder.visit_expr_mut(&mut expr.clone());
+
+        for ident in finder.idents {
+            let name = ident.to_string();
+            if self.fields.contains(&name)
+                || self.code_idents.contains(&name)
+                || finder.bound.contains(&name)
+                || name == "globals"
+                || name == "self"
+            {
+                continue;
+            }
+
+            if let Some(similar) = similar_name(&name, &self.fields) {
+                let mut err = make_error!(ErrorKind::AnalyzeError(format!(
+                    "Cannot find value `{}` in this template. Did you mean `{}`?",
+                    name, similar
+                )));
+                err.offset =
+                    into_offset(token.as_str(), ident.span()).map(|p| token.offset() + p);
+                return Err(err);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// path to the filter function
+    ///
+    /// Custom filters take precedence over the built-in filters.
+    fn filter_path(&self, name: &str) -> Option<String> {
+        if let Some(path) = self.filters.get(name) {
+            Some(path.clone())
+        } else if BUILTIN_FILTERS.contains(&name) {
+            Some(format!("sailfish::runtime::filter::{}", name))
+        } else {
+            None
+        }
+    }
+
+    /// expression of the custom escaper, or `None` if the default HTML escaping
+    /// is used
+    fn escaper(&self) -> Option<String> {
+        match self.escape_mode {
+            EscapeMode::Json => {
+                Some(String::from("&sailfish::runtime::escape::JsonEscaper"))
+            }
+            EscapeMode::Xml => {
+                Some(String::from("&sailfish::runtime::escape::XmlEscaper"))
+            }
+            EscapeMode::Html if !self.escape_extra.is_empty() => Some(format!(
+                "&sailfish::runtime::escape::ExtendedHtmlEscaper({:?})",
+                self.escape_extra
+            )),
+            EscapeMode::Html => None,
+        }
+    }
+
+    fn error_context<'a>(&self, token: &Token<'a>, original_source: &str) -> String {
+        let code = token.as_str().trim();
+        let (line, column) = location(token, original_source);
+
+        match self.path {
+            Some(ref path) => format!(
+                "failed to render `{}` at {}:{}:{}",
+                code,
+                relative_path(path).display(),
+                line,
+                column
+            ),
+            None => format!(
+                "failed to render `{}` at line {}, column {}",
+                code, line, column
+            ),
+        }
+    }
+
+    /// Write `__sf_loc!("path:line:column");` marker before the statement
+    ///
+    /// The markers are replaced with comments when the artifact is written, so
+    /// that the compiler errors in the generated code show the location in the
+    /// template file. The path is shortened to be relative to the crate root
+    /// since `rustc` truncates long lines.
+    fn write_location<'a>(&mut self, token: &Token<'a>, original_source: &str) {
+        if let Some(ref path) = self.path {
+            let (line, column) = location(token, original_source);
+            let loc = format!("{}:{}:{}", relative_path(path).display(), line, column);
+            writeln!(self.source, "__sf_loc!({:?});", loc).unwrap();
+        }
+    }
+
     pub fn feed_tokens<'a>(&mut self, token_iter: ParseStream<'a>) -> Result<(), Error> {
+        let original_source = token_iter.original_source;
         let mut it = token_iter.peekable();
         while let Some(token) = it.next() {
             let token = token?;
             match token.kind() {
-                TokenKind::Code => self.write_code(&token)?,
+                TokenKind::Code => {
+                    if !matches!(
+                        Directive::parse(token.as_str()),
+                        Some(Directive::When(_)) | Some(Directive::EndMatch)
+                    ) {
+                        self.check_match_arm(token.offset())?;
+                    }
+                    if is_statements(token.as_str()) {
+                        self.write_location(&token, original_source);
+                    }
+                    self.write_code(&token)?
+                }
                 TokenKind::Comment => {}
                 TokenKind::BufferedCode { escape } => {
-                    self.write_buffered_code(&token, escape)?
+                    self.check_match_arm(token.offset())?;
+                    self.write_location(&token, original_source);
+                    self.write_buffered_code(&token, escape, original_source)?
                 }
                 TokenKind::Text => {
                     // concatenate repeated text token
@@ -256,12 +945,28 @@ impl SourceBuilder {
                         }
                     }
 
+                    // whitespaces before the first `when` are ignored
+                    if concatenated.trim().is_empty()
+                        && matches!(self.block_stack.last(), Some(&("match", ..)))
+                    {
+                        continue;
+                    }
+                    self.check_match_arm(offset)?;
+
                     let new_token = Token::new(&*concatenated, offset, TokenKind::Text);
                     self.write_text(&new_token)?;
                 }
             }
         }
 
+        if let Some((kind, name, offset)) = self.block_stack.pop() {
+            let kind = kind.split('-').next().unwrap();
+            return Err(make_error!(
+                ErrorKind::ParseError(format!("Unterminated {} `{}`", kind, name)),
+                offset
+            ));
+        }
+
         Ok(())
     }
 
@@ -289,6 +994,113 @@ impl SourceBuilder {
     }
 }
 
+/// Returns the position right after the tag name of the first `<script>` or
+/// `<style>` tag which does not have `nonce` attribute yet
+fn find_nonce_position(text: &str) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let mut start = 0;
+    while let Some(p) = text[start..].find('<') {
+        start += p + 1;
+        for name in ["script", "style"].iter() {
+            let end = start + name.len();
+            let matched = bytes.len() >= end
+                && bytes[start..end].eq_ignore_ascii_case(name.as_bytes())
+                && !matches!(bytes.get(end), Some(c) if !b">/ \t\r\n".contains(c));
+            if matched {
+                let rest = &text[end..];
+                let attrs = &rest[..rest.find('>').unwrap_or(rest.len())];
+                if !attrs.contains("nonce=") {
+                    return Some(end);
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Collect the identifiers referred by the expression and the names bound
+/// inside it (e.g. closure parameters)
+///
+/// Functions and macros are not collected.
+#[derive(Default)]
+struct FreeIdentFinder {
+    bound: HashSet<String>,
+    idents: Vec<Ident>,
+}
+
+impl VisitMut for FreeIdentFinder {
+    fn visit_pat_ident_mut(&mut self, i: &mut PatIdent) {
+        self.bound.insert(i.ident.to_string());
+        syn::visit_mut::visit_pat_ident_mut(self, i);
+    }
+
+    fn visit_expr_path_mut(&mut self, i: &mut ExprPath) {
+        if i.qself.is_none() && i.path.leading_colon.is_none() {
+            if let Some(ident) = i.path.get_ident() {
+                self.idents.push(ident.clone());
+            }
+        }
+    }
+
+    fn visit_expr_call_mut(&mut self, i: &mut ExprCall) {
+        if !matches!(*i.func, Expr::Path(_)) {
+            self.visit_expr_mut(&mut i.func);
+        }
+        for arg in i.args.iter_mut() {
+            self.visit_expr_mut(arg);
+        }
+    }
+}
+
+/// Returns the most similar name in the candidates, using the same threshold
+/// as rustc
+fn similar_name<'a>(name: &str, candidates: &'a [String]) -> Option<&'a str> {
+    let max_dist = std::cmp::max(name.chars().count(), 3) / 3;
+    candidates
+        .iter()
+        .map(|c| (edit_distance(name, c), c))
+        .filter(|&(dist, _)| dist <= max_dist)
+        .min_by_key(|&(dist, _)| dist)
+        .map(|(_, c)| c.as_str())
+}
+
+/// Path to the template relative to the crate root, so that the absolute paths
+/// on the build machine are not embedded into the generated code
+fn relative_path(path: &str) -> &Path {
+    let manifest_dir = std::env::var_os("CARGO_MANIFEST_DIR").unwrap_or_default();
+    Path::new(path)
+        .strip_prefix(&manifest_dir)
+        .unwrap_or_else(|_| Path::new(path))
+}
+
+/// Returns the line and column of the code in the template
+fn location<'a>(token: &Token<'a>, original_source: &str) -> (usize, usize) {
+    let leading_spaces = token.as_str().len() - token.as_str().trim_start().len();
+    let before = &original_source[..token.offset() + leading_spaces];
+    let line = before.matches('\n').count() + 1;
+    let column = before[before.rfind('\n').map_or(0, |p| p + 1)..]
+        .chars()
+        .count()
+        + 1;
+    (line, column)
+}
+
+/// Returns `true` if the code consists of the complete statements, after which
+/// another statement can be inserted
+fn is_statements(code: &str) -> bool {
+    code.trim_end().ends_with(';')
+        && syn::parse_str::<Block>(&format!("{{{}}}", code)).is_ok()
+}
+
+/// check if the expression is evaluated into an owned temporary value
+fn is_owned_temporary(expr: &Expr) -> bool {
+    match *expr {
+        Expr::Call(_) | Expr::MethodCall(_) | Expr::Macro(_) | Expr::Struct(_) => true,
+        Expr::Paren(ref p) => is_owned_temporary(&p.expr),
+        _ => false,
+    }
+}
+
 fn into_offset(source: &str, span: Span) -> Option<usize> {
     let lc = span.start();
     if lc.line > 0 {
@@ -313,12 +1125,26 @@ pub struct TranslatedSource {
 #[derive(Clone, Debug, Default)]
 pub struct Translator {
     escape: bool,
+    escape_mode: EscapeMode,
+    escape_extra: String,
+    filters: HashMap<String, String>,
+    path: Option<String>,
+    nonce: Option<String>,
+    fields: Vec<String>,
 }
 
 impl Translator {
     #[inline]
     pub fn new() -> Self {
-        Self { escape: true }
+        Self {
+            escape: true,
+            escape_mode: EscapeMode::Html,
+            escape_extra: String::new(),
+            filters: HashMap::new(),
+            path: None,
+            nonce: None,
+            fields: Vec::new(),
+        }
     }
 
     #[inline]
@@ -327,13 +1153,78 @@ impl Translator {
         self
     }
 
+    /// escaping scheme used when `escape` is enabled
+    #[inline]
+    pub fn escape_mode(mut self, new: EscapeMode) -> Self {
+        self.escape_mode = new;
+        self
+    }
+
+    /// ASCII characters which are escaped in addition to the HTML special
+    /// characters
+    #[inline]
+    pub fn escape_extra(mut self, new: &str) -> Self {
+        self.escape_extra = new.to_owned();
+        self
+    }
+
+    /// custom filters which map the filter names to the paths to functions
+    #[inline]
+    pub fn filters(mut self, new: &HashMap<String, String>) -> Self {
+        self.filters = new.clone();
+        self
+    }
+
+    /// template path which is shown in the runtime error messages
+    #[inline]
+    pub fn path(mut self, new: &Path) -> Self {
+        self.path = Some(new.display().to_string());
+        self
+    }
+
+    /// expression of the CSP nonce which is added to `<script>` and `<style>`
+    /// tags
+    #[inline]
+    pub fn nonce(mut self, new: Option<&str>) -> Self {
+        self.nonce = new.map(|s| s.to_owned());
+        self
+    }
+
+    /// fields of the template struct, which are used to detect the typos of
+    /// the identifiers
+    #[inline]
+    pub fn fields(mut self, new: &[String]) -> Self {
+        self.fields = new.to_vec();
+        self
+    }
+
     pub fn translate<'a>(
         &self,
         token_iter: ParseStream<'a>,
     ) -> Result<TranslatedSource, Error> {
         let original_source = token_iter.original_source;
+        // spans of the parsed code are used to locate the errors
+        proc_macro2::fallback::force();
 
         let mut ps = SourceBuilder::new(self.escape);
+        ps.escape_mode = self.escape_mode;
+        ps.escape_extra = self.escape_extra.clone();
+        ps.filters = self.filters.clone();
+        ps.path = self.path.clone();
+        ps.nonce = self.nonce.clone();
+        if !self.fields.is_empty() {
+            ps.fields = self.fields.clone();
+            // syntax errors are reported by the main pass
+            for token in token_iter.clone().take_while(Result::is_ok).flatten() {
+                if matches!(token.kind(), TokenKind::Code) {
+                    let idents = token
+                        .as_str()
+                        .split(|c: char| !c.is_alphanumeric() && c != '_')
+                        .filter(|w| !w.is_empty());
+                    ps.code_idents.extend(idents.map(|w| w.to_owned()));
+                }
+            }
+        }
         ps.reserve(original_source.len());
         ps.feed_tokens(token_iter)?;
 
@@ -353,10 +1244,424 @@ mod tests {
         let token_iter = lexer.parse(src);
         let mut ps = SourceBuilder {
             escape: true,
+            escape_mode: EscapeMode::Html,
+            escape_extra: String::new(),
+            filters: HashMap::new(),
+            path: None,
+            nonce: None,
             source: String::with_capacity(token_iter.original_source.len()),
             source_map: SourceMap::default(),
+            block_stack: Vec::new(),
+            macros: Vec::new(),
+            fields: Vec::new(),
+            code_idents: HashSet::new(),
         };
         ps.feed_tokens(token_iter.clone()).unwrap();
         Translator::new().translate(token_iter).unwrap();
     }
+
+    #[test]
+    fn owned_temporary() {
+        let src = "<%= a %><%= format!(\"{}\", a) %><%- a.to_string() | trim %>";
+        let lexer = Parser::new();
+        let token_iter = lexer.parse(src);
+        let mut ps = SourceBuilder::new(true);
+        ps.feed_tokens(token_iter).unwrap();
+        assert!(ps.source.contains("__sf_rt::render_escaped!(__sf_buf, a, "));
+        assert!(ps
+            .source
+            .contains("__sf_rt::render_once_escaped!(__sf_buf, format!"));
+        assert!(ps
+            .source
+            .contains("__sf_rt::render!(__sf_buf, sailfish::runtime::filter::trim"));
+    }
+
+    #[test]
+    fn filter_pipeline() {
+        let src = "<%= name | trim | truncate(20) | upper %><%= a | b(1) | (f)(2) %>";
+        let token_iter = Parser::new().parse(src);
+        let mut ps = SourceBuilder::new(true);
+        ps.feed_tokens(token_iter).unwrap();
+        assert!(ps.source.contains(
+            "__sf_rt::render_escaped!(__sf_buf, \
+             sailfish::runtime::filter::upper(&(\
+             sailfish::runtime::filter::truncate(&(\
+             sailfish::runtime::filter::trim(&(name))), 20))), "
+        ));
+        assert!(ps
+            .source
+            .contains("__sf_rt::render_escaped!(__sf_buf, a | b(1) | (f)(2), "));
+
+        for src in &[
+            "<%= a | 1 %>",
+            "<%= a | b::c %>",
+            "<%= a | b::c | d %>",
+            "<%= a | unknown %>",
+        ] {
+            let token_iter = Parser::new().parse(src);
+            let mut ps = SourceBuilder::new(true);
+            assert!(ps.feed_tokens(token_iter).is_err());
+        }
+    }
+
+    #[test]
+    fn loop_metadata() {
+        assert_eq!(
+            loop_with("for (i, item) in items.iter().skip(1) with meta {"),
+            Some(ForLoop {
+                pat: "(i, item)",
+                expr: "items.iter().skip(1)",
+                name: Some("meta")
+            })
+        );
+        assert_eq!(loop_with("for item in items.iter() {"), None);
+        assert_eq!(loop_with("for item in with with {"), None);
+
+        let src = "<% for item in items with meta { %><%= meta.index %><% } %>";
+        let token_iter = Parser::new().parse(src);
+        let mut ps = SourceBuilder::new(true);
+        ps.feed_tokens(token_iter).unwrap();
+        assert!(ps
+            .source
+            .contains("for (meta, item) in sailfish::runtime::LoopIter::new(items) {\n"));
+    }
+
+    #[test]
+    fn for_else() {
+        let src = "<% for item in items.iter() %><%= item %><% else %>a<% endfor %>\
+                   <% for item in items with meta %><% endfor %>";
+        let token_iter = Parser::new().parse(src);
+        let mut ps = SourceBuilder::new(true);
+        ps.feed_tokens(token_iter).unwrap();
+        assert!(ps.source.contains(
+            "{\nlet mut __sf_empty = true;\nfor item in items.iter() {\n\
+             __sf_empty = false;\n"
+        ));
+        assert!(ps.source.contains("}\nif __sf_empty {\n"));
+        assert!(ps.source.contains(
+            "for (meta, item) in sailfish::runtime::LoopIter::new(items) {\n\
+             __sf_empty = false;\n}\n}\n"
+        ));
+
+        for src in &[
+            "<% for item in items %>",
+            "<% else %>",
+            "<% endfor %>",
+            "<% for item in items %><% else %><% else %><% endfor %>",
+            "<% block a %><% else %><% endblock %>",
+            "<% for item in items %><% endblock %>",
+        ] {
+            let token_iter = Parser::new().parse(src);
+            let mut ps = SourceBuilder::new(true);
+            assert!(ps.feed_tokens(token_iter).is_err(), "{}", src);
+        }
+    }
+
+    #[test]
+    fn if_directives() {
+        let src =
+            "<% if let Some(user) = &user %>a<% else if n > 0 %>b<% else %>c<% endif %>\
+                   <% if x %><% endif %>";
+        let token_iter = Parser::new().parse(src);
+        let mut ps = SourceBuilder::new(true);
+        ps.feed_tokens(token_iter).unwrap();
+        assert!(ps.source.contains(
+            "if let Some(user) = &user {\n\
+             __sf_rt::render_text!(__sf_buf, \"a\");\n\
+             } else if n > 0 {\n\
+             __sf_rt::render_text!(__sf_buf, \"b\");\n\
+             } else {\n\
+             __sf_rt::render_text!(__sf_buf, \"c\");\n\
+             }\n\
+             if x {\n}\n"
+        ));
+
+        for src in &[
+            "<% if x %>",
+            "<% endif %>",
+            "<% else if x %>",
+            "<% if x %><% else %><% else %><% endif %>",
+            "<% if x %><% else %><% else if y %><% endif %>",
+            "<% for i in a %><% else if x %><% endfor %>",
+            "<% if x %><% endfor %>",
+        ] {
+            let token_iter = Parser::new().parse(src);
+            let mut ps = SourceBuilder::new(true);
+            assert!(ps.feed_tokens(token_iter).is_err(), "{}", src);
+        }
+
+        // Rust code is not a directive
+        for code in &["if x {", "if a { b() }", "if a { b() } else { c() }"] {
+            assert!(Directive::parse(code).is_none(), "{}", code);
+        }
+    }
+
+    #[test]
+    fn match_directives() {
+        let src = "<% match value %>\n  <% when Some(n) if n > 1 %>many\
+                   <% when Some(_) | None %>few<% endmatch %>\
+                   <% match x %><% endmatch %>";
+        let token_iter = Parser::new().parse(src);
+        let mut ps = SourceBuilder::new(true);
+        ps.feed_tokens(token_iter).unwrap();
+        assert!(ps.source.contains(
+            "match value {\n\
+             Some(n) if n > 1 => {\n\
+             __sf_rt::render_text!(__sf_buf, \"many\");\n\
+             }\n\
+             Some(_) | None => {\n\
+             __sf_rt::render_text!(__sf_buf, \"few\");\n\
+             }\n\
+             }\n\
+             match x {\n}\n"
+        ));
+
+        for src in &[
+            "<% match x %>",
+            "<% when 1 %>",
+            "<% endmatch %>",
+            "<% match x %>a<% when 1 %><% endmatch %>",
+            "<% match x %><%= a %><% when 1 %><% endmatch %>",
+            "<% match x %><% when 1 %><% endfor %>",
+            "<% for i in a %><% when 1 %><% endfor %>",
+        ] {
+            let token_iter = Parser::new().parse(src);
+            let mut ps = SourceBuilder::new(true);
+            assert!(ps.feed_tokens(token_iter).is_err(), "{}", src);
+        }
+
+        // `match x {` is written as a Rust code
+        assert!(Directive::parse("match x {").is_none());
+    }
+
+    #[test]
+    fn capture() {
+        let src = "<% capture sidebar %><%= a %><% endcapture %><%- sidebar %>";
+        let token_iter = Parser::new().parse(src);
+        let mut ps = SourceBuilder::new(true);
+        ps.feed_tokens(token_iter).unwrap();
+        assert!(ps.source.contains(
+            "let sidebar = {\n\
+             let mut __sf_capture = __sf_rt::Buffer::new();\n\
+             {\n\
+             #[allow(unused_mut)]\n\
+             let mut __sf_buf = &mut __sf_capture;\n"
+        ));
+        assert!(ps.source.contains("}\n__sf_capture\n};\n"));
+
+        for src in &[
+            "<% capture a %>",
+            "<% endcapture %>",
+            "<% capture a %><% endblock %>",
+        ] {
+            let token_iter = Parser::new().parse(src);
+            let mut ps = SourceBuilder::new(true);
+            assert!(ps.feed_tokens(token_iter).is_err());
+        }
+    }
+
+    #[test]
+    fn nonce() {
+        let src = "<script>a</script><SCRIPT src=\"x.js\"></SCRIPT>\
+                   <scripts><style nonce=\"n\"></style><style";
+        let token_iter = Parser::new().parse(src);
+        let mut ps = SourceBuilder::new(true);
+        ps.nonce = Some("csp_nonce".to_owned());
+        ps.feed_tokens(token_iter).unwrap();
+        assert_eq!(ps.source.matches("(csp_nonce).nonce_attr()").count(), 3);
+        assert!(ps.source.contains(
+            "render_text!(__sf_buf, \"<script\");\n\
+             __sf_rt::render!(__sf_buf, (csp_nonce).nonce_attr());\n\
+             __sf_rt::render_text!(__sf_buf, \">a</script><SCRIPT\");"
+        ));
+        assert!(ps.source.ends_with(
+            "<scripts><style nonce=\\\"n\\\"></style><style\");\n\
+             __sf_rt::render!(__sf_buf, (csp_nonce).nonce_attr());\n"
+        ));
+    }
+
+    #[test]
+    fn helper_macros() {
+        let src = "<%= classes![(\"a\", x)] %><%- classes!(\"b\") | upper %>\
+                   <%= sailfish::classes![\"c\"] %>";
+        let token_iter = Parser::new().parse(src);
+        let mut ps = SourceBuilder::new(true);
+        ps.feed_tokens(token_iter).unwrap();
+        assert!(ps
+            .source
+            .contains("!(__sf_buf, sailfish::classes![(\"a\", x)], "));
+        assert!(ps.source.contains("upper(&(sailfish::classes ! (\"b\")))"));
+        assert!(ps
+            .source
+            .contains("!(__sf_buf, sailfish::classes![\"c\"], "));
+    }
+
+    #[test]
+    fn custom_filters() {
+        let src = "<%= a | markdown | upper %><%- b | trim(1) %>";
+        let token_iter = Parser::new().parse(src);
+        let mut ps = SourceBuilder::new(true);
+        ps.filters
+            .insert("markdown".to_owned(), "my_crate::markdown".to_owned());
+        ps.filters
+            .insert("trim".to_owned(), "my_crate::trim".to_owned());
+        ps.feed_tokens(token_iter).unwrap();
+        assert!(ps.source.contains(
+            "__sf_rt::render_escaped!(__sf_buf, \
+             sailfish::runtime::filter::upper(&(my_crate::markdown(&(a)))), "
+        ));
+        assert!(ps
+            .source
+            .contains("__sf_rt::render!(__sf_buf, my_crate::trim(&(b), 1), "));
+    }
+
+    #[test]
+    fn location_markers() {
+        let src = "<% let a = 1; %><% for i in 0..a { %><%= i %><% } %>";
+        let mut ps = SourceBuilder::new(true);
+        ps.path = Some(String::from("templates/index.stpl"));
+        ps.feed_tokens(Parser::new().parse(src)).unwrap();
+        assert_eq!(ps.source.matches("__sf_loc!").count(), 2);
+        assert!(ps
+            .source
+            .contains("__sf_loc!(\"templates/index.stpl:1:4\");\nlet a = 1;"));
+        assert!(ps
+            .source
+            .contains("__sf_loc!(\"templates/index.stpl:1:42\");\n__sf_rt::"));
+
+        // markers are only used for the artifacts of template files
+        let mut ps = SourceBuilder::new(true);
+        ps.feed_tokens(Parser::new().parse(src)).unwrap();
+        assert!(!ps.source.contains("__sf_loc!"));
+    }
+
+    #[test]
+    fn undefined_idents() {
+        let fields = vec!["name".to_owned(), "items".to_owned()];
+        let translator = Translator::new().fields(&fields);
+
+        let err = match translator.translate(Parser::new().parse("<p><%= nmae %></p>")) {
+            Ok(_) => panic!("typo is not detected"),
+            Err(e) => e,
+        };
+        assert!(err
+            .to_string()
+            .contains("Cannot find value `nmae` in this template. Did you mean `name`?"));
+        assert_eq!(err.offset, Some(7));
+
+        // variables defined in the template are not reported
+        let src = "<% let itms = items.len(); %><%= itms %>\
+                   <%= items.iter().map(|nme| nme.len()).sum::<usize>() %>";
+        translator.translate(Parser::new().parse(src)).unwrap();
+
+        // identifiers which are not similar to any field are left to rustc
+        translator
+            .translate(Parser::new().parse("<%= CONSTANT %><%= other %>"))
+            .unwrap();
+    }
+
+    #[test]
+    fn error_context() {
+        let src = "<%= a %>\n  <%- b | trim %>";
+        let token_iter = Parser::new().parse(src);
+        let mut ps = SourceBuilder::new(true);
+        ps.feed_tokens(token_iter.clone()).unwrap();
+        assert!(ps
+            .source
+            .contains(", \"failed to render `a` at line 1, column 5\");"));
+        assert!(ps
+            .source
+            .contains("\"failed to render `b | trim` at line 2, column 7\")"));
+
+        ps = SourceBuilder::new(true);
+        ps.path = Some(String::from("templates/index.stpl"));
+        ps.feed_tokens(token_iter).unwrap();
+        assert!(ps
+            .source
+            .contains("\"failed to render `a` at templates/index.stpl:1:5\")"));
+
+        // the path is relative to the crate root
+        let manifest_dir = env!("CARGO_MANIFEST_DIR");
+        ps = SourceBuilder::new(true);
+        ps.path = Some(format!("{}/templates/index.stpl", manifest_dir));
+        ps.feed_tokens(Parser::new().parse(src)).unwrap();
+        assert!(!ps.source.contains(manifest_dir));
+        assert!(ps
+            .source
+            .contains("\"failed to render `a` at templates/index.stpl:1:5\")"));
+    }
+
+    #[test]
+    fn json_escape_mode() {
+        let src = "<%= a %><%= a.to_string() %><%- b %>";
+        let token_iter = Parser::new().parse(src);
+        let mut ps = SourceBuilder::new(true);
+        ps.escape_mode = EscapeMode::Json;
+        ps.feed_tokens(token_iter).unwrap();
+        assert!(ps.source.contains(
+            "__sf_rt::render_escaped_with!(__sf_buf, a, \
+             &sailfish::runtime::escape::JsonEscaper, "
+        ));
+        assert!(ps.source.contains(
+            "__sf_rt::render_escaped_with!(__sf_buf, a.to_string(), \
+             &sailfish::runtime::escape::JsonEscaper, "
+        ));
+        assert!(ps.source.contains("__sf_rt::render!(__sf_buf, b, "));
+    }
+
+    #[test]
+    fn escape_extra() {
+        let src = "<%= a %><%- b %>";
+        let token_iter = Parser::new().parse(src);
+        let mut ps = SourceBuilder::new(true);
+        ps.escape_extra = String::from("`=");
+        ps.feed_tokens(token_iter).unwrap();
+        assert!(ps.source.contains(
+            "__sf_rt::render_escaped_with!(__sf_buf, a, \
+             &sailfish::runtime::escape::ExtendedHtmlEscaper(\"`=\"), "
+        ));
+        assert!(ps.source.contains("__sf_rt::render!(__sf_buf, b, "));
+    }
+
+    #[test]
+    fn inheritance_directives() {
+        let src = "<% extends \"layout.stpl\" %><% block title %>a<% endblock %>\
+                   <% block body %><% endblock body %><% let block = 1; %>";
+        let token_iter = Parser::new().parse(src);
+        let mut ps = SourceBuilder::new(true);
+        ps.feed_tokens(token_iter).unwrap();
+        assert!(ps.source.contains("__sf_extends!(\"layout.stpl\");\n"));
+        assert!(ps.source.contains("{\n__sf_block!(title);\n"));
+        assert!(ps.source.contains("{\n__sf_block!(body);\n}\n"));
+        assert!(ps.source.contains("let block = 1;"));
+
+        for src in &[
+            "<% block a %>",
+            "<% endblock %>",
+            "<% block a %><% endblock b %>",
+            "<% block a %><% extends \"layout.stpl\" %><% endblock %>",
+            "<% macro a() %><% endblock %>",
+            "<% macro a() %><% macro b() %><% endmacro %><% endmacro %>",
+            "<% macro a() %>",
+        ] {
+            let token_iter = Parser::new().parse(src);
+            let mut ps = SourceBuilder::new(true);
+            assert!(ps.feed_tokens(token_iter).is_err());
+        }
+    }
+
+    #[test]
+    fn macro_directives() {
+        let src = "<% macro badge(label, color) %><%= label %><% endmacro %>\
+                   <%= badge(\"New\", \"green\") %><%- badge(1, 2) %><%= other(1) %>";
+        let token_iter = Parser::new().parse(src);
+        let mut ps = SourceBuilder::new(true);
+        ps.feed_tokens(token_iter).unwrap();
+        assert!(ps.source.contains("{\n__sf_macro!(badge(label, color));\n"));
+        assert!(ps.source.contains("badge!(\"New\" , \"green\");\n"));
+        assert!(ps.source.contains("badge!(1 , 2);\n"));
+        assert!(ps
+            .source
+            .contains("__sf_rt::render_once_escaped!(__sf_buf, other(1), "));
+    }
 }
@@ -1,3 +1,5 @@
+use proc_macro2::{TokenStream, TokenTree};
+use std::collections::HashSet;
 use std::fs;
 use std::io::{self, Write};
 use std::path::{Path, PathBuf};
@@ -17,6 +19,110 @@ pub fn read_to_string(path: &Path) -> io::Result<String> {
     Ok(content)
 }
 
+/// 64-bit FNV hash of the bytes
+pub fn fnv_hash(bytes: &[u8]) -> u64 {
+    const FNV_PRIME: u64 = 1_099_511_628_211;
+    const FNV_OFFSET_BASIS: u64 = 14_695_981_039_346_656_037;
+
+    let mut h = FNV_OFFSET_BASIS;
+    for &b in bytes {
+        h = h.wrapping_mul(FNV_PRIME);
+        h ^= b as u64;
+    }
+    h
+}
+
+/// Replace the HTML special characters with the same entities as the runtime
+/// escaping
+pub fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("&quot;"),
+            '&' => escaped.push_str("&amp;"),
+            '\'' => escaped.push_str("&#039;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Encode the bytes with the standard base64 alphabet with padding
+pub fn base64_encode(bytes: &[u8]) -> String {
+    const TABLE: &[u8; 64] =
+        b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity((bytes.len() / 3 + 1) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(TABLE[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Collect the identifiers which appear in the tokens
+///
+/// Identifiers captured in the format strings (e.g. `"{name}"`) are also
+/// collected, so the result may contain some identifiers which are not
+/// actually referenced.
+pub fn collect_idents(tokens: TokenStream, idents: &mut HashSet<String>) {
+    for tt in tokens {
+        match tt {
+            TokenTree::Ident(i) => {
+                idents.insert(i.to_string());
+            }
+            TokenTree::Group(g) => collect_idents(g.stream(), idents),
+            TokenTree::Literal(l) => {
+                let l = l.to_string();
+                for part in l.split('{').skip(1) {
+                    let end = part
+                        .find(|c: char| !c.is_alphanumeric() && c != '_')
+                        .unwrap_or(part.len());
+                    if end > 0 {
+                        idents.insert(part[..end].to_owned());
+                    }
+                }
+            }
+            TokenTree::Punct(_) => {}
+        }
+    }
+}
+
+/// Edit distance between two strings
+///
+/// Transposition of two adjacent characters is counted as a single edit.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut prev2 = vec![0; b.len() + 1];
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut cur = vec![0; b.len() + 1];
+    for i in 1..=a.len() {
+        cur[0] = i;
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            cur[j] = (prev[j] + 1).min(cur[j - 1] + 1).min(prev[j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                cur[j] = cur[j].min(prev2[j - 2] + 1);
+            }
+        }
+        std::mem::swap(&mut prev2, &mut prev);
+        std::mem::swap(&mut prev, &mut cur);
+    }
+    prev[b.len()]
+}
+
 fn find_rustfmt() -> io::Result<Option<PathBuf>> {
     let mut toolchain_dir = home::rustup_home()?;
     toolchain_dir.push("toolchains");
@@ -0,0 +1,348 @@
+//! Expressions which can be evaluated against the JSON context
+
+use sailfish::runtime::{Buffer, Render};
+use serde_json::{Map, Number, Value};
+use std::cmp::Ordering;
+
+/// Subset of Rust expressions supported in the dynamic templates
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Expr {
+    Lit(Value),
+    Var(String),
+    Field(Box<Expr>, String),
+    Index(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Neg(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    Method(Box<Expr>, Method),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum BinOp {
+    And,
+    Or,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Method {
+    Len,
+    IsEmpty,
+    ToString,
+    ToUppercase,
+    ToLowercase,
+    Trim,
+}
+
+impl Expr {
+    pub(crate) fn parse(code: &str) -> Result<Expr, String> {
+        let expr = syn::parse_str::<syn::Expr>(code).map_err(|e| e.to_string())?;
+        Expr::from_syn(&expr)
+    }
+
+    pub(crate) fn from_syn(expr: &syn::Expr) -> Result<Expr, String> {
+        use syn::Expr as E;
+
+        match *expr {
+            E::Lit(ref l) => literal(&l.lit).map(Expr::Lit),
+            E::Path(ref p) => match p.path.get_ident() {
+                Some(i) if p.qself.is_none() => Ok(Expr::Var(i.to_string())),
+                _ => Err("only local variables are available".to_owned()),
+            },
+            E::Field(ref f) => {
+                let member = match f.member {
+                    syn::Member::Named(ref i) => i.to_string(),
+                    syn::Member::Unnamed(ref i) => i.index.to_string(),
+                };
+                Ok(Expr::Field(Box::new(Expr::from_syn(&f.base)?), member))
+            }
+            E::Index(ref i) => Ok(Expr::Index(
+                Box::new(Expr::from_syn(&i.expr)?),
+                Box::new(Expr::from_syn(&i.index)?),
+            )),
+            E::Paren(ref p) => Expr::from_syn(&p.expr),
+            E::Group(ref g) => Expr::from_syn(&g.expr),
+            // values are always borrowed from the context
+            E::Reference(ref r) => Expr::from_syn(&r.expr),
+            E::Unary(ref u) => {
+                let operand = Box::new(Expr::from_syn(&u.expr)?);
+                match u.op {
+                    syn::UnOp::Not(_) => Ok(Expr::Not(operand)),
+                    syn::UnOp::Neg(_) => Ok(Expr::Neg(operand)),
+                    syn::UnOp::Deref(_) => Ok(*operand),
+                }
+            }
+            E::Binary(ref b) => {
+                use syn::BinOp as B;
+                let op = match b.op {
+                    B::And(_) => BinOp::And,
+                    B::Or(_) => BinOp::Or,
+                    B::Eq(_) => BinOp::Eq,
+                    B::Ne(_) => BinOp::Ne,
+                    B::Lt(_) => BinOp::Lt,
+                    B::Le(_) => BinOp::Le,
+                    B::Gt(_) => BinOp::Gt,
+                    B::Ge(_) => BinOp::Ge,
+                    B::Add(_) => BinOp::Add,
+                    B::Sub(_) => BinOp::Sub,
+                    B::Mul(_) => BinOp::Mul,
+                    B::Div(_) => BinOp::Div,
+                    B::Rem(_) => BinOp::Rem,
+                    _ => return Err("unsupported operator".to_owned()),
+                };
+                Ok(Expr::Binary(
+                    op,
+                    Box::new(Expr::from_syn(&b.left)?),
+                    Box::new(Expr::from_syn(&b.right)?),
+                ))
+            }
+            E::MethodCall(ref m) => {
+                if !m.args.is_empty() || m.turbofish.is_some() {
+                    return Err(format!("unsupported method: {}", m.method));
+                }
+                let receiver = Expr::from_syn(&m.receiver)?;
+                let method = match &*m.method.to_string() {
+                    "len" => Method::Len,
+                    "is_empty" => Method::IsEmpty,
+                    "to_string" => Method::ToString,
+                    "to_uppercase" => Method::ToUppercase,
+                    "to_lowercase" => Method::ToLowercase,
+                    "trim" => Method::Trim,
+                    // iterators over the arrays are the arrays themselves
+                    "iter" | "clone" | "as_str" => return Ok(receiver),
+                    _ => return Err(format!("unsupported method: {}", m.method)),
+                };
+                Ok(Expr::Method(Box::new(receiver), method))
+            }
+            _ => Err("unsupported expression".to_owned()),
+        }
+    }
+
+    /// Evaluate the expression
+    ///
+    /// `lookup` returns the value of the variable, or `None` if it is not
+    /// defined.
+    pub(crate) fn eval<'a, F>(&self, lookup: &F) -> Result<Value, String>
+    where
+        F: Fn(&str) -> Option<&'a Value>,
+    {
+        match *self {
+            Expr::Lit(ref v) => Ok(v.clone()),
+            Expr::Var(ref name) => lookup(name)
+                .cloned()
+                .ok_or_else(|| format!("undefined variable `{}`", name)),
+            Expr::Field(ref base, ref name) => match base.eval(lookup)? {
+                Value::Object(mut map) => Ok(map.remove(name).unwrap_or(Value::Null)),
+                Value::Array(mut arr) => match name.parse::<usize>() {
+                    Ok(i) if i < arr.len() => Ok(arr.swap_remove(i)),
+                    _ => Err(format!("no field `{}` on array", name)),
+                },
+                v => Err(format!("no field `{}` on {}", name, type_name(&v))),
+            },
+            Expr::Index(ref base, ref index) => {
+                match (base.eval(lookup)?, index.eval(lookup)?) {
+                    (Value::Array(mut arr), Value::Number(n)) => {
+                        match n.as_u64().map(|i| i as usize) {
+                            Some(i) if i < arr.len() => Ok(arr.swap_remove(i)),
+                            _ => Err(format!(
+                                "index out of bounds: the len is {} but the index is {}",
+                                arr.len(),
+                                n
+                            )),
+                        }
+                    }
+                    (Value::Object(mut map), Value::String(key)) => {
+                        Ok(map.remove(&key).unwrap_or(Value::Null))
+                    }
+                    (b, i) => Err(format!(
+                        "cannot index {} with {}",
+                        type_name(&b),
+                        type_name(&i)
+                    )),
+                }
+            }
+            Expr::Not(ref e) => match e.eval(lookup)? {
+                Value::Bool(b) => Ok(Value::Bool(!b)),
+                v => Err(format!("cannot apply `!` to {}", type_name(&v))),
+            },
+            Expr::Neg(ref e) => match e.eval(lookup)? {
+                Value::Number(n) => arith(BinOp::Sub, &Number::from(0), &n),
+                v => Err(format!("cannot apply `-` to {}", type_name(&v))),
+            },
+            Expr::Binary(BinOp::And, ref l, ref r) => Ok(Value::Bool(
+                as_bool(&l.eval(lookup)?)? && as_bool(&r.eval(lookup)?)?,
+            )),
+            Expr::Binary(BinOp::Or, ref l, ref r) => Ok(Value::Bool(
+                as_bool(&l.eval(lookup)?)? || as_bool(&r.eval(lookup)?)?,
+            )),
+            Expr::Binary(op, ref l, ref r) => {
+                binary(op, l.eval(lookup)?, r.eval(lookup)?)
+            }
+            Expr::Method(ref e, method) => call(method, e.eval(lookup)?),
+        }
+    }
+}
+
+fn literal(lit: &syn::Lit) -> Result<Value, String> {
+    let value = match *lit {
+        syn::Lit::Str(ref s) => Value::String(s.value()),
+        syn::Lit::Char(ref c) => Value::String(c.value().to_string()),
+        syn::Lit::Bool(ref b) => Value::Bool(b.value),
+        syn::Lit::Int(ref i) => {
+            Value::Number(i.base10_parse::<i64>().map_err(|e| e.to_string())?.into())
+        }
+        syn::Lit::Float(ref f) => {
+            let f = f.base10_parse::<f64>().map_err(|e| e.to_string())?;
+            Number::from_f64(f)
+                .map(Value::Number)
+                .ok_or("invalid float")?
+        }
+        _ => return Err("unsupported literal".to_owned()),
+    };
+    Ok(value)
+}
+
+pub(crate) fn type_name(v: &Value) -> &'static str {
+    match *v {
+        Value::Null => "null",
+        Value::Bool(_) => "bool",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+pub(crate) fn as_bool(v: &Value) -> Result<bool, String> {
+    match *v {
+        Value::Bool(b) => Ok(b),
+        _ => Err(format!("expected bool, found {}", type_name(v))),
+    }
+}
+
+fn binary(op: BinOp, l: Value, r: Value) -> Result<Value, String> {
+    let ord = match (&l, &r) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64().partial_cmp(&b.as_f64()),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+        _ => None,
+    };
+
+    let result = match op {
+        BinOp::Eq => ord.map_or(l == r, |o| o == Ordering::Equal),
+        BinOp::Ne => ord.map_or(l != r, |o| o != Ordering::Equal),
+        BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => {
+            let ord = ord.ok_or_else(|| {
+                format!("cannot compare {} with {}", type_name(&l), type_name(&r))
+            })?;
+            match op {
+                BinOp::Lt => ord == Ordering::Less,
+                BinOp::Le => ord != Ordering::Greater,
+                BinOp::Gt => ord == Ordering::Greater,
+                _ => ord != Ordering::Less,
+            }
+        }
+        _ => {
+            return match (l, r) {
+                (Value::Number(a), Value::Number(b)) => arith(op, &a, &b),
+                (Value::String(mut a), Value::String(b)) if op == BinOp::Add => {
+                    a.push_str(&b);
+                    Ok(Value::String(a))
+                }
+                (l, r) => Err(format!(
+                    "unsupported operands: {} and {}",
+                    type_name(&l),
+                    type_name(&r)
+                )),
+            };
+        }
+    };
+
+    Ok(Value::Bool(result))
+}
+
+fn arith(op: BinOp, a: &Number, b: &Number) -> Result<Value, String> {
+    if let (Some(a), Some(b)) = (a.as_i64(), b.as_i64()) {
+        let result = match op {
+            BinOp::Add => a.checked_add(b),
+            BinOp::Sub => a.checked_sub(b),
+            BinOp::Mul => a.checked_mul(b),
+            BinOp::Div => a.checked_div(b),
+            _ => a.checked_rem(b),
+        };
+        return result
+            .map(|n| Value::Number(n.into()))
+            .ok_or_else(|| "attempt to divide by zero or overflow".to_owned());
+    }
+
+    let (a, b) = (a.as_f64().unwrap(), b.as_f64().unwrap());
+    let result = match op {
+        BinOp::Add => a + b,
+        BinOp::Sub => a - b,
+        BinOp::Mul => a * b,
+        BinOp::Div => a / b,
+        _ => a % b,
+    };
+    Ok(Number::from_f64(result).map_or(Value::Null, Value::Number))
+}
+
+fn call(method: Method, v: Value) -> Result<Value, String> {
+    let len = |v: &Value| match *v {
+        Value::String(ref s) => Ok(s.len()),
+        Value::Array(ref a) => Ok(a.len()),
+        Value::Object(ref o) => Ok(o.len()),
+        _ => Err(format!("{} has no length", type_name(v))),
+    };
+
+    match method {
+        Method::Len => Ok(Value::Number(len(&v)?.into())),
+        Method::IsEmpty => Ok(Value::Bool(len(&v)? == 0)),
+        Method::ToString => Ok(Value::String(to_string(&v)?)),
+        Method::ToUppercase => Ok(Value::String(to_string(&v)?.to_uppercase())),
+        Method::ToLowercase => Ok(Value::String(to_string(&v)?.to_lowercase())),
+        Method::Trim => Ok(Value::String(to_string(&v)?.trim().to_owned())),
+    }
+}
+
+/// Convert the value into the string as rendered in the template
+pub(crate) fn to_string(v: &Value) -> Result<String, String> {
+    match *v {
+        Value::String(ref s) => Ok(s.clone()),
+        Value::Number(ref n) => match n.as_f64() {
+            // floats are formatted in the same way as the compiled templates
+            Some(f) if n.is_f64() => {
+                let mut b = Buffer::new();
+                f.render(&mut b).map_err(|e| e.to_string())?;
+                Ok(b.into_string())
+            }
+            _ => Ok(n.to_string()),
+        },
+        Value::Bool(b) => Ok(b.to_string()),
+        _ => Err(format!("cannot render {}", type_name(v))),
+    }
+}
+
+/// Variables which are not defined in the template are looked up in the
+/// context object
+pub(crate) fn lookup<'a>(
+    scope: &'a [(String, Value)],
+    context: &'a Map<String, Value>,
+    name: &str,
+) -> Option<&'a Value> {
+    scope
+        .iter()
+        .rev()
+        .find(|(n, _)| n == name)
+        .map(|(_, v)| v)
+        .or_else(|| context.get(name))
+}
@@ -0,0 +1,715 @@
+//! Render sailfish templates which are loaded at runtime
+//!
+//! The templates are parsed with the same parser as the derive macro, and
+//! rendered against the context which implements `serde::Serialize`. This is
+//! useful for the templates which cannot be known at compile time (e.g. email
+//! templates stored in the database).
+//!
+//! ```
+//! use sailfish_dyn::Template;
+//! use serde_json::json;
+//!
+//! let template = Template::parse(
+//!     "<% for user in users { %><li><%= user.name %></li><% } %>",
+//! ).unwrap();
+//! let context = json!({ "users": [{ "name": "Tom" }, { "name": "<Jerry>" }] });
+//! assert_eq!(
+//!     template.render(&context).unwrap(),
+//!     "<li>Tom</li><li>&lt;Jerry&gt;</li>"
+//! );
+//! ```
+//!
+//! Since the templates are interpreted, only a subset of the syntax is
+//! supported:
+//!
+//! - Variables, field access, indexing, literals, and arithmetic, comparison
+//!   and logical operators
+//! - `len()`, `is_empty()`, `to_string()`, `to_uppercase()`, `to_lowercase()`
+//!   and `trim()` methods
+//! - `if`/`else if`/`else` and `for` loops, in both braces and
+//!   `endif`/`endfor` forms
+//! - `let` statements
+//! - `upper`, `lower`, `trim`, `truncate`, `default`, `json`, `disp` and
+//!   `safe` filters
+//!
+//! Other features such as `include!`, template inheritance and macros are
+//! rejected when the template is parsed.
+
+mod expr;
+
+use sailfish::runtime::{Buffer, Render, RenderError};
+use sailfish_compiler::{Parser, TokenKind};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use crate::expr::{as_bool, lookup, to_string, type_name, Expr};
+
+pub use sailfish_compiler::{Error, ErrorKind};
+
+#[derive(Clone, Debug)]
+enum Filter {
+    Upper,
+    Lower,
+    Trim,
+    Truncate(Expr),
+    Default(Expr),
+    Json,
+    Disp,
+    Safe,
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    Text(String),
+    Output {
+        expr: Expr,
+        filters: Vec<Filter>,
+        escape: bool,
+        offset: usize,
+    },
+    Let {
+        name: String,
+        expr: Expr,
+        offset: usize,
+    },
+    If {
+        branches: Vec<(Expr, Vec<Node>)>,
+        otherwise: Vec<Node>,
+        offset: usize,
+    },
+    For {
+        var: String,
+        expr: Expr,
+        body: Vec<Node>,
+        offset: usize,
+    },
+}
+
+/// Control flow which is not closed yet while parsing
+enum Frame {
+    Root,
+    If {
+        branches: Vec<(Expr, Vec<Node>)>,
+        /// condition of the current branch, or `None` for `else` branch
+        cond: Option<Expr>,
+        offset: usize,
+    },
+    For {
+        var: String,
+        expr: Expr,
+        offset: usize,
+    },
+}
+
+/// Template parsed at runtime
+#[derive(Clone, Debug)]
+pub struct Template {
+    source: String,
+    nodes: Vec<Node>,
+    escape: bool,
+}
+
+impl Template {
+    /// Parse the template source with the default delimiter (`<% %>`)
+    pub fn parse(source: &str) -> Result<Template, Error> {
+        Template::parse_with(source, &Parser::new())
+    }
+
+    /// Parse the template source with the parser configured with the custom
+    /// delimiters
+    pub fn parse_with(source: &str, parser: &Parser) -> Result<Template, Error> {
+        let mut stack = vec![(Frame::Root, Vec::new())];
+
+        for token in parser.parse(source) {
+            let token = token?;
+            let offset = token.offset();
+            let error = |msg: String| {
+                Error::from_kind(ErrorKind::AnalyzeError(msg)).with_source(source, offset)
+            };
+            let nodes = &mut stack.last_mut().unwrap().1;
+
+            match token.kind() {
+                TokenKind::Text => nodes.push(Node::Text(token.as_str().to_owned())),
+                TokenKind::Comment => {}
+                TokenKind::BufferedCode { escape } => {
+                    let (expr, filters) = parse_output(token.as_str()).map_err(error)?;
+                    nodes.push(Node::Output {
+                        expr,
+                        filters,
+                        escape,
+                        offset,
+                    });
+                }
+                TokenKind::Code => {
+                    let stmt = Statement::parse(token.as_str()).map_err(&error)?;
+                    apply_statement(&mut stack, stmt, offset).map_err(error)?;
+                }
+            }
+        }
+
+        let (frame, nodes) = stack.pop().unwrap();
+        match frame {
+            Frame::Root => Ok(Template {
+                source: source.to_owned(),
+                nodes,
+                escape: true,
+            }),
+            Frame::If { offset, .. } | Frame::For { offset, .. } => Err(
+                Error::from_kind(ErrorKind::AnalyzeError("unclosed block".to_owned()))
+                    .with_source(source, offset),
+            ),
+        }
+    }
+
+    /// Read the template from the file
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Template, Error> {
+        let source = read_template(path.as_ref())?;
+        Template::parse(&source)
+    }
+
+    /// Enable or disable HTML escaping of `<%= %>` tags (default: `true`)
+    pub fn escape(mut self, new: bool) -> Self {
+        self.escape = new;
+        self
+    }
+
+    /// Render the template against the context
+    ///
+    /// The context must be serialized into an object (e.g. a struct or a map),
+    /// whose fields are available as the variables in the template.
+    pub fn render<T: Serialize + ?Sized>(&self, context: &T) -> Result<String, Error> {
+        let mut buf = Buffer::new();
+        self.render_to_buffer(context, &mut buf)?;
+        Ok(buf.into_string())
+    }
+
+    /// Render the template into the buffer
+    pub fn render_to_buffer<T: Serialize + ?Sized>(
+        &self,
+        context: &T,
+        buf: &mut Buffer,
+    ) -> Result<(), Error> {
+        let context = match serde_json::to_value(context) {
+            Ok(Value::Object(map)) => map,
+            Ok(v) => {
+                return Err(Error::from_kind(ErrorKind::Other(format!(
+                    "context must be an object, found {}",
+                    type_name(&v)
+                ))));
+            }
+            Err(e) => return Err(Error::from_kind(ErrorKind::Other(e.to_string()))),
+        };
+
+        let mut renderer = Renderer {
+            context: &context,
+            scope: Vec::new(),
+            escape: self.escape,
+            buf,
+        };
+        renderer.render_nodes(&self.nodes).map_err(|(msg, offset)| {
+            Error::from_kind(ErrorKind::Other(msg)).with_source(&self.source, offset)
+        })
+    }
+}
+
+/// Render the template file for `hot_reload` option of the derive macro
+///
+/// The file is read on each call, so that the modifications are reflected
+/// without recompilation. Returns `None` if the template cannot be interpreted,
+/// and then the caller renders the compiled template instead.
+#[doc(hidden)]
+pub fn __hot_reload<T: Serialize>(
+    path: &str,
+    delimiters: (&str, &str),
+    escape: bool,
+    context: &T,
+    buf: &mut Buffer,
+) -> Option<Result<(), RenderError>> {
+    let template = read_template(Path::new(path)).and_then(|source| {
+        // options in the front-matter are not supported
+        if source.starts_with("---") {
+            return Err(Error::from("front-matter is not supported"));
+        }
+        let parser = Parser::new().delimiters(delimiters.0, delimiters.1);
+        Template::parse_with(&source, &parser)
+    });
+
+    match template {
+        Ok(template) => Some(
+            template
+                .escape(escape)
+                .render_to_buffer(context, buf)
+                .map_err(|e| RenderError::new(&e.to_string())),
+        ),
+        Err(e) => {
+            // warn only once for each template
+            WARNED.with(|warned| {
+                if warned.borrow_mut().insert(path.to_owned()) {
+                    eprintln!(
+                        "warning: {} cannot be reloaded, so the compiled template \
+                         is rendered instead: {}",
+                        path, e
+                    );
+                }
+            });
+            None
+        }
+    }
+}
+
+/// Read the template file, stripping the line break at the end of the file in
+/// the same way as the compiler
+fn read_template(path: &Path) -> Result<String, Error> {
+    let mut content = fs::read_to_string(path)?;
+    if content.ends_with('\n') {
+        content.pop();
+        if content.ends_with('\r') {
+            content.pop();
+        }
+    }
+    Ok(content)
+}
+
+thread_local! {
+    static WARNED: RefCell<HashSet<String>> = RefCell::new(HashSet::new());
+}
+
+/// Statements written in `<% %>` tags
+enum Statement {
+    If(Expr),
+    ElseIf(Expr),
+    Else,
+    For(String, Expr),
+    End,
+    Let(String, Expr),
+}
+
+impl Statement {
+    fn parse(code: &str) -> Result<Statement, String> {
+        let code = code.trim();
+
+        // `} else {` and `else` are treated in the same way
+        let opens = code.ends_with('{');
+        let code = if opens {
+            code[..code.len() - 1].trim_end()
+        } else {
+            code
+        };
+        let closes = code.starts_with('}');
+        let code = if closes { code[1..].trim_start() } else { code };
+
+        let (keyword, rest) = match code.find(char::is_whitespace) {
+            Some(p) => (&code[..p], code[p..].trim_start()),
+            None => (code, ""),
+        };
+
+        let stmt = match keyword {
+            "" if closes && !opens => Statement::End,
+            "endif" | "endfor" if rest.is_empty() => Statement::End,
+            "if" if !closes => Statement::If(Expr::parse(rest)?),
+            "else" if rest.is_empty() => Statement::Else,
+            "else"
+                if rest.starts_with("if")
+                    && rest["if".len()..].starts_with(char::is_whitespace) =>
+            {
+                Statement::ElseIf(Expr::parse(&rest["if".len()..])?)
+            }
+            "else" => return Err(format!("unsupported statement: {}", code)),
+            "for" if !closes => {
+                let p = rest.find(" in ").ok_or("expected `in` in `for` loop")?;
+                let var = syn::parse_str::<syn::Ident>(&rest[..p])
+                    .map_err(|_| "only a variable is allowed in `for` pattern")?;
+                Statement::For(var.to_string(), Expr::parse(&rest[p + 4..])?)
+            }
+            "let" if !opens && !closes => {
+                let local =
+                    syn::parse_str::<syn::Stmt>(code).map_err(|e| e.to_string())?;
+                let local = match local {
+                    syn::Stmt::Local(l) => l,
+                    _ => return Err(format!("unsupported statement: {}", code)),
+                };
+                match (local.pat, local.init) {
+                    (syn::Pat::Ident(ref p), Some((_, ref init))) => {
+                        Statement::Let(p.ident.to_string(), Expr::from_syn(init)?)
+                    }
+                    _ => return Err("only `let name = expr;` is supported".to_owned()),
+                }
+            }
+            _ => {
+                return Err(format!(
+                    "unsupported statement in dynamic template: {}",
+                    code
+                ))
+            }
+        };
+
+        // braces must match the kind of the statement
+        let (need_open, need_close) = match stmt {
+            Statement::If(_) | Statement::For(..) => (true, false),
+            Statement::ElseIf(_) | Statement::Else => (true, true),
+            Statement::End => (false, true),
+            Statement::Let(..) => (false, false),
+        };
+        if (opens || closes) && (opens != need_open || closes != need_close) {
+            return Err(format!("unbalanced braces: {}", code));
+        }
+
+        Ok(stmt)
+    }
+}
+
+fn apply_statement(
+    stack: &mut Vec<(Frame, Vec<Node>)>,
+    stmt: Statement,
+    offset: usize,
+) -> Result<(), String> {
+    match stmt {
+        Statement::If(cond) => stack.push((
+            Frame::If {
+                branches: Vec::new(),
+                cond: Some(cond),
+                offset,
+            },
+            Vec::new(),
+        )),
+        Statement::For(var, expr) => {
+            stack.push((Frame::For { var, expr, offset }, Vec::new()))
+        }
+        Statement::Let(name, expr) => {
+            stack
+                .last_mut()
+                .unwrap()
+                .1
+                .push(Node::Let { name, expr, offset });
+        }
+        Statement::ElseIf(_) | Statement::Else => {
+            let (frame, nodes) = stack.last_mut().unwrap();
+            match *frame {
+                Frame::If {
+                    ref mut branches,
+                    ref mut cond,
+                    ..
+                } => {
+                    let current = cond.take().ok_or("`else` after `else` branch")?;
+                    branches.push((current, std::mem::take(nodes)));
+                    if let Statement::ElseIf(new_cond) = stmt {
+                        *cond = Some(new_cond);
+                    }
+                }
+                _ => return Err("`else` outside `if`".to_owned()),
+            }
+        }
+        Statement::End => {
+            let (frame, nodes) = stack.pop().unwrap();
+            let node = match frame {
+                Frame::Root => return Err("unexpected end of block".to_owned()),
+                Frame::If {
+                    mut branches,
+                    cond,
+                    offset,
+                } => match cond {
+                    Some(cond) => {
+                        branches.push((cond, nodes));
+                        Node::If {
+                            branches,
+                            otherwise: Vec::new(),
+                            offset,
+                        }
+                    }
+                    None => Node::If {
+                        branches,
+                        otherwise: nodes,
+                        offset,
+                    },
+                },
+                Frame::For { var, expr, offset } => Node::For {
+                    var,
+                    expr,
+                    body: nodes,
+                    offset,
+                },
+            };
+            stack.last_mut().unwrap().1.push(node);
+        }
+    }
+    Ok(())
+}
+
+/// Parse the expression and the filters (e.g. `name | upper | truncate(10)`)
+fn parse_output(code: &str) -> Result<(Expr, Vec<Filter>), String> {
+    let mut expr = syn::parse_str::<syn::Expr>(code).map_err(|e| e.to_string())?;
+    let mut filters = Vec::new();
+
+    while let syn::Expr::Binary(b) = expr {
+        if !matches!(b.op, syn::BinOp::BitOr(_)) {
+            expr = syn::Expr::Binary(b);
+            break;
+        }
+        filters.push(parse_filter(&b.right)?);
+        expr = *b.left;
+    }
+
+    filters.reverse();
+    Ok((Expr::from_syn(&expr)?, filters))
+}
+
+fn parse_filter(expr: &syn::Expr) -> Result<Filter, String> {
+    let (name, args) = match *expr {
+        syn::Expr::Path(ref p) => (p.path.get_ident(), Vec::new()),
+        syn::Expr::Call(ref c) => match *c.func {
+            syn::Expr::Path(ref p) => (
+                p.path.get_ident(),
+                c.args
+                    .iter()
+                    .map(Expr::from_syn)
+                    .collect::<Result<Vec<_>, _>>()?,
+            ),
+            _ => (None, Vec::new()),
+        },
+        _ => (None, Vec::new()),
+    };
+    let name = name.ok_or("Invalid filter name")?.to_string();
+
+    let mut args = args.into_iter();
+    let filter = match (&*name, args.next()) {
+        ("upper", None) => Filter::Upper,
+        ("lower", None) => Filter::Lower,
+        ("trim", None) => Filter::Trim,
+        ("json", None) => Filter::Json,
+        ("disp", None) => Filter::Disp,
+        ("safe", None) => Filter::Safe,
+        ("truncate", Some(n)) => Filter::Truncate(n),
+        ("default", Some(v)) => Filter::Default(v),
+        _ => return Err(format!("unsupported filter: {}", name)),
+    };
+    if args.next().is_some() {
+        return Err(format!("too many arguments for `{}` filter", name));
+    }
+    Ok(filter)
+}
+
+struct Renderer<'a> {
+    context: &'a Map<String, Value>,
+    /// variables defined in the template, from the outermost one
+    scope: Vec<(String, Value)>,
+    escape: bool,
+    buf: &'a mut Buffer,
+}
+
+impl<'a> Renderer<'a> {
+    fn eval(&self, expr: &Expr, offset: usize) -> Result<Value, (String, usize)> {
+        let lookup = |name: &str| lookup(&self.scope, self.context, name);
+        expr.eval(&lookup).map_err(|msg| (msg, offset))
+    }
+
+    fn render_nodes(&mut self, nodes: &[Node]) -> Result<(), (String, usize)> {
+        let depth = self.scope.len();
+        for node in nodes {
+            match *node {
+                Node::Text(ref text) => self.buf.push_str(text),
+                Node::Output {
+                    ref expr,
+                    ref filters,
+                    escape,
+                    offset,
+                } => {
+                    let value = self.eval(expr, offset)?;
+                    self.render_output(value, filters, escape, offset)?;
+                }
+                Node::Let {
+                    ref name,
+                    ref expr,
+                    offset,
+                } => {
+                    let value = self.eval(expr, offset)?;
+                    self.scope.push((name.clone(), value));
+                }
+                Node::If {
+                    ref branches,
+                    ref otherwise,
+                    offset,
+                } => {
+                    let mut body = otherwise;
+                    for (cond, nodes) in branches {
+                        let cond = self.eval(cond, offset)?;
+                        if as_bool(&cond).map_err(|msg| (msg, offset))? {
+                            body = nodes;
+                            break;
+                        }
+                    }
+                    self.render_nodes(body)?;
+                }
+                Node::For {
+                    ref var,
+                    ref expr,
+                    ref body,
+                    offset,
+                } => {
+                    let items = match self.eval(expr, offset)? {
+                        Value::Array(items) => items,
+                        v => {
+                            let msg = format!("cannot iterate over {}", type_name(&v));
+                            return Err((msg, offset));
+                        }
+                    };
+                    for item in items {
+                        self.scope.push((var.clone(), item));
+                        self.render_nodes(body)?;
+                        self.scope.pop();
+                    }
+                }
+            }
+        }
+        self.scope.truncate(depth);
+        Ok(())
+    }
+
+    fn render_output(
+        &mut self,
+        mut value: Value,
+        filters: &[Filter],
+        mut escape: bool,
+        offset: usize,
+    ) -> Result<(), (String, usize)> {
+        let error = |msg| (msg, offset);
+        for filter in filters {
+            value = match *filter {
+                Filter::Upper => {
+                    Value::String(to_string(&value).map_err(error)?.to_uppercase())
+                }
+                Filter::Lower => {
+                    Value::String(to_string(&value).map_err(error)?.to_lowercase())
+                }
+                Filter::Trim => {
+                    Value::String(to_string(&value).map_err(error)?.trim().to_owned())
+                }
+                Filter::Truncate(ref limit) => {
+                    let limit = self.eval(limit, offset)?;
+                    let limit = limit.as_u64().ok_or_else(|| {
+                        error("invalid limit for `truncate`".to_owned())
+                    })?;
+                    let mut b = Buffer::new();
+                    sailfish::runtime::filter::truncate(
+                        &to_string(&value).map_err(error)?,
+                        limit as usize,
+                    )
+                    .render(&mut b)
+                    .map_err(|e| error(e.to_string()))?;
+                    Value::String(b.into_string())
+                }
+                Filter::Default(ref default) => match value {
+                    Value::Null => self.eval(default, offset)?,
+                    v => v,
+                },
+                Filter::Json => Value::String(value.to_string()),
+                Filter::Disp => Value::String(to_string(&value).map_err(error)?),
+                Filter::Safe => {
+                    escape = false;
+                    value
+                }
+            };
+        }
+
+        let s = to_string(&value).map_err(error)?;
+        let result = if escape && self.escape {
+            s.render_escaped(self.buf)
+        } else {
+            s.render(self.buf)
+        };
+        result.map_err(|e| error(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn render(source: &str, context: Value) -> String {
+        Template::parse(source).unwrap().render(&context).unwrap()
+    }
+
+    #[test]
+    fn output() {
+        let ctx = json!({ "name": "<Tom>", "n": 3, "user": { "tags": ["a", "b"] } });
+        assert_eq!(
+            render("Hello, <%= name %>!", ctx.clone()),
+            "Hello, &lt;Tom&gt;!"
+        );
+        assert_eq!(render("<%- name %>", ctx.clone()), "<Tom>");
+        assert_eq!(render("<%= n * 2 + 1 %>", ctx.clone()), "7");
+        assert_eq!(render("<%= user.tags[1] %>", ctx.clone()), "b");
+        assert_eq!(render("<%= user.tags.len() %>", ctx.clone()), "2");
+        assert_eq!(render("<%= name | upper | safe %>", ctx.clone()), "<TOM>");
+        assert_eq!(render("<%= user.missing | default(\"-\") %>", ctx), "-");
+
+        // floats are rendered in the same way as the compiled templates
+        for &f in &[3.0, 0.1, 1e21, -2.5e-8] {
+            let mut b = Buffer::new();
+            f.render(&mut b).unwrap();
+            assert_eq!(render("<%= f %>", json!({ "f": f })), b.as_str());
+        }
+    }
+
+    #[test]
+    fn control_flow() {
+        let ctx = json!({ "items": [1, 2, 3], "flag": false });
+        let src =
+            "<% for i in items { %><% if i == 2 { %>two<% } else { %><%= i %><% } %>\
+                   <% } %>";
+        assert_eq!(render(src, ctx.clone()), "1two3");
+
+        let src = "<% if flag %>yes<% else if items.is_empty() %>empty<% else %>\
+                   <% let n = items.len(); %><%= n %><% endif %>";
+        assert_eq!(render(src, ctx), "3");
+    }
+
+    #[test]
+    fn errors() {
+        assert!(Template::parse("<% if a { %>").is_err());
+        assert!(Template::parse("<% } %>").is_err());
+        assert!(Template::parse("<%= include!(\"a.stpl\") %>").is_err());
+        assert!(Template::parse("<%= a | unknown %>").is_err());
+
+        let template = Template::parse("<%= a.b %>").unwrap();
+        let err = template.render(&json!({})).unwrap_err();
+        assert!(err.to_string().contains("undefined variable `a`"));
+        assert!(template.render(&json!([1])).is_err());
+
+        // errors in the filter arguments point to the output tag
+        let template = Template::parse("a\n<%= a | truncate(n) %>").unwrap();
+        let err = template.render(&json!({ "a": "b" })).unwrap_err();
+        assert!(err.to_string().contains("line 2"), "{}", err);
+    }
+
+    #[test]
+    fn hot_reload() {
+        let path =
+            std::env::temp_dir().join(format!("hot-reload-{}.stpl", std::process::id()));
+        let path_str = path.to_str().unwrap();
+        let ctx = json!({ "name": "<Tom>" });
+
+        let render = |escape| {
+            let mut buf = Buffer::new();
+            __hot_reload(path_str, ("{%", "%}"), escape, &ctx, &mut buf)
+                .map(|r| r.map(|_| buf.into_string()).unwrap())
+        };
+
+        fs::write(&path, "Hello, {%= name %}").unwrap();
+        assert_eq!(render(true).as_deref(), Some("Hello, &lt;Tom&gt;"));
+        assert_eq!(render(false).as_deref(), Some("Hello, <Tom>"));
+
+        // modifications are reflected immediately
+        fs::write(&path, "Bye, {%= name | lower %}").unwrap();
+        assert_eq!(render(true).as_deref(), Some("Bye, &lt;tom&gt;"));
+
+        fs::write(&path, "{%= include!(\"a.stpl\") %}").unwrap();
+        assert_eq!(render(true), None);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
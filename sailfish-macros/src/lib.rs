@@ -16,3 +16,12 @@ pub fn derive_template(tokens: TokenStream) -> TokenStream {
     let output = sailfish_compiler::procmacro::derive_template(input);
     TokenStream::from(output)
 }
+
+/// Implement `Render` trait for the struct with a single field by delegating to
+/// that field
+#[proc_macro_derive(Render)]
+pub fn derive_render(tokens: TokenStream) -> TokenStream {
+    let input = proc_macro2::TokenStream::from(tokens);
+    let output = sailfish_compiler::procmacro::derive_render(input);
+    TokenStream::from(output)
+}
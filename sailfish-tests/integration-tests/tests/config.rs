@@ -1,4 +1,4 @@
-use sailfish_compiler::Config;
+use sailfish_compiler::{Config, GlobalValue};
 use std::path::Path;
 
 #[test]
@@ -7,7 +7,35 @@ fn read_config() {
     let config = Config::search_file_and_read(&*path).unwrap();
 
     assert_eq!(config.delimiter, '%');
+    assert_eq!(config.delimiters, None);
     assert_eq!(config.escape, true);
     assert_eq!(config.rm_whitespace, false);
-    assert_eq!(config.template_dirs.len(), 1);
+    // `template_dir` in the parent directory is also loaded
+    assert_eq!(config.template_dirs.len(), 3);
+    // globals are merged, and the deeper configuration file takes precedence
+    assert_eq!(
+        config.globals["site_name"],
+        GlobalValue::String("Tom & Jerry".to_owned())
+    );
+    assert_eq!(
+        config.globals["version"],
+        GlobalValue::String("2.0.0-dev".to_owned())
+    );
+    assert_eq!(config.globals["max_items"], GlobalValue::Integer(3));
+}
+
+#[test]
+fn template_dirs() {
+    let base = Path::new(env!("CARGO_MANIFEST_DIR"));
+    std::env::set_var("SAILFISH_SHARED_DIR", base.join("shared"));
+    let path = base.join("config").join("search_path");
+    let config = Config::search_file_and_read(&path).unwrap();
+
+    // directories in the deeper configuration file take precedence
+    let dirs = &config.template_dirs[config.template_dirs.len() - 2..];
+    assert_eq!(dirs[0], base.join("shared").join("partials"));
+    assert_eq!(dirs[1], path.join("templates"));
+
+    std::env::remove_var("SAILFISH_SHARED_DIR");
+    assert!(Config::search_file_and_read(&path).is_err());
 }
@@ -0,0 +1,41 @@
+#[macro_use]
+extern crate sailfish_macros;
+
+use sailfish::runtime::{Buffer, Render};
+
+#[derive(Render)]
+struct UserId(u64);
+
+#[derive(Render)]
+struct UserName {
+    name: String,
+}
+
+#[derive(Render)]
+struct Wrapper<'a, T>(&'a T);
+
+#[test]
+fn newtype() {
+    let mut b = Buffer::new();
+    UserId(42).render(&mut b).unwrap();
+    UserName {
+        name: String::from("<Taro>"),
+    }
+    .render_escaped(&mut b)
+    .unwrap();
+    Wrapper(&UserId(3)).render_escaped(&mut b).unwrap();
+    assert_eq!(b.as_str(), "42&lt;Taro&gt;3");
+}
+
+#[test]
+fn custom_escaper() {
+    let escaper = |feed: &str, buf: &mut Buffer| buf.push_str(&feed.replace('<', "\\<"));
+
+    let mut b = Buffer::new();
+    UserName {
+        name: String::from("<Taro>"),
+    }
+    .render_escaped_with(&mut b, &escaper)
+    .unwrap();
+    assert_eq!(b.as_str(), "\\<Taro>");
+}
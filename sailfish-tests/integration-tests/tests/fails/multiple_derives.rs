@@ -0,0 +1,25 @@
+use sailfish::TemplateOnce;
+use sailfish_macros::TemplateOnce;
+
+#[derive(TemplateOnce)]
+#[template(path = "unbalanced_brace.stpl")]
+struct UnbalancedBrace {
+    name: String,
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "hello.stpl")]
+struct Hello {
+    name: String,
+}
+
+fn main() {
+    println!(
+        "{}",
+        Hello {
+            name: "Hanako".to_owned()
+        }
+        .render_once()
+        .unwrap()
+    )
+}
@@ -2,8 +2,9 @@
 extern crate sailfish_macros;
 
 use integration_tests::assert_string_eq;
-use sailfish::runtime::RenderResult;
+use sailfish::runtime::{Attributes, Nonce, RenderResult};
 use sailfish::TemplateOnce;
+use std::collections::HashMap;
 use std::path::PathBuf;
 
 fn assert_render_result(name: &str, result: RenderResult) {
@@ -59,6 +60,94 @@ fn noescape() {
     );
 }
 
+#[derive(TemplateOnce)]
+#[template(path = "json_escape.stpl", escape = "json")]
+struct JsonEscape<'a> {
+    name: &'a str,
+    tags: [&'a str; 2],
+    count: u32,
+}
+
+#[test]
+fn json_escape() {
+    assert_render(
+        "json_escape",
+        JsonEscape {
+            name: "Alice \"<b>\"\\n",
+            tags: ["a\\b", "line1\nline2"],
+            count: 3,
+        },
+    );
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "mail.txt.stpl")]
+struct PlainTextMail<'a> {
+    name: &'a str,
+    item: &'a str,
+}
+
+#[test]
+fn escape_by_extension_txt() {
+    assert_render(
+        "mail",
+        PlainTextMail {
+            name: "Tom & Jerry",
+            item: "<Cheese>",
+        },
+    );
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "text_mode.stpl", text_mode = true)]
+struct TextMode<'a> {
+    options: &'a [(&'a str, Option<&'a str>)],
+}
+
+#[test]
+fn text_mode() {
+    assert_render(
+        "text_mode",
+        TextMode {
+            options: &[("host", Some("a&b")), ("port", None)],
+        },
+    );
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "feed.xml.stpl")]
+struct XmlFeed<'a> {
+    title: &'a str,
+    author: &'a str,
+}
+
+#[test]
+fn escape_by_extension_xml() {
+    assert_render(
+        "feed",
+        XmlFeed {
+            title: "Q&A: <tags>",
+            author: "O'Brien \"Bob\"",
+        },
+    );
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "escape_extra.stpl", escape_extra = "`=")]
+struct EscapeExtra<'a> {
+    value: &'a str,
+}
+
+#[test]
+fn escape_extra() {
+    assert_render(
+        "escape_extra",
+        EscapeExtra {
+            value: "`a=b` & <c>",
+        },
+    );
+}
+
 #[derive(TemplateOnce)]
 #[template(path = "json.stpl")]
 struct Json {
@@ -87,6 +176,136 @@ fn custom_delimiter() {
     assert_render("custom_delimiter", CustomDelimiter);
 }
 
+#[derive(TemplateOnce)]
+#[template(path = "custom_delimiters.stpl")]
+#[template(delimiters = ("{%", "%}"))]
+struct CustomDelimiters;
+
+#[test]
+fn custom_delimiters() {
+    assert_render("custom_delimiters", CustomDelimiters);
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "front_matter.stpl")]
+struct FrontMatter<'a> {
+    items: &'a [&'a str],
+}
+
+#[test]
+fn front_matter() {
+    assert_render(
+        "front_matter",
+        FrontMatter {
+            items: &["<b>bold</b>", "<i>italic</i>"],
+        },
+    );
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "globals.stpl")]
+struct Globals;
+
+#[test]
+fn test_globals() {
+    assert_render("globals", Globals);
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "env.stpl")]
+struct Env;
+
+#[test]
+fn test_env() {
+    assert_render("env", Env);
+}
+
+#[derive(serde::Serialize)]
+struct HotReloadItem {
+    name: &'static str,
+    price: u32,
+}
+
+// debug builds render the template with `sailfish-dyn`, which must produce the
+// same output as the compiled template for these field types
+#[derive(TemplateOnce, serde::Serialize)]
+#[template(path = "hot_reload.stpl", hot_reload = true)]
+struct HotReload {
+    title: &'static str,
+    items: Vec<HotReloadItem>,
+}
+
+#[test]
+fn test_hot_reload() {
+    assert_render(
+        "hot_reload",
+        HotReload {
+            title: "<menu>",
+            items: vec![
+                HotReloadItem {
+                    name: "tea",
+                    price: 3,
+                },
+                HotReloadItem {
+                    name: "cake",
+                    price: 5,
+                },
+            ],
+        },
+    );
+
+    // the limit is checked even if the template is reloaded
+    let mut buf = sailfish::runtime::Buffer::with_limit(8);
+    let template = HotReload {
+        title: "<menu>",
+        items: Vec::new(),
+    };
+    assert!(template.render_once_to_buffer(&mut buf).is_err());
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "static_page.stpl")]
+struct StaticPage;
+
+#[test]
+fn static_page() {
+    const RENDERED: &str = StaticPage::rendered();
+    assert_render_result("static_page", Ok(RENDERED.to_owned()));
+    assert_eq!(StaticPage.render_once_static(), RENDERED);
+    assert_render("static_page", StaticPage);
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "large_static.stpl")]
+struct LargeStatic<'a> {
+    items: &'a [&'a str],
+}
+
+#[test]
+fn large_static() {
+    let items = ["a", "<b>"];
+    assert_render("large_static", LargeStatic { items: &items });
+
+    // the large static texts are appended by reference
+    let mut buf = sailfish::runtime::ChunkedBuffer::with_chunk_size(64);
+    LargeStatic { items: &items }
+        .render_once_to_chunked(&mut buf)
+        .unwrap();
+    assert!(buf.chunks() > 1);
+    assert_render_result("large_static", Ok(buf.into_string()));
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "raw.stpl")]
+struct RawBlock<'a> {
+    name: &'a str,
+}
+
+#[test]
+fn test_raw() {
+    assert_render("raw", RawBlock { name: "Taro" });
+}
+
 #[derive(TemplateOnce)]
 #[template(path = "include.stpl")]
 struct Include<'a> {
@@ -103,6 +322,249 @@ fn test_include() {
     );
 }
 
+#[derive(TemplateOnce)]
+#[template(path = "include-args.stpl")]
+struct IncludeArgs {
+    items: Vec<(String, usize)>,
+}
+
+#[test]
+fn test_include_args() {
+    assert_render(
+        "include-args",
+        IncludeArgs {
+            items: vec![("foo".to_owned(), 1), ("<bar>".to_owned(), 2)],
+        },
+    );
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "macro.stpl")]
+struct Macro<'a> {
+    items: &'a [&'a str],
+    color: &'a str,
+}
+
+#[test]
+fn test_macro() {
+    assert_render(
+        "macro",
+        Macro {
+            items: &["foo", "bar"],
+            color: "red",
+        },
+    );
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "extends.stpl")]
+struct Extends<'a> {
+    name: &'a str,
+    s: &'a str,
+    year: u32,
+}
+
+#[test]
+fn test_extends() {
+    assert_render(
+        "extends",
+        Extends {
+            name: "foo",
+            s: "foo",
+            year: 2020,
+        },
+    );
+}
+
+// `year` is only used in the footer of the base layout, which is overridden
+#[derive(TemplateOnce)]
+#[template(path = "extends-nest.stpl", allow_unused_fields = true)]
+struct ExtendsNest<'a> {
+    strs: &'a [&'a str],
+    year: u32,
+}
+
+#[test]
+fn test_extends_nest() {
+    assert_render(
+        "extends-nest",
+        ExtendsNest {
+            strs: &["foo", "bar"],
+            year: 2020,
+        },
+    );
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "extends.stpl", check_html = true)]
+struct CheckedExtends<'a> {
+    name: &'a str,
+    s: &'a str,
+    year: u32,
+}
+
+#[test]
+fn test_check_html() {
+    assert_render(
+        "extends",
+        CheckedExtends {
+            name: "foo",
+            s: "foo",
+            year: 2020,
+        },
+    );
+}
+
+#[test]
+fn test_render_block() {
+    let ctx = || Extends {
+        name: "foo",
+        s: "foo",
+        year: 2020,
+    };
+    assert_eq!(ctx().render_block_once("title").unwrap(), "foo - Page");
+    assert_eq!(
+        ctx().render_block_once("content").unwrap(),
+        "\n<h1>FOO</h1>\nINCLUDED: foo\n"
+    );
+    assert_eq!(ctx().render_block_once("footer").unwrap(), "&copy; 2020");
+    assert!(ctx().render_block_once("main").is_err());
+
+    let ctx = ExtendsNest {
+        strs: &["foo", "bar"],
+        year: 2020,
+    };
+    assert_eq!(
+        ctx.render_block_once("main").unwrap(),
+        "\n\n<p>foo</p>\n\n<p>bar</p>\n\n"
+    );
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "minify.stpl", minify = true)]
+struct Minify<'a> {
+    class: &'a str,
+    items: &'a [&'a str],
+    code: &'a str,
+}
+
+#[test]
+fn test_minify() {
+    assert_render(
+        "minify",
+        Minify {
+            class: "dark",
+            items: &["foo", "bar"],
+            code: "fn main() {}",
+        },
+    );
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "csp_nonce.stpl", nonce = "nonce")]
+struct CspNonce<'a> {
+    nonce: Nonce,
+    src: &'a str,
+}
+
+#[test]
+fn test_csp_nonce() {
+    assert_render(
+        "csp_nonce",
+        CspNonce {
+            nonce: Nonce::new("Zm9vYmFy+/=="),
+            src: "app.js",
+        },
+    );
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "if_directive.stpl")]
+struct IfDirective {
+    users: Vec<(Option<&'static str>, u32)>,
+}
+
+#[test]
+fn test_if_directive() {
+    assert_render(
+        "if_directive",
+        IfDirective {
+            users: vec![(Some("<alice>"), 0), (None, 3), (None, 0)],
+        },
+    );
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "shared_include.stpl")]
+struct SharedInclude;
+
+#[test]
+fn test_shared_include() {
+    assert_render("shared_include", SharedInclude);
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "shared_templates::components/button.stpl")]
+struct CrateTemplate<'a> {
+    label: &'a str,
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "crate_include.stpl")]
+struct CrateInclude;
+
+#[test]
+fn test_crate_template() {
+    let s = CrateTemplate { label: "<OK>" }.render_once().unwrap();
+    assert_eq!(s, "<button class=\"btn\">&lt;OK&gt;</button>");
+    assert_render("crate_include", CrateInclude);
+}
+
+enum Item {
+    Text(&'static str),
+    Count(usize),
+    Empty,
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "match_directive.stpl")]
+struct MatchDirective {
+    items: Vec<Item>,
+}
+
+#[test]
+fn test_match_directive() {
+    assert_render(
+        "match_directive",
+        MatchDirective {
+            items: vec![
+                Item::Text("<a>"),
+                Item::Count(3),
+                Item::Count(1),
+                Item::Empty,
+            ],
+        },
+    );
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "include_static.stpl")]
+struct IncludeStatic;
+
+#[test]
+fn test_include_static() {
+    assert_render("include_static", IncludeStatic);
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "asset.stpl")]
+struct Asset;
+
+#[test]
+fn test_asset() {
+    assert_render("asset", Asset);
+}
+
 #[derive(TemplateOnce)]
 #[template(path = "big-table.stpl", rm_whitespace = true)]
 struct BigTable {
@@ -154,6 +616,135 @@ fn test_teams() {
     assert_render("teams", teams);
 }
 
+#[derive(TemplateOnce)]
+#[template(path = "trim.stpl")]
+struct Trim<'a> {
+    name: &'a str,
+    items: Vec<&'a str>,
+}
+
+#[test]
+fn test_trim() {
+    assert_render(
+        "trim",
+        Trim {
+            name: "Alice",
+            items: vec!["apple", "banana"],
+        },
+    );
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "loop_meta.stpl")]
+struct LoopMeta<'a> {
+    items: &'a [&'a str],
+}
+
+#[test]
+fn test_loop_meta() {
+    assert_render(
+        "loop_meta",
+        LoopMeta {
+            items: &["apple", "banana", "cherry"],
+        },
+    );
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "for_else.stpl")]
+struct ForElse<'a> {
+    lists: &'a [&'a [&'a str]],
+}
+
+#[test]
+fn test_for_else() {
+    assert_render(
+        "for_else",
+        ForElse {
+            lists: &[&["a", "b"], &[]],
+        },
+    );
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "capture.stpl")]
+struct Capture<'a> {
+    links: &'a [(&'a str, &'a str)],
+}
+
+#[test]
+fn test_capture() {
+    assert_render(
+        "capture",
+        Capture {
+            links: &[("Home", "/"), ("A & B", "/a?b=1&c=2")],
+        },
+    );
+}
+
+struct ClassItem {
+    name: &'static str,
+    active: bool,
+    error: bool,
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "classes.stpl")]
+struct Classes {
+    items: Vec<ClassItem>,
+}
+
+#[test]
+fn test_classes() {
+    assert_render(
+        "classes",
+        Classes {
+            items: vec![
+                ClassItem {
+                    name: "a",
+                    active: true,
+                    error: false,
+                },
+                ClassItem {
+                    name: "b",
+                    active: false,
+                    error: true,
+                },
+                ClassItem {
+                    name: "c",
+                    active: false,
+                    error: false,
+                },
+            ],
+        },
+    );
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "attributes.stpl")]
+struct ForwardAttributes<'a> {
+    attrs: Attributes<'a>,
+    extra: HashMap<&'a str, &'a str>,
+    body: &'a str,
+}
+
+#[test]
+fn test_attributes() {
+    let mut extra = HashMap::new();
+    extra.insert("name", "q");
+    assert_render(
+        "attributes",
+        ForwardAttributes {
+            attrs: Attributes::new()
+                .set("id", "main")
+                .set("data-tooltip", "\"hi\"")
+                .flag("hidden"),
+            extra,
+            body: "content",
+        },
+    );
+}
+
 #[derive(TemplateOnce)]
 #[template(path = "rm_whitespace.stpl")]
 #[template(rm_whitespace = true)]
@@ -204,11 +795,136 @@ fn test_formatting() {
 #[template(path = "filter.stpl")]
 struct Filter<'a> {
     message: &'a str,
+    missing: Option<&'a str>,
 }
 
 #[test]
 fn test_filter() {
-    assert_render("filter", Filter { message: "hello" });
+    assert_render(
+        "filter",
+        Filter {
+            message: "hello",
+            missing: None,
+        },
+    );
+}
+
+mod custom_filters {
+    pub fn shout<T: std::fmt::Display>(value: &T) -> String {
+        format!("{}!", value.to_string().to_uppercase())
+    }
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "custom_filter.stpl")]
+struct CustomFilter<'a> {
+    message: &'a str,
+}
+
+#[test]
+fn test_custom_filter() {
+    assert_render("custom_filter", CustomFilter { message: "<hello>" });
+}
+
+struct Point {
+    x: i32,
+    y: i32,
+}
+
+impl std::fmt::Display for Point {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "<{}, {}>", self.x, self.y)
+    }
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "display.stpl")]
+struct DisplayFallback {
+    point: Point,
+}
+
+#[test]
+fn test_display_fallback() {
+    assert_render(
+        "display",
+        DisplayFallback {
+            point: Point { x: 1, y: -2 },
+        },
+    );
+}
+
+struct Invalid;
+
+impl sailfish::runtime::Render for Invalid {
+    fn render(
+        &self,
+        _: &mut sailfish::runtime::Buffer,
+    ) -> Result<(), sailfish::runtime::RenderError> {
+        Err(sailfish::runtime::RenderError::new("invalid value"))
+    }
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "error_context.stpl")]
+struct ErrorContext<'a> {
+    title: &'a str,
+    value: Invalid,
+}
+
+#[test]
+fn test_error_context() {
+    let err = ErrorContext {
+        title: "title",
+        value: Invalid,
+    }
+    .render_once()
+    .unwrap_err();
+
+    let msg = err.to_string();
+    assert_eq!(
+        msg,
+        "failed to render `value` at templates/error_context.stpl:2:8: invalid value"
+    );
+    assert_eq!(
+        std::error::Error::source(&err).unwrap().to_string(),
+        "invalid value"
+    );
+}
+
+#[test]
+fn test_render_to_writer() {
+    let mut out = Vec::new();
+    Noescape { raw: "<b>raw</b>" }
+        .render_once_to_writer(&mut out)
+        .unwrap();
+    assert_eq!(out, b"raw: <b>raw</b>");
+
+    let mut buf = sailfish::runtime::Buffer::from("head ");
+    Noescape { raw: "tail" }
+        .render_once_to_writer(&mut buf)
+        .unwrap();
+    assert_eq!(buf.as_str(), "head raw: tail");
+}
+
+#[test]
+fn test_render_with_limit() {
+    let table: Vec<Vec<usize>> = (0..10).map(|_| (0..10).collect()).collect();
+    let expected = BigTable {
+        table: table.clone(),
+    }
+    .render_once()
+    .unwrap();
+
+    let result = BigTable {
+        table: table.clone(),
+    }
+    .render_once_with_limit(expected.len());
+    assert_eq!(result.unwrap(), expected);
+
+    let err = BigTable { table }
+        .render_once_with_limit(expected.len() / 2)
+        .unwrap_err();
+    assert!(err.is_output_too_large());
 }
 
 #[cfg(unix)]
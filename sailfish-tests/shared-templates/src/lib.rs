@@ -0,0 +1,2 @@
+//! Templates which are referenced from other crates with
+//! `shared_templates::` prefix
@@ -6,8 +6,9 @@
 //! If you want to use sailfish templates, import `sailfish-macros` crate and use
 //! derive macro `#[derive(TemplateOnce)]` or `#[derive(Template)]`.
 //!
-//! In most cases you don't need to care about the `runtime` module in this crate, but
-//! if you want to render custom data inside templates, you must implement
+//! In most cases you don't need to care about the `runtime` module in this crate.
+//! Types which implement `std::fmt::Display` can be rendered inside templates as-is,
+//! but if you want to customize how the data is rendered, you must implement
 //! `runtime::Render` trait for that type.
 //!
 //! ```ignore
@@ -35,11 +36,27 @@
     html_logo_url = "https://raw.githubusercontent.com/Kogia-sima/sailfish/master/resources/icon.png"
 )]
 #![cfg_attr(sailfish_nightly, feature(core_intrinsics))]
+#![cfg_attr(not(feature = "std"), no_std)]
+#![cfg_attr(feature = "safe", forbid(unsafe_code))]
+
+#[doc(hidden)]
+pub extern crate alloc;
+#[cfg(all(test, not(feature = "std")))]
+#[macro_use]
+extern crate std;
 
 pub mod runtime;
 
+use alloc::string::String;
+#[cfg(feature = "std")]
+use std::io;
+
 pub use runtime::{RenderError, RenderResult};
 
+#[cfg(feature = "std")]
+use runtime::BufferPool;
+use runtime::{Buffer, ChunkedBuffer};
+
 /// Template that can be rendered with consuming itself.
 pub trait TemplateOnce: Sized {
     /// Render the template and return the rendering result as `RenderResult`
@@ -54,6 +71,101 @@ pub trait TemplateOnce: Sized {
         Ok(buf)
     }
 
+    /// Render the template and return the rendering result as `RenderResult`
+    ///
+    /// This method returns `Err` if the rendered contents exceed `max_bytes` bytes.
+    /// Use this method to protect the server from the templates which may
+    /// generate huge pages (e.g. templates with a buggy loop).
+    #[inline]
+    fn render_once_with_limit(self, max_bytes: usize) -> runtime::RenderResult {
+        let mut buf = Buffer::with_limit(max_bytes);
+        self.render_once_to_buffer(&mut buf)?;
+        buf.check_limit()?;
+        Ok(buf.into_string())
+    }
+
+    /// Render the template and write the result into `writer`
+    ///
+    /// The template is rendered into the buffer taken from the thread-local
+    /// [`BufferPool`](runtime/struct.BufferPool.html), and the rendered contents
+    /// are written with a single `write_all` call. Thus the writer does not need to
+    /// be buffered, and no allocation happens in the steady state.
+    ///
+    /// This method returns `Err` if writing to `writer` failed.
+    #[cfg(feature = "std")]
+    #[inline]
+    fn render_once_to_writer<W: io::Write + ?Sized>(
+        self,
+        writer: &mut W,
+    ) -> Result<(), RenderError> {
+        BufferPool::with_local(|pool| {
+            let mut buf = pool.get();
+            self.render_once_to_buffer(&mut buf)?;
+            writer.write_all(buf.as_bytes())?;
+            Ok(())
+        })
+    }
+
+    /// Render the template and append the result to `buf`.
+    ///
+    /// This is useful to reuse the allocated buffer (e.g. with
+    /// [`BufferPool`](runtime/struct.BufferPool.html)).
+    ///
+    /// This method never returns `Err`, unless you explicitly return RenderError
+    /// inside templates
+    #[inline]
+    #[allow(deprecated)]
+    fn render_once_to_buffer(self, buf: &mut Buffer) -> Result<(), RenderError> {
+        let mut s = String::new();
+        self.render_once_to_string(&mut s)?;
+        buf.push_str(&s);
+        Ok(())
+    }
+
+    /// Render the template and append the result to the chunked buffer
+    ///
+    /// Templates derived with `#[derive(TemplateOnce)]` append the large static
+    /// texts by reference, so that they are never copied until the contents are
+    /// flattened. See [`ChunkedBuffer`](runtime/struct.ChunkedBuffer.html).
+    ///
+    /// This method never returns `Err`, unless you explicitly return RenderError
+    /// inside templates
+    #[inline]
+    fn render_once_to_chunked(self, buf: &mut ChunkedBuffer) -> Result<(), RenderError> {
+        let mut b = Buffer::new();
+        self.render_once_to_buffer(&mut b)?;
+        buf.push_str(b.as_str());
+        Ok(())
+    }
+
+    /// Render only the block named `name` and return the rendering result
+    ///
+    /// This is useful to render a fragment of the page (e.g. for partial updates
+    /// with htmx) without splitting the template into several files.
+    ///
+    /// This method returns `Err` if the template does not contain the block.
+    #[inline]
+    fn render_block_once(self, name: &str) -> runtime::RenderResult {
+        let mut buf = Buffer::new();
+        self.render_block_once_to_buffer(name, &mut buf)?;
+        Ok(buf.into_string())
+    }
+
+    /// Render only the block named `name` and append the result to `buf`
+    ///
+    /// This method returns `Err` if the template does not contain the block.
+    fn render_block_once_to_buffer(
+        self,
+        name: &str,
+        buf: &mut Buffer,
+    ) -> Result<(), RenderError> {
+        let _ = buf;
+        Err(RenderError::new(&alloc::format!(
+            "block `{}` is not defined",
+            name
+        )))
+    }
+
     /// Render the template and append the result to `buf`.
     ///
     /// This method never returns `Err`, unless you explicitly return RenderError
@@ -0,0 +1,187 @@
+use alloc::borrow::Cow;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::fmt;
+use core::iter::FromIterator;
+
+use super::escape::{self, Escaper};
+use super::{Buffer, Render, RenderError};
+
+/// Set of HTML attributes which can be forwarded to an element
+///
+/// Each attribute is rendered with a leading space in the insertion order, so
+/// the attributes can be placed right after the tag name or other attributes.
+/// Both names and values are escaped if it is rendered inside `<%= %>` tag.
+///
+/// Attributes can also be collected from an iterator of name-value pairs (e.g.
+/// `HashMap<&str, T>`). Note that the iteration order of `HashMap` is not
+/// specified.
+///
+/// # Examples
+///
+/// ```
+/// use sailfish::runtime::{Attributes, Buffer, Render};
+///
+/// let attrs = Attributes::new()
+///     .set("id", "main")
+///     .set("data-count", 3)
+///     .flag("hidden");
+///
+/// let mut b = Buffer::new();
+/// b.push_str("<div");
+/// attrs.render_escaped(&mut b).unwrap();
+/// b.push_str(">");
+/// assert_eq!(b.as_str(), r#"<div id="main" data-count="3" hidden>"#);
+/// ```
+#[derive(Default)]
+pub struct Attributes<'a> {
+    entries: Vec<(Cow<'a, str>, Option<BoxedValue<'a>>)>,
+}
+
+type BoxedValue<'a> = Box<dyn Render + 'a>;
+
+impl<'a> Attributes<'a> {
+    /// Create an empty set of attributes
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the attribute with the value
+    #[inline]
+    pub fn set<N, V>(mut self, name: N, value: V) -> Self
+    where
+        N: Into<Cow<'a, str>>,
+        V: Render + 'a,
+    {
+        self.entries.push((name.into(), Some(Box::new(value))));
+        self
+    }
+
+    /// Add the attribute without value (e.g. `disabled`)
+    #[inline]
+    pub fn flag<N: Into<Cow<'a, str>>>(mut self, name: N) -> Self {
+        self.entries.push((name.into(), None));
+        self
+    }
+
+    /// Number of attributes
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn write<F, G>(&self, b: &mut Buffer, name: F, value: G) -> Result<(), RenderError>
+    where
+        F: Fn(&str, &mut Buffer),
+        G: Fn(&dyn Render, &mut Buffer) -> Result<(), RenderError>,
+    {
+        for (n, v) in self.entries.iter() {
+            b.push(' ');
+            name(n, b);
+            if let Some(v) = v {
+                b.push_str("=\"");
+                value(&**v, b)?;
+                b.push('"');
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Render for Attributes<'a> {
+    #[inline]
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        self.write(b, |n, b| b.push_str(n), |v, b| v.render(b))
+    }
+
+    #[inline]
+    fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        self.write(b, escape::escape_to_buf, |v, b| v.render_escaped(b))
+    }
+
+    #[inline]
+    fn render_escaped_with(
+        &self,
+        b: &mut Buffer,
+        escaper: &dyn Escaper,
+    ) -> Result<(), RenderError> {
+        self.write(
+            b,
+            |n, b| escaper.escape(n, b),
+            |v, b| v.render_escaped_with(b, escaper),
+        )
+    }
+}
+
+impl<'a, N, V> Extend<(N, V)> for Attributes<'a>
+where
+    N: Into<Cow<'a, str>>,
+    V: Render + 'a,
+{
+    fn extend<I: IntoIterator<Item = (N, V)>>(&mut self, iter: I) {
+        for (name, value) in iter {
+            self.entries.push((name.into(), Some(Box::new(value))));
+        }
+    }
+}
+
+impl<'a, N, V> FromIterator<(N, V)> for Attributes<'a>
+where
+    N: Into<Cow<'a, str>>,
+    V: Render + 'a,
+{
+    fn from_iter<I: IntoIterator<Item = (N, V)>>(iter: I) -> Self {
+        let mut attrs = Attributes::new();
+        attrs.extend(iter);
+        attrs
+    }
+}
+
+impl<'a> fmt::Debug for Attributes<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list()
+            .entries(self.entries.iter().map(|(n, _)| n))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::collections::BTreeMap;
+    use alloc::string::String;
+
+    #[test]
+    fn escape() {
+        let attrs = Attributes::new()
+            .set("title", "a&b")
+            .flag("x\"y")
+            .set(String::from("data-n"), 1.5f32);
+        let mut b = Buffer::new();
+        attrs.render_escaped(&mut b).unwrap();
+        attrs.render(&mut b).unwrap();
+        assert_eq!(
+            b.as_str(),
+            " title=\"a&amp;b\" x&quot;y data-n=\"1.5\" title=\"a&b\" x\"y data-n=\"1.5\""
+        );
+    }
+
+    #[test]
+    fn from_map() {
+        let mut map = BTreeMap::new();
+        map.insert("id", "main");
+        map.insert("class", "<c>");
+        let attrs = map.into_iter().collect::<Attributes>();
+        assert_eq!(attrs.len(), 2);
+
+        let mut b = Buffer::new();
+        attrs.render_escaped(&mut b).unwrap();
+        assert_eq!(b.as_str(), " class=\"&lt;c&gt;\" id=\"main\"");
+    }
+}
@@ -1,55 +1,286 @@
-use std::alloc::{alloc, dealloc, handle_alloc_error, realloc, Layout};
-use std::fmt;
-use std::mem::{align_of, ManuallyDrop};
-use std::ops::{Add, AddAssign};
-use std::ptr;
+#[cfg(not(feature = "safe"))]
+use alloc::alloc::{alloc, dealloc, handle_alloc_error, realloc, GlobalAlloc, Layout};
+use alloc::borrow::Cow;
+#[cfg(not(feature = "safe"))]
+use alloc::borrow::ToOwned;
+#[cfg(not(feature = "safe"))]
+use alloc::boxed::Box;
+#[cfg(not(feature = "safe"))]
+use alloc::rc::Rc;
+use alloc::string::String;
+#[cfg(not(feature = "safe"))]
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+#[cfg(not(feature = "safe"))]
+use core::cell::UnsafeCell;
+use core::cmp::Ordering;
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use core::iter::FromIterator;
+#[cfg(not(feature = "safe"))]
+use core::mem::{ManuallyDrop, MaybeUninit};
+use core::ops::{Add, AddAssign, Deref};
+#[cfg(not(feature = "safe"))]
+use core::ptr;
+#[cfg(not(feature = "safe"))]
+use core::str::Utf8Error;
+#[cfg(feature = "std")]
+use std::io;
+
+#[cfg(all(feature = "buffer-stats", not(feature = "safe")))]
+use super::stats::{self, BufferStats};
+#[cfg(not(feature = "safe"))]
+use super::RenderError;
+
+#[cfg(feature = "safe")]
+mod safe;
+#[cfg(feature = "safe")]
+pub use self::safe::Buffer;
+
+/// Contents up to this size are stored inside `Buffer` without heap allocation
+#[cfg(not(feature = "safe"))]
+const INLINE_CAPACITY: usize = 24;
+
+/// How `Buffer` computes the new capacity when the contents do not fit
+///
+/// # Examples
+///
+/// ```
+/// use sailfish::runtime::{Buffer, GrowthStrategy};
+///
+/// let mut buf = Buffer::new();
+/// buf.set_growth_strategy(GrowthStrategy::SizeClass);
+/// buf.push_str(&"x".repeat(100));
+/// assert_eq!(buf.capacity(), 112);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GrowthStrategy {
+    /// Double the capacity (default)
+    Double,
+    /// Multiply the capacity by `percent / 100`
+    ///
+    /// Values not larger than 100 grow the buffer only to the required size, so
+    /// that pushing small contents repeatedly may reallocate on every push.
+    Percent(u16),
+    /// Round the required capacity up to the allocator size class
+    ///
+    /// There are 4 size classes for each power of two, which matches the size
+    /// classes of jemalloc and mimalloc, so that the capacity never exceeds
+    /// the required size by more than 25%.
+    SizeClass,
+}
+
+impl GrowthStrategy {
+    /// Returns the new capacity which can hold at least `size` more bytes
+    #[inline]
+    pub(crate) fn next_capacity(self, len: usize, capacity: usize, size: usize) -> usize {
+        let required = len.saturating_add(size);
+        match self {
+            GrowthStrategy::Double => {
+                core::cmp::max(capacity.saturating_mul(2), capacity.saturating_add(size))
+            }
+            GrowthStrategy::Percent(percent) => {
+                core::cmp::max(capacity.saturating_mul(percent as usize) / 100, required)
+            }
+            GrowthStrategy::SizeClass => {
+                if required <= 16 {
+                    return 16;
+                }
+                let step = required.next_power_of_two() / 8;
+                required.saturating_add(step - 1) / step * step
+            }
+        }
+    }
+}
+
+impl Default for GrowthStrategy {
+    #[inline]
+    fn default() -> Self {
+        GrowthStrategy::Double
+    }
+}
 
 /// Buffer for rendered contents
 ///
 /// This struct is quite simular to `String`, but some methods are
 /// re-implemented for faster buffering.
+///
+/// Short contents (up to 24 bytes) are stored inline, and the heap memory is
+/// allocated only when the contents grow beyond it.
+///
+/// The memory is allocated from the global allocator by default. Use
+/// [`new_in`](#method.new_in) to allocate the contents with another allocator
+/// (e.g. arena allocator for each request).
+#[cfg(not(feature = "safe"))]
 pub struct Buffer {
+    /// null while the contents are stored in `inline`
     data: *mut u8,
     len: usize,
     capacity: usize,
+    limit: usize,
+    limit_exceeded: bool,
+    allocator: Option<Rc<dyn GlobalAlloc>>,
+    growth: GrowthStrategy,
+    #[cfg(feature = "buffer-stats")]
+    stats: BufferStats,
+    #[cfg(feature = "std")]
+    pending: PendingBytes,
+    inline: UnsafeCell<[MaybeUninit<u8>; INLINE_CAPACITY]>,
 }
 
+#[cfg(not(feature = "safe"))]
 impl Buffer {
     #[inline]
     pub const fn new() -> Buffer {
         Self {
-            data: align_of::<u8>() as *mut u8, // dangling pointer
+            data: ptr::null_mut(),
             len: 0,
-            capacity: 0,
+            capacity: INLINE_CAPACITY,
+            limit: core::usize::MAX,
+            limit_exceeded: false,
+            allocator: None,
+            growth: GrowthStrategy::Double,
+            #[cfg(feature = "buffer-stats")]
+            stats: BufferStats {
+                reallocs: 0,
+                peak_len: 0,
+            },
+            #[cfg(feature = "std")]
+            pending: PendingBytes::new(),
+            inline: UnsafeCell::new([MaybeUninit::uninit(); INLINE_CAPACITY]),
         }
     }
 
     #[cfg_attr(feature = "perf-inline", inline)]
     pub fn with_capacity(n: usize) -> Buffer {
-        unsafe {
-            if unlikely!(n == 0) {
-                Self::new()
-            } else {
-                Self {
-                    data: safe_alloc(n),
-                    len: 0,
-                    capacity: n,
-                }
+        let mut buf = Self::new();
+        if n > INLINE_CAPACITY {
+            unsafe {
+                buf.data = safe_alloc(None, n);
+            }
+            buf.capacity = n;
+        }
+        buf
+    }
+
+    /// Create an empty buffer whose contents are allocated with `allocator`
+    ///
+    /// Since `String` always uses the global allocator, `into_string` copies the
+    /// contents of buffers created by this method.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sailfish::runtime::Buffer;
+    /// use std::alloc::System;
+    /// use std::rc::Rc;
+    ///
+    /// let mut buf = Buffer::new_in(Rc::new(System));
+    /// buf.push_str("allocated by System");
+    /// assert_eq!(buf.as_str(), "allocated by System");
+    /// ```
+    #[inline]
+    pub fn new_in(allocator: Rc<dyn GlobalAlloc>) -> Buffer {
+        let mut buf = Buffer::new();
+        buf.allocator = Some(allocator);
+        buf
+    }
+
+    /// Create an empty buffer with the specified capacity whose contents are
+    /// allocated with `allocator`
+    pub fn with_capacity_in(n: usize, allocator: Rc<dyn GlobalAlloc>) -> Buffer {
+        let mut buf = Buffer::new_in(allocator);
+        if n > INLINE_CAPACITY {
+            unsafe {
+                buf.data = safe_alloc(buf.allocator.as_deref(), n);
             }
+            buf.capacity = n;
+        }
+        buf
+    }
+
+    /// Create an empty buffer whose contents cannot grow beyond `max_bytes`
+    ///
+    /// Once the limit is exceeded, `push_str` stops appending the contents and
+    /// [`check_limit`](#method.check_limit) returns an error. `reserve` beyond the
+    /// limit also marks the buffer as exceeded, but still allocates the requested
+    /// capacity since the caller may write into it through raw pointers. Templates check the
+    /// limit after rendering each value, so that a runaway loop inside templates
+    /// fails with an error instead of consuming all memory.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sailfish::runtime::Buffer;
+    ///
+    /// let mut buf = Buffer::with_limit(8);
+    /// buf.push_str("apple");
+    /// assert!(buf.check_limit().is_ok());
+    /// buf.push_str("pie!");
+    /// assert!(buf.check_limit().unwrap_err().is_output_too_large());
+    /// assert_eq!(buf.as_str(), "apple");
+    /// ```
+    #[inline]
+    pub fn with_limit(max_bytes: usize) -> Buffer {
+        let mut buf = Buffer::new();
+        buf.set_limit(max_bytes);
+        buf
+    }
+
+    /// Maximum size of the contents in bytes
+    ///
+    /// Returns `usize::MAX` if the size is not limited.
+    #[inline]
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Set the maximum size of the contents in bytes
+    #[inline]
+    pub fn set_limit(&mut self, max_bytes: usize) {
+        self.limit = max_bytes;
+        if self.is_inline() {
+            // inline storage must not accept the contents beyond the limit
+            let capacity = core::cmp::min(INLINE_CAPACITY, max_bytes);
+            self.capacity = core::cmp::max(capacity, self.len);
+        }
+    }
+
+    /// Returns an error if the contents exceeded the limit
+    #[inline]
+    pub fn check_limit(&self) -> Result<(), RenderError> {
+        if unlikely!(self.limit_exceeded || self.len > self.limit) {
+            Err(RenderError::output_too_large(self.limit))
+        } else {
+            Ok(())
         }
     }
 
     #[inline]
     pub fn as_str(&self) -> &str {
         unsafe {
-            let bytes = std::slice::from_raw_parts(self.data, self.len);
-            std::str::from_utf8_unchecked(bytes)
+            let bytes = core::slice::from_raw_parts(self.as_mut_ptr(), self.len);
+            core::str::from_utf8_unchecked(bytes)
         }
     }
 
+    /// Returns a byte slice of the contents
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.as_mut_ptr(), self.len) }
+    }
+
+    /// Returns a raw pointer to the contents
+    ///
+    /// The pointer is invalidated when the buffer is moved, since short contents
+    /// are stored inside the buffer itself.
     #[inline]
     pub fn as_mut_ptr(&self) -> *mut u8 {
-        self.data
+        if self.is_inline() {
+            self.inline.get() as *mut u8
+        } else {
+            self.data
+        }
     }
 
     #[inline]
@@ -65,6 +296,7 @@ impl Buffer {
     #[inline]
     #[doc(hidden)]
     pub unsafe fn _set_len(&mut self, new_len: usize) {
+        self.record_peak();
         self.len = new_len;
     }
 
@@ -79,6 +311,60 @@ impl Buffer {
         self.len += additional;
     }
 
+    /// Increase the length of buffer by `additional` bytes, checking that it does
+    /// not exceed the capacity
+    ///
+    /// This is intended to be used after writing the contents into
+    /// [`spare_capacity_mut`](#method.spare_capacity_mut).
+    ///
+    /// # Safety
+    ///
+    /// The first `additional` bytes of the spare capacity must be initialized with
+    /// valid UTF-8 contents.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `additional` is greater than `capacity() - len()`.
+    #[inline]
+    pub unsafe fn advance_checked(&mut self, additional: usize) {
+        assert!(
+            additional <= self.capacity - self.len,
+            "additional exceeds the spare capacity"
+        );
+        self.len += additional;
+    }
+
+    /// Returns the spare capacity of the buffer as a slice of `MaybeUninit<u8>`
+    ///
+    /// Call [`reserve`](#method.reserve) before this method to make sure that
+    /// the slice is large enough, and then call
+    /// [`advance_checked`](#method.advance_checked) after writing the contents.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sailfish::runtime::Buffer;
+    /// use std::mem::MaybeUninit;
+    ///
+    /// let mut buf = Buffer::new();
+    /// buf.reserve(3);
+    /// let spare = buf.spare_capacity_mut();
+    /// for (dst, &src) in spare.iter_mut().zip(b"abc") {
+    ///     *dst = MaybeUninit::new(src);
+    /// }
+    /// unsafe { buf.advance_checked(3) };
+    /// assert_eq!(buf.as_str(), "abc");
+    /// ```
+    #[inline]
+    pub fn spare_capacity_mut(&mut self) -> &mut [MaybeUninit<u8>] {
+        unsafe {
+            core::slice::from_raw_parts_mut(
+                self.as_mut_ptr().add(self.len) as *mut MaybeUninit<u8>,
+                self.capacity - self.len,
+            )
+        }
+    }
+
     #[inline]
     pub fn is_empty(&self) -> bool {
         self.len == 0
@@ -93,59 +379,373 @@ impl Buffer {
         debug_assert!(self.len + size <= self.capacity);
     }
 
+    /// Reserves the capacity for exactly `size` more bytes
+    ///
+    /// Unlike [`reserve`](#method.reserve), the growth strategy is not applied.
+    /// This is useful when the final size of the contents is known.
+    #[inline]
+    pub fn reserve_exact(&mut self, size: usize) {
+        if size <= self.capacity.wrapping_sub(self.len) {
+            return;
+        }
+        self.grow(size, self.len + size);
+        debug_assert!(self.len + size <= self.capacity);
+    }
+
+    /// Strategy to compute the new capacity when the contents do not fit
+    #[inline]
+    pub fn growth_strategy(&self) -> GrowthStrategy {
+        self.growth
+    }
+
+    /// Set the strategy to compute the new capacity when the contents do not fit
+    #[inline]
+    pub fn set_growth_strategy(&mut self, strategy: GrowthStrategy) {
+        self.growth = strategy;
+    }
+
+    /// Truncates the buffer, removing all contents
+    ///
+    /// The allocated capacity is kept so that the buffer can be reused for
+    /// the next rendering without reallocation. Call
+    /// [`shrink_to_fit`](#method.shrink_to_fit) or [`shrink_to`](#method.shrink_to)
+    /// afterwards to release the memory.
     #[inline]
     pub fn clear(&mut self) {
+        self.record_peak();
         self.len = 0;
+        self.limit_exceeded = false;
+        #[cfg(feature = "std")]
+        {
+            self.pending = PendingBytes::new();
+        }
+    }
+
+    /// Shrinks the capacity of the buffer to match its length
+    #[inline]
+    pub fn shrink_to_fit(&mut self) {
+        self.shrink_to(0);
+    }
+
+    /// Shrinks the capacity of the buffer with a lower bound
+    ///
+    /// The capacity will remain at least as large as both the length and the
+    /// supplied value. If the current capacity is less than the lower bound,
+    /// this is a no-op.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sailfish::runtime::Buffer;
+    ///
+    /// let mut buf = Buffer::with_capacity(100);
+    /// buf.push_str("foo");
+    /// buf.shrink_to(50);
+    /// assert_eq!(buf.capacity(), 50);
+    ///
+    /// // short contents are moved into the inline storage
+    /// buf.shrink_to(0);
+    /// assert_eq!(buf.capacity(), 24);
+    /// ```
+    pub fn shrink_to(&mut self, min_capacity: usize) {
+        let new_capacity = core::cmp::max(self.len, min_capacity);
+        if self.is_inline() || new_capacity >= self.capacity {
+            return;
+        }
+
+        unsafe {
+            let old_layout = Layout::from_size_align_unchecked(self.capacity, 1);
+            let allocator = self.allocator.as_deref();
+            self.data = if new_capacity <= INLINE_CAPACITY {
+                let inline = self.inline.get() as *mut u8;
+                ptr::copy_nonoverlapping(self.data, inline, self.len);
+                safe_dealloc(allocator, self.data, self.capacity);
+                ptr::null_mut()
+            } else {
+                let data = match allocator {
+                    Some(a) => a.realloc(self.data, old_layout, new_capacity),
+                    None => realloc(self.data, old_layout, new_capacity),
+                };
+                if data.is_null() {
+                    handle_alloc_error(Layout::from_size_align_unchecked(
+                        new_capacity,
+                        1,
+                    ));
+                }
+                data
+            };
+        }
+
+        if self.is_inline() {
+            self.capacity = INLINE_CAPACITY;
+            self.set_limit(self.limit);
+        } else {
+            self.capacity = new_capacity;
+        }
+        self.record_realloc();
+        debug_assert!(self.len <= self.capacity);
+    }
+
+    /// Shortens the buffer to the specified length
+    ///
+    /// If `new_len` is greater than the current length, this has no effect.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `new_len` does not lie on a `char` boundary.
+    #[inline]
+    pub fn truncate(&mut self, new_len: usize) {
+        if new_len < self.len {
+            assert!(
+                self.as_str().is_char_boundary(new_len),
+                "new_len does not lie on a char boundary"
+            );
+            self.record_peak();
+            self.len = new_len;
+        }
+    }
+
+    /// Removes the last character from the buffer and returns it
+    ///
+    /// Returns `None` if the buffer is empty.
+    #[inline]
+    pub fn pop(&mut self) -> Option<char> {
+        let ch = self.as_str().chars().next_back()?;
+        self.record_peak();
+        self.len -= ch.len_utf8();
+        Some(ch)
+    }
+
+    /// Inserts a string slice into the buffer at a byte position
+    ///
+    /// This is an `O(n)` operation as it requires copying every byte after
+    /// `idx`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `idx` is larger than the buffer's length, or if it does not
+    /// lie on a `char` boundary.
+    pub fn insert_str(&mut self, idx: usize, data: &str) {
+        assert!(
+            self.as_str().is_char_boundary(idx),
+            "idx does not lie on a char boundary"
+        );
+
+        let size = data.len();
+        self.reserve(size);
+        unsafe {
+            let p = self.as_mut_ptr().add(idx);
+            ptr::copy(p, p.add(size), self.len - idx);
+            ptr::copy_nonoverlapping(data.as_ptr(), p, size);
+            self.len += size;
+        }
+        debug_assert!(self.len <= self.capacity);
     }
 
     /// Converts a `Buffer` into a `String`.
     ///
-    /// This consumes the `Buffer`, so we do not need to copy its contents.
+    /// This consumes the `Buffer`, so the heap allocation is reused as is. The
+    /// contents are copied into a new `String` only if they are stored inline, or
+    /// if the buffer was created with [`new_in`](#method.new_in).
     #[inline]
     pub fn into_string(self) -> String {
+        if self.is_inline() || self.allocator.is_some() {
+            return self.as_str().to_owned();
+        }
+
         debug_assert!(self.len <= self.capacity);
+        #[cfg(feature = "buffer-stats")]
+        stats::record(&self.stats());
+
         let buf = ManuallyDrop::new(self);
         unsafe { String::from_raw_parts(buf.data, buf.len, buf.capacity) }
     }
 
+    /// Converts a `Buffer` into a byte vector.
+    ///
+    /// The contents are copied in the same cases as
+    /// [`into_string`](#method.into_string).
+    #[inline]
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.into_string().into_bytes()
+    }
+
     #[inline]
     pub fn push_str(&mut self, data: &str) {
         let size = data.len();
         if unlikely!(size > self.capacity.wrapping_sub(self.len)) {
+            if unlikely!(size > self.limit.saturating_sub(self.len)) {
+                self.limit_exceeded = true;
+                return;
+            }
             self.reserve_internal(size);
         }
         unsafe {
-            let p = self.data.add(self.len);
-            std::ptr::copy_nonoverlapping(data.as_ptr(), p, size);
+            let p = self.as_mut_ptr().add(self.len);
+            core::ptr::copy_nonoverlapping(data.as_ptr(), p, size);
             self.len += size;
         }
         debug_assert!(self.len <= self.capacity);
     }
 
+    /// Append the contents of another buffer
+    ///
+    /// If this buffer is empty and both buffers use the same allocator, the heap
+    /// allocation of `other` is reused instead of copying its contents. Otherwise
+    /// the contents are copied with a single reservation.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sailfish::runtime::Buffer;
+    ///
+    /// let mut child = Buffer::new();
+    /// child.push_str("<li>item</li>");
+    ///
+    /// let mut parent = Buffer::new();
+    /// parent.append(child);
+    /// parent.append(Buffer::from("<li>item2</li>"));
+    /// assert_eq!(parent.as_str(), "<li>item</li><li>item2</li>");
+    /// ```
+    #[inline]
+    pub fn append(&mut self, mut other: Buffer) {
+        let same_allocator = match (&self.allocator, &other.allocator) {
+            (None, None) => true,
+            (Some(a), Some(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        };
+        if self.len == 0
+            && !other.is_inline()
+            && (self.is_inline() || other.capacity >= self.capacity)
+            && same_allocator
+        {
+            other.record_peak();
+            core::mem::swap(&mut self.data, &mut other.data);
+            core::mem::swap(&mut self.len, &mut other.len);
+            core::mem::swap(&mut self.capacity, &mut other.capacity);
+        } else {
+            self.push_str(other.as_str());
+        }
+    }
+
     #[inline]
     pub fn push(&mut self, data: char) {
         let mut buf = [0u8; 4];
         self.push_str(data.encode_utf8(&mut buf));
     }
 
+    /// Append the bytes to the buffer
+    ///
+    /// `Buffer` always holds valid UTF-8 contents, so the bytes are validated
+    /// before being appended. If `data` is not valid UTF-8, an error is returned
+    /// and the buffer is left unchanged.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sailfish::runtime::Buffer;
+    ///
+    /// let mut buf = Buffer::new();
+    /// buf.write_bytes(b"<svg/>").unwrap();
+    /// assert!(buf.write_bytes(&[0xff, 0xfe]).is_err());
+    /// assert_eq!(buf.as_bytes(), b"<svg/>");
+    /// ```
+    #[inline]
+    pub fn write_bytes(&mut self, data: &[u8]) -> Result<(), Utf8Error> {
+        self.push_str(core::str::from_utf8(data)?);
+        Ok(())
+    }
+
+    /// Counters of how this buffer grew
+    ///
+    /// The counters are added to
+    /// [`global_buffer_stats`](fn.global_buffer_stats.html) when the buffer is
+    /// dropped or converted into `String`.
+    #[cfg(feature = "buffer-stats")]
+    #[inline]
+    pub fn stats(&self) -> BufferStats {
+        BufferStats {
+            reallocs: self.stats.reallocs,
+            peak_len: core::cmp::max(self.stats.peak_len, self.len),
+        }
+    }
+
+    /// Returns `true` if the contents are stored inline
+    #[inline]
+    fn is_inline(&self) -> bool {
+        self.data.is_null()
+    }
+
+    /// Returns `true` if the buffer owns the heap memory
+    #[inline]
+    pub(crate) fn spilled(&self) -> bool {
+        !self.is_inline() && self.capacity != 0
+    }
+
+    #[inline(always)]
+    fn record_peak(&mut self) {
+        #[cfg(feature = "buffer-stats")]
+        {
+            self.stats.peak_len = core::cmp::max(self.stats.peak_len, self.len);
+        }
+    }
+
+    #[inline(always)]
+    fn record_realloc(&mut self) {
+        #[cfg(feature = "buffer-stats")]
+        {
+            self.stats.reallocs += 1;
+        }
+    }
+
     #[cfg_attr(feature = "perf-inline", inline)]
     #[cold]
     fn reserve_internal(&mut self, size: usize) {
+        let new_capacity = self.growth.next_capacity(self.len, self.capacity, size);
+        self.grow(size, new_capacity);
+    }
+
+    #[cold]
+    fn grow(&mut self, size: usize, mut new_capacity: usize) {
         unsafe {
-            let new_capacity = std::cmp::max(self.capacity * 2, self.capacity + size);
+            if size <= self.limit.saturating_sub(self.len) {
+                new_capacity = core::cmp::min(new_capacity, self.limit);
+            } else {
+                // contents written through raw pointers must be accepted, so
+                // allocate only the required size.
+                self.limit_exceeded = true;
+                new_capacity = self.len + size;
+            }
             debug_assert!(new_capacity > self.capacity);
-            self.data = safe_realloc(self.data, self.capacity, new_capacity, size);
+            if self.is_inline() {
+                let data = safe_alloc(self.allocator.as_deref(), new_capacity);
+                ptr::copy_nonoverlapping(self.as_mut_ptr(), data, self.len);
+                self.data = data;
+            } else {
+                self.data = safe_realloc(
+                    self.allocator.as_deref(),
+                    self.data,
+                    self.capacity,
+                    new_capacity,
+                    size,
+                );
+            }
             self.capacity = new_capacity;
         }
+        self.record_realloc();
         debug_assert!(!self.data.is_null());
         debug_assert!(self.len <= self.capacity);
     }
 }
 
-unsafe fn safe_alloc(capacity: usize) -> *mut u8 {
-    assert!(capacity <= std::usize::MAX / 2, "capacity is too large");
+#[cfg(not(feature = "safe"))]
+unsafe fn safe_alloc(allocator: Option<&dyn GlobalAlloc>, capacity: usize) -> *mut u8 {
+    assert!(capacity <= core::usize::MAX / 2, "capacity is too large");
     let layout = Layout::from_size_align_unchecked(capacity, 1);
-    let data = alloc(layout);
+    let data = match allocator {
+        Some(a) => a.alloc(layout),
+        None => alloc(layout),
+    };
     if data.is_null() {
         handle_alloc_error(layout);
     }
@@ -153,21 +753,32 @@ unsafe fn safe_alloc(capacity: usize) -> *mut u8 {
     data
 }
 
+#[cfg(not(feature = "safe"))]
 #[cold]
 unsafe fn safe_realloc(
+    allocator: Option<&dyn GlobalAlloc>,
     ptr: *mut u8,
     capacity: usize,
     new_capacity: usize,
     size: usize,
 ) -> *mut u8 {
-    assert!(size <= std::usize::MAX / 2, "capacity is too large");
-    assert!(new_capacity <= std::usize::MAX / 2, "capacity is too large");
+    assert!(size <= core::usize::MAX / 2, "capacity is too large");
+    assert!(
+        new_capacity <= core::usize::MAX / 2,
+        "capacity is too large"
+    );
     let data = if unlikely!(capacity == 0) {
         let new_layout = Layout::from_size_align_unchecked(new_capacity, 1);
-        alloc(new_layout)
+        match allocator {
+            Some(a) => a.alloc(new_layout),
+            None => alloc(new_layout),
+        }
     } else {
         let old_layout = Layout::from_size_align_unchecked(capacity, 1);
-        realloc(ptr, old_layout, new_capacity)
+        match allocator {
+            Some(a) => a.realloc(ptr, old_layout, new_capacity),
+            None => realloc(ptr, old_layout, new_capacity),
+        }
     };
 
     if data.is_null() {
@@ -177,22 +788,36 @@ unsafe fn safe_realloc(
     data
 }
 
+#[cfg(not(feature = "safe"))]
+unsafe fn safe_dealloc(
+    allocator: Option<&dyn GlobalAlloc>,
+    ptr: *mut u8,
+    capacity: usize,
+) {
+    let layout = Layout::from_size_align_unchecked(capacity, 1);
+    match allocator {
+        Some(a) => a.dealloc(ptr, layout),
+        None => dealloc(ptr, layout),
+    }
+}
+
+#[cfg(not(feature = "safe"))]
 impl Clone for Buffer {
     fn clone(&self) -> Self {
+        let mut buf = Self::new();
+        buf.limit_exceeded = self.limit_exceeded;
+        buf.allocator = self.allocator.clone();
+        buf.growth = self.growth;
         unsafe {
-            if self.capacity == 0 {
-                Self::new()
-            } else {
-                let buf = Self {
-                    data: safe_alloc(self.len),
-                    len: self.len,
-                    capacity: self.len,
-                };
-
-                ptr::copy_nonoverlapping(self.data, buf.data, self.len);
-                buf
+            if self.len > INLINE_CAPACITY {
+                buf.data = safe_alloc(buf.allocator.as_deref(), self.len);
+                buf.capacity = self.len;
             }
+            ptr::copy_nonoverlapping(self.as_mut_ptr(), buf.as_mut_ptr(), self.len);
+            buf.len = self.len;
         }
+        buf.set_limit(self.limit);
+        buf
     }
 }
 
@@ -202,12 +827,98 @@ impl fmt::Debug for Buffer {
     }
 }
 
+impl Deref for Buffer {
+    type Target = str;
+
+    #[inline]
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl AsRef<str> for Buffer {
+    #[inline]
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl AsRef<[u8]> for Buffer {
+    #[inline]
+    fn as_ref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl Borrow<str> for Buffer {
+    #[inline]
+    fn borrow(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl PartialEq for Buffer {
+    #[inline]
+    fn eq(&self, other: &Buffer) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for Buffer {}
+
+macro_rules! impl_eq {
+    ($($type:ty),*) => {
+        $(
+            impl PartialEq<$type> for Buffer {
+                #[inline]
+                fn eq(&self, other: &$type) -> bool {
+                    self.as_str() == &other[..]
+                }
+            }
+
+            impl PartialEq<Buffer> for $type {
+                #[inline]
+                fn eq(&self, other: &Buffer) -> bool {
+                    &self[..] == other.as_str()
+                }
+            }
+        )*
+    };
+}
+
+impl_eq!(str, &str, String);
+
+impl PartialOrd for Buffer {
+    #[inline]
+    fn partial_cmp(&self, other: &Buffer) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Buffer {
+    #[inline]
+    fn cmp(&self, other: &Buffer) -> Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+/// Hashes the contents in the same way as `str`
+impl Hash for Buffer {
+    #[inline]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state)
+    }
+}
+
+#[cfg(not(feature = "safe"))]
 impl Drop for Buffer {
     fn drop(&mut self) {
-        if self.capacity != 0 {
+        #[cfg(feature = "buffer-stats")]
+        stats::record(&self.stats());
+
+        if self.spilled() {
             unsafe {
-                let layout = Layout::from_size_align_unchecked(self.capacity, 1);
-                dealloc(self.data, layout);
+                safe_dealloc(self.allocator.as_deref(), self.data, self.capacity);
             }
         }
     }
@@ -221,6 +932,93 @@ impl fmt::Write for Buffer {
     }
 }
 
+/// Leading bytes of the UTF-8 sequence which was split across `write` calls
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug)]
+struct PendingBytes {
+    bytes: [u8; 4],
+    len: usize,
+}
+
+#[cfg(feature = "std")]
+impl PendingBytes {
+    const fn new() -> Self {
+        Self {
+            bytes: [0; 4],
+            len: 0,
+        }
+    }
+
+    /// Length of the sequence which starts with the pending bytes
+    fn sequence_len(&self) -> usize {
+        match self.bytes[0] {
+            0xf0..=0xff => 4,
+            0xe0..=0xef => 3,
+            _ => 2,
+        }
+    }
+}
+
+/// `io::Write` implementation which appends UTF-8 bytes to the buffer
+///
+/// Since `Buffer` always holds valid UTF-8 contents, the written bytes are
+/// validated. If `buf` ends with an incomplete UTF-8 sequence, the bytes are
+/// kept until the rest of the sequence is written in the next call, so that the
+/// contents can be written in arbitrary chunks (e.g. with `io::copy`). Invalid
+/// UTF-8 sequences cause an error of `io::ErrorKind::InvalidData`, as well as
+/// `flush` while the sequence is incomplete.
+#[cfg(feature = "std")]
+impl io::Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let invalid = |e| io::Error::new(io::ErrorKind::InvalidData, e);
+        let mut rest = buf;
+
+        if self.pending.len > 0 {
+            let mut pending = self.pending;
+            let n = (pending.sequence_len() - pending.len).min(rest.len());
+            pending.bytes[pending.len..pending.len + n].copy_from_slice(&rest[..n]);
+            pending.len += n;
+            rest = &rest[n..];
+
+            match core::str::from_utf8(&pending.bytes[..pending.len]) {
+                Ok(s) => {
+                    self.push_str(s);
+                    self.pending = PendingBytes::new();
+                }
+                Err(e) if e.error_len().is_none() => {
+                    self.pending = pending;
+                    return Ok(buf.len());
+                }
+                Err(e) => return Err(invalid(e)),
+            }
+        }
+
+        match core::str::from_utf8(rest) {
+            Ok(s) => self.push_str(s),
+            Err(e) if e.error_len().is_none() => {
+                let (valid, tail) = rest.split_at(e.valid_up_to());
+                self.write_bytes(valid).map_err(invalid)?;
+                self.pending.bytes[..tail.len()].copy_from_slice(tail);
+                self.pending.len = tail.len();
+            }
+            Err(e) => return Err(invalid(e)),
+        }
+        Ok(buf.len())
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        if self.pending.len > 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "incomplete UTF-8 sequence",
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "safe"))]
 impl From<String> for Buffer {
     /// Shrink the data and pass raw pointer directory to buffer
     ///
@@ -233,6 +1031,15 @@ impl From<String> for Buffer {
             data: data.as_mut_ptr(),
             len: data.len(),
             capacity: data.len(),
+            limit: core::usize::MAX,
+            limit_exceeded: false,
+            allocator: None,
+            growth: GrowthStrategy::Double,
+            #[cfg(feature = "buffer-stats")]
+            stats: BufferStats::default(),
+            #[cfg(feature = "std")]
+            pending: PendingBytes::new(),
+            inline: UnsafeCell::new([MaybeUninit::uninit(); INLINE_CAPACITY]),
         }
     }
 }
@@ -241,14 +1048,68 @@ impl From<&str> for Buffer {
     #[inline]
     fn from(other: &str) -> Buffer {
         let mut buf = Buffer::with_capacity(other.len());
-        unsafe {
-            ptr::copy_nonoverlapping(other.as_ptr(), buf.as_mut_ptr(), other.len());
-            buf.advance(other.len());
-        }
+        buf.push_str(other);
         buf
     }
 }
 
+impl From<Cow<'_, str>> for Buffer {
+    /// Owned strings are moved into the buffer without copying
+    #[inline]
+    fn from(other: Cow<'_, str>) -> Buffer {
+        match other {
+            Cow::Borrowed(s) => Buffer::from(s),
+            Cow::Owned(s) => Buffer::from(s),
+        }
+    }
+}
+
+impl Buffer {
+    /// Converts a `Buffer` into a `Cow<str>`.
+    ///
+    /// The contents are not copied unless they are stored inline, and an empty
+    /// buffer is converted into `Cow::Borrowed("")` without allocation.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::borrow::Cow;
+    /// use sailfish::runtime::Buffer;
+    ///
+    /// let buf = Buffer::from(Cow::Borrowed("<p>hello</p>"));
+    /// let cow: Cow<'static, str> = buf.into_cow();
+    /// assert_eq!(cow, "<p>hello</p>");
+    /// ```
+    #[inline]
+    pub fn into_cow(self) -> Cow<'static, str> {
+        if self.is_empty() {
+            Cow::Borrowed("")
+        } else {
+            Cow::Owned(self.into_string())
+        }
+    }
+}
+
+impl Buffer {
+    /// Append the static string
+    ///
+    /// This is the same as [`push_str`](#method.push_str), and exists so that the
+    /// generated code can append the static texts in the same way as
+    /// [`ChunkedBuffer::push_static`](struct.ChunkedBuffer.html#method.push_static),
+    /// which appends large texts by reference.
+    #[inline]
+    pub fn push_static(&mut self, data: &'static str) {
+        self.push_str(data);
+    }
+}
+
+impl From<Buffer> for Cow<'_, str> {
+    #[inline]
+    fn from(other: Buffer) -> Self {
+        other.into_cow()
+    }
+}
+
 impl Add<&str> for Buffer {
     type Output = Buffer;
 
@@ -266,6 +1127,53 @@ impl AddAssign<&str> for Buffer {
     }
 }
 
+impl<'a> Extend<&'a str> for Buffer {
+    #[inline]
+    fn extend<I: IntoIterator<Item = &'a str>>(&mut self, iter: I) {
+        iter.into_iter().for_each(|s| self.push_str(s));
+    }
+}
+
+impl Extend<String> for Buffer {
+    #[inline]
+    fn extend<I: IntoIterator<Item = String>>(&mut self, iter: I) {
+        iter.into_iter().for_each(|s| self.push_str(&s));
+    }
+}
+
+impl Extend<char> for Buffer {
+    #[inline]
+    fn extend<I: IntoIterator<Item = char>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0);
+        iter.for_each(|c| self.push(c));
+    }
+}
+
+impl<'a> Extend<&'a char> for Buffer {
+    #[inline]
+    fn extend<I: IntoIterator<Item = &'a char>>(&mut self, iter: I) {
+        self.extend(iter.into_iter().copied());
+    }
+}
+
+macro_rules! impl_from_iter {
+    ($($type:ty),*) => {
+        $(
+            impl<'a> FromIterator<$type> for Buffer {
+                #[inline]
+                fn from_iter<I: IntoIterator<Item = $type>>(iter: I) -> Buffer {
+                    let mut buf = Buffer::new();
+                    buf.extend(iter);
+                    buf
+                }
+            }
+        )*
+    };
+}
+
+impl_from_iter!(&'a str, String, char, &'a char);
+
 impl Default for Buffer {
     #[inline]
     fn default() -> Buffer {
@@ -276,20 +1184,28 @@ impl Default for Buffer {
 #[cfg(test)]
 mod tests {
     use super::Buffer;
+    use alloc::string::String;
 
     #[test]
+    #[cfg(not(feature = "safe"))]
     fn test1() {
         let mut buffer = Buffer::new();
         assert_eq!(buffer.len(), 0);
-        assert_eq!(buffer.capacity(), 0);
+        assert_eq!(buffer.capacity(), 24);
 
         buffer.push_str("apple");
         assert_eq!(buffer.len(), 5);
-        assert_eq!(buffer.capacity(), 5);
+        assert_eq!(buffer.capacity(), 24);
 
         buffer.push_str("pie");
         assert_eq!(buffer.len(), 8);
-        assert_eq!(buffer.capacity(), 10);
+        assert_eq!(buffer.capacity(), 24);
+
+        // spill to the heap
+        buffer.push_str(" is a delicious dessert");
+        assert_eq!(buffer.len(), 31);
+        assert_eq!(buffer.capacity(), 48);
+        assert_eq!(buffer.as_str(), "applepie is a delicious dessert");
     }
 
     #[test]
@@ -334,6 +1250,56 @@ mod tests {
         assert_eq!(s, "apple");
     }
 
+    #[test]
+    fn growth_strategy() {
+        use super::GrowthStrategy;
+
+        let mut buf = Buffer::with_capacity(100);
+        buf.set_growth_strategy(GrowthStrategy::Percent(150));
+        buf.push_str(&"x".repeat(101));
+        assert_eq!(buf.capacity(), 150);
+
+        buf.reserve_exact(60);
+        assert_eq!(buf.capacity(), 161);
+        let mut cloned = buf.clone();
+        assert_eq!(cloned.growth_strategy(), GrowthStrategy::Percent(150));
+
+        cloned.set_growth_strategy(GrowthStrategy::SizeClass);
+        cloned.reserve(100);
+        assert_eq!(cloned.capacity(), 224);
+        cloned.reserve(100);
+        assert_eq!(cloned.capacity(), 224);
+
+        for &(required, class) in
+            &[(1, 16), (17, 20), (33, 40), (1000, 1024), (1025, 1280)]
+        {
+            assert_eq!(
+                GrowthStrategy::SizeClass.next_capacity(0, 0, required),
+                class
+            );
+        }
+    }
+
+    #[test]
+    fn cow_conversion() {
+        use alloc::borrow::Cow;
+
+        let s = String::from("a string which does not fit inline");
+        let ptr = s.as_ptr();
+        let buf = Buffer::from(Cow::Owned(s));
+        assert_eq!(buf.as_str().as_ptr(), ptr);
+
+        let cow = buf.into_cow();
+        assert!(matches!(cow, Cow::Owned(_)));
+        assert_eq!(cow.as_ptr(), ptr);
+
+        let buf = Buffer::from(Cow::Borrowed("abc"));
+        assert_eq!(Cow::from(buf), "abc");
+
+        let cow = Buffer::new().into_cow();
+        assert!(matches!(cow, Cow::Borrowed("")));
+    }
+
     #[test]
     fn clone() {
         use std::fmt::Write;
@@ -356,4 +1322,335 @@ mod tests {
         assert_eq!(s1.as_str(), "foobar");
         assert_eq!(s2.as_str(), "foobaz");
     }
+
+    #[test]
+    fn comparison() {
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::{Hash, Hasher};
+
+        let buf = Buffer::from("apple");
+        assert_eq!(buf, "apple");
+        assert_eq!(buf, *"apple");
+        assert_eq!(buf, String::from("apple"));
+        assert_eq!("apple", buf);
+        assert_eq!(String::from("apple"), buf);
+        assert_ne!(buf, "pie");
+
+        let pie = Buffer::from("pie");
+        assert_eq!(buf, Buffer::from("apple"));
+        assert!(buf < pie);
+        assert!(Buffer::new() < buf);
+
+        let hash = |v: &dyn Fn(&mut DefaultHasher)| {
+            let mut hasher = DefaultHasher::new();
+            v(&mut hasher);
+            hasher.finish()
+        };
+        assert_eq!(hash(&|h| buf.hash(h)), hash(&|h| "apple".hash(h)));
+    }
+
+    #[test]
+    #[allow(clippy::mutable_key_type)]
+    fn str_conversion() {
+        use std::collections::HashSet;
+
+        fn takes_str(s: &str) -> usize {
+            s.len()
+        }
+
+        let buf = Buffer::from("apple pie");
+        assert_eq!(takes_str(&buf), 9);
+        assert!(buf.starts_with("apple"));
+        assert_eq!(buf.split(' ').count(), 2);
+        assert_eq!(AsRef::<str>::as_ref(&buf), "apple pie");
+        assert_eq!(AsRef::<[u8]>::as_ref(&buf), b"apple pie");
+
+        let mut set = HashSet::new();
+        set.insert(buf);
+        assert!(set.contains("apple pie"));
+    }
+
+    #[test]
+    fn iterator() {
+        let mut buf: Buffer = vec!["a", "b", "c"].into_iter().collect();
+        buf.extend(vec![String::from("d"), String::from("e")]);
+        buf.extend("漢字".chars());
+        buf.extend(['!', '?'].iter());
+        assert_eq!(buf.as_str(), "abcde漢字!?");
+
+        let buf: Buffer = "apple".chars().rev().collect();
+        assert_eq!(buf.as_str(), "elppa");
+
+        let buf: Buffer = std::iter::empty::<String>().collect();
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn bytes() {
+        let mut buf = Buffer::new();
+        buf.write_bytes(b"abc").unwrap();
+        buf.write_bytes("漢字".as_bytes()).unwrap();
+        assert!(buf.write_bytes(b"d\xe6\xbc").is_err());
+        assert_eq!(buf.as_bytes(), "abc漢字".as_bytes());
+        assert_eq!(buf.into_bytes(), "abc漢字".as_bytes());
+    }
+
+    #[test]
+    #[cfg(not(feature = "safe"))]
+    fn shrink() {
+        let mut buf = Buffer::with_capacity(64);
+        buf.push_str("apple");
+        buf.clear();
+        assert_eq!(buf.capacity(), 64);
+
+        buf.push_str("pie");
+        buf.shrink_to(100);
+        assert_eq!(buf.capacity(), 64);
+        buf.shrink_to(32);
+        assert_eq!(buf.capacity(), 32);
+
+        // move back to the inline storage
+        buf.shrink_to_fit();
+        assert_eq!(buf.capacity(), 24);
+        assert_eq!(buf.as_str(), "pie");
+
+        buf.clear();
+        buf.shrink_to_fit();
+        assert_eq!(buf.capacity(), 24);
+        buf.push_str("apple pie");
+        assert_eq!(buf.as_str(), "apple pie");
+
+        let mut buf = Buffer::with_limit(8);
+        assert_eq!(buf.capacity(), 8);
+        buf.push_str(&"x".repeat(40));
+        assert!(buf.check_limit().is_err());
+        buf.set_limit(usize::MAX);
+        assert_eq!(buf.capacity(), 24);
+    }
+
+    #[test]
+    fn limit() {
+        let mut buf = Buffer::with_limit(10);
+        assert_eq!(buf.limit(), 10);
+        buf.push_str("apple");
+        buf.push_str("pie");
+        assert!(buf.check_limit().is_ok());
+        assert!(buf.capacity() <= 10);
+
+        // exceeding contents are discarded
+        buf.push_str("!!!");
+        assert!(buf.check_limit().is_err());
+        assert_eq!(buf.as_str(), "applepie");
+        assert!(buf.clone().check_limit().is_err());
+
+        // raw writes after `reserve` are always accepted
+        buf.reserve(5);
+        assert!(buf.capacity() >= 13);
+
+        buf.clear();
+        assert!(buf.check_limit().is_ok());
+
+        let mut buf = Buffer::with_limit(10);
+        buf.reserve(10);
+        assert!(buf.check_limit().is_ok());
+        buf.reserve(11);
+        assert!(buf.check_limit().is_err());
+
+        let mut buf = Buffer::from("apple pie");
+        buf.set_limit(5);
+        assert!(buf.check_limit().is_err());
+        assert_eq!(Buffer::new().limit(), usize::MAX);
+    }
+
+    #[test]
+    fn append() {
+        let mut child = Buffer::with_capacity(64);
+        child.push_str("child");
+        let ptr = child.as_ptr();
+
+        let mut parent = Buffer::new();
+        parent.append(child);
+        assert_eq!(parent.as_ptr(), ptr);
+        assert_eq!(parent.capacity(), 64);
+
+        parent.append(Buffer::from(", sibling"));
+        parent.append(Buffer::new());
+        assert_eq!(parent.as_str(), "child, sibling");
+
+        let mut parent = Buffer::with_limit(8);
+        parent.append(Buffer::from("too large contents"));
+        assert!(parent.check_limit().is_err());
+    }
+
+    #[test]
+    #[cfg(not(feature = "safe"))]
+    fn allocator() {
+        use std::alloc::{GlobalAlloc, Layout, System};
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        #[derive(Default)]
+        struct Counter {
+            allocated: Cell<usize>,
+        }
+
+        unsafe impl GlobalAlloc for Counter {
+            unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+                self.allocated.set(self.allocated.get() + layout.size());
+                System.alloc(layout)
+            }
+
+            unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+                self.allocated.set(self.allocated.get() - layout.size());
+                System.dealloc(ptr, layout)
+            }
+        }
+
+        let counter = Rc::new(Counter::default());
+        let mut buf = Buffer::new_in(counter.clone());
+        buf.push_str("apple pie");
+        assert_eq!(counter.allocated.get(), 0);
+
+        let contents = "apple pie ".repeat(4);
+        buf.push_str(&contents[9..]);
+        assert_eq!(counter.allocated.get(), buf.capacity());
+
+        let buf2 = buf.clone();
+        assert_eq!(counter.allocated.get(), buf.capacity() + buf2.capacity());
+        drop(buf2);
+
+        buf.shrink_to_fit();
+        assert_eq!(counter.allocated.get(), 40);
+        assert_eq!(buf.clone().into_string(), contents);
+
+        let mut global = Buffer::new();
+        global.append(buf);
+        assert_eq!(global.as_str(), contents);
+        assert_eq!(counter.allocated.get(), 0);
+
+        let buf = Buffer::with_capacity_in(32, counter.clone());
+        assert_eq!(counter.allocated.get(), 32);
+        drop(buf);
+        assert_eq!(counter.allocated.get(), 0);
+    }
+
+    #[test]
+    #[cfg(not(feature = "safe"))]
+    fn spare_capacity() {
+        use std::mem::MaybeUninit;
+
+        let mut buf = Buffer::new();
+        assert_eq!(buf.spare_capacity_mut().len(), 24);
+
+        buf.push_str("a");
+        buf.reserve(8);
+        let capacity = buf.capacity();
+        let spare = buf.spare_capacity_mut();
+        assert_eq!(spare.len(), capacity - 1);
+        spare[0] = MaybeUninit::new(b'b');
+        spare[1] = MaybeUninit::new(b'c');
+        unsafe { buf.advance_checked(2) };
+        assert_eq!(buf.as_str(), "abc");
+    }
+
+    #[test]
+    #[cfg(not(feature = "safe"))]
+    #[should_panic]
+    fn advance_checked_overflow() {
+        let mut buf = Buffer::with_capacity(32);
+        unsafe { buf.advance_checked(33) };
+    }
+
+    #[test]
+    #[cfg(feature = "buffer-stats")]
+    fn stats() {
+        let mut buf = Buffer::with_capacity(32);
+        buf.push_str(&"apple".repeat(10));
+        buf.push_str(&"pie".repeat(20));
+        buf.clear();
+        buf.push_str("x");
+
+        let stats = buf.stats();
+        assert_eq!(stats.reallocs, 2);
+        assert_eq!(stats.peak_len, 110);
+
+        let cloned = buf.clone();
+        assert_eq!(cloned.stats().reallocs, 0);
+        assert_eq!(cloned.stats().peak_len, 1);
+    }
+
+    #[test]
+    fn truncate_and_pop() {
+        let mut buf = Buffer::from("a, b, 漢, ");
+        buf.truncate(buf.len() - 2);
+        assert_eq!(buf.as_str(), "a, b, 漢");
+        buf.truncate(100);
+        assert_eq!(buf.as_str(), "a, b, 漢");
+
+        assert_eq!(buf.pop(), Some('漢'));
+        assert_eq!(buf.pop(), Some(' '));
+        assert_eq!(buf.as_str(), "a, b,");
+
+        buf.clear();
+        assert_eq!(buf.pop(), None);
+        buf.truncate(0);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    #[should_panic]
+    fn truncate_char_boundary() {
+        let mut buf = Buffer::from("漢字");
+        buf.truncate(1);
+    }
+
+    #[test]
+    fn insert_str() {
+        let mut buf = Buffer::new();
+        buf.insert_str(0, "<body></body>");
+        buf.insert_str(6, "漢字");
+        buf.insert_str(0, "<!DOCTYPE html>");
+        let len = buf.len();
+        buf.insert_str(len, "\n");
+        assert_eq!(buf.as_str(), "<!DOCTYPE html><body>漢字</body>\n");
+        buf.insert_str(24, "");
+        assert_eq!(buf.as_str(), "<!DOCTYPE html><body>漢字</body>\n");
+    }
+
+    #[test]
+    #[should_panic]
+    fn insert_str_char_boundary() {
+        let mut buf = Buffer::from("漢字");
+        buf.insert_str(2, "a");
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn io_write() {
+        use std::io::Write;
+
+        let mut buf = Buffer::new();
+        write!(buf, "{}-漢", 1).unwrap();
+        // the character split across the calls
+        assert_eq!(buf.write(&[0xe5, 0xad, 0x97, 0xe5]).unwrap(), 4);
+        assert_eq!(buf.as_str(), "1-漢字");
+        assert!(buf.flush().is_err());
+        assert_eq!(buf.write(&[0xad]).unwrap(), 1);
+        assert_eq!(buf.write(&[0x97, b'!']).unwrap(), 2);
+        assert!(buf.flush().is_ok());
+        assert_eq!(buf.as_str(), "1-漢字字!");
+
+        assert_eq!(
+            buf.write(&[0xe5, b'a']).unwrap_err().kind(),
+            std::io::ErrorKind::InvalidData
+        );
+        assert!(buf.write_all(&[b'a', 0xff]).is_err());
+
+        // `io::copy` with the chunks which split the characters
+        let text = "日本語のテキスト".repeat(100);
+        let mut reader = std::io::BufReader::with_capacity(7, text.as_bytes());
+        let mut buf = Buffer::new();
+        std::io::copy(&mut reader, &mut buf).unwrap();
+        assert_eq!(buf.as_str(), text);
+    }
 }
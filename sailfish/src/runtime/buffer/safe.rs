@@ -0,0 +1,391 @@
+//! `Buffer` implementation without unsafe code
+//!
+//! This implementation is used instead of the default one when the `safe`
+//! feature is enabled. It stores the contents in `String`, so that the
+//! memory safety does not depend on the correctness of sailfish itself.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::str::Utf8Error;
+
+#[cfg(feature = "buffer-stats")]
+use crate::runtime::stats::{self, BufferStats};
+use crate::runtime::{GrowthStrategy, RenderError};
+
+#[cfg(feature = "std")]
+use super::PendingBytes;
+
+/// Buffer for rendered contents
+///
+/// This struct is quite simular to `String`, but some methods are
+/// re-implemented for faster buffering.
+///
+/// The `safe` feature is enabled, so the contents are stored in `String` and
+/// the methods which expose raw pointers or uninitialized memory are not
+/// available.
+pub struct Buffer {
+    inner: String,
+    limit: usize,
+    limit_exceeded: bool,
+    growth: GrowthStrategy,
+    #[cfg(feature = "buffer-stats")]
+    stats: BufferStats,
+    #[cfg(feature = "std")]
+    pub(super) pending: PendingBytes,
+}
+
+impl Buffer {
+    #[inline]
+    pub const fn new() -> Buffer {
+        Self {
+            inner: String::new(),
+            limit: core::usize::MAX,
+            limit_exceeded: false,
+            growth: GrowthStrategy::Double,
+            #[cfg(feature = "std")]
+            pending: PendingBytes::new(),
+            #[cfg(feature = "buffer-stats")]
+            stats: BufferStats {
+                reallocs: 0,
+                peak_len: 0,
+            },
+        }
+    }
+
+    #[inline]
+    pub fn with_capacity(n: usize) -> Buffer {
+        Self {
+            inner: String::with_capacity(n),
+            limit: core::usize::MAX,
+            limit_exceeded: false,
+            growth: GrowthStrategy::Double,
+            #[cfg(feature = "std")]
+            pending: PendingBytes::new(),
+            #[cfg(feature = "buffer-stats")]
+            stats: BufferStats::default(),
+        }
+    }
+
+    /// Create an empty buffer whose contents cannot grow beyond `max_bytes`
+    ///
+    /// Once the limit is exceeded, `push_str` stops appending the contents and
+    /// [`check_limit`](#method.check_limit) returns an error.
+    #[inline]
+    pub fn with_limit(max_bytes: usize) -> Buffer {
+        let mut buf = Buffer::new();
+        buf.limit = max_bytes;
+        buf
+    }
+
+    /// Maximum size of the contents in bytes
+    ///
+    /// Returns `usize::MAX` if the size is not limited.
+    #[inline]
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Set the maximum size of the contents in bytes
+    #[inline]
+    pub fn set_limit(&mut self, max_bytes: usize) {
+        self.limit = max_bytes;
+    }
+
+    /// Returns an error if the contents exceeded the limit
+    #[inline]
+    pub fn check_limit(&self) -> Result<(), RenderError> {
+        if unlikely!(self.limit_exceeded || self.inner.len() > self.limit) {
+            Err(RenderError::output_too_large(self.limit))
+        } else {
+            Ok(())
+        }
+    }
+
+    #[inline]
+    pub fn as_str(&self) -> &str {
+        self.inner.as_str()
+    }
+
+    /// Returns a byte slice of the contents
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        self.inner.as_bytes()
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    #[inline]
+    pub fn reserve(&mut self, size: usize) {
+        if size <= self.inner.capacity() - self.inner.len() {
+            return;
+        }
+        self.reserve_internal(size);
+    }
+
+    /// Reserves the capacity for exactly `size` more bytes
+    ///
+    /// Unlike [`reserve`](#method.reserve), the growth strategy is not applied.
+    #[inline]
+    pub fn reserve_exact(&mut self, size: usize) {
+        if size <= self.inner.capacity() - self.inner.len() {
+            return;
+        }
+        self.grow(size, self.inner.len() + size);
+    }
+
+    /// Strategy to compute the new capacity when the contents do not fit
+    #[inline]
+    pub fn growth_strategy(&self) -> GrowthStrategy {
+        self.growth
+    }
+
+    /// Set the strategy to compute the new capacity when the contents do not fit
+    #[inline]
+    pub fn set_growth_strategy(&mut self, strategy: GrowthStrategy) {
+        self.growth = strategy;
+    }
+
+    /// Truncates the buffer, removing all contents
+    ///
+    /// The allocated capacity is kept so that the buffer can be reused for
+    /// the next rendering without reallocation.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.record_peak();
+        self.inner.clear();
+        self.limit_exceeded = false;
+        #[cfg(feature = "std")]
+        {
+            self.pending = PendingBytes::new();
+        }
+    }
+
+    /// Shrinks the capacity of the buffer to match its length
+    #[inline]
+    pub fn shrink_to_fit(&mut self) {
+        self.shrink_to(0);
+    }
+
+    /// Shrinks the capacity of the buffer with a lower bound
+    #[inline]
+    pub fn shrink_to(&mut self, min_capacity: usize) {
+        let new_capacity = core::cmp::max(self.inner.len(), min_capacity);
+        if new_capacity < self.inner.capacity() {
+            self.record_realloc();
+            let mut inner = String::with_capacity(new_capacity);
+            inner.push_str(&self.inner);
+            self.inner = inner;
+        }
+    }
+
+    /// Shortens the buffer to the specified length
+    ///
+    /// # Panics
+    ///
+    /// Panics if `new_len` does not lie on a `char` boundary.
+    #[inline]
+    pub fn truncate(&mut self, new_len: usize) {
+        self.record_peak();
+        self.inner.truncate(new_len);
+    }
+
+    /// Removes the last character from the buffer and returns it
+    #[inline]
+    pub fn pop(&mut self) -> Option<char> {
+        self.record_peak();
+        self.inner.pop()
+    }
+
+    /// Inserts a string slice into the buffer at a byte position
+    ///
+    /// # Panics
+    ///
+    /// Panics if `idx` is larger than the buffer's length, or if it does not
+    /// lie on a `char` boundary.
+    pub fn insert_str(&mut self, idx: usize, data: &str) {
+        self.reserve(data.len());
+        self.inner.insert_str(idx, data);
+    }
+
+    /// Converts a `Buffer` into a `String` without copying its contents.
+    #[inline]
+    pub fn into_string(mut self) -> String {
+        self.record_peak();
+        core::mem::take(&mut self.inner)
+    }
+
+    /// Converts a `Buffer` into a byte vector without copying its contents.
+    #[inline]
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.into_string().into_bytes()
+    }
+
+    #[inline]
+    pub fn push_str(&mut self, data: &str) {
+        let size = data.len();
+        if unlikely!(size > self.inner.capacity() - self.inner.len()) {
+            if unlikely!(size > self.limit.saturating_sub(self.inner.len())) {
+                self.limit_exceeded = true;
+                return;
+            }
+            self.reserve_internal(size);
+        }
+        self.inner.push_str(data);
+    }
+
+    /// Append the contents of another buffer
+    ///
+    /// If this buffer is empty, the allocation of `other` is reused instead of
+    /// copying its contents.
+    #[inline]
+    pub fn append(&mut self, mut other: Buffer) {
+        if self.inner.is_empty() && other.capacity() >= self.capacity() {
+            other.record_peak();
+            core::mem::swap(&mut self.inner, &mut other.inner);
+        } else {
+            self.push_str(other.as_str());
+        }
+    }
+
+    #[inline]
+    pub fn push(&mut self, data: char) {
+        let mut buf = [0u8; 4];
+        self.push_str(data.encode_utf8(&mut buf));
+    }
+
+    /// Append the bytes to the buffer
+    ///
+    /// If `data` is not valid UTF-8, an error is returned and the buffer is left
+    /// unchanged.
+    #[inline]
+    pub fn write_bytes(&mut self, data: &[u8]) -> Result<(), Utf8Error> {
+        self.push_str(core::str::from_utf8(data)?);
+        Ok(())
+    }
+
+    /// Counters of how this buffer grew
+    #[cfg(feature = "buffer-stats")]
+    #[inline]
+    pub fn stats(&self) -> BufferStats {
+        BufferStats {
+            reallocs: self.stats.reallocs,
+            peak_len: core::cmp::max(self.stats.peak_len, self.inner.len()),
+        }
+    }
+
+    /// Returns `true` if the buffer owns the heap memory
+    #[inline]
+    pub(crate) fn spilled(&self) -> bool {
+        self.inner.capacity() != 0
+    }
+
+    #[inline(always)]
+    fn record_peak(&mut self) {
+        #[cfg(feature = "buffer-stats")]
+        {
+            self.stats.peak_len = core::cmp::max(self.stats.peak_len, self.inner.len());
+        }
+    }
+
+    #[inline(always)]
+    fn record_realloc(&mut self) {
+        #[cfg(feature = "buffer-stats")]
+        {
+            self.stats.reallocs += 1;
+        }
+    }
+
+    #[cold]
+    fn reserve_internal(&mut self, size: usize) {
+        let new_capacity =
+            self.growth
+                .next_capacity(self.inner.len(), self.inner.capacity(), size);
+        self.grow(size, new_capacity);
+    }
+
+    #[cold]
+    fn grow(&mut self, size: usize, mut new_capacity: usize) {
+        let len = self.inner.len();
+        if size <= self.limit.saturating_sub(len) {
+            new_capacity = core::cmp::min(new_capacity, self.limit);
+        } else {
+            // keep the behaviour consistent with the default implementation
+            self.limit_exceeded = true;
+            new_capacity = len + size;
+        }
+        self.inner.reserve_exact(new_capacity - len);
+        self.record_realloc();
+    }
+}
+
+impl Clone for Buffer {
+    fn clone(&self) -> Self {
+        let mut buf = Buffer::from(self.inner.clone());
+        buf.limit = self.limit;
+        buf.limit_exceeded = self.limit_exceeded;
+        buf.growth = self.growth;
+        buf
+    }
+}
+
+impl From<String> for Buffer {
+    #[inline]
+    fn from(other: String) -> Buffer {
+        Buffer {
+            inner: other,
+            limit: core::usize::MAX,
+            limit_exceeded: false,
+            growth: GrowthStrategy::Double,
+            #[cfg(feature = "std")]
+            pending: PendingBytes::new(),
+            #[cfg(feature = "buffer-stats")]
+            stats: BufferStats::default(),
+        }
+    }
+}
+
+#[cfg(feature = "buffer-stats")]
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        stats::record(&self.stats());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Buffer;
+
+    #[test]
+    fn capacity() {
+        let mut buffer = Buffer::new();
+        assert_eq!(buffer.capacity(), 0);
+
+        buffer.push_str("apple");
+        assert_eq!(buffer.capacity(), 5);
+
+        buffer.push_str("pie");
+        assert_eq!(buffer.capacity(), 10);
+
+        buffer.shrink_to(9);
+        assert_eq!(buffer.capacity(), 9);
+        buffer.shrink_to_fit();
+        assert_eq!(buffer.capacity(), 8);
+
+        buffer.clear();
+        buffer.shrink_to_fit();
+        assert_eq!(buffer.capacity(), 0);
+    }
+}
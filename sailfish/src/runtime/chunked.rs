@@ -0,0 +1,438 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+#[cfg(feature = "std")]
+use std::io;
+
+use super::{Buffer, Render, RenderError};
+
+const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Static strings shorter than this are copied instead of being referenced
+const STATIC_SEGMENT_THRESHOLD: usize = 256;
+
+enum Segment {
+    Owned(Buffer),
+    Static(&'static str),
+}
+
+impl Segment {
+    #[inline]
+    fn as_str(&self) -> &str {
+        match *self {
+            Segment::Owned(ref b) => b.as_str(),
+            Segment::Static(s) => s,
+        }
+    }
+}
+
+/// Buffer which stores the contents as a chain of chunks
+///
+/// `Buffer` holds its contents in a single contiguous allocation, so that
+/// appending data to a huge buffer may reallocate and copy the whole contents.
+/// `ChunkedBuffer` instead allocates a new chunk when the current chunk is full,
+/// and never moves the data already written. The chunks are flattened only when
+/// the contents are converted into [`Buffer`](struct.Buffer.html) or `String`,
+/// and are not flattened at all when written into `io::Write`.
+///
+/// Large static strings can also be appended by reference with
+/// [`push_static`](#method.push_static), so that they are never copied until the
+/// contents are flattened.
+///
+/// This is useful when rendering multi-megabyte pages. Templates can be
+/// rendered into this buffer with
+/// [`TemplateOnce::render_once_to_chunked`](../trait.TemplateOnce.html#method.render_once_to_chunked),
+/// which appends the large static texts by reference.
+///
+/// `ChunkedBuffer` dereferences to the last chunk, so that the values can be
+/// rendered into it directly.
+///
+/// # Examples
+///
+/// ```
+/// use sailfish::runtime::ChunkedBuffer;
+///
+/// let mut buf = ChunkedBuffer::with_chunk_size(4);
+/// buf.push_str("<ul>");
+/// for i in 0..3 {
+///     buf.push_str("<li>");
+///     buf.render(&i).unwrap();
+///     buf.push_str("</li>");
+/// }
+/// buf.push_str("</ul>");
+///
+/// let mut out = Vec::new();
+/// buf.write_to(&mut out).unwrap();
+/// assert_eq!(out, b"<ul><li>0</li><li>1</li><li>2</li></ul>");
+/// ```
+pub struct ChunkedBuffer {
+    /// the last segment is always an owned chunk
+    chunks: Vec<Segment>,
+    chunk_size: usize,
+}
+
+impl ChunkedBuffer {
+    /// Create an empty buffer with the default chunk size (64 KiB)
+    #[inline]
+    pub fn new() -> Self {
+        Self::with_chunk_size(DEFAULT_CHUNK_SIZE)
+    }
+
+    /// Create an empty buffer which allocates chunks of `chunk_size` bytes
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_size` is zero.
+    #[inline]
+    pub fn with_chunk_size(chunk_size: usize) -> Self {
+        assert!(chunk_size > 0, "chunk size must be positive");
+        Self {
+            chunks: alloc::vec![Segment::Owned(Buffer::new())],
+            chunk_size,
+        }
+    }
+
+    /// Total length of the contents in bytes
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.chunks.iter().map(|c| c.as_str().len()).sum()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.chunks.iter().all(|c| c.as_str().is_empty())
+    }
+
+    /// Number of non-empty chunks and static segments currently held
+    #[inline]
+    pub fn chunks(&self) -> usize {
+        self.chunks
+            .iter()
+            .filter(|c| !c.as_str().is_empty())
+            .count()
+    }
+
+    /// Remove all contents, keeping only the first chunk allocated
+    pub fn clear(&mut self) {
+        let first = self.chunks.drain(..).find_map(|segment| match segment {
+            Segment::Owned(b) => Some(b),
+            Segment::Static(_) => None,
+        });
+        let mut chunk = first.unwrap_or_default();
+        chunk.clear();
+        self.chunks.push(Segment::Owned(chunk));
+    }
+
+    /// Shorten the contents to `new_len` bytes
+    ///
+    /// # Panics
+    ///
+    /// Panics if `new_len` does not lie on a `char` boundary.
+    pub fn truncate(&mut self, new_len: usize) {
+        let mut len = self.len();
+        while len > new_len {
+            let last = self.chunks.last_mut().unwrap();
+            let segment_len = last.as_str().len();
+            if len - segment_len >= new_len {
+                self.chunks.pop();
+                len -= segment_len;
+                continue;
+            }
+
+            let keep = segment_len - (len - new_len);
+            match *last {
+                Segment::Owned(ref mut b) => b.truncate(keep),
+                Segment::Static(ref mut s) => *s = &s[..keep],
+            }
+            len = new_len;
+        }
+
+        if !matches!(self.chunks.last(), Some(Segment::Owned(_))) {
+            self.chunks.push(Segment::Owned(Buffer::new()));
+        }
+    }
+
+    pub fn push_str(&mut self, data: &str) {
+        self.reserve(data.len()).push_str(data);
+    }
+
+    /// Append the static string by reference
+    ///
+    /// Short strings are copied into the current chunk since referencing them
+    /// would cost more than copying.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sailfish::runtime::ChunkedBuffer;
+    ///
+    /// static FOOTER: &str = "<footer>Copyright (c) 2020</footer>";
+    ///
+    /// let mut buf = ChunkedBuffer::new();
+    /// buf.push_str("<main></main>");
+    /// buf.push_static(FOOTER);
+    /// assert_eq!(buf.len(), FOOTER.len() + 13);
+    /// ```
+    pub fn push_static(&mut self, data: &'static str) {
+        if data.len() < STATIC_SEGMENT_THRESHOLD {
+            self.push_str(data);
+        } else {
+            self.chunks.push(Segment::Static(data));
+            self.chunks.push(Segment::Owned(Buffer::new()));
+        }
+    }
+
+    #[inline]
+    pub fn push(&mut self, data: char) {
+        let mut buf = [0u8; 4];
+        self.push_str(data.encode_utf8(&mut buf));
+    }
+
+    /// Render the value into the buffer
+    ///
+    /// The value is rendered into the current chunk, so the chunk may grow
+    /// beyond the chunk size if the rendered value is large.
+    pub fn render<T: Render + ?Sized>(&mut self, value: &T) -> Result<(), RenderError> {
+        value.render(self.reserve(1))
+    }
+
+    /// Render the value into the buffer with HTML escaping
+    pub fn render_escaped<T: Render + ?Sized>(
+        &mut self,
+        value: &T,
+    ) -> Result<(), RenderError> {
+        value.render_escaped(self.reserve(1))
+    }
+
+    /// Write all chunks into the writer without flattening them
+    #[cfg(feature = "std")]
+    pub fn write_to<W: io::Write + ?Sized>(&self, writer: &mut W) -> io::Result<()> {
+        for chunk in self.chunks.iter() {
+            writer.write_all(chunk.as_str().as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Flatten the chunks into a single `Buffer`
+    pub fn into_buffer(mut self) -> Buffer {
+        if let [Segment::Owned(_)] = self.chunks[..] {
+            if let Some(Segment::Owned(b)) = self.chunks.pop() {
+                return b;
+            }
+        }
+
+        let mut buf = Buffer::with_capacity(self.len());
+        for chunk in self.chunks.iter() {
+            buf.push_str(chunk.as_str());
+        }
+        buf
+    }
+
+    /// Flatten the chunks into a `String`
+    #[inline]
+    pub fn into_string(self) -> String {
+        self.into_buffer().into_string()
+    }
+
+    /// Returns the chunk which can hold at least `size` more bytes without
+    /// reallocation, allocating a new chunk if necessary
+    fn reserve(&mut self, size: usize) -> &mut Buffer {
+        let chunk_size = self.chunk_size;
+        let chunk = self.last_chunk_mut();
+        let (full, unused) = if chunk.is_empty() {
+            // the chunk has not been allocated yet
+            (chunk.capacity() < core::cmp::max(chunk_size, size), true)
+        } else {
+            // short chunks may have larger inline capacity than the chunk size
+            let capacity =
+                core::cmp::min(chunk.capacity(), core::cmp::max(chunk_size, chunk.len()));
+            (size > capacity - chunk.len(), false)
+        };
+        if full {
+            let new_chunk = Buffer::with_capacity(core::cmp::max(chunk_size, size));
+            if unused {
+                *chunk = new_chunk;
+            } else {
+                self.chunks.push(Segment::Owned(new_chunk));
+            }
+        }
+        self.last_chunk_mut()
+    }
+
+    #[inline]
+    fn last_chunk_mut(&mut self) -> &mut Buffer {
+        match self.chunks.last_mut() {
+            Some(Segment::Owned(chunk)) => chunk,
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Default for ChunkedBuffer {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Deref for ChunkedBuffer {
+    type Target = Buffer;
+
+    #[inline]
+    fn deref(&self) -> &Buffer {
+        match self.chunks.last() {
+            Some(Segment::Owned(chunk)) => chunk,
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl DerefMut for ChunkedBuffer {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Buffer {
+        self.last_chunk_mut()
+    }
+}
+
+impl fmt::Debug for ChunkedBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ChunkedBuffer")
+            .field("len", &self.len())
+            .field("chunks", &self.chunks.len())
+            .field("chunk_size", &self.chunk_size)
+            .finish()
+    }
+}
+
+impl fmt::Write for ChunkedBuffer {
+    #[inline]
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        ChunkedBuffer::push_str(self, s);
+        Ok(())
+    }
+}
+
+impl From<ChunkedBuffer> for Buffer {
+    #[inline]
+    fn from(other: ChunkedBuffer) -> Buffer {
+        other.into_buffer()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::boxed::Box;
+
+    #[test]
+    fn chunks() {
+        let mut buf = ChunkedBuffer::with_chunk_size(8);
+        assert!(buf.is_empty());
+        assert_eq!(buf.chunks(), 0);
+
+        buf.push_str("apple");
+        buf.push_str("pie");
+        assert_eq!(buf.chunks(), 1);
+
+        // does not fit in the first chunk
+        buf.push('漢');
+        buf.push_str(" large contents ");
+        assert_eq!(buf.chunks(), 3);
+        buf.render_escaped(&"<&>").unwrap();
+        buf.render(&1.5f32).unwrap();
+        assert_eq!(buf.len(), 43);
+        assert_eq!(
+            buf.into_string(),
+            "applepie漢 large contents &lt;&amp;&gt;1.5"
+        );
+    }
+
+    #[test]
+    fn static_segments() {
+        let large = "x".repeat(STATIC_SEGMENT_THRESHOLD);
+        let large: &'static str = Box::leak(large.into_boxed_str());
+
+        let mut buf = ChunkedBuffer::with_chunk_size(16);
+        buf.push_str("<p>");
+        buf.push_static(large);
+        buf.push_static("</p>");
+        assert_eq!(buf.chunks(), 3);
+        assert_eq!(buf.len(), large.len() + 7);
+
+        let expected = format!("<p>{}</p>", large);
+        assert_eq!(buf.into_string(), expected);
+
+        let mut buf = ChunkedBuffer::new();
+        buf.push_static(large);
+        assert_eq!(buf.into_string(), large);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn write_to() {
+        let large: &'static str =
+            Box::leak("x".repeat(STATIC_SEGMENT_THRESHOLD).into_boxed_str());
+
+        let mut buf = ChunkedBuffer::with_chunk_size(8);
+        buf.push_str("applepie漢");
+        buf.push_static(large);
+        buf.render_escaped(&"<&>").unwrap();
+
+        let mut out = Vec::new();
+        buf.write_to(&mut out).unwrap();
+        assert_eq!(out, format!("applepie漢{}&lt;&amp;&gt;", large).as_bytes());
+    }
+
+    #[test]
+    fn truncate() {
+        let large: &'static str =
+            Box::leak("x".repeat(STATIC_SEGMENT_THRESHOLD).into_boxed_str());
+
+        let mut buf = ChunkedBuffer::with_chunk_size(4);
+        buf.push_str("abcdef");
+        buf.push_static(large);
+        buf.push_str("ghi");
+        buf.truncate(buf.len() - 1);
+        assert_eq!(buf.len(), large.len() + 8);
+        buf.truncate(8);
+        assert_eq!(buf.chunks(), 2);
+        buf.push_str("j");
+        assert_eq!(buf.into_string(), "abcdefxxj");
+
+        // render into the last chunk through `DerefMut`
+        let mut buf = ChunkedBuffer::new();
+        buf.push_static(large);
+        1.5f32.render(&mut buf).unwrap();
+        assert_eq!(buf.len(), large.len() + 3);
+        assert!(buf.check_limit().is_ok());
+    }
+
+    #[test]
+    fn single_chunk() {
+        use core::fmt::Write;
+
+        let mut buf = ChunkedBuffer::new();
+        write!(buf, "{}-{}", 1, 2).unwrap();
+        let buf = Buffer::from(buf);
+        assert_eq!(buf.as_str(), "1-2");
+        assert_eq!(buf.capacity(), DEFAULT_CHUNK_SIZE);
+    }
+
+    #[test]
+    fn clear() {
+        let mut buf = ChunkedBuffer::with_chunk_size(4);
+        buf.push_str("abcdefghijkl");
+        buf.push_str("mnop");
+        assert_eq!(buf.chunks(), 2);
+
+        buf.push_static(Box::leak("y".repeat(1000).into_boxed_str()));
+        assert_eq!(buf.chunks(), 3);
+
+        buf.clear();
+        assert!(buf.is_empty());
+        assert_eq!(buf.chunks(), 0);
+        buf.push_str("xyz");
+        assert_eq!(buf.into_string(), "xyz");
+    }
+}
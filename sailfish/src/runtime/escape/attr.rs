@@ -0,0 +1,54 @@
+use crate::runtime::Buffer;
+
+/// write the contents escaped for double-quoted attribute value into `Buffer`
+///
+/// Only `"` and `&` are replaced with the character references, so the result
+/// must be surrounded by double quotes. This is faster than
+/// [`escape_to_buf`](fn.escape_to_buf.html) for the contents which contain many
+/// `<`, `>` and `'` characters.
+///
+/// # Examples
+///
+/// ```
+/// use sailfish::runtime::escape::escape_attr_to_buf;
+/// use sailfish::runtime::Buffer;
+///
+/// let mut buf = Buffer::new();
+/// escape_attr_to_buf("a > b && c == \"d\"", &mut buf);
+/// assert_eq!(buf.as_str(), "a > b &amp;&amp; c == &quot;d&quot;");
+/// ```
+pub fn escape_attr_to_buf(feed: &str, buf: &mut Buffer) {
+    let mut start = 0;
+
+    for (i, &c) in feed.as_bytes().iter().enumerate() {
+        let escaped = match c {
+            b'"' => "&quot;",
+            b'&' => "&amp;",
+            _ => continue,
+        };
+
+        // `i` is always on the character boundary since `c` is ASCII character
+        buf.push_str(&feed[start..i]);
+        buf.push_str(escaped);
+        start = i + 1;
+    }
+
+    buf.push_str(&feed[start..]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_attr() {
+        let mut buf = Buffer::new();
+        escape_attr_to_buf("", &mut buf);
+        escape_attr_to_buf("<a href='x'> 漢字", &mut buf);
+        assert_eq!(buf.as_str(), "<a href='x'> 漢字");
+        buf.clear();
+
+        escape_attr_to_buf("\"&&quot;\"", &mut buf);
+        assert_eq!(buf.as_str(), "&quot;&amp;&amp;quot;&quot;");
+    }
+}
@@ -1,15 +1,15 @@
 #![allow(clippy::cast_ptr_alignment)]
 
 #[cfg(target_arch = "x86")]
-use std::arch::x86::*;
+use core::arch::x86::*;
 #[cfg(target_arch = "x86_64")]
-use std::arch::x86_64::*;
-use std::slice;
+use core::arch::x86_64::*;
+use core::slice;
 
 use super::super::Buffer;
 use super::{ESCAPED, ESCAPED_LEN, ESCAPE_LUT};
 
-const VECTOR_BYTES: usize = std::mem::size_of::<__m256i>();
+const VECTOR_BYTES: usize = core::mem::size_of::<__m256i>();
 const VECTOR_ALIGN: usize = VECTOR_BYTES - 1;
 
 #[target_feature(enable = "avx2")]
@@ -57,7 +57,7 @@ pub unsafe fn escape(feed: &str, buffer: &mut Buffer) {
                         start_ptr,
                         ptr2 as usize - start_ptr as usize,
                     );
-                    buffer.push_str(std::str::from_utf8_unchecked(slc));
+                    buffer.push_str(core::str::from_utf8_unchecked(slc));
                 }
                 buffer.push_str(*ESCAPED.get_unchecked(c));
                 start_ptr = ptr2.add(1);
@@ -81,7 +81,7 @@ pub unsafe fn escape(feed: &str, buffer: &mut Buffer) {
                         start_ptr,
                         ptr2 as usize - start_ptr as usize,
                     );
-                    buffer.push_str(std::str::from_utf8_unchecked(slc));
+                    buffer.push_str(core::str::from_utf8_unchecked(slc));
                 }
                 buffer.push_str(*ESCAPED.get_unchecked(c));
                 start_ptr = ptr2.add(1);
@@ -111,7 +111,7 @@ pub unsafe fn escape(feed: &str, buffer: &mut Buffer) {
                         start_ptr,
                         ptr2 as usize - start_ptr as usize,
                     );
-                    buffer.push_str(std::str::from_utf8_unchecked(slc));
+                    buffer.push_str(core::str::from_utf8_unchecked(slc));
                 }
                 buffer.push_str(*ESCAPED.get_unchecked(c));
                 start_ptr = ptr2.add(1);
@@ -122,7 +122,7 @@ pub unsafe fn escape(feed: &str, buffer: &mut Buffer) {
 
     if end_ptr > start_ptr {
         let slc = slice::from_raw_parts(start_ptr, end_ptr as usize - start_ptr as usize);
-        buffer.push_str(std::str::from_utf8_unchecked(slc));
+        buffer.push_str(core::str::from_utf8_unchecked(slc));
     }
 }
 
@@ -158,7 +158,7 @@ unsafe fn escape_small(feed: &str, buffer: &mut Buffer) {
             if start_ptr < ptr2 {
                 let slc =
                     slice::from_raw_parts(start_ptr, ptr2 as usize - start_ptr as usize);
-                buffer.push_str(std::str::from_utf8_unchecked(slc));
+                buffer.push_str(core::str::from_utf8_unchecked(slc));
             }
             buffer.push_str(*ESCAPED.get_unchecked(c));
             start_ptr = ptr2.add(1);
@@ -182,7 +182,7 @@ unsafe fn escape_small(feed: &str, buffer: &mut Buffer) {
                         start_ptr,
                         ptr2 as usize - start_ptr as usize,
                     );
-                    buffer.push_str(std::str::from_utf8_unchecked(slc));
+                    buffer.push_str(core::str::from_utf8_unchecked(slc));
                 }
                 buffer.push_str(*ESCAPED.get_unchecked(c));
                 start_ptr = ptr2.add(1);
@@ -193,6 +193,6 @@ unsafe fn escape_small(feed: &str, buffer: &mut Buffer) {
 
     if end_ptr > start_ptr {
         let slc = slice::from_raw_parts(start_ptr, end_ptr as usize - start_ptr as usize);
-        buffer.push_str(std::str::from_utf8_unchecked(slc));
+        buffer.push_str(core::str::from_utf8_unchecked(slc));
     }
 }
@@ -0,0 +1,76 @@
+use core::fmt::Write;
+
+use crate::runtime::Buffer;
+
+/// write the contents escaped as CSS identifier into `Buffer`
+///
+/// This follows the [`CSS.escape()`](https://drafts.csswg.org/cssom/#serialize-an-identifier)
+/// algorithm. The result can be used for the class names, identifiers or the
+/// contents of CSS strings in `<style>` blocks. Note that the characters which
+/// have special meaning in HTML (e.g. `"` or `&`) are escaped with backslash,
+/// so you still need HTML escaping when you put the result into `style=""`
+/// attribute.
+///
+/// # Examples
+///
+/// ```
+/// use sailfish::runtime::escape::escape_css_to_buf;
+/// use sailfish::runtime::Buffer;
+///
+/// let mut buf = Buffer::new();
+/// escape_css_to_buf("1st item.active", &mut buf);
+/// assert_eq!(buf.as_str(), "\\31 st\\ item\\.active");
+/// ```
+pub fn escape_css_to_buf(feed: &str, buf: &mut Buffer) {
+    let first = feed.chars().next();
+
+    for (i, c) in feed.chars().enumerate() {
+        match c {
+            '\0' => buf.push('\u{fffd}'),
+            '\x01'..='\x1f' | '\x7f' => write_code_point(c, buf),
+            '0'..='9' if i == 0 || (i == 1 && first == Some('-')) => {
+                write_code_point(c, buf)
+            }
+            '-' if i == 0 && feed.len() == 1 => buf.push_str("\\-"),
+            '-' | '_' | '0'..='9' | 'A'..='Z' | 'a'..='z' | '\u{80}'..=core::char::MAX => {
+                buf.push(c)
+            }
+            _ => {
+                buf.push('\\');
+                buf.push(c);
+            }
+        }
+    }
+}
+
+/// escape the character as hexadecimal code point followed by a space
+#[inline]
+fn write_code_point(c: char, buf: &mut Buffer) {
+    let _ = write!(buf, "\\{:x} ", c as u32);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::String;
+
+    fn escape(feed: &str) -> String {
+        let mut buf = Buffer::new();
+        escape_css_to_buf(feed, &mut buf);
+        buf.into_string()
+    }
+
+    #[test]
+    fn escape_css() {
+        assert_eq!(escape(""), "");
+        assert_eq!(escape("main-content_2"), "main-content_2");
+        assert_eq!(escape("漢字"), "漢字");
+        assert_eq!(escape("\0a\x01\x7f"), "\u{fffd}a\\1 \\7f ");
+        assert_eq!(escape("0a"), "\\30 a");
+        assert_eq!(escape("-1a"), "-\\31 a");
+        assert_eq!(escape("a1-2"), "a1-2");
+        assert_eq!(escape("-"), "\\-");
+        assert_eq!(escape("--"), "--");
+        assert_eq!(escape("</style>\"'{}"), "\\<\\/style\\>\\\"\\'\\{\\}");
+    }
+}
@@ -1,7 +1,10 @@
 #![allow(clippy::cast_ptr_alignment)]
 
+#[cfg(not(feature = "safe"))]
 use super::super::Buffer;
+#[cfg(not(feature = "safe"))]
 use super::naive;
+use super::{ESCAPED_LEN, ESCAPE_LUT};
 
 #[cfg(target_pointer_width = "16")]
 const USIZE_BYTES: usize = 2;
@@ -12,6 +15,7 @@ const USIZE_BYTES: usize = 4;
 #[cfg(target_pointer_width = "64")]
 const USIZE_BYTES: usize = 8;
 
+#[cfg(not(feature = "safe"))]
 const USIZE_ALIGN: usize = USIZE_BYTES - 1;
 
 #[inline(always)]
@@ -38,6 +42,29 @@ fn contains_key(x: usize) -> bool {
     contains_zero_byte(z1) || contains_zero_byte(z2)
 }
 
+/// check if the string may contain any characters to be escaped
+///
+/// This might return `true` for some other characters (e.g. `#`), but never
+/// returns `false` if the string contains the characters to be escaped.
+#[inline]
+pub fn contains_escape_char(feed: &str) -> bool {
+    let bytes = feed.as_bytes();
+    let mut chunks = bytes.chunks_exact(USIZE_BYTES);
+    for chunk in &mut chunks {
+        let mut word = [0; USIZE_BYTES];
+        word.copy_from_slice(chunk);
+        if contains_key(usize::from_ne_bytes(word)) {
+            return true;
+        }
+    }
+
+    chunks
+        .remainder()
+        .iter()
+        .any(|&c| (ESCAPE_LUT[c as usize] as usize) < ESCAPED_LEN)
+}
+
+#[cfg(not(feature = "safe"))]
 #[inline]
 pub unsafe fn escape(feed: &str, buffer: &mut Buffer) {
     debug_assert!(feed.len() >= 16);
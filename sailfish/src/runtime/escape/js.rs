@@ -0,0 +1,84 @@
+use crate::runtime::{utils, Buffer};
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789ABCDEF";
+
+/// write the contents escaped as JavaScript string literal into `Buffer`
+///
+/// In addition to quotation marks, backslashes and control characters, the
+/// characters which can terminate the `<script>` block or the template literal
+/// (`<`, `>`, `&`, `` ` ``, `$`) and line separators (U+2028, U+2029) are
+/// escaped, so that the result can be safely embedded into the string literal
+/// in inline `<script>` block regardless of the quotation marks. The
+/// surrounding quotation marks are not written.
+///
+/// # Examples
+///
+/// ```
+/// use sailfish::runtime::escape::escape_js_to_buf;
+/// use sailfish::runtime::Buffer;
+///
+/// let mut buf = Buffer::new();
+/// escape_js_to_buf("</script>", &mut buf);
+/// assert_eq!(buf.as_str(), "\\u003C/script\\u003E");
+/// ```
+pub fn escape_js_to_buf(feed: &str, buf: &mut Buffer) {
+    let mut start = 0;
+
+    for (i, c) in feed.char_indices() {
+        let escaped = match c {
+            '"' => "\\\"",
+            '\'' => "\\'",
+            '\\' => "\\\\",
+            '\n' => "\\n",
+            '\r' => "\\r",
+            '\t' => "\\t",
+            '\u{2028}' => "\\u2028",
+            '\u{2029}' => "\\u2029",
+            '\0'..='\x1f' | '<' | '>' | '&' | '`' | '$' => "",
+            _ => continue,
+        };
+
+        buf.push_str(&feed[start..i]);
+        if escaped.is_empty() {
+            let c = c as u8;
+            let digits = [
+                b'\\',
+                b'u',
+                b'0',
+                b'0',
+                HEX_DIGITS[(c >> 4) as usize],
+                HEX_DIGITS[(c & 0xf) as usize],
+            ];
+            buf.push_str(utils::ascii_to_str(&digits));
+        } else {
+            buf.push_str(escaped);
+        }
+        start = i + c.len_utf8();
+    }
+
+    buf.push_str(&feed[start..]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_js() {
+        let mut buf = Buffer::new();
+        escape_js_to_buf("", &mut buf);
+        escape_js_to_buf("plain text 漢字", &mut buf);
+        assert_eq!(buf.as_str(), "plain text 漢字");
+        buf.clear();
+
+        escape_js_to_buf(
+            "'a' \"b\" `${c}`\\\r\n\t\u{0}\u{2028}\u{2029}<!-- </script> &amp;",
+            &mut buf,
+        );
+        assert_eq!(
+            buf.as_str(),
+            "\\'a\\' \\\"b\\\" \\u0060\\u0024{c}\\u0060\\\\\\r\\n\\t\\u0000\\u2028\\u2029\
+             \\u003C!-- \\u003C/script\\u003E \\u0026amp;"
+        );
+    }
+}
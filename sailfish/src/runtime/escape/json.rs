@@ -0,0 +1,88 @@
+use super::Escaper;
+use crate::runtime::{utils, Buffer};
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+/// write the contents escaped as JSON string into `Buffer`
+///
+/// Quotation marks, backslashes and control characters are escaped. The
+/// surrounding quotation marks are not written.
+///
+/// # Examples
+///
+/// ```
+/// use sailfish::runtime::escape::escape_json_to_buf;
+/// use sailfish::runtime::Buffer;
+///
+/// let mut buf = Buffer::new();
+/// escape_json_to_buf("say \"hi\"\n", &mut buf);
+/// assert_eq!(buf.as_str(), "say \\\"hi\\\"\\n");
+/// ```
+pub fn escape_json_to_buf(feed: &str, buf: &mut Buffer) {
+    let mut start = 0;
+
+    for (i, &c) in feed.as_bytes().iter().enumerate() {
+        let escaped = match c {
+            b'"' => "\\\"",
+            b'\\' => "\\\\",
+            b'\n' => "\\n",
+            b'\r' => "\\r",
+            b'\t' => "\\t",
+            0x08 => "\\b",
+            0x0c => "\\f",
+            0x00..=0x1f => "",
+            _ => continue,
+        };
+
+        // `i` is always on the character boundary since `c` is ASCII character
+        buf.push_str(&feed[start..i]);
+        if escaped.is_empty() {
+            let digits = [
+                b'\\',
+                b'u',
+                b'0',
+                b'0',
+                HEX_DIGITS[(c >> 4) as usize],
+                HEX_DIGITS[(c & 0xf) as usize],
+            ];
+            buf.push_str(utils::ascii_to_str(&digits));
+        } else {
+            buf.push_str(escaped);
+        }
+        start = i + 1;
+    }
+
+    buf.push_str(&feed[start..]);
+}
+
+/// JSON string escaper, which is used inside `<%= %>` tag if `escape = "json"`
+/// option is specified
+#[derive(Clone, Copy, Debug, Default)]
+pub struct JsonEscaper;
+
+impl Escaper for JsonEscaper {
+    #[inline]
+    fn escape(&self, feed: &str, buf: &mut Buffer) {
+        escape_json_to_buf(feed, buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_json() {
+        let mut buf = Buffer::new();
+        escape_json_to_buf("", &mut buf);
+        escape_json_to_buf("<tag> & 'quote' 漢字", &mut buf);
+        assert_eq!(buf.as_str(), "<tag> & 'quote' 漢字");
+        buf.clear();
+
+        escape_json_to_buf("\"a\\b\"\r\n\t\u{8}\u{c}\u{0}\u{1f}end", &mut buf);
+        assert_eq!(
+            buf.as_str(),
+            "\\\"a\\\\b\\\"\\r\\n\\t\\b\\f\\u0000\\u001fend"
+        );
+    }
+}
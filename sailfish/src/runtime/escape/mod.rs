@@ -1,19 +1,57 @@
-//! HTML escaping utilities
+//! Escaping utilities
 //!
 //! By default sailfish replaces the characters `&"'<>` with the equivalent html.
+//! Escapers for other contexts (e.g. attributes, JavaScript, CSS, URL) and output
+//! formats (e.g. JSON) are also provided.
 
-#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+mod attr;
+#[cfg(all(
+    any(target_arch = "x86", target_arch = "x86_64"),
+    not(feature = "safe")
+))]
 mod avx2;
+mod css;
 mod fallback;
+mod js;
+mod json;
+#[cfg(not(feature = "safe"))]
 mod naive;
-#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[cfg(all(target_arch = "aarch64", not(feature = "safe")))]
+mod neon;
+mod percent;
+#[cfg(all(
+    target_arch = "wasm32",
+    target_feature = "simd128",
+    not(feature = "safe")
+))]
+mod simd128;
+#[cfg(all(
+    any(target_arch = "x86", target_arch = "x86_64"),
+    not(feature = "safe")
+))]
 mod sse2;
+mod xml;
 
-use std::mem;
-use std::sync::atomic::{AtomicPtr, Ordering};
+use alloc::borrow::Cow;
+use alloc::string::String;
+#[cfg(feature = "std")]
+use core::cell::RefCell;
+use core::fmt;
+#[cfg(not(feature = "safe"))]
+use core::mem;
+#[cfg(not(feature = "safe"))]
+use core::sync::atomic::{AtomicPtr, Ordering};
 
 use super::buffer::Buffer;
 
+pub use self::attr::escape_attr_to_buf;
+pub use self::css::escape_css_to_buf;
+pub use self::js::escape_js_to_buf;
+pub use self::json::{escape_json_to_buf, JsonEscaper};
+pub use self::percent::{percent_encode_to_buf, EncodeSet};
+pub use self::xml::{escape_xml_to_buf, XmlEscaper};
+
+#[cfg(not(feature = "safe"))]
 type FnRaw = *mut ();
 
 static ESCAPE_LUT: [u8; 256] = [
@@ -31,13 +69,21 @@ static ESCAPE_LUT: [u8; 256] = [
 
 const ESCAPED: [&str; 5] = ["&quot;", "&amp;", "&#039;", "&lt;", "&gt;"];
 const ESCAPED_LEN: usize = 5;
+const UNESCAPED: [char; 5] = ['"', '&', '\'', '<', '>'];
 
-#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[cfg(all(
+    any(target_arch = "x86", target_arch = "x86_64"),
+    not(feature = "safe")
+))]
 static FN: AtomicPtr<()> = AtomicPtr::new(escape as FnRaw);
 
-#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[cfg(all(
+    any(target_arch = "x86", target_arch = "x86_64"),
+    not(feature = "safe")
+))]
 fn escape(feed: &str, buf: &mut Buffer) {
     debug_assert!(feed.len() >= 16);
+    #[cfg(feature = "std")]
     let fun = if is_x86_feature_detected!("avx2") {
         avx2::escape
     } else if is_x86_feature_detected!("sse2") {
@@ -46,11 +92,22 @@ fn escape(feed: &str, buf: &mut Buffer) {
         fallback::escape
     };
 
+    // runtime feature detection requires `std`
+    #[cfg(not(feature = "std"))]
+    let fun = if cfg!(target_feature = "avx2") {
+        avx2::escape
+    } else if cfg!(target_feature = "sse2") {
+        sse2::escape
+    } else {
+        fallback::escape
+    };
+
     FN.store(fun as FnRaw, Ordering::Relaxed);
     unsafe { fun(feed, buf) };
 }
 
 /// write the escaped contents into `Buffer`
+#[cfg(not(feature = "safe"))]
 #[cfg_attr(feature = "perf-inline", inline)]
 pub fn escape_to_buf(feed: &str, buf: &mut Buffer) {
     unsafe {
@@ -73,7 +130,25 @@ pub fn escape_to_buf(feed: &str, buf: &mut Buffer) {
                 }
             }
 
-            #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+            // NEON is always available on AArch64
+            #[cfg(target_arch = "aarch64")]
+            {
+                neon::escape(feed, buf);
+            }
+
+            // WebAssembly does not support runtime feature detection, so SIMD128
+            // is used only if it is enabled at compile time
+            #[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+            {
+                simd128::escape(feed, buf);
+            }
+
+            #[cfg(not(any(
+                target_arch = "x86",
+                target_arch = "x86_64",
+                target_arch = "aarch64",
+                all(target_arch = "wasm32", target_feature = "simd128")
+            )))]
             {
                 fallback::escape(feed, buf);
             }
@@ -81,25 +156,284 @@ pub fn escape_to_buf(feed: &str, buf: &mut Buffer) {
     }
 }
 
-/// write the escaped contents into `String`
+/// write the escaped contents into `Buffer`
+///
+/// SIMD implementations require unsafe code, so the contents are scanned byte by
+/// byte if the `safe` feature is enabled.
+#[cfg(feature = "safe")]
+#[cfg_attr(feature = "perf-inline", inline)]
+pub fn escape_to_buf(feed: &str, buf: &mut Buffer) {
+    let mut start = 0;
+    for (i, &c) in feed.as_bytes().iter().enumerate() {
+        let idx = ESCAPE_LUT[c as usize] as usize;
+        if idx < ESCAPED_LEN {
+            buf.push_str(&feed[start..i]);
+            buf.push_str(ESCAPED[idx]);
+            start = i + 1;
+        }
+    }
+    buf.push_str(&feed[start..]);
+}
+
+/// escape the contents and return them as `String`
 ///
 /// # Examples
 ///
 /// ```
 /// use sailfish::runtime::escape::escape_to_string;
 ///
-/// let mut buf = String::new();
-/// escape_to_string("<h1>Hello, world!</h1>", &mut buf);
-/// assert_eq!(buf, "&lt;h1&gt;Hello, world!&lt;/h1&gt;");
+/// let s = escape_to_string("<h1>Hello, world!</h1>");
+/// assert_eq!(s, "&lt;h1&gt;Hello, world!&lt;/h1&gt;");
 /// ```
 #[inline]
-pub fn escape_to_string(feed: &str, s: &mut String) {
-    let mut s2 = String::new();
-    std::mem::swap(s, &mut s2);
-    let mut buf = Buffer::from(s2);
+pub fn escape_to_string(feed: &str) -> String {
+    let mut buf = Buffer::with_capacity(feed.len());
     escape_to_buf(feed, &mut buf);
-    let mut s2 = buf.into_string();
-    std::mem::swap(s, &mut s2);
+    buf.into_string()
+}
+
+/// replace the character references generated by
+/// [`escape_to_buf`](fn.escape_to_buf.html) with the original characters
+///
+/// Only the five references (`&quot;`, `&amp;`, `&#039;`, `&lt;` and `&gt;`) are
+/// recognized and the other ones are left as is. The input is returned without
+/// allocation if it does not contain `&`.
+///
+/// # Examples
+///
+/// ```
+/// use sailfish::runtime::escape::unescape;
+///
+/// assert_eq!(unescape("&lt;a href=&quot;#&quot;&gt;"), "<a href=\"#\">");
+/// assert_eq!(unescape("&copy; 2020"), "&copy; 2020");
+/// ```
+pub fn unescape(feed: &str) -> Cow<'_, str> {
+    let mut rest = match feed.find('&') {
+        Some(p) => p,
+        None => return Cow::Borrowed(feed),
+    };
+
+    let mut s = String::with_capacity(feed.len());
+    s.push_str(&feed[..rest]);
+    let mut feed = &feed[rest..];
+
+    while !feed.is_empty() {
+        let matched = ESCAPED
+            .iter()
+            .position(|e| feed.starts_with(e))
+            .map(|i| (i, ESCAPED[i].len()));
+        match matched {
+            Some((i, len)) => {
+                s.push(UNESCAPED[i]);
+                feed = &feed[len..];
+            }
+            None => {
+                s.push('&');
+                feed = &feed[1..];
+            }
+        }
+
+        rest = feed.find('&').unwrap_or(feed.len());
+        s.push_str(&feed[..rest]);
+        feed = &feed[rest..];
+    }
+
+    Cow::Owned(s)
+}
+
+/// scratch buffers larger than this size are not kept for reuse
+#[cfg(feature = "std")]
+const SCRATCH_MAX_CAPACITY: usize = 64 * 1024;
+
+#[cfg(feature = "std")]
+thread_local! {
+    static SCRATCH: RefCell<Buffer> = RefCell::new(Buffer::new());
+}
+
+/// escape the contents which were written into `buf` after `old_len`
+///
+/// The contents are moved into the thread-local scratch buffer only if they contain
+/// the characters to be escaped, so no allocation happens in most cases.
+#[inline]
+pub(crate) fn escape_tail(buf: &mut Buffer, old_len: usize) {
+    if fallback::contains_escape_char(&buf.as_str()[old_len..]) {
+        escape_tail_with(buf, old_len, &HtmlEscaper);
+    }
+}
+
+/// escape the contents which were written into `buf` after `old_len` with the
+/// custom escaper
+pub(crate) fn escape_tail_with(buf: &mut Buffer, old_len: usize, escaper: &dyn Escaper) {
+    if buf.len() == old_len {
+        return;
+    }
+
+    let mut escape = |scratch: &mut Buffer| {
+        scratch.push_str(&buf.as_str()[old_len..]);
+        buf.truncate(old_len);
+        escaper.escape(scratch.as_str(), buf);
+    };
+
+    #[cfg(feature = "std")]
+    SCRATCH.with(|s| match s.try_borrow_mut() {
+        Ok(mut scratch) => {
+            scratch.clear();
+            escape(&mut scratch);
+            if scratch.capacity() > SCRATCH_MAX_CAPACITY {
+                *scratch = Buffer::new();
+            }
+        }
+        // custom escaper may render another value recursively
+        Err(_) => escape(&mut Buffer::new()),
+    });
+
+    #[cfg(not(feature = "std"))]
+    escape(&mut Buffer::new());
+}
+
+/// `fmt::Write` implementation which escapes the contents before writing them into
+/// `Buffer`
+///
+/// This is useful to render the values which implement `Display` with HTML escaping
+/// without allocating temporary buffer.
+///
+/// # Examples
+///
+/// ```
+/// use sailfish::runtime::escape::EscapeWriter;
+/// use sailfish::runtime::Buffer;
+/// use std::fmt::Write;
+///
+/// let mut buf = Buffer::new();
+/// write!(EscapeWriter(&mut buf), "<{}>", "&").unwrap();
+/// assert_eq!(buf.as_str(), "&lt;&amp;&gt;");
+/// ```
+pub struct EscapeWriter<'a>(pub &'a mut Buffer);
+
+impl<'a> fmt::Write for EscapeWriter<'a> {
+    #[inline]
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        escape_to_buf(s, self.0);
+        Ok(())
+    }
+}
+
+/// Escaping strategy used by `Render::render_escaped_with`
+///
+/// Implement this trait to reuse `Render` implementations for output formats other
+/// than HTML (e.g. XML, LaTeX or CSV). Closures which take `&str` and `&mut Buffer`
+/// also implement this trait.
+///
+/// # Examples
+///
+/// ```
+/// use sailfish::runtime::{Buffer, Render};
+///
+/// let latex = |feed: &str, buf: &mut Buffer| {
+///     for c in feed.chars() {
+///         if "&%$#_{}".contains(c) {
+///             buf.push('\\');
+///         }
+///         buf.push(c);
+///     }
+/// };
+///
+/// let mut b = Buffer::new();
+/// "50% & more".render_escaped_with(&mut b, &latex).unwrap();
+/// assert_eq!(b.as_str(), "50\\% \\& more");
+/// ```
+pub trait Escaper {
+    /// write the escaped contents into `Buffer`
+    fn escape(&self, feed: &str, buf: &mut Buffer);
+}
+
+impl<F: Fn(&str, &mut Buffer)> Escaper for F {
+    #[inline]
+    fn escape(&self, feed: &str, buf: &mut Buffer) {
+        self(feed, buf)
+    }
+}
+
+/// HTML escaper, which is used inside `<%= %>` tag
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HtmlEscaper;
+
+impl Escaper for HtmlEscaper {
+    #[inline]
+    fn escape(&self, feed: &str, buf: &mut Buffer) {
+        escape_to_buf(feed, buf)
+    }
+}
+
+/// write the escaped contents into `Buffer`, also escaping the extra ASCII
+/// characters
+///
+/// The characters contained in `extra` are replaced with the numeric character
+/// references (e.g. `` ` `` with `&#96;`), and the rest of the contents are
+/// escaped by [`escape_to_buf`](fn.escape_to_buf.html), so the SIMD
+/// implementation is still used for them. Non-ASCII characters in `extra` are
+/// ignored.
+///
+/// # Examples
+///
+/// ```
+/// use sailfish::runtime::escape::escape_extra_to_buf;
+/// use sailfish::runtime::Buffer;
+///
+/// let mut buf = Buffer::new();
+/// escape_extra_to_buf("a=`<b>`", &mut buf, "`=");
+/// assert_eq!(buf.as_str(), "a&#61;&#96;&lt;b&gt;&#96;");
+/// ```
+pub fn escape_extra_to_buf(feed: &str, buf: &mut Buffer, extra: &str) {
+    let set = extra
+        .bytes()
+        .filter(|c| c.is_ascii())
+        .fold(0u128, |set, c| set | (1 << c));
+
+    let mut start = 0;
+    for (i, c) in feed.bytes().enumerate() {
+        if c.is_ascii() && set & (1 << c) != 0 {
+            // `i` is always on the character boundary since `c` is ASCII character
+            escape_to_buf(&feed[start..i], buf);
+            buf.push_str("&#");
+            if c >= 100 {
+                buf.push((b'0' + c / 100) as char);
+            }
+            if c >= 10 {
+                buf.push((b'0' + c / 10 % 10) as char);
+            }
+            buf.push((b'0' + c % 10) as char);
+            buf.push(';');
+            start = i + 1;
+        }
+    }
+
+    escape_to_buf(&feed[start..], buf);
+}
+
+/// HTML escaper which also escapes the extra ASCII characters
+///
+/// This is used inside `<%= %>` tag if `escape_extra` option is specified.
+/// See [`escape_extra_to_buf`](fn.escape_extra_to_buf.html) for details.
+///
+/// # Examples
+///
+/// ```
+/// use sailfish::runtime::escape::ExtendedHtmlEscaper;
+/// use sailfish::runtime::{Buffer, Render};
+///
+/// let mut b = Buffer::new();
+/// "x=`1`".render_escaped_with(&mut b, &ExtendedHtmlEscaper("`")).unwrap();
+/// assert_eq!(b.as_str(), "x=&#96;1&#96;");
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct ExtendedHtmlEscaper<'a>(pub &'a str);
+
+impl<'a> Escaper for ExtendedHtmlEscaper<'a> {
+    #[inline]
+    fn escape(&self, feed: &str, buf: &mut Buffer) {
+        escape_extra_to_buf(feed, buf, self.0)
+    }
 }
 
 #[cfg(test)]
@@ -107,9 +441,7 @@ mod tests {
     use super::*;
 
     fn escape(feed: &str) -> String {
-        let mut s = String::new();
-        escape_to_string(feed, &mut s);
-        s
+        escape_to_string(feed)
     }
 
     #[test]
@@ -152,10 +484,52 @@ mod tests {
     }
 
     #[test]
+    fn escape_extra() {
+        let mut buf = Buffer::new();
+        escape_extra_to_buf("", &mut buf, "`=");
+        escape_extra_to_buf("no extra characters <here>", &mut buf, "");
+        assert_eq!(buf.as_str(), "no extra characters &lt;here&gt;");
+        buf.clear();
+
+        escape_extra_to_buf("\t`a=b`&\x7f漢字=", &mut buf, "`=\t\x7f漢");
+        assert_eq!(buf.as_str(), "&#9;&#96;a&#61;b&#96;&amp;&#127;漢字&#61;");
+    }
+
+    #[test]
+    fn escape_tail_in_place() {
+        let mut buf = Buffer::from("<a>");
+        escape_tail(&mut buf, 3);
+        buf.push_str("plain");
+        escape_tail(&mut buf, 3);
+        buf.push_str("<b>&");
+        escape_tail(&mut buf, 8);
+        assert_eq!(buf.as_str(), "<a>plain&lt;b&gt;&amp;");
+
+        let upper = |feed: &str, buf: &mut Buffer| {
+            let mut inner = Buffer::from(feed);
+            escape_tail(&mut inner, 0);
+            buf.push_str(&inner.as_str().to_uppercase());
+        };
+        escape_tail_with(&mut buf, 3, &upper);
+        assert_eq!(buf.as_str(), "<a>PLAIN&AMP;LT;B&AMP;GT;&AMP;AMP;");
+    }
+
+    #[test]
+    fn unescape_entities() {
+        assert!(matches!(unescape("no entities"), Cow::Borrowed(_)));
+        assert_eq!(unescape("&quot;&amp;&#039;&lt;&gt;"), "\"&'<>");
+        assert_eq!(unescape("a & b &amp;amp; &nbsp;&"), "a & b &amp; &nbsp;&");
+
+        let s = "<p class=\"x\">Tom & Jerry's</p> 漢字";
+        assert_eq!(unescape(&escape(s)), s);
+    }
+
+    #[test]
+    #[cfg(not(feature = "safe"))]
     fn random() {
         const ASCII_CHARS: &'static [u8] = br##"abcdefghijklmnopqrstuvwxyz0123456789-^\@[;:],./\!"#$%&'()~=~|`{+*}<>?_"##;
         let mut state = 88172645463325252u64;
-        let mut data = Vec::with_capacity(100);
+        let mut data = alloc::vec::Vec::with_capacity(100);
 
         let mut buf_naive = Buffer::new();
         let mut buf = Buffer::new();
@@ -188,14 +562,31 @@ mod tests {
                     assert_eq!(buf.as_str(), buf_naive.as_str());
                     buf.clear();
 
-                    if is_x86_feature_detected!("sse2") {
-                        sse2::escape(s, &mut buf);
+                    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+                    {
+                        if is_x86_feature_detected!("sse2") {
+                            sse2::escape(s, &mut buf);
+                            assert_eq!(buf.as_str(), buf_naive.as_str());
+                            buf.clear();
+                        }
+
+                        if is_x86_feature_detected!("avx2") {
+                            avx2::escape(s, &mut buf);
+                            assert_eq!(buf.as_str(), buf_naive.as_str());
+                            buf.clear();
+                        }
+                    }
+
+                    #[cfg(target_arch = "aarch64")]
+                    {
+                        neon::escape(s, &mut buf);
                         assert_eq!(buf.as_str(), buf_naive.as_str());
                         buf.clear();
                     }
 
-                    if is_x86_feature_detected!("avx2") {
-                        avx2::escape(s, &mut buf);
+                    #[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+                    {
+                        simd128::escape(s, &mut buf);
                         assert_eq!(buf.as_str(), buf_naive.as_str());
                         buf.clear();
                     }
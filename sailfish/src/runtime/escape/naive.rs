@@ -15,7 +15,7 @@ pub(super) unsafe fn escape(
 
     if likely!(end_ptr > start_ptr) {
         let slc = slice::from_raw_parts(start_ptr, end_ptr as usize - start_ptr as usize);
-        buffer.push_str(std::str::from_utf8_unchecked(slc));
+        buffer.push_str(core::str::from_utf8_unchecked(slc));
     }
 }
 
@@ -34,7 +34,7 @@ pub(super) unsafe fn proceed(
             if ptr > start_ptr {
                 let slc =
                     slice::from_raw_parts(start_ptr, ptr as usize - start_ptr as usize);
-                buffer.push_str(std::str::from_utf8_unchecked(slc));
+                buffer.push_str(core::str::from_utf8_unchecked(slc));
             }
             buffer.push_str(*ESCAPED.get_unchecked(idx));
             start_ptr = ptr.add(1);
@@ -0,0 +1,99 @@
+use core::arch::aarch64::*;
+use core::slice;
+
+use super::super::Buffer;
+use super::{ESCAPED, ESCAPED_LEN, ESCAPE_LUT};
+
+const VECTOR_BYTES: usize = core::mem::size_of::<uint8x16_t>();
+const VECTOR_ALIGN: usize = VECTOR_BYTES - 1;
+
+/// NEON does not have `movemask` instruction, so the comparison result is
+/// narrowed into 64-bit integer, in which each byte is represented by 4 bits.
+#[inline(always)]
+unsafe fn maskgen(x: uint8x16_t) -> u64 {
+    let v_independent1 = vdupq_n_u8(5);
+    let v_independent2 = vdupq_n_u8(2);
+    let v_key1 = vdupq_n_u8(0x27);
+    let v_key2 = vdupq_n_u8(0x3e);
+
+    let cmp = vorrq_u8(
+        vceqq_u8(vorrq_u8(x, v_independent1), v_key1),
+        vceqq_u8(vorrq_u8(x, v_independent2), v_key2),
+    );
+    let narrowed = vshrn_n_u16(vreinterpretq_u16_u8(cmp), 4);
+    vget_lane_u64(vreinterpret_u64_u8(narrowed), 0)
+}
+
+#[inline(always)]
+unsafe fn proceed(
+    buffer: &mut Buffer,
+    mut start_ptr: *const u8,
+    ptr: *const u8,
+    limit: *const u8,
+    mut mask: u64,
+) -> *const u8 {
+    while mask != 0 {
+        let trailing_zeros = mask.trailing_zeros() as usize;
+        let ptr2 = ptr.add(trailing_zeros / 4);
+        if ptr2 >= limit {
+            break;
+        }
+
+        let c = ESCAPE_LUT[*ptr2 as usize] as usize;
+        if c < ESCAPED_LEN {
+            if start_ptr < ptr2 {
+                let slc =
+                    slice::from_raw_parts(start_ptr, ptr2 as usize - start_ptr as usize);
+                buffer.push_str(core::str::from_utf8_unchecked(slc));
+            }
+            buffer.push_str(*ESCAPED.get_unchecked(c));
+            start_ptr = ptr2.add(1);
+        }
+        mask &= !(0xf << trailing_zeros);
+    }
+
+    start_ptr
+}
+
+#[target_feature(enable = "neon")]
+pub unsafe fn escape(feed: &str, buffer: &mut Buffer) {
+    debug_assert!(feed.len() >= 16);
+
+    let len = feed.len();
+    let mut start_ptr = feed.as_ptr();
+    let end_ptr = start_ptr.add(len);
+
+    let mut ptr = start_ptr;
+    let aligned_ptr = ptr.add(VECTOR_BYTES - (start_ptr as usize & VECTOR_ALIGN));
+
+    let mask = maskgen(vld1q_u8(ptr));
+    start_ptr = proceed(buffer, start_ptr, ptr, aligned_ptr, mask);
+
+    ptr = aligned_ptr;
+    let mut next_ptr = ptr.add(VECTOR_BYTES);
+
+    while next_ptr <= end_ptr {
+        debug_assert_eq!((ptr as usize) % VECTOR_BYTES, 0);
+        let mask = maskgen(vld1q_u8(ptr));
+        start_ptr = proceed(buffer, start_ptr, ptr, next_ptr, mask);
+
+        ptr = next_ptr;
+        next_ptr = next_ptr.add(VECTOR_BYTES);
+    }
+
+    debug_assert!(next_ptr > end_ptr);
+
+    if ptr < end_ptr {
+        debug_assert!((end_ptr as usize - ptr as usize) < VECTOR_BYTES);
+        let backs = VECTOR_BYTES - (end_ptr as usize - ptr as usize);
+        let read_ptr = ptr.sub(backs);
+
+        let mask = maskgen(vld1q_u8(read_ptr)) >> (backs * 4);
+        start_ptr = proceed(buffer, start_ptr, ptr, end_ptr, mask);
+    }
+
+    if end_ptr > start_ptr {
+        let slc = slice::from_raw_parts(start_ptr, end_ptr as usize - start_ptr as usize);
+        buffer.push_str(core::str::from_utf8_unchecked(slc));
+    }
+}
@@ -0,0 +1,91 @@
+use crate::runtime::{utils, Buffer};
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789ABCDEF";
+
+/// Set of characters which are left unencoded by
+/// [`percent_encode_to_buf`](fn.percent_encode_to_buf.html)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EncodeSet {
+    /// encode the characters other than the unreserved characters and
+    /// `!$&'()*+,;=:@`, which is suitable for a path segment
+    PathSegment,
+    /// encode the characters other than the unreserved characters
+    /// (`A-Za-z0-9-._~`), which is suitable for a key or value in query string
+    QueryComponent,
+}
+
+impl EncodeSet {
+    #[inline]
+    fn contains(self, c: u8) -> bool {
+        match c {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => false,
+            b'!' | b'$' | b'&' | b'\'' | b'(' | b')' | b'*' | b'+' | b',' | b';'
+            | b'=' | b':' | b'@' => self == EncodeSet::QueryComponent,
+            _ => true,
+        }
+    }
+}
+
+/// write the percent-encoded contents into `Buffer`
+///
+/// Each byte of the UTF-8 representation is encoded unless it is contained in
+/// the specified set.
+///
+/// # Examples
+///
+/// ```
+/// use sailfish::runtime::escape::{percent_encode_to_buf, EncodeSet};
+/// use sailfish::runtime::Buffer;
+///
+/// let mut buf = Buffer::new();
+/// percent_encode_to_buf("a/b c", &mut buf, EncodeSet::PathSegment);
+/// buf.push('?');
+/// percent_encode_to_buf("q&a=1", &mut buf, EncodeSet::QueryComponent);
+/// assert_eq!(buf.as_str(), "a%2Fb%20c?q%26a%3D1");
+/// ```
+pub fn percent_encode_to_buf(feed: &str, buf: &mut Buffer, set: EncodeSet) {
+    let mut start = 0;
+
+    for (i, &c) in feed.as_bytes().iter().enumerate() {
+        if !set.contains(c) {
+            continue;
+        }
+
+        // non-ASCII bytes are always encoded, so `feed[start..i]` is either
+        // empty or contains ASCII characters only
+        if start < i {
+            buf.push_str(&feed[start..i]);
+        }
+        let digits = [
+            b'%',
+            HEX_DIGITS[(c >> 4) as usize],
+            HEX_DIGITS[(c & 0xf) as usize],
+        ];
+        buf.push_str(utils::ascii_to_str(&digits));
+        start = i + 1;
+    }
+
+    if start < feed.len() {
+        buf.push_str(&feed[start..]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percent_encode() {
+        let mut buf = Buffer::new();
+        percent_encode_to_buf("AZaz09-._~", &mut buf, EncodeSet::QueryComponent);
+        assert_eq!(buf.as_str(), "AZaz09-._~");
+        buf.clear();
+
+        percent_encode_to_buf("a+b=c&d/e?f#g %", &mut buf, EncodeSet::PathSegment);
+        assert_eq!(buf.as_str(), "a+b=c&d%2Fe%3Ff%23g%20%25");
+        buf.clear();
+
+        percent_encode_to_buf("a+b=c&d/e 漢", &mut buf, EncodeSet::QueryComponent);
+        assert_eq!(buf.as_str(), "a%2Bb%3Dc%26d%2Fe%20%E6%BC%A2");
+    }
+}
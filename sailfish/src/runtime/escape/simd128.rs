@@ -0,0 +1,96 @@
+use core::arch::wasm32::*;
+use core::slice;
+
+use super::super::Buffer;
+use super::{ESCAPED, ESCAPED_LEN, ESCAPE_LUT};
+
+const VECTOR_BYTES: usize = core::mem::size_of::<v128>();
+const VECTOR_ALIGN: usize = VECTOR_BYTES - 1;
+
+#[inline(always)]
+unsafe fn maskgen(ptr: *const u8) -> u32 {
+    let v_independent1 = u8x16_splat(5);
+    let v_independent2 = u8x16_splat(2);
+    let v_key1 = u8x16_splat(0x27);
+    let v_key2 = u8x16_splat(0x3e);
+
+    // wasm allows unaligned loads
+    let x = v128_load(ptr as *const v128);
+    u8x16_bitmask(v128_or(
+        u8x16_eq(v128_or(x, v_independent1), v_key1),
+        u8x16_eq(v128_or(x, v_independent2), v_key2),
+    )) as u32
+}
+
+#[inline(always)]
+unsafe fn proceed(
+    buffer: &mut Buffer,
+    mut start_ptr: *const u8,
+    ptr: *const u8,
+    limit: *const u8,
+    mut mask: u32,
+) -> *const u8 {
+    while mask != 0 {
+        let trailing_zeros = mask.trailing_zeros() as usize;
+        let ptr2 = ptr.add(trailing_zeros);
+        if ptr2 >= limit {
+            break;
+        }
+
+        let c = ESCAPE_LUT[*ptr2 as usize] as usize;
+        if c < ESCAPED_LEN {
+            if start_ptr < ptr2 {
+                let slc =
+                    slice::from_raw_parts(start_ptr, ptr2 as usize - start_ptr as usize);
+                buffer.push_str(core::str::from_utf8_unchecked(slc));
+            }
+            buffer.push_str(*ESCAPED.get_unchecked(c));
+            start_ptr = ptr2.add(1);
+        }
+        mask ^= 1 << trailing_zeros;
+    }
+
+    start_ptr
+}
+
+pub unsafe fn escape(feed: &str, buffer: &mut Buffer) {
+    debug_assert!(feed.len() >= 16);
+
+    let len = feed.len();
+    let mut start_ptr = feed.as_ptr();
+    let end_ptr = start_ptr.add(len);
+
+    let mut ptr = start_ptr;
+    let aligned_ptr = ptr.add(VECTOR_BYTES - (start_ptr as usize & VECTOR_ALIGN));
+
+    let mask = maskgen(ptr);
+    start_ptr = proceed(buffer, start_ptr, ptr, aligned_ptr, mask);
+
+    ptr = aligned_ptr;
+    let mut next_ptr = ptr.add(VECTOR_BYTES);
+
+    while next_ptr <= end_ptr {
+        debug_assert_eq!((ptr as usize) % VECTOR_BYTES, 0);
+        let mask = maskgen(ptr);
+        start_ptr = proceed(buffer, start_ptr, ptr, next_ptr, mask);
+
+        ptr = next_ptr;
+        next_ptr = next_ptr.add(VECTOR_BYTES);
+    }
+
+    debug_assert!(next_ptr > end_ptr);
+
+    if ptr < end_ptr {
+        debug_assert!((end_ptr as usize - ptr as usize) < VECTOR_BYTES);
+        let backs = VECTOR_BYTES - (end_ptr as usize - ptr as usize);
+        let read_ptr = ptr.sub(backs);
+
+        let mask = maskgen(read_ptr) >> backs;
+        start_ptr = proceed(buffer, start_ptr, ptr, end_ptr, mask);
+    }
+
+    if end_ptr > start_ptr {
+        let slc = slice::from_raw_parts(start_ptr, end_ptr as usize - start_ptr as usize);
+        buffer.push_str(core::str::from_utf8_unchecked(slc));
+    }
+}
@@ -0,0 +1,72 @@
+use super::Escaper;
+use crate::runtime::Buffer;
+
+/// write the contents escaped as XML character data into `Buffer`
+///
+/// Unlike [`escape_to_buf`](fn.escape_to_buf.html), apostrophes are replaced
+/// with the predefined entity `&apos;` instead of the numeric character
+/// reference.
+///
+/// # Examples
+///
+/// ```
+/// use sailfish::runtime::escape::escape_xml_to_buf;
+/// use sailfish::runtime::Buffer;
+///
+/// let mut buf = Buffer::new();
+/// escape_xml_to_buf("<a title='R&D'>", &mut buf);
+/// assert_eq!(buf.as_str(), "&lt;a title=&apos;R&amp;D&apos;&gt;");
+/// ```
+pub fn escape_xml_to_buf(feed: &str, buf: &mut Buffer) {
+    let mut start = 0;
+
+    for (i, &c) in feed.as_bytes().iter().enumerate() {
+        let escaped = match c {
+            b'"' => "&quot;",
+            b'&' => "&amp;",
+            b'\'' => "&apos;",
+            b'<' => "&lt;",
+            b'>' => "&gt;",
+            _ => continue,
+        };
+
+        // `i` is always on the character boundary since `c` is ASCII character
+        buf.push_str(&feed[start..i]);
+        buf.push_str(escaped);
+        start = i + 1;
+    }
+
+    buf.push_str(&feed[start..]);
+}
+
+/// XML escaper, which is used inside `<%= %>` tag if `escape = "xml"` option is
+/// specified
+#[derive(Clone, Copy, Debug, Default)]
+pub struct XmlEscaper;
+
+impl Escaper for XmlEscaper {
+    #[inline]
+    fn escape(&self, feed: &str, buf: &mut Buffer) {
+        escape_xml_to_buf(feed, buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_xml() {
+        let mut buf = Buffer::new();
+        escape_xml_to_buf("", &mut buf);
+        escape_xml_to_buf("plain text 漢字", &mut buf);
+        assert_eq!(buf.as_str(), "plain text 漢字");
+        buf.clear();
+
+        escape_xml_to_buf("\"<a>\" & '<b>'", &mut buf);
+        assert_eq!(
+            buf.as_str(),
+            "&quot;&lt;a&gt;&quot; &amp; &apos;&lt;b&gt;&apos;"
+        );
+    }
+}
@@ -0,0 +1,39 @@
+use bytes::Bytes;
+
+use crate::runtime::Buffer;
+
+/// Convert the buffer into `Bytes`, reusing its allocation
+///
+/// The rendered page can be passed to web frameworks as a response body without
+/// being copied into another buffer.
+///
+/// # Examples
+///
+/// ```
+/// use bytes::Bytes;
+/// use sailfish::runtime::Buffer;
+///
+/// let mut buf = Buffer::new();
+/// buf.push_str("<h1>Hello</h1>");
+/// let body = Bytes::from(buf);
+/// assert_eq!(&body[..], b"<h1>Hello</h1>");
+/// ```
+impl From<Buffer> for Bytes {
+    #[inline]
+    fn from(other: Buffer) -> Bytes {
+        Bytes::from(other.into_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn into_bytes() {
+        let mut buf = Buffer::with_capacity(16);
+        buf.push_str("漢字");
+        assert_eq!(&Bytes::from(buf)[..], "漢字".as_bytes());
+        assert!(Bytes::from(Buffer::new()).is_empty());
+    }
+}
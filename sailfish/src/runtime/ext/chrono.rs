@@ -0,0 +1,145 @@
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, TimeZone};
+use core::fmt;
+
+use crate::runtime::escape::EscapeWriter;
+use crate::runtime::{Buffer, Render, RenderError};
+
+const DATE_FORMAT: &str = "%Y-%m-%d";
+const TIME_FORMAT: &str = "%H:%M:%S%.f";
+const NAIVE_DATETIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.f";
+const DATETIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.f%:z";
+
+macro_rules! render_chrono {
+    ($(#[doc = $doc:tt])* [$($bounds:tt)*] [$($wc:tt)*] $type:ty, $format:expr) => {
+        $(#[doc = $doc])*
+        impl<$($bounds)*> Render for $type where $($wc)* {
+            #[inline]
+            fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+                fmt::write(b, format_args!("{}", self.format($format)))?;
+                Ok(())
+            }
+
+            #[inline]
+            fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+                // escape string
+                self.render(b)
+            }
+        }
+    };
+    ($(#[doc = $doc:tt])* [$($bounds:tt)*] $type:ty, $format:expr) => {
+        render_chrono!($(#[doc = $doc])* [$($bounds)*] [] $type, $format);
+    };
+}
+
+render_chrono!(
+    /// render in `%Y-%m-%d` format (e.g. `2020-09-13`)
+    [] NaiveDate,
+    DATE_FORMAT
+);
+render_chrono!(
+    /// render in `%H:%M:%S%.f` format (e.g. `12:26:40.500`)
+    [] NaiveTime,
+    TIME_FORMAT
+);
+render_chrono!(
+    /// render in RFC 3339 format without timezone (e.g. `2020-09-13T12:26:40`)
+    [] NaiveDateTime,
+    NAIVE_DATETIME_FORMAT
+);
+render_chrono!(
+    /// render in RFC 3339 format (e.g. `2020-09-13T12:26:40+09:00`)
+    [Tz: TimeZone] [Tz::Offset: fmt::Display] DateTime<Tz>,
+    DATETIME_FORMAT
+);
+
+/// Render the date and time with custom format
+///
+/// See [`chrono::format::strftime`](https://docs.rs/chrono/0.4/chrono/format/strftime/index.html)
+/// for the supported escape sequences.
+///
+/// # Examples
+///
+/// ```
+/// use chrono::NaiveDate;
+/// use sailfish::runtime::{Buffer, Render, Strftime};
+///
+/// let date = NaiveDate::from_ymd_opt(2020, 9, 13).unwrap();
+/// let mut b = Buffer::new();
+/// Strftime(&date, "%b %e, %Y").render(&mut b).unwrap();
+/// assert_eq!(b.as_str(), "Sep 13, 2020");
+/// ```
+pub struct Strftime<'a, T>(pub &'a T, pub &'a str);
+
+macro_rules! render_strftime {
+    ([$($bounds:tt)*] [$($wc:tt)*] $type:ty) => {
+        impl<'a, $($bounds)*> Render for Strftime<'a, $type> where $($wc)* {
+            #[inline]
+            fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+                fmt::write(b, format_args!("{}", self.0.format(self.1)))?;
+                Ok(())
+            }
+
+            #[inline]
+            fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+                fmt::write(&mut EscapeWriter(b), format_args!("{}", self.0.format(self.1)))?;
+                Ok(())
+            }
+        }
+    };
+    ([$($bounds:tt)*] $type:ty) => {
+        render_strftime!([$($bounds)*] [] $type);
+    };
+}
+
+render_strftime!([] NaiveDate);
+render_strftime!([] NaiveTime);
+render_strftime!([] NaiveDateTime);
+render_strftime!([Tz: TimeZone] [Tz::Offset: fmt::Display] DateTime<Tz>);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{FixedOffset, Utc};
+
+    #[test]
+    fn rfc3339() {
+        let date = NaiveDate::from_ymd_opt(2020, 9, 13).unwrap();
+        let datetime = date.and_hms_milli_opt(12, 26, 40, 500).unwrap();
+
+        let mut b = Buffer::new();
+        date.render(&mut b).unwrap();
+        b.push(' ');
+        datetime.time().render(&mut b).unwrap();
+        b.push(' ');
+        datetime.render(&mut b).unwrap();
+        b.push(' ');
+        Utc.from_utc_datetime(&datetime).render(&mut b).unwrap();
+        b.push(' ');
+        FixedOffset::east_opt(9 * 3600)
+            .unwrap()
+            .from_local_datetime(&date.and_hms_opt(21, 0, 0).unwrap())
+            .unwrap()
+            .render_escaped(&mut b)
+            .unwrap();
+        assert_eq!(
+            b.as_str(),
+            "2020-09-13 12:26:40.500 2020-09-13T12:26:40.500 \
+             2020-09-13T12:26:40.500+00:00 2020-09-13T21:00:00+09:00"
+        );
+    }
+
+    #[test]
+    fn strftime() {
+        let datetime = NaiveDate::from_ymd_opt(2020, 9, 13)
+            .unwrap()
+            .and_hms_opt(12, 26, 40)
+            .unwrap();
+
+        let mut b = Buffer::new();
+        Strftime(&datetime, "<%Y/%m/%d>").render(&mut b).unwrap();
+        Strftime(&datetime, "<%H:%M>")
+            .render_escaped(&mut b)
+            .unwrap();
+        assert_eq!(b.as_str(), "<2020/09/13>&lt;12:26&gt;");
+    }
+}
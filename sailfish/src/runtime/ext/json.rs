@@ -0,0 +1,148 @@
+use core::fmt;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::runtime::escape::{self, EscapeWriter};
+use crate::runtime::{Buffer, Render, RenderError};
+
+/// render the JSON value
+///
+/// Strings are rendered without quotes, `null`, booleans and numbers are rendered
+/// as-is, and arrays and objects are serialized into compact JSON.
+impl Render for Value {
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        match *self {
+            Value::Null => b.push_str("null"),
+            Value::Bool(v) => v.render(b)?,
+            Value::Number(ref n) => fmt::write(b, format_args!("{}", n))?,
+            Value::String(ref s) => b.push_str(s),
+            Value::Array(_) | Value::Object(_) => {
+                fmt::write(b, format_args!("{}", self))?
+            }
+        }
+        Ok(())
+    }
+
+    fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        match *self {
+            Value::String(ref s) => escape::escape_to_buf(s, b),
+            Value::Array(_) | Value::Object(_) => {
+                fmt::write(&mut EscapeWriter(b), format_args!("{}", self))?
+            }
+            // escape string
+            _ => self.render(b)?,
+        }
+        Ok(())
+    }
+}
+
+/// Render the value serialized as JSON
+///
+/// Inside `<%= %>` tag, `<`, `>`, `&` and the line separators (U+2028, U+2029)
+/// are replaced with the unicode escape sequences (e.g. `\u003c`) instead of
+/// HTML escaping, so that the result can be safely embedded into `<script>`
+/// block (e.g. for hydration payloads) and still be parsed as the same value.
+///
+/// # Examples
+///
+/// ```
+/// use sailfish::runtime::{Buffer, Render, ToJson};
+/// use serde_json::json;
+///
+/// let props = json!({"title": "</script><script>alert(1)</script>"});
+/// let mut b = Buffer::new();
+/// ToJson(&props).render_escaped(&mut b).unwrap();
+/// assert_eq!(
+///     b.as_str(),
+///     r#"{"title":"\u003c/script\u003e\u003cscript\u003ealert(1)\u003c/script\u003e"}"#
+/// );
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct ToJson<T>(pub T);
+
+impl<T: Serialize> ToJson<T> {
+    fn serialize(&self) -> Result<String, RenderError> {
+        serde_json::to_string(&self.0).map_err(|e| RenderError::new(&e.to_string()))
+    }
+}
+
+impl<T: Serialize> Render for ToJson<T> {
+    #[inline]
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        b.push_str(&self.serialize()?);
+        Ok(())
+    }
+
+    fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        let json = self.serialize()?;
+
+        // these characters can only appear inside JSON strings, where the unicode
+        // escape sequences represent the same characters
+        let mut start = 0;
+        for (i, c) in json.char_indices() {
+            let escaped = match c {
+                '<' => "\\u003c",
+                '>' => "\\u003e",
+                '&' => "\\u0026",
+                '\u{2028}' => "\\u2028",
+                '\u{2029}' => "\\u2029",
+                _ => continue,
+            };
+
+            b.push_str(&json[start..i]);
+            b.push_str(escaped);
+            start = i + c.len_utf8();
+        }
+
+        b.push_str(&json[start..]);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn value() {
+        let mut b = Buffer::new();
+        for v in &[
+            json!(null),
+            json!(true),
+            json!(-1),
+            json!(0.5),
+            json!("<a>"),
+        ] {
+            v.render(&mut b).unwrap();
+            b.push(' ');
+        }
+        json!("<a>").render_escaped(&mut b).unwrap();
+        assert_eq!(b.as_str(), "null true -1 0.5 <a> &lt;a&gt;");
+        b.clear();
+
+        let v = json!({"name": "<b>", "tags": [1, "x"]});
+        v.render(&mut b).unwrap();
+        b.push(' ');
+        v.render_escaped(&mut b).unwrap();
+        assert_eq!(
+            b.as_str(),
+            "{\"name\":\"<b>\",\"tags\":[1,\"x\"]} \
+             {&quot;name&quot;:&quot;&lt;b&gt;&quot;,&quot;tags&quot;:[1,&quot;x&quot;]}"
+        );
+    }
+
+    #[test]
+    fn to_json() {
+        let props = (1, "<!-- a & b -->\u{2028}'\"");
+        let mut b = Buffer::new();
+        ToJson(&props).render(&mut b).unwrap();
+        b.push(' ');
+        ToJson(&props).render_escaped(&mut b).unwrap();
+        assert_eq!(
+            b.as_str(),
+            "[1,\"<!-- a & b -->\u{2028}'\\\"\"] \
+             [1,\"\\u003c!-- a \\u0026 b --\\u003e\\u2028'\\\"\"]"
+        );
+    }
+}
@@ -0,0 +1,25 @@
+//! `Render` implementations and conversions for types in external crates
+
+#[cfg(feature = "bytes")]
+mod bytes;
+
+#[cfg(feature = "chrono")]
+mod chrono;
+
+#[cfg(feature = "json")]
+mod json;
+
+#[cfg(feature = "serde")]
+mod serde;
+
+#[cfg(feature = "time")]
+mod time;
+
+#[cfg(feature = "uuid")]
+mod uuid;
+
+#[cfg(feature = "chrono")]
+pub use self::chrono::*;
+
+#[cfg(feature = "json")]
+pub use self::json::*;
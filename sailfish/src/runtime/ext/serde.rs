@@ -0,0 +1,42 @@
+use serde::{Serialize, Serializer};
+
+use crate::runtime::Buffer;
+
+/// Serialize the buffer as a string
+///
+/// # Examples
+///
+/// ```
+/// # #[cfg(feature = "json")] {
+/// use sailfish::runtime::Buffer;
+///
+/// let fragment = Buffer::from("<p>Hello</p>");
+/// let json = serde_json::to_string(&("html", fragment)).unwrap();
+/// assert_eq!(json, r#"["html","<p>Hello</p>"]"#);
+/// # }
+/// ```
+impl Serialize for Buffer {
+    #[inline]
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[cfg(all(test, feature = "json"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialize() {
+        let mut buf = Buffer::new();
+        buf.push_str("<a href=\"/\">漢字</a>");
+        assert_eq!(
+            serde_json::to_string(&buf).unwrap(),
+            r#""<a href=\"/\">漢字</a>""#
+        );
+        assert_eq!(
+            serde_json::to_value(&buf).unwrap(),
+            serde_json::Value::from(buf.as_str())
+        );
+    }
+}
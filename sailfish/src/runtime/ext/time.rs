@@ -0,0 +1,130 @@
+use time::{Date, OffsetDateTime, PrimitiveDateTime, Time, UtcOffset};
+
+use crate::runtime::render::{render_date, render_time};
+use crate::runtime::{utils, Buffer, Render, RenderError};
+
+macro_rules! render_time_escaped {
+    () => {
+        #[inline]
+        fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+            // escape string
+            self.render(b)
+        }
+    };
+}
+
+/// render in `YYYY-MM-DD` format (e.g. `2020-09-13`)
+impl Render for Date {
+    #[inline]
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        let (year, month, day) = self.to_calendar_date();
+        render_date(year as i64, month as u8, day, b)
+    }
+
+    render_time_escaped!();
+}
+
+/// render in `HH:MM:SS[.fraction]` format (e.g. `12:26:40.5`)
+impl Render for Time {
+    #[inline]
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        let (hour, minute, second, nanos) = self.as_hms_nano();
+        render_time(hour, minute, second, nanos, b);
+        Ok(())
+    }
+
+    render_time_escaped!();
+}
+
+/// render in RFC 3339 format without timezone (e.g. `2020-09-13T12:26:40.5`)
+impl Render for PrimitiveDateTime {
+    #[inline]
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        self.date().render(b)?;
+        b.push('T');
+        self.time().render(b)
+    }
+
+    render_time_escaped!();
+}
+
+/// render in `Z` or `±HH:MM` format (e.g. `+09:00`)
+///
+/// Seconds of the offset are truncated since RFC 3339 cannot represent them.
+impl Render for UtcOffset {
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        if self.is_utc() {
+            b.push('Z');
+            return Ok(());
+        }
+
+        let (hours, minutes, _) = self.as_hms();
+        b.push(if self.is_negative() { '-' } else { '+' });
+        render_time_offset(hours.abs() as u8, minutes.abs() as u8, b);
+        Ok(())
+    }
+
+    render_time_escaped!();
+}
+
+/// render in RFC 3339 format (e.g. `2020-09-13T12:26:40.5+09:00`)
+impl Render for OffsetDateTime {
+    #[inline]
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        self.date().render(b)?;
+        b.push('T');
+        self.time().render(b)?;
+        self.offset().render(b)
+    }
+
+    render_time_escaped!();
+}
+
+#[inline]
+fn render_time_offset(hours: u8, minutes: u8, b: &mut Buffer) {
+    let digits = [
+        b'0' + hours / 10,
+        b'0' + hours % 10,
+        b':',
+        b'0' + minutes / 10,
+        b'0' + minutes % 10,
+    ];
+    b.push_str(utils::ascii_to_str(&digits));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::Month;
+
+    #[test]
+    fn rfc3339() {
+        let date = Date::from_calendar_date(2020, Month::September, 13).unwrap();
+        let datetime = date.with_hms_milli(12, 26, 40, 500).unwrap();
+
+        let mut b = Buffer::new();
+        date.render(&mut b).unwrap();
+        b.push(' ');
+        datetime.time().render(&mut b).unwrap();
+        b.push(' ');
+        datetime.render(&mut b).unwrap();
+        b.push(' ');
+        datetime.assume_utc().render(&mut b).unwrap();
+        b.push(' ');
+        date.with_hms(21, 0, 0)
+            .unwrap()
+            .assume_offset(UtcOffset::from_hms(9, 0, 0).unwrap())
+            .render_escaped(&mut b)
+            .unwrap();
+        b.push(' ');
+        date.midnight()
+            .assume_offset(UtcOffset::from_hms(-3, -30, 0).unwrap())
+            .render(&mut b)
+            .unwrap();
+        assert_eq!(
+            b.as_str(),
+            "2020-09-13 12:26:40.5 2020-09-13T12:26:40.5 2020-09-13T12:26:40.5Z \
+             2020-09-13T21:00:00+09:00 2020-09-13T00:00:00-03:30"
+        );
+    }
+}
@@ -0,0 +1,38 @@
+use uuid::Uuid;
+
+use crate::runtime::{Buffer, Render, RenderError};
+
+/// render in lowercase hyphenated format (e.g. `67e55044-10b1-426f-9247-bb680e5fe0c8`)
+impl Render for Uuid {
+    #[inline]
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        let mut buf = Uuid::encode_buffer();
+        b.push_str(self.hyphenated().encode_lower(&mut buf));
+        Ok(())
+    }
+
+    #[inline]
+    fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        // escape string
+        self.render(b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hyphenated() {
+        let id = Uuid::from_u128(0x67e5_5044_10b1_426f_9247_bb68_0e5f_e0c8);
+
+        let mut b = Buffer::new();
+        id.render(&mut b).unwrap();
+        b.push(' ');
+        Uuid::nil().render_escaped(&mut b).unwrap();
+        assert_eq!(
+            b.as_str(),
+            "67e55044-10b1-426f-9247-bb680e5fe0c8 00000000-0000-0000-0000-000000000000"
+        );
+    }
+}
@@ -2,9 +2,14 @@
 
 // TODO: performance improvement
 
-use std::fmt;
-use std::ptr;
-
+use alloc::borrow::ToOwned;
+use core::fmt;
+#[cfg(not(feature = "safe"))]
+use core::ptr;
+
+use super::escape::{self, Escaper};
+#[cfg(feature = "json")]
+use super::ToJson;
 use super::{Buffer, Render, RenderError};
 
 pub struct Display<'a, T>(&'a T);
@@ -39,7 +44,10 @@ pub fn dbg<T: fmt::Debug>(expr: &T) -> Debug<T> {
     Debug(expr)
 }
 
-pub struct Upper<'a, T>(&'a T);
+/// Render the contents converted to uppercase
+///
+/// This is the adaptor returned by [`upper`](fn.upper.html) filter.
+pub struct Upper<'a, T>(pub &'a T);
 
 impl<'a, T: Render> Render for Upper<'a, T> {
     fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
@@ -47,7 +55,7 @@ impl<'a, T: Render> Render for Upper<'a, T> {
         self.0.render(b)?;
 
         let s = b.as_str()[old_len..].to_uppercase();
-        unsafe { b._set_len(old_len) };
+        b.truncate(old_len);
         b.push_str(&*s);
         Ok(())
     }
@@ -59,7 +67,10 @@ pub fn upper<T: Render>(expr: &T) -> Upper<T> {
     Upper(expr)
 }
 
-pub struct Lower<'a, T>(&'a T);
+/// Render the contents converted to lowercase
+///
+/// This is the adaptor returned by [`lower`](fn.lower.html) filter.
+pub struct Lower<'a, T>(pub &'a T);
 
 impl<'a, T: Render> Render for Lower<'a, T> {
     fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
@@ -67,7 +78,7 @@ impl<'a, T: Render> Render for Lower<'a, T> {
         self.0.render(b)?;
 
         let s = b.as_str()[old_len..].to_lowercase();
-        unsafe { b._set_len(old_len) };
+        b.truncate(old_len);
         b.push_str(&*s);
         Ok(())
     }
@@ -77,7 +88,7 @@ impl<'a, T: Render> Render for Lower<'a, T> {
         self.0.render_escaped(b)?;
 
         let s = b.as_str()[old_len..].to_lowercase();
-        unsafe { b._set_len(old_len) };
+        b.truncate(old_len);
         b.push_str(&*s);
         Ok(())
     }
@@ -120,20 +131,26 @@ fn trim_impl(b: &mut Buffer, old_len: usize) {
 
         if new_contents.as_ptr() != trimmed.as_ptr() {
             debug_assert!(new_contents.as_ptr() < trimmed.as_ptr());
-            let offset = trimmed.as_ptr() as usize - new_contents.as_ptr() as usize;
+
+            #[cfg(not(feature = "safe"))]
             unsafe {
+                let offset = trimmed.as_ptr() as usize - new_contents.as_ptr() as usize;
                 ptr::copy(
                     b.as_mut_ptr().add(old_len + offset),
                     b.as_mut_ptr().add(old_len),
                     trimmed_len,
                 );
             }
-        }
 
-        debug_assert!(b.capacity() >= old_len + trimmed_len);
-        unsafe {
-            b._set_len(old_len + trimmed_len);
+            #[cfg(feature = "safe")]
+            {
+                let trimmed = trimmed.to_owned();
+                b.truncate(old_len);
+                b.push_str(&trimmed);
+            }
         }
+
+        b.truncate(old_len + trimmed_len);
     }
 }
 
@@ -143,6 +160,267 @@ pub fn trim<T: Render>(expr: &T) -> Trim<T> {
     Trim(expr)
 }
 
+pub struct OrElse<'a, T>(&'a Option<T>, &'a str);
+
+impl<'a, T: Render> Render for OrElse<'a, T> {
+    #[inline]
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        match *self.0 {
+            Some(ref v) => v.render(b),
+            None => {
+                b.push_str(self.1);
+                Ok(())
+            }
+        }
+    }
+
+    #[inline]
+    fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        match *self.0 {
+            Some(ref v) => v.render_escaped(b),
+            None => {
+                escape::escape_to_buf(self.1, b);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// render the placeholder instead of nothing if the value is `None`
+#[inline]
+pub fn or_else<'a, T: Render>(
+    expr: &'a Option<T>,
+    placeholder: &'a str,
+) -> OrElse<'a, T> {
+    OrElse(expr, placeholder)
+}
+
+/// Render at most the specified number of characters, followed by `…` if the
+/// contents are truncated
+///
+/// This is the adaptor returned by [`truncate`](fn.truncate.html) filter.
+pub struct Truncate<'a, T>(&'a T, usize);
+
+impl<'a, T: Render> Render for Truncate<'a, T> {
+    #[inline]
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        let old_len = b.len();
+        self.0.render(b)?;
+        truncate_impl(b, old_len, self.1, false)
+    }
+
+    #[inline]
+    fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        let old_len = b.len();
+        self.0.render(b)?;
+        truncate_impl(b, old_len, self.1, true)
+    }
+}
+
+fn truncate_impl(
+    b: &mut Buffer,
+    old_len: usize,
+    limit: usize,
+    escape: bool,
+) -> Result<(), RenderError> {
+    debug_assert!(b.len() >= old_len);
+    let contents = b.as_str()[old_len..].to_owned();
+    b.truncate(old_len);
+
+    let truncated = super::Truncate(&contents, limit);
+    if escape {
+        truncated.render_escaped(b)
+    } else {
+        truncated.render(b)
+    }
+}
+
+/// truncate the rendered contents to at most `limit` characters
+///
+/// If the contents are truncated, `…` is appended to the result. The contents
+/// are truncated before escaping, so that HTML entities are never split.
+#[inline]
+pub fn truncate<T: Render>(expr: &T, limit: usize) -> Truncate<'_, T> {
+    Truncate(expr, limit)
+}
+
+pub struct DefaultValue<'a, T, U>(&'a Option<T>, U);
+
+impl<'a, T: Render, U: Render> Render for DefaultValue<'a, T, U> {
+    #[inline]
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        match *self.0 {
+            Some(ref v) => v.render(b),
+            None => self.1.render(b),
+        }
+    }
+
+    #[inline]
+    fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        match *self.0 {
+            Some(ref v) => v.render_escaped(b),
+            None => self.1.render_escaped(b),
+        }
+    }
+
+    #[inline]
+    fn render_escaped_with(
+        &self,
+        b: &mut Buffer,
+        escaper: &dyn Escaper,
+    ) -> Result<(), RenderError> {
+        match *self.0 {
+            Some(ref v) => v.render_escaped_with(b, escaper),
+            None => self.1.render_escaped_with(b, escaper),
+        }
+    }
+}
+
+/// render the default value if the value is `None`
+///
+/// Unlike [`or_else`](fn.or_else.html), the default value can be any type which
+/// implements `Render`.
+#[inline]
+pub fn default<T: Render, U: Render>(
+    expr: &Option<T>,
+    value: U,
+) -> DefaultValue<'_, T, U> {
+    DefaultValue(expr, value)
+}
+
+/// render the value serialized as JSON
+///
+/// See [`ToJson`](../struct.ToJson.html) for how the value is escaped.
+#[cfg(feature = "json")]
+#[inline]
+pub fn json<T: serde::Serialize + ?Sized>(expr: &T) -> ToJson<&T> {
+    ToJson(expr)
+}
+
+pub struct Safe<'a, T: ?Sized>(&'a T);
+
+impl<'a, T: Render + ?Sized> Render for Safe<'a, T> {
+    #[inline]
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        self.0.render(b)
+    }
+
+    #[inline]
+    fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        self.0.render(b)
+    }
+
+    #[inline]
+    fn render_escaped_with(
+        &self,
+        b: &mut Buffer,
+        _escaper: &dyn Escaper,
+    ) -> Result<(), RenderError> {
+        self.0.render(b)
+    }
+}
+
+/// render the trusted contents without escaping even inside `<%= %>` tag
+///
+/// This is equivalent to [`Raw`](../struct.Raw.html) wrapper. Make sure that the
+/// contents never contains untrusted inputs.
+#[inline]
+pub fn safe<T: Render + ?Sized>(expr: &T) -> Safe<'_, T> {
+    Safe(expr)
+}
+
+/// Values which decide whether the attribute is rendered
+///
+/// `true` renders the attribute without value (e.g. `disabled`), and `Some(v)`
+/// renders the attribute with the value. `false` and `None` render nothing.
+pub trait AttrValue {
+    /// Returns `None` if the attribute is omitted, or the value of the attribute
+    fn attr_value(&self) -> Option<Option<&dyn Render>>;
+}
+
+impl AttrValue for bool {
+    #[inline]
+    fn attr_value(&self) -> Option<Option<&dyn Render>> {
+        if *self {
+            Some(None)
+        } else {
+            None
+        }
+    }
+}
+
+impl<T: Render> AttrValue for Option<T> {
+    #[inline]
+    fn attr_value(&self) -> Option<Option<&dyn Render>> {
+        self.as_ref().map(|v| Some(v as &dyn Render))
+    }
+}
+
+impl<T: AttrValue + ?Sized> AttrValue for &T {
+    #[inline]
+    fn attr_value(&self) -> Option<Option<&dyn Render>> {
+        (**self).attr_value()
+    }
+}
+
+/// Render the HTML attribute only if the value is `true` or `Some`
+///
+/// This is the adaptor returned by [`attr_if`](fn.attr_if.html) filter.
+pub struct AttrIf<'a, T: ?Sized>(&'a T, &'a str);
+
+impl<'a, T: AttrValue + ?Sized> AttrIf<'a, T> {
+    #[inline]
+    fn write<F>(&self, b: &mut Buffer, f: F) -> Result<(), RenderError>
+    where
+        F: FnOnce(&dyn Render, &mut Buffer) -> Result<(), RenderError>,
+    {
+        if let Some(value) = self.0.attr_value() {
+            b.push(' ');
+            b.push_str(self.1);
+            if let Some(value) = value {
+                b.push_str("=\"");
+                f(value, b)?;
+                b.push('"');
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a, T: AttrValue + ?Sized> Render for AttrIf<'a, T> {
+    #[inline]
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        self.write(b, |v, b| v.render(b))
+    }
+
+    #[inline]
+    fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        self.write(b, |v, b| v.render_escaped(b))
+    }
+
+    #[inline]
+    fn render_escaped_with(
+        &self,
+        b: &mut Buffer,
+        escaper: &dyn Escaper,
+    ) -> Result<(), RenderError> {
+        self.write(b, |v, b| v.render_escaped_with(b, escaper))
+    }
+}
+
+/// render the HTML attribute only if the value is `true` or `Some`
+///
+/// The attribute is rendered with a leading space, so the filter can be placed
+/// right after the other attributes. The attribute name is never escaped.
+///
+/// ```text
+/// <input type="text"<%= !editable | attr_if("disabled") %><%= title | attr_if("title") %>>
+/// ```
+#[inline]
+pub fn attr_if<'a, T: AttrValue + ?Sized>(expr: &'a T, name: &'a str) -> AttrIf<'a, T> {
+    AttrIf(expr, name)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -180,4 +458,66 @@ mod tests {
         trim(&" hello").render(&mut buf).unwrap();
         assert_eq!(buf.as_str(), "hello");
     }
+
+    #[test]
+    fn or_else_test() {
+        let mut buf = Buffer::new();
+        or_else(&Some(1), "none").render(&mut buf).unwrap();
+        or_else(&None::<i32>, "<none>").render(&mut buf).unwrap();
+        or_else(&None::<i32>, "<none>")
+            .render_escaped(&mut buf)
+            .unwrap();
+        assert_eq!(buf.as_str(), "1<none>&lt;none&gt;");
+    }
+
+    #[test]
+    fn truncate_test() {
+        let mut buf = Buffer::new();
+        truncate(&"hello", 5).render(&mut buf).unwrap();
+        truncate(&"hello, world", 5).render(&mut buf).unwrap();
+        truncate(&"日本語の文章", 3).render(&mut buf).unwrap();
+        truncate(&"<b>&amp;", 4).render_escaped(&mut buf).unwrap();
+        assert_eq!(buf.as_str(), "hellohello…日本語…&lt;b&gt;&amp;…");
+    }
+
+    #[test]
+    fn default_test() {
+        let mut buf = Buffer::new();
+        default(&Some("a"), 0).render(&mut buf).unwrap();
+        default(&None::<&str>, 0).render(&mut buf).unwrap();
+        default(&None::<i32>, "<none>")
+            .render_escaped(&mut buf)
+            .unwrap();
+        assert_eq!(buf.as_str(), "a0&lt;none&gt;");
+    }
+
+    #[test]
+    fn safe_test() {
+        let mut buf = Buffer::new();
+        safe(&"<b>bold</b>").render_escaped(&mut buf).unwrap();
+        assert_eq!(buf.as_str(), "<b>bold</b>");
+    }
+
+    #[test]
+    fn attr_if_test() {
+        let mut buf = Buffer::new();
+        attr_if(&true, "disabled").render_escaped(&mut buf).unwrap();
+        attr_if(&false, "hidden").render_escaped(&mut buf).unwrap();
+        attr_if(&Some("a&b"), "title")
+            .render_escaped(&mut buf)
+            .unwrap();
+        attr_if(&None::<i32>, "tabindex")
+            .render_escaped(&mut buf)
+            .unwrap();
+        attr_if(&Some(1), "tabindex").render(&mut buf).unwrap();
+        assert_eq!(buf.as_str(), " disabled title=\"a&amp;b\" tabindex=\"1\"");
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json_test() {
+        let mut buf = Buffer::new();
+        json(&vec!["<a>", "b"]).render_escaped(&mut buf).unwrap();
+        assert_eq!(buf.as_str(), r#"["\u003ca\u003e","b"]"#);
+    }
 }
@@ -0,0 +1,945 @@
+//! Adaptors to render values in the specific format
+
+use core::fmt;
+#[cfg(not(feature = "safe"))]
+use core::ptr;
+
+pub use super::filter::{Lower, Upper};
+use super::{escape, filter, Buffer, Render, RenderError};
+
+/// Alignment of the contents used by [`Fmt`](struct.Fmt.html)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Right,
+    Center,
+}
+
+/// Render the value with the specified width and precision
+///
+/// This works like the format specifiers in `format!` macro (e.g. `{:>10.2}`),
+/// but the parameters can be determined at runtime and no temporary `String` is
+/// allocated.
+///
+/// The contents are right-aligned by default.
+///
+/// # Examples
+///
+/// ```
+/// use sailfish::runtime::{Align, Buffer, Fmt, Render};
+///
+/// let mut b = Buffer::new();
+/// Fmt::new(3.14159).width(8).precision(2).render(&mut b).unwrap();
+/// Fmt::new("ab").width(6).align(Align::Center).fill('*').render(&mut b).unwrap();
+/// assert_eq!(b.as_str(), "    3.14**ab**");
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct Fmt<T> {
+    value: T,
+    width: Option<usize>,
+    precision: Option<usize>,
+    align: Align,
+    fill: char,
+}
+
+impl<T> Fmt<T> {
+    /// Create a new adaptor without width and precision
+    #[inline]
+    pub fn new(value: T) -> Self {
+        Self {
+            value,
+            width: None,
+            precision: None,
+            align: Align::Right,
+            fill: ' ',
+        }
+    }
+
+    /// Set the minimum number of characters to be rendered
+    #[inline]
+    pub fn width(mut self, width: usize) -> Self {
+        self.width = Some(width);
+        self
+    }
+
+    /// Set the precision which is passed to `Display` implementation
+    ///
+    /// For floating point numbers, this is the number of digits after the decimal
+    /// point. For strings, this is the maximum number of characters.
+    #[inline]
+    pub fn precision(mut self, precision: usize) -> Self {
+        self.precision = Some(precision);
+        self
+    }
+
+    /// Set the alignment of the contents
+    #[inline]
+    pub fn align(mut self, align: Align) -> Self {
+        self.align = align;
+        self
+    }
+
+    /// Set the character used for padding
+    #[inline]
+    pub fn fill(mut self, fill: char) -> Self {
+        self.fill = fill;
+        self
+    }
+}
+
+impl<T: fmt::Display> Render for Fmt<T> {
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        let old_len = b.len();
+        match self.precision {
+            Some(p) => fmt::write(b, format_args!("{:.*}", p, self.value))?,
+            None => fmt::write(b, format_args!("{}", self.value))?,
+        }
+
+        if let Some(width) = self.width {
+            let count = b.as_str()[old_len..].chars().count();
+            if count < width {
+                let padding = width - count;
+                let (pre, post) = match self.align {
+                    Align::Left => (0, padding),
+                    Align::Right => (padding, 0),
+                    Align::Center => (padding / 2, padding - padding / 2),
+                };
+                pad_impl(b, old_len, self.fill, pre, post);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        // width is calculated from the contents before escaping
+        let old_len = b.len();
+        self.render(b)?;
+        escape::escape_tail(b, old_len);
+        Ok(())
+    }
+}
+
+/// insert `pre` fill characters before the contents rendered after `old_len`, and
+/// append `post` fill characters after them
+fn pad_impl(b: &mut Buffer, old_len: usize, fill: char, pre: usize, post: usize) {
+    let mut tmp = [0; 4];
+    let fill: &str = fill.encode_utf8(&mut tmp);
+
+    if pre > 0 {
+        #[cfg(feature = "safe")]
+        b.insert_str(old_len, &fill.repeat(pre));
+
+        #[cfg(not(feature = "safe"))]
+        unsafe {
+            let content_len = b.len() - old_len;
+            let pre_len = fill.len() * pre;
+            b.reserve(pre_len);
+
+            let p = b.as_mut_ptr().add(old_len);
+            ptr::copy(p, p.add(pre_len), content_len);
+            for i in 0..pre {
+                ptr::copy_nonoverlapping(
+                    fill.as_ptr(),
+                    p.add(i * fill.len()),
+                    fill.len(),
+                );
+            }
+            b.advance(pre_len);
+        }
+    }
+
+    for _ in 0..post {
+        b.push_str(fill);
+    }
+}
+
+/// Render the integer with thousands separators (e.g. `1,234,567`)
+///
+/// The separator is `,` by default.
+///
+/// # Examples
+///
+/// ```
+/// use sailfish::runtime::{Buffer, Grouped, Render};
+///
+/// let mut b = Buffer::new();
+/// Grouped::new(1234567).render(&mut b).unwrap();
+/// b.push(' ');
+/// Grouped::new(-98765).separator('_').render(&mut b).unwrap();
+/// assert_eq!(b.as_str(), "1,234,567 -98_765");
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct Grouped<T> {
+    value: T,
+    separator: char,
+}
+
+impl<T> Grouped<T> {
+    /// Create a new adaptor with the default separator
+    #[inline]
+    pub fn new(value: T) -> Self {
+        Self {
+            value,
+            separator: ',',
+        }
+    }
+
+    /// Set the character inserted between each group of three digits
+    #[inline]
+    pub fn separator(mut self, separator: char) -> Self {
+        self.separator = separator;
+        self
+    }
+}
+
+macro_rules! render_grouped {
+    ($($int:ty),*) => {
+        $(
+            impl Render for Grouped<$int> {
+                #[inline]
+                fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+                    let mut tmp = [0; 4];
+                    let separator = self.separator.encode_utf8(&mut tmp);
+
+                    let old_len = b.len();
+                    self.value.render(b)?;
+                    group_impl(b, old_len, separator);
+                    Ok(())
+                }
+
+                #[inline]
+                fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+                    let mut tmp = [0; 4];
+                    let separator = match self.separator {
+                        '\"' => "&quot;",
+                        '&' => "&amp;",
+                        '<' => "&lt;",
+                        '>' => "&gt;",
+                        '\'' => "&#039;",
+                        c => c.encode_utf8(&mut tmp),
+                    };
+
+                    let old_len = b.len();
+                    self.value.render(b)?;
+                    group_impl(b, old_len, separator);
+                    Ok(())
+                }
+            }
+        )*
+    }
+}
+
+render_grouped!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128, usize, isize);
+
+/// insert the separator between each group of three digits rendered after `old_len`
+fn group_impl(b: &mut Buffer, old_len: usize, separator: &str) {
+    let start = if b.as_str().as_bytes().get(old_len) == Some(&b'-') {
+        old_len + 1
+    } else {
+        old_len
+    };
+
+    let digits = b.len() - start;
+    if digits <= 3 {
+        return;
+    }
+
+    #[cfg(feature = "safe")]
+    {
+        let mut pos = b.len();
+        while pos - start > 3 {
+            pos -= 3;
+            b.insert_str(pos, separator);
+        }
+    }
+
+    // move each group from the last one to make space for separators
+    #[cfg(not(feature = "safe"))]
+    unsafe {
+        let extra = (digits - 1) / 3 * separator.len();
+        b.reserve(extra);
+
+        let base = b.as_mut_ptr().add(start);
+        let mut src = digits;
+        let mut dst = digits + extra;
+        while src > 3 {
+            src -= 3;
+            dst -= 3;
+            ptr::copy(base.add(src), base.add(dst), 3);
+            dst -= separator.len();
+            ptr::copy_nonoverlapping(separator.as_ptr(), base.add(dst), separator.len());
+        }
+        debug_assert_eq!(src, dst);
+        b.advance(extra);
+    }
+}
+
+/// Render the floating point number with the fixed number of decimal places
+///
+/// The value is rounded half to even based on its exact decimal representation,
+/// which is the same as `format!("{:.*}", precision, value)`.
+///
+/// # Examples
+///
+/// ```
+/// use sailfish::runtime::{Buffer, FixedFloat, Render};
+///
+/// let mut b = Buffer::new();
+/// FixedFloat(2.0 / 3.0, 2).render(&mut b).unwrap();
+/// b.push(' ');
+/// FixedFloat(12.0, 3).render(&mut b).unwrap();
+/// assert_eq!(b.as_str(), "0.67 12.000");
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FixedFloat(pub f64, pub usize);
+
+impl Render for FixedFloat {
+    #[inline]
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        if self.0.is_finite() {
+            fmt::write(b, format_args!("{:.*}", self.1, self.0))?;
+            Ok(())
+        } else {
+            // `inf`, `-inf` or `NaN`
+            self.0.render(b)
+        }
+    }
+
+    #[inline]
+    fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        // escape string
+        self.render(b)
+    }
+}
+
+/// Render the number of bytes in binary units with one decimal place
+///
+/// # Examples
+///
+/// ```
+/// use sailfish::runtime::{Buffer, HumanBytes, Render};
+///
+/// let mut b = Buffer::new();
+/// HumanBytes(512).render(&mut b).unwrap();
+/// b.push(' ');
+/// HumanBytes(1_500_000_000).render(&mut b).unwrap();
+/// assert_eq!(b.as_str(), "512 B 1.4 GiB");
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HumanBytes(pub u64);
+
+impl Render for HumanBytes {
+    #[inline]
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        if self.0 < 1024 {
+            self.0.render(b)?;
+            b.push_str(" B");
+            Ok(())
+        } else {
+            render_scaled(
+                self.0,
+                1024,
+                &[" KiB", " MiB", " GiB", " TiB", " PiB", " EiB"],
+                b,
+            )
+        }
+    }
+
+    #[inline]
+    fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        // escape string
+        self.render(b)
+    }
+}
+
+/// Render the count in metric units with one decimal place
+///
+/// # Examples
+///
+/// ```
+/// use sailfish::runtime::{Buffer, HumanCount, Render};
+///
+/// let mut b = Buffer::new();
+/// HumanCount(999).render(&mut b).unwrap();
+/// b.push(' ');
+/// HumanCount(2_345_678).render(&mut b).unwrap();
+/// assert_eq!(b.as_str(), "999 2.3M");
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HumanCount(pub u64);
+
+impl Render for HumanCount {
+    #[inline]
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        if self.0 < 1000 {
+            self.0.render(b)
+        } else {
+            render_scaled(self.0, 1000, &["K", "M", "G", "T", "P", "E"], b)
+        }
+    }
+
+    #[inline]
+    fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        // escape string
+        self.render(b)
+    }
+}
+
+/// render the value divided by the largest power of `base` with its unit
+fn render_scaled(
+    value: u64,
+    base: u64,
+    units: &[&str],
+    b: &mut Buffer,
+) -> Result<(), RenderError> {
+    debug_assert!(value >= base);
+
+    let mut divisor = base as u128;
+    let mut unit = 0;
+    loop {
+        // rounded value in tenths of the current unit
+        let tenths = (value as u128 * 10 + divisor / 2) / divisor;
+        if tenths < base as u128 * 10 || unit + 1 == units.len() {
+            (tenths / 10).render(b)?;
+            b.push('.');
+            ((tenths % 10) as u8).render(b)?;
+            b.push_str(units[unit]);
+            return Ok(());
+        }
+
+        divisor *= base as u128;
+        unit += 1;
+    }
+}
+
+/// Render at most the specified number of characters, followed by `…` if the
+/// string is truncated
+///
+/// The string is cut on the character boundaries and escaped after truncation,
+/// so that the HTML entities are never split.
+///
+/// # Examples
+///
+/// ```
+/// use sailfish::runtime::{Buffer, Render, Truncate};
+///
+/// let mut b = Buffer::new();
+/// Truncate("Tom & Jerry", 5).render_escaped(&mut b).unwrap();
+/// b.push(' ');
+/// Truncate("short", 5).render_escaped(&mut b).unwrap();
+/// assert_eq!(b.as_str(), "Tom &amp;… short");
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Truncate<'a>(pub &'a str, pub usize);
+
+impl<'a> Truncate<'a> {
+    /// split off the string at the character limit
+    #[inline]
+    fn split(&self) -> (&'a str, bool) {
+        match self.0.char_indices().nth(self.1) {
+            Some((idx, _)) => (&self.0[..idx], true),
+            None => (self.0, false),
+        }
+    }
+}
+
+impl<'a> Render for Truncate<'a> {
+    #[inline]
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        let (s, truncated) = self.split();
+        b.push_str(s);
+        if truncated {
+            b.push('…');
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        let (s, truncated) = self.split();
+        escape::escape_to_buf(s, b);
+        if truncated {
+            b.push('…');
+        }
+        Ok(())
+    }
+}
+
+/// Render the string with the first letter of each word in uppercase and the
+/// rest in lowercase
+///
+/// Words are separated by whitespaces. Use [`Upper`](struct.Upper.html) and
+/// [`Lower`](struct.Lower.html) to convert the whole contents.
+///
+/// # Examples
+///
+/// ```
+/// use sailfish::runtime::{Buffer, Lower, Render, Title, Upper};
+///
+/// let mut b = Buffer::new();
+/// Title("hello wORLD").render(&mut b).unwrap();
+/// b.push(' ');
+/// Upper(&"straße").render(&mut b).unwrap();
+/// b.push(' ');
+/// Lower(&"ÀB<C>").render_escaped(&mut b).unwrap();
+/// assert_eq!(b.as_str(), "Hello World STRASSE àb&lt;c&gt;");
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Title<'a>(pub &'a str);
+
+impl<'a> Render for Title<'a> {
+    #[inline]
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        convert_case(self.0, b, false, title_case)
+    }
+
+    #[inline]
+    fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        convert_case(self.0, b, true, title_case)
+    }
+}
+
+enum CaseIter {
+    Upper(core::char::ToUppercase),
+    Lower(core::char::ToLowercase),
+}
+
+impl Iterator for CaseIter {
+    type Item = char;
+
+    #[inline]
+    fn next(&mut self) -> Option<char> {
+        match *self {
+            CaseIter::Upper(ref mut it) => it.next(),
+            CaseIter::Lower(ref mut it) => it.next(),
+        }
+    }
+}
+
+#[inline]
+fn title_case(prev: Option<char>, c: char) -> CaseIter {
+    match prev {
+        Some(p) if !p.is_whitespace() => CaseIter::Lower(c.to_lowercase()),
+        _ => CaseIter::Upper(c.to_uppercase()),
+    }
+}
+
+/// render each character of the string converted by `f`, which receives the
+/// previous character and the current character
+fn convert_case<F>(s: &str, b: &mut Buffer, escape: bool, f: F) -> Result<(), RenderError>
+where
+    F: Fn(Option<char>, char) -> CaseIter,
+{
+    b.reserve(s.len());
+
+    let mut prev = None;
+    for c in s.chars() {
+        for converted in f(prev, c) {
+            if escape {
+                converted.render_escaped(b)?;
+            } else {
+                b.push(converted);
+            }
+        }
+        prev = Some(c);
+    }
+    Ok(())
+}
+
+/// Render the placeholder if the value is `None`
+///
+/// This is the owned version of [`or_else`](filter/fn.or_else.html) filter. The
+/// placeholder is escaped as well as the value if it is rendered inside
+/// `<%= %>` tag.
+///
+/// # Examples
+///
+/// ```
+/// use sailfish::runtime::{Buffer, OrDefault, Render};
+///
+/// let mut b = Buffer::new();
+/// OrDefault(Some("<b>"), "anonymous").render_escaped(&mut b).unwrap();
+/// b.push(' ');
+/// OrDefault(None::<&str>, "anonymous").render_escaped(&mut b).unwrap();
+/// assert_eq!(b.as_str(), "&lt;b&gt; anonymous");
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OrDefault<'a, T>(pub Option<T>, pub &'a str);
+
+impl<'a, T: Render> Render for OrDefault<'a, T> {
+    #[inline]
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        filter::or_else(&self.0, self.1).render(b)
+    }
+
+    #[inline]
+    fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        filter::or_else(&self.0, self.1).render_escaped(b)
+    }
+}
+
+/// Render the placeholder if the string is empty
+///
+/// # Examples
+///
+/// ```
+/// use sailfish::runtime::{Buffer, NonEmptyOr, Render};
+///
+/// let mut b = Buffer::new();
+/// NonEmptyOr("", "(no title)").render_escaped(&mut b).unwrap();
+/// assert_eq!(b.as_str(), "(no title)");
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NonEmptyOr<'a>(pub &'a str, pub &'a str);
+
+impl<'a> NonEmptyOr<'a> {
+    #[inline]
+    fn value(&self) -> Option<&'a str> {
+        if self.0.is_empty() {
+            None
+        } else {
+            Some(self.0)
+        }
+    }
+}
+
+impl<'a> Render for NonEmptyOr<'a> {
+    #[inline]
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        filter::or_else(&self.value(), self.1).render(b)
+    }
+
+    #[inline]
+    fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        filter::or_else(&self.value(), self.1).render_escaped(b)
+    }
+}
+
+/// Render the value as JavaScript string literal
+///
+/// The contents are escaped with
+/// [`escape_js_to_buf`](escape/fn.escape_js_to_buf.html) and surrounded by
+/// double quotes, so that the value can be safely embedded into inline
+/// `<script>` block. HTML escaping is never applied since it is meaningless
+/// inside `<script>` block.
+///
+/// # Examples
+///
+/// ```
+/// use sailfish::runtime::{Buffer, JsString, Render};
+///
+/// let mut b = Buffer::new();
+/// JsString("</script><script>alert(1)")
+///     .render_escaped(&mut b)
+///     .unwrap();
+/// assert_eq!(
+///     b.as_str(),
+///     "\"\\u003C/script\\u003E\\u003Cscript\\u003Ealert(1)\""
+/// );
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct JsString<T>(pub T);
+
+impl<T: Render> Render for JsString<T> {
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        let mut tmp = Buffer::new();
+        self.0.render(&mut tmp)?;
+        b.push('"');
+        escape::escape_js_to_buf(tmp.as_str(), b);
+        b.push('"');
+        Ok(())
+    }
+
+    #[inline]
+    fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        self.render(b)
+    }
+}
+
+/// Render the percent-encoded string
+///
+/// All characters except the unreserved characters (`A-Za-z0-9-._~`) are
+/// encoded, so the result can be used both as a path segment and as a key or
+/// value in query string. Use
+/// [`percent_encode_to_buf`](escape/fn.percent_encode_to_buf.html) if you
+/// want to keep the sub-delimiters in path segments.
+///
+/// # Examples
+///
+/// ```
+/// use sailfish::runtime::{Buffer, Render, UrlEncode};
+///
+/// let mut b = Buffer::new();
+/// b.push_str("/search?q=");
+/// UrlEncode("fish & chips").render_escaped(&mut b).unwrap();
+/// assert_eq!(b.as_str(), "/search?q=fish%20%26%20chips");
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UrlEncode<'a>(pub &'a str);
+
+impl<'a> Render for UrlEncode<'a> {
+    #[inline]
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        escape::percent_encode_to_buf(self.0, b, escape::EncodeSet::QueryComponent);
+        Ok(())
+    }
+
+    #[inline]
+    fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        // encoded string does not contain any HTML special characters
+        self.render(b)
+    }
+}
+
+/// Render the value escaped for double-quoted attribute value
+///
+/// Only `"` and `&` are escaped by
+/// [`escape_attr_to_buf`](escape/fn.escape_attr_to_buf.html), which is cheaper
+/// than the full HTML escaping. The value is escaped in the same way whether or
+/// not it is rendered inside `<%= %>` tag.
+///
+/// # Examples
+///
+/// ```
+/// use sailfish::runtime::{Attr, Buffer, Render};
+///
+/// let mut b = Buffer::new();
+/// b.push_str("<input value=\"");
+/// Attr("<\"quoted\">").render_escaped(&mut b).unwrap();
+/// b.push_str("\">");
+/// assert_eq!(b.as_str(), "<input value=\"<&quot;quoted&quot;>\">");
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Attr<T>(pub T);
+
+impl<T: Render> Render for Attr<T> {
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        let old_len = b.len();
+        self.0.render(b)?;
+
+        if b.as_str()[old_len..]
+            .bytes()
+            .any(|c| c == b'"' || c == b'&')
+        {
+            escape::escape_tail_with(b, old_len, &escape::escape_attr_to_buf);
+        }
+
+        Ok(())
+    }
+
+    #[inline]
+    fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        self.render(b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::String;
+
+    #[test]
+    fn fmt() {
+        let mut b = Buffer::new();
+        Fmt::new(42).width(5).render(&mut b).unwrap();
+        b.push('|');
+        Fmt::new(1.0 / 3.0).precision(3).render(&mut b).unwrap();
+        b.push('|');
+        Fmt::new("abc")
+            .width(5)
+            .align(Align::Left)
+            .render(&mut b)
+            .unwrap();
+        b.push('|');
+        Fmt::new("abc")
+            .width(6)
+            .align(Align::Center)
+            .fill('あ')
+            .render(&mut b)
+            .unwrap();
+        b.push('|');
+        Fmt::new("abcdef")
+            .width(2)
+            .precision(4)
+            .render(&mut b)
+            .unwrap();
+        assert_eq!(b.as_str(), "   42|0.333|abc  |あabcああ|abcd");
+        b.clear();
+
+        Fmt::new("<a>")
+            .width(5)
+            .fill('&')
+            .render_escaped(&mut b)
+            .unwrap();
+        b.push('|');
+        Fmt::new(7)
+            .width(3)
+            .fill('0')
+            .render_escaped(&mut b)
+            .unwrap();
+        assert_eq!(b.as_str(), "&amp;&amp;&lt;a&gt;|007");
+    }
+
+    #[test]
+    fn grouped() {
+        let mut b = Buffer::new();
+        for &n in &[
+            0,
+            12,
+            123,
+            1234,
+            12345,
+            123456,
+            1234567,
+            -1234,
+            -123,
+            i64::MIN,
+        ] {
+            Grouped::new(n).render(&mut b).unwrap();
+            b.push(' ');
+        }
+        Grouped::new(u128::MAX)
+            .separator('.')
+            .render(&mut b)
+            .unwrap();
+        assert_eq!(
+            b.as_str(),
+            "0 12 123 1,234 12,345 123,456 1,234,567 -1,234 -123 \
+             -9,223,372,036,854,775,808 \
+             340.282.366.920.938.463.463.374.607.431.768.211.455"
+        );
+        b.clear();
+
+        Grouped::new(1_000_000u32)
+            .separator('\'')
+            .render_escaped(&mut b)
+            .unwrap();
+        b.push(' ');
+        Grouped::new(1_000_000u32)
+            .separator('\u{202f}')
+            .render_escaped(&mut b)
+            .unwrap();
+        assert_eq!(b.as_str(), "1&#039;000&#039;000 1\u{202f}000\u{202f}000");
+    }
+
+    #[test]
+    fn fixed_float() {
+        let mut b = Buffer::new();
+        FixedFloat(0.125, 2).render(&mut b).unwrap();
+        b.push(' ');
+        FixedFloat(-1.5, 0).render(&mut b).unwrap();
+        b.push(' ');
+        FixedFloat(1e21, 1).render(&mut b).unwrap();
+        b.push(' ');
+        FixedFloat(f64::NAN, 2).render_escaped(&mut b).unwrap();
+        b.push(' ');
+        FixedFloat(f64::NEG_INFINITY, 2)
+            .render_escaped(&mut b)
+            .unwrap();
+        assert_eq!(b.as_str(), "0.12 -2 1000000000000000000000.0 NaN -inf");
+    }
+
+    #[test]
+    fn human() {
+        let mut b = Buffer::new();
+        for &n in &[0, 1023, 1024, 1536, 1_048_575, 10 << 30, u64::MAX] {
+            HumanBytes(n).render(&mut b).unwrap();
+            b.push('|');
+        }
+        assert_eq!(
+            b.as_str(),
+            "0 B|1023 B|1.0 KiB|1.5 KiB|1.0 MiB|10.0 GiB|16.0 EiB|"
+        );
+        b.clear();
+
+        for &n in &[0, 999, 1000, 999_949, 999_950, 1_250_000_000, u64::MAX] {
+            HumanCount(n).render_escaped(&mut b).unwrap();
+            b.push('|');
+        }
+        assert_eq!(b.as_str(), "0|999|1.0K|999.9K|1.0M|1.3G|18.4E|");
+    }
+
+    #[test]
+    fn truncate() {
+        let mut b = Buffer::new();
+        Truncate("hello", 0).render(&mut b).unwrap();
+        b.push('|');
+        Truncate("hello", 5).render(&mut b).unwrap();
+        b.push('|');
+        Truncate("こんにちは", 3).render(&mut b).unwrap();
+        b.push('|');
+        Truncate("<b>&amp;", 4).render_escaped(&mut b).unwrap();
+        assert_eq!(b.as_str(), "…|hello|こんに…|&lt;b&gt;&amp;…");
+    }
+
+    #[test]
+    fn case() {
+        let mut b = Buffer::new();
+        Upper(&"hElLO, WOrLd!").render(&mut b).unwrap();
+        b.push('|');
+        Lower(&"hElLO, WOrLd!").render(&mut b).unwrap();
+        b.push('|');
+        Title("  the ǆungla\tbook").render(&mut b).unwrap();
+        b.push('|');
+        Upper(&"<a href='x'>").render_escaped(&mut b).unwrap();
+        assert_eq!(
+            b.as_str(),
+            "HELLO, WORLD!|hello, world!|  The Ǆungla\tBook|&lt;A HREF=&#039;X&#039;&gt;"
+        );
+    }
+
+    #[test]
+    fn default_value() {
+        let nick: Option<String> = None;
+        let mut b = Buffer::new();
+        OrDefault(nick.as_ref(), "<anonymous>")
+            .render(&mut b)
+            .unwrap();
+        b.push('|');
+        OrDefault(Some(1), "<none>").render_escaped(&mut b).unwrap();
+        b.push('|');
+        OrDefault(None::<i32>, "<none>")
+            .render_escaped(&mut b)
+            .unwrap();
+        b.push('|');
+        NonEmptyOr("a&b", "-").render_escaped(&mut b).unwrap();
+        b.push('|');
+        NonEmptyOr("", "<empty>").render(&mut b).unwrap();
+        assert_eq!(b.as_str(), "<anonymous>|1|&lt;none&gt;|a&amp;b|<empty>");
+    }
+
+    #[test]
+    fn js_string() {
+        let mut b = Buffer::new();
+        JsString("it's <b>").render(&mut b).unwrap();
+        b.push('|');
+        JsString(42).render_escaped(&mut b).unwrap();
+        assert_eq!(b.as_str(), "\"it\\'s \\u003Cb\\u003E\"|\"42\"");
+    }
+
+    #[test]
+    fn url_encode() {
+        let mut b = Buffer::new();
+        UrlEncode("a/b?c=d&e").render(&mut b).unwrap();
+        b.push('|');
+        UrlEncode("<'\"日本'>").render_escaped(&mut b).unwrap();
+        assert_eq!(
+            b.as_str(),
+            "a%2Fb%3Fc%3Dd%26e|%3C%27%22%E6%97%A5%E6%9C%AC%27%3E"
+        );
+    }
+
+    #[test]
+    fn attr() {
+        let mut b = Buffer::new();
+        Attr("it's <b>").render(&mut b).unwrap();
+        b.push('|');
+        Attr(String::from("\"a&b\""))
+            .render_escaped(&mut b)
+            .unwrap();
+        b.push('|');
+        Attr(3.5).render_escaped(&mut b).unwrap();
+        assert_eq!(b.as_str(), "it's <b>|&quot;a&amp;b&quot;|3.5");
+    }
+}
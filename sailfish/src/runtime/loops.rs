@@ -0,0 +1,113 @@
+use alloc::vec::{self, Vec};
+
+/// Metadata of the current iteration in template `for` loops
+///
+/// Use `with` clause in the template to bind this value.
+///
+/// ```text
+/// <% for item in items.iter() with meta { %>
+///   <li class="<%= if meta.index % 2 == 0 { "even" } else { "odd" } %>">
+///     <%= item %><% if !meta.last { %>,<% } %>
+///   </li>
+/// <% } %>
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Loop {
+    /// Index of the current iteration (starting from 0)
+    pub index: usize,
+    /// `true` if this is the first iteration
+    pub first: bool,
+    /// `true` if this is the last iteration
+    pub last: bool,
+    /// Total number of iterations
+    pub length: usize,
+}
+
+/// Iterator which yields the loop metadata with each item
+///
+/// The items are collected beforehand so that the total number of iterations is
+/// known at the first iteration.
+///
+/// # Examples
+///
+/// ```
+/// use sailfish::runtime::LoopIter;
+///
+/// let mut it = LoopIter::new(vec!["a", "b"]);
+/// let (meta, item) = it.next().unwrap();
+/// assert_eq!(item, "a");
+/// assert!(meta.first && !meta.last);
+/// assert_eq!(meta.length, 2);
+/// ```
+pub struct LoopIter<T> {
+    items: vec::IntoIter<T>,
+    index: usize,
+    length: usize,
+}
+
+impl<T> LoopIter<T> {
+    pub fn new<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let items = iter.into_iter().collect::<Vec<_>>();
+        let length = items.len();
+        Self {
+            items: items.into_iter(),
+            index: 0,
+            length,
+        }
+    }
+}
+
+impl<T> Iterator for LoopIter<T> {
+    type Item = (Loop, T);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.items.next()?;
+        let meta = Loop {
+            index: self.index,
+            first: self.index == 0,
+            last: self.index + 1 == self.length,
+            length: self.length,
+        };
+        self.index += 1;
+        Some((meta, item))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.items.size_hint()
+    }
+}
+
+impl<T> ExactSizeIterator for LoopIter<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metadata() {
+        let metas = LoopIter::new((0..3).filter(|i| i % 2 == 0))
+            .map(|(meta, _)| meta)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            metas,
+            [
+                Loop {
+                    index: 0,
+                    first: true,
+                    last: false,
+                    length: 2
+                },
+                Loop {
+                    index: 1,
+                    first: false,
+                    last: true,
+                    length: 2
+                },
+            ]
+        );
+
+        assert_eq!(LoopIter::new(Vec::<u8>::new()).next(), None);
+    }
+}
@@ -1,24 +1,105 @@
 #[macro_export]
 #[doc(hidden)]
 macro_rules! render {
-    ($buf:ident, $value:expr) => {
-        $crate::runtime::Render::render(&($value), &mut $buf)?
-    };
+    ($buf:ident, $value:expr) => {{
+        #[allow(unused_imports)]
+        use $crate::runtime::{ViaDisplay as _, ViaRender as _};
+        (&$crate::runtime::Specialize(&($value))).__sf_render(&mut $buf)?;
+        $buf.check_limit()?
+    }};
+    ($buf:ident, $value:expr, $context:expr) => {{
+        #[allow(unused_imports)]
+        use $crate::runtime::{ViaDisplay as _, ViaRender as _};
+        (&$crate::runtime::Specialize(&($value)))
+            .__sf_render(&mut $buf)
+            .map_err(|e| $crate::runtime::RenderError::with_context(e, $context))?;
+        $buf.check_limit()?
+    }};
 }
 
 #[macro_export]
 #[doc(hidden)]
 macro_rules! render_escaped {
-    ($buf:ident, $value:expr) => {
-        $crate::runtime::Render::render_escaped(&($value), &mut $buf)?
-    };
+    ($buf:ident, $value:expr) => {{
+        #[allow(unused_imports)]
+        use $crate::runtime::{ViaDisplay as _, ViaRender as _};
+        (&$crate::runtime::Specialize(&($value))).__sf_render_escaped(&mut $buf)?;
+        $buf.check_limit()?
+    }};
+    ($buf:ident, $value:expr, $context:expr) => {{
+        #[allow(unused_imports)]
+        use $crate::runtime::{ViaDisplay as _, ViaRender as _};
+        (&$crate::runtime::Specialize(&($value)))
+            .__sf_render_escaped(&mut $buf)
+            .map_err(|e| $crate::runtime::RenderError::with_context(e, $context))?;
+        $buf.check_limit()?
+    }};
+}
+
+#[macro_export]
+#[doc(hidden)]
+macro_rules! render_escaped_with {
+    ($buf:ident, $value:expr, $escaper:expr) => {{
+        #[allow(unused_imports)]
+        use $crate::runtime::{ViaDisplay as _, ViaRender as _};
+        (&$crate::runtime::Specialize(&($value)))
+            .__sf_render_escaped_with(&mut $buf, $escaper)?;
+        $buf.check_limit()?
+    }};
+    ($buf:ident, $value:expr, $escaper:expr, $context:expr) => {{
+        #[allow(unused_imports)]
+        use $crate::runtime::{ViaDisplay as _, ViaRender as _};
+        (&$crate::runtime::Specialize(&($value)))
+            .__sf_render_escaped_with(&mut $buf, $escaper)
+            .map_err(|e| $crate::runtime::RenderError::with_context(e, $context))?;
+        $buf.check_limit()?
+    }};
+}
+
+#[macro_export]
+#[doc(hidden)]
+macro_rules! render_once {
+    ($buf:ident, $value:expr) => {{
+        #[allow(unused_imports)]
+        use $crate::runtime::{ViaDisplay as _, ViaRender as _, ViaRenderOnce as _};
+        (&&$crate::runtime::SpecializeOnce::new($value)).__sf_render(&mut $buf)?;
+        $buf.check_limit()?
+    }};
+    ($buf:ident, $value:expr, $context:expr) => {{
+        #[allow(unused_imports)]
+        use $crate::runtime::{ViaDisplay as _, ViaRender as _, ViaRenderOnce as _};
+        (&&$crate::runtime::SpecializeOnce::new($value))
+            .__sf_render(&mut $buf)
+            .map_err(|e| $crate::runtime::RenderError::with_context(e, $context))?;
+        $buf.check_limit()?
+    }};
+}
+
+#[macro_export]
+#[doc(hidden)]
+macro_rules! render_once_escaped {
+    ($buf:ident, $value:expr) => {{
+        #[allow(unused_imports)]
+        use $crate::runtime::{ViaDisplay as _, ViaRender as _, ViaRenderOnce as _};
+        (&&$crate::runtime::SpecializeOnce::new($value))
+            .__sf_render_escaped(&mut $buf)?;
+        $buf.check_limit()?
+    }};
+    ($buf:ident, $value:expr, $context:expr) => {{
+        #[allow(unused_imports)]
+        use $crate::runtime::{ViaDisplay as _, ViaRender as _, ViaRenderOnce as _};
+        (&&$crate::runtime::SpecializeOnce::new($value))
+            .__sf_render_escaped(&mut $buf)
+            .map_err(|e| $crate::runtime::RenderError::with_context(e, $context))?;
+        $buf.check_limit()?
+    }};
 }
 
 #[macro_export]
 #[doc(hidden)]
 macro_rules! render_text {
     ($buf:ident, $value:expr) => {
-        $buf.push_str($value)
+        $buf.push_static($value)
     };
 }
 
@@ -27,3 +108,29 @@ macro_rules! render_text {
 macro_rules! render_noop {
     ($buf:ident, $value:expr) => {};
 }
+
+/// Create the space-separated list of class names
+///
+/// Each entry is either a class name, or a tuple of a class name and the
+/// condition. Class names whose condition is `false` are skipped.
+///
+/// Inside templates, this macro can be used without importing.
+///
+/// # Examples
+///
+/// ```
+/// use sailfish::runtime::{Buffer, Render};
+///
+/// let (is_active, has_error) = (true, false);
+/// let mut b = Buffer::new();
+/// sailfish::classes!["btn", ("active", is_active), ("danger", has_error)]
+///     .render_escaped(&mut b)
+///     .unwrap();
+/// assert_eq!(b.as_str(), "btn active");
+/// ```
+#[macro_export]
+macro_rules! classes {
+    ($($entry:expr),* $(,)?) => {
+        $crate::runtime::Classes(&[$($crate::runtime::Class::from($entry)),*])
+    };
+}
@@ -3,26 +3,54 @@
 #[macro_use]
 mod utils;
 
+mod attributes;
 mod buffer;
+mod chunked;
 pub mod escape;
+mod ext;
 pub mod filter;
+mod format;
+mod loops;
 mod macros;
+mod nonce;
+mod pool;
 mod render;
 mod size_hint;
+#[cfg(feature = "buffer-stats")]
+mod stats;
 
+pub use attributes::*;
 pub use buffer::*;
+pub use chunked::*;
+#[allow(unused_imports)]
+pub use ext::*;
+pub use format::*;
+pub use loops::*;
+pub use nonce::*;
+pub use pool::*;
 pub use render::*;
 pub use size_hint::*;
+#[cfg(feature = "buffer-stats")]
+pub use stats::*;
 
-use std::fmt;
+use alloc::borrow::ToOwned;
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::{String, ToString};
+use core::fmt;
 
 #[doc(hidden)]
-pub use crate::{render, render_escaped, render_noop, render_text};
+pub use crate::{
+    render, render_escaped, render_escaped_with, render_noop, render_once,
+    render_once_escaped, render_text,
+};
 
 #[derive(Clone, Debug)]
 enum RenderErrorKind {
     Msg(String),
     Fmt(fmt::Error),
+    Context(String, Box<RenderError>),
+    OutputTooLarge(usize),
 }
 
 /// The error type which is returned from template function
@@ -39,6 +67,40 @@ impl RenderError {
             kind: RenderErrorKind::Msg(msg.to_owned()),
         }
     }
+
+    /// Wrap the error with the context which describes where the error occurred
+    ///
+    /// The original error can be retrieved with `Error::source()` method.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sailfish::runtime::RenderError;
+    ///
+    /// let err = RenderError::new("invalid value").with_context("failed to render `x`");
+    /// assert_eq!(err.to_string(), "failed to render `x`: invalid value");
+    /// ```
+    pub fn with_context<C: fmt::Display>(self, context: C) -> Self {
+        Self {
+            kind: RenderErrorKind::Context(context.to_string(), Box::new(self)),
+        }
+    }
+
+    pub(crate) fn output_too_large(limit: usize) -> Self {
+        Self {
+            kind: RenderErrorKind::OutputTooLarge(limit),
+        }
+    }
+
+    /// Returns `true` if the error was caused by the rendered contents exceeding
+    /// the size limit of the buffer
+    pub fn is_output_too_large(&self) -> bool {
+        match self.kind {
+            RenderErrorKind::OutputTooLarge(_) => true,
+            RenderErrorKind::Context(_, ref e) => e.is_output_too_large(),
+            _ => false,
+        }
+    }
 }
 
 impl fmt::Display for RenderError {
@@ -46,15 +108,21 @@ impl fmt::Display for RenderError {
         match self.kind {
             RenderErrorKind::Msg(ref s) => f.write_str(&**s),
             RenderErrorKind::Fmt(ref e) => fmt::Display::fmt(e, f),
+            RenderErrorKind::Context(ref c, ref e) => write!(f, "{}: {}", c, e),
+            RenderErrorKind::OutputTooLarge(limit) => {
+                write!(f, "rendered contents exceeded the limit of {} bytes", limit)
+            }
         }
     }
 }
 
+#[cfg(feature = "std")]
 impl std::error::Error for RenderError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self.kind {
-            RenderErrorKind::Msg(_) => None,
+            RenderErrorKind::Msg(_) | RenderErrorKind::OutputTooLarge(_) => None,
             RenderErrorKind::Fmt(ref e) => Some(e),
+            RenderErrorKind::Context(_, ref e) => Some(&**e),
         }
     }
 }
@@ -68,6 +136,21 @@ impl From<fmt::Error> for RenderError {
     }
 }
 
+#[cfg(feature = "std")]
+impl From<std::io::Error> for RenderError {
+    #[inline]
+    fn from(other: std::io::Error) -> Self {
+        Self::new(&format!("failed to write the rendered contents: {}", other))
+    }
+}
+
+impl From<core::str::Utf8Error> for RenderError {
+    #[inline]
+    fn from(other: core::str::Utf8Error) -> Self {
+        Self::new(&format!("invalid UTF-8 sequence: {}", other))
+    }
+}
+
 pub type RenderResult = Result<String, RenderError>;
 
 // #[inline(never)]
@@ -90,15 +173,42 @@ pub type RenderResult = Result<String, RenderError>;
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::error::Error;
 
     #[test]
     fn render_error() {
         let err = RenderError::new("custom error");
-        assert!(err.source().is_none());
         assert_eq!(format!("{}", err), "custom error");
 
-        let err = RenderError::from(std::fmt::Error::default());
+        let err = RenderError::new("custom error")
+            .with_context("inner")
+            .with_context("outer");
+        assert_eq!(format!("{}", err), "outer: inner: custom error");
+        assert!(!err.is_output_too_large());
+
+        let err = RenderError::output_too_large(1024).with_context("outer");
+        assert!(err.is_output_too_large());
+        assert_eq!(
+            format!("{}", err),
+            "outer: rendered contents exceeded the limit of 1024 bytes"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn source() {
+        use std::error::Error;
+
+        let err = RenderError::new("custom error");
+        assert!(err.source().is_none());
+
+        let err = RenderError::from(fmt::Error);
         assert!(err.source().is_some());
+
+        let err = RenderError::new("custom error")
+            .with_context("inner")
+            .with_context("outer");
+        let source = err.source().unwrap();
+        assert_eq!(format!("{}", source), "inner: custom error");
+        assert_eq!(format!("{}", source.source().unwrap()), "custom error");
     }
 }
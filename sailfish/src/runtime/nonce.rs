@@ -0,0 +1,134 @@
+use alloc::string::String;
+use core::fmt;
+
+use super::escape::{self, Escaper};
+use super::{Buffer, Render, RenderError};
+
+/// Per-request nonce for Content Security Policy
+///
+/// The nonce should be generated for each response by the application and
+/// sent in the `Content-Security-Policy` header (e.g. `script-src 'nonce-...'`).
+/// Inline `<script>` and `<style>` elements are then allowed by adding the
+/// same nonce to their `nonce` attribute with
+/// [`nonce_attr`](#method.nonce_attr).
+///
+/// If the template is compiled with `nonce` option, the attribute is added to
+/// all `<script>` and `<style>` tags in the template automatically.
+///
+/// # Examples
+///
+/// ```
+/// use sailfish::runtime::{Buffer, Nonce, Render};
+///
+/// let nonce = Nonce::new("r4nd0m");
+///
+/// let mut b = Buffer::new();
+/// b.push_str("<script");
+/// nonce.nonce_attr().render(&mut b).unwrap();
+/// b.push_str(">");
+/// assert_eq!(b.as_str(), r#"<script nonce="r4nd0m">"#);
+/// ```
+#[derive(Clone, PartialEq, Eq)]
+pub struct Nonce(String);
+
+impl Nonce {
+    /// Create a nonce from the value generated by the application
+    #[inline]
+    pub fn new<T: Into<String>>(value: T) -> Self {
+        Nonce(value.into())
+    }
+
+    #[inline]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Returns the value which renders ` nonce="..."` attribute
+    #[inline]
+    pub fn nonce_attr(&self) -> NonceAttr<'_> {
+        NonceAttr(&self.0)
+    }
+}
+
+impl From<String> for Nonce {
+    #[inline]
+    fn from(other: String) -> Self {
+        Nonce(other)
+    }
+}
+
+impl<'a> From<&'a str> for Nonce {
+    #[inline]
+    fn from(other: &'a str) -> Self {
+        Nonce::new(other)
+    }
+}
+
+impl fmt::Debug for Nonce {
+    // do not leak the nonce into logs
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Nonce(..)")
+    }
+}
+
+impl Render for Nonce {
+    #[inline]
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        b.push_str(&self.0);
+        Ok(())
+    }
+
+    #[inline]
+    fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        escape::escape_to_buf(&self.0, b);
+        Ok(())
+    }
+
+    #[inline]
+    fn render_escaped_with(
+        &self,
+        b: &mut Buffer,
+        escaper: &dyn Escaper,
+    ) -> Result<(), RenderError> {
+        escaper.escape(&self.0, b);
+        Ok(())
+    }
+}
+
+/// ` nonce="..."` attribute returned from
+/// [`Nonce::nonce_attr`](struct.Nonce.html#method.nonce_attr)
+///
+/// The attribute is rendered with a leading space, and the value is always
+/// HTML-escaped since it is placed inside an HTML tag.
+#[derive(Clone, Copy)]
+pub struct NonceAttr<'a>(&'a str);
+
+impl<'a> Render for NonceAttr<'a> {
+    #[inline]
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        b.push_str(" nonce=\"");
+        escape::escape_to_buf(self.0, b);
+        b.push('"');
+        Ok(())
+    }
+
+    #[inline]
+    fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        self.render(b)
+    }
+
+    #[inline]
+    fn render_escaped_with(
+        &self,
+        b: &mut Buffer,
+        _escaper: &dyn Escaper,
+    ) -> Result<(), RenderError> {
+        self.render(b)
+    }
+}
+
+impl<'a> fmt::Debug for NonceAttr<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("NonceAttr(..)")
+    }
+}
@@ -0,0 +1,214 @@
+use alloc::vec::Vec;
+use core::cell::{Cell, RefCell};
+use core::fmt;
+use core::mem;
+use core::ops::{Deref, DerefMut};
+
+use super::Buffer;
+
+#[cfg(feature = "std")]
+thread_local! {
+    static LOCAL_POOL: BufferPool = BufferPool::new();
+}
+
+/// Pool of buffers which can be reused across renderings
+///
+/// Buffers handed out by [`get`](#method.get) are returned to the pool when they
+/// are dropped, with their contents cleared and the capacity retained. Buffers
+/// whose capacity exceeds the maximum retained size are deallocated instead of
+/// being returned, so that a single huge page does not keep memory forever.
+///
+/// Each thread has its own default pool, which can be accessed with
+/// [`with_local`](#method.with_local). `TemplateOnce::render_once_to_writer` uses
+/// this pool, so rendering into writers does not allocate in the steady state.
+///
+/// # Examples
+///
+/// ```
+/// use sailfish::runtime::BufferPool;
+///
+/// let pool = BufferPool::new();
+/// {
+///     let mut buf = pool.get();
+///     buf.push_str("hello");
+/// }
+///
+/// // the buffer is reused
+/// let buf = pool.get();
+/// assert!(buf.is_empty());
+/// assert!(buf.capacity() >= 5);
+/// ```
+pub struct BufferPool {
+    buffers: RefCell<Vec<Buffer>>,
+    max_buffers: Cell<usize>,
+    max_capacity: Cell<usize>,
+}
+
+impl BufferPool {
+    /// Create an empty pool
+    ///
+    /// By default, at most 8 buffers whose capacity is less than or equal to 1 MiB
+    /// are retained.
+    #[inline]
+    pub const fn new() -> Self {
+        Self {
+            buffers: RefCell::new(Vec::new()),
+            max_buffers: Cell::new(8),
+            max_capacity: Cell::new(1024 * 1024),
+        }
+    }
+
+    /// Call the function with the thread-local default pool
+    #[cfg(feature = "std")]
+    #[inline]
+    pub fn with_local<F: FnOnce(&BufferPool) -> R, R>(f: F) -> R {
+        LOCAL_POOL.with(f)
+    }
+
+    /// Set the maximum number of buffers retained in the pool
+    #[inline]
+    pub fn set_max_buffers(&self, n: usize) {
+        self.max_buffers.set(n);
+        self.buffers.borrow_mut().truncate(n);
+    }
+
+    /// Set the maximum capacity of buffers retained in the pool
+    #[inline]
+    pub fn set_max_capacity(&self, n: usize) {
+        self.max_capacity.set(n);
+        self.buffers.borrow_mut().retain(|b| b.capacity() <= n);
+    }
+
+    /// Take the buffer from the pool, or allocate a new one if the pool is empty
+    #[inline]
+    pub fn get(&self) -> PooledBuffer<'_> {
+        let buf = self.buffers.borrow_mut().pop().unwrap_or_default();
+        PooledBuffer { buf, pool: self }
+    }
+
+    /// Number of buffers currently retained in the pool
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.buffers.borrow().len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn put(&self, mut buf: Buffer) {
+        if !buf.spilled() || buf.capacity() > self.max_capacity.get() {
+            return;
+        }
+
+        let mut buffers = self.buffers.borrow_mut();
+        if buffers.len() < self.max_buffers.get() {
+            buf.clear();
+            buf.set_limit(core::usize::MAX);
+            buffers.push(buf);
+        }
+    }
+}
+
+impl Default for BufferPool {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for BufferPool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BufferPool")
+            .field("len", &self.len())
+            .field("max_buffers", &self.max_buffers.get())
+            .field("max_capacity", &self.max_capacity.get())
+            .finish()
+    }
+}
+
+/// Buffer taken from [`BufferPool`](struct.BufferPool.html)
+///
+/// The buffer is returned to the pool when this value is dropped.
+pub struct PooledBuffer<'a> {
+    buf: Buffer,
+    pool: &'a BufferPool,
+}
+
+impl<'a> PooledBuffer<'a> {
+    /// Take the buffer out without returning it to the pool
+    #[inline]
+    pub fn into_inner(mut self) -> Buffer {
+        // empty buffer left here is not retained by the pool
+        mem::take(&mut self.buf)
+    }
+}
+
+impl<'a> Deref for PooledBuffer<'a> {
+    type Target = Buffer;
+
+    #[inline]
+    fn deref(&self) -> &Buffer {
+        &self.buf
+    }
+}
+
+impl<'a> DerefMut for PooledBuffer<'a> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Buffer {
+        &mut self.buf
+    }
+}
+
+impl<'a> fmt::Debug for PooledBuffer<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<'a> Drop for PooledBuffer<'a> {
+    fn drop(&mut self) {
+        self.pool.put(mem::take(&mut self.buf));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reuse() {
+        let pool = BufferPool::new();
+        pool.set_max_buffers(1);
+        pool.set_max_capacity(64);
+
+        let mut b1 = pool.get();
+        let mut b2 = pool.get();
+        b1.push_str(&"abc".repeat(10));
+        b2.push_str(&"x".repeat(100));
+        let p1 = b1.as_ptr();
+        let capacity = b1.capacity();
+        drop(b2);
+        drop(b1);
+
+        // too large buffer is not retained
+        assert_eq!(pool.len(), 1);
+        let b3 = pool.get();
+        assert_eq!(b3.as_ptr(), p1);
+        assert!(b3.is_empty());
+        assert_eq!(b3.into_inner().capacity(), capacity);
+        assert!(pool.is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn local() {
+        BufferPool::with_local(|pool| {
+            let mut buf = pool.get();
+            buf.push_str(&"hello".repeat(10));
+            assert_eq!(buf.len(), 50);
+        });
+        assert!(BufferPool::with_local(|pool| pool.len()) >= 1);
+    }
+}
@@ -1,15 +1,35 @@
-use std::borrow::Cow;
-use std::cell::{Ref, RefMut};
-use std::num::{
+use alloc::borrow::{Cow, ToOwned};
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::cell::{Cell, Ref, RefCell, RefMut};
+use core::fmt;
+use core::num::{
     NonZeroI128, NonZeroI16, NonZeroI32, NonZeroI64, NonZeroI8, NonZeroIsize,
     NonZeroU128, NonZeroU16, NonZeroU32, NonZeroU64, NonZeroU8, NonZeroUsize, Wrapping,
 };
+use core::sync::atomic::{
+    AtomicBool, AtomicI16, AtomicI32, AtomicI64, AtomicI8, AtomicIsize, AtomicU16,
+    AtomicU32, AtomicU64, AtomicU8, AtomicUsize, Ordering,
+};
+#[cfg(feature = "std")]
+use std::ffi::{OsStr, OsString};
+#[cfg(feature = "std")]
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+#[cfg(feature = "std")]
 use std::path::{Path, PathBuf};
-use std::rc::Rc;
-use std::sync::{Arc, MutexGuard, RwLockReadGuard, RwLockWriteGuard};
+#[cfg(feature = "std")]
+use std::sync::{MutexGuard, RwLockReadGuard, RwLockWriteGuard};
+#[cfg(feature = "std-time")]
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use super::buffer::Buffer;
-use super::{escape, RenderError};
+use super::escape::{self, EscapeWriter, Escaper};
+#[cfg(any(feature = "std", feature = "time"))]
+use super::utils;
+use super::RenderError;
 
 /// types which can be rendered inside buffer block (`<%= %>`)
 ///
@@ -30,6 +50,17 @@ use super::{escape, RenderError};
 ///     }
 /// }
 /// ```
+///
+/// For such a newtype, you can use `#[derive(Render)]` in `sailfish-macros` crate
+/// instead of implementing this trait manually.
+///
+/// ```ignore
+/// #[derive(Render)]
+/// struct MyU64(u64);
+/// ```
+///
+/// This trait is object safe, so the values of different types can be stored as
+/// `Box<dyn Render>` or `&dyn Render` and rendered in the same way.
 pub trait Render {
     /// render to `Buffer` without escaping
     fn render(&self, b: &mut Buffer) -> Result<(), RenderError>;
@@ -37,35 +68,216 @@ pub trait Render {
     /// render to `Buffer` with HTML escaping
     #[inline]
     fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
-        let mut tmp = Buffer::new();
-        self.render(&mut tmp)?;
-        escape::escape_to_buf(tmp.as_str(), b);
-        Ok(())
-    }
-}
-
-// /// Autoref-based stable specialization
-// ///
-// /// Explanation can be found [here](https://github.com/dtolnay/case-studies/blob/master/autoref-specialization/README.md)
-// impl<T: Display> Render for &T {
-//     fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
-//         fmt::write(b, format_args!("{}", self))
-//     }
-//
-//     fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
-//         struct Wrapper<'a>(&'a mut Buffer);
-//
-//         impl<'a> fmt::Write for Wrapper<'a> {
-//             #[inline]
-//             fn push_str(&mut self, s: &str) -> Result<(), RenderError> {
-//                 escape::escape_to_buf(s, self.0);
-//                 Ok(())
-//             }
-//         }
-//
-//         fmt::write(&mut Wrapper(b), format_args!("{}", self))
-//     }
-// }
+        // render in place, and escape the contents only if required
+        let old_len = b.len();
+        if let Err(e) = self.render(b) {
+            b.truncate(old_len);
+            return Err(e);
+        }
+        escape::escape_tail(b, old_len);
+        Ok(())
+    }
+
+    /// render to `Buffer` with the custom escaping
+    #[inline]
+    fn render_escaped_with(
+        &self,
+        b: &mut Buffer,
+        escaper: &dyn Escaper,
+    ) -> Result<(), RenderError> {
+        let old_len = b.len();
+        if let Err(e) = self.render(b) {
+            b.truncate(old_len);
+            return Err(e);
+        }
+        escape::escape_tail_with(b, old_len, escaper);
+        Ok(())
+    }
+}
+
+/// types which can be rendered by consuming itself
+///
+/// When the expression inside `<%= %>` tag is an owned temporary value (e.g.
+/// `format!` macro or function call), sailfish prefers this trait over `Render` so
+/// that the value can be moved into the buffer instead of being copied.
+pub trait RenderOnce: Sized {
+    /// render to `Buffer` without escaping
+    fn render_once(self, b: &mut Buffer) -> Result<(), RenderError>;
+
+    /// render to `Buffer` with HTML escaping
+    #[inline]
+    fn render_once_escaped(self, b: &mut Buffer) -> Result<(), RenderError> {
+        let old_len = b.len();
+        if let Err(e) = self.render_once(b) {
+            b.truncate(old_len);
+            return Err(e);
+        }
+        escape::escape_tail(b, old_len);
+        Ok(())
+    }
+}
+
+/// Autoref-based stable specialization
+///
+/// Explanation can be found [here](https://github.com/dtolnay/case-studies/blob/master/autoref-specialization/README.md)
+///
+/// Inside templates, values are wrapped with `Specialize` and rendered by method call
+/// syntax. The method provided by `ViaRender` takes precedence, and values which
+/// does not implement `Render` trait falls back to `ViaDisplay`.
+#[doc(hidden)]
+pub struct Specialize<'a, T: ?Sized>(pub &'a T);
+
+#[doc(hidden)]
+pub trait ViaRender {
+    fn __sf_render(&self, b: &mut Buffer) -> Result<(), RenderError>;
+    fn __sf_render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError>;
+    fn __sf_render_escaped_with(
+        &self,
+        b: &mut Buffer,
+        escaper: &dyn Escaper,
+    ) -> Result<(), RenderError>;
+}
+
+impl<'a, T: Render + ?Sized> ViaRender for Specialize<'a, T> {
+    #[inline]
+    fn __sf_render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        self.0.render(b)
+    }
+
+    #[inline]
+    fn __sf_render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        self.0.render_escaped(b)
+    }
+
+    #[inline]
+    fn __sf_render_escaped_with(
+        &self,
+        b: &mut Buffer,
+        escaper: &dyn Escaper,
+    ) -> Result<(), RenderError> {
+        self.0.render_escaped_with(b, escaper)
+    }
+}
+
+#[doc(hidden)]
+pub trait ViaDisplay {
+    fn __sf_render(&self, b: &mut Buffer) -> Result<(), RenderError>;
+    fn __sf_render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError>;
+    fn __sf_render_escaped_with(
+        &self,
+        b: &mut Buffer,
+        escaper: &dyn Escaper,
+    ) -> Result<(), RenderError>;
+}
+
+impl<'a, T: fmt::Display + ?Sized> ViaDisplay for &Specialize<'a, T> {
+    #[inline]
+    fn __sf_render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        fmt::write(b, format_args!("{}", self.0))?;
+        Ok(())
+    }
+
+    #[inline]
+    fn __sf_render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        fmt::write(&mut EscapeWriter(b), format_args!("{}", self.0))?;
+        Ok(())
+    }
+
+    #[inline]
+    fn __sf_render_escaped_with(
+        &self,
+        b: &mut Buffer,
+        escaper: &dyn Escaper,
+    ) -> Result<(), RenderError> {
+        let old_len = b.len();
+        if let Err(e) = fmt::write(b, format_args!("{}", self.0)) {
+            b.truncate(old_len);
+            return Err(e.into());
+        }
+        escape::escape_tail_with(b, old_len, escaper);
+        Ok(())
+    }
+}
+
+/// Specialization for owned temporary values
+///
+/// Priority: `RenderOnce` > `Render` > `Display`
+#[doc(hidden)]
+pub struct SpecializeOnce<T>(Cell<Option<T>>);
+
+impl<T> SpecializeOnce<T> {
+    #[inline]
+    pub fn new(value: T) -> Self {
+        Self(Cell::new(Some(value)))
+    }
+
+    #[inline]
+    fn take(&self) -> T {
+        self.0
+            .take()
+            .expect("Internal error: value was already rendered (error code: 29173)")
+    }
+}
+
+#[doc(hidden)]
+pub trait ViaRenderOnce {
+    fn __sf_render(&self, b: &mut Buffer) -> Result<(), RenderError>;
+    fn __sf_render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError>;
+}
+
+impl<T: RenderOnce> ViaRenderOnce for &SpecializeOnce<T> {
+    #[inline]
+    fn __sf_render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        self.take().render_once(b)
+    }
+
+    #[inline]
+    fn __sf_render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        self.take().render_once_escaped(b)
+    }
+}
+
+impl<T: Render> ViaRender for &&SpecializeOnce<T> {
+    #[inline]
+    fn __sf_render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        self.take().render(b)
+    }
+
+    #[inline]
+    fn __sf_render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        self.take().render_escaped(b)
+    }
+
+    #[inline]
+    fn __sf_render_escaped_with(
+        &self,
+        b: &mut Buffer,
+        escaper: &dyn Escaper,
+    ) -> Result<(), RenderError> {
+        self.take().render_escaped_with(b, escaper)
+    }
+}
+
+impl<T: fmt::Display> ViaDisplay for SpecializeOnce<T> {
+    #[inline]
+    fn __sf_render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        (&Specialize(&self.take())).__sf_render(b)
+    }
+
+    #[inline]
+    fn __sf_render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        (&Specialize(&self.take())).__sf_render_escaped(b)
+    }
+
+    #[inline]
+    fn __sf_render_escaped_with(
+        &self,
+        b: &mut Buffer,
+        escaper: &dyn Escaper,
+    ) -> Result<(), RenderError> {
+        (&Specialize(&self.take())).__sf_render_escaped_with(b, escaper)
+    }
+}
 
 impl Render for String {
     #[inline]
@@ -79,6 +291,16 @@ impl Render for String {
         escape::escape_to_buf(&**self, b);
         Ok(())
     }
+
+    #[inline]
+    fn render_escaped_with(
+        &self,
+        b: &mut Buffer,
+        escaper: &dyn Escaper,
+    ) -> Result<(), RenderError> {
+        escaper.escape(self, b);
+        Ok(())
+    }
 }
 
 impl Render for &str {
@@ -93,6 +315,40 @@ impl Render for &str {
         escape::escape_to_buf(*self, b);
         Ok(())
     }
+
+    #[inline]
+    fn render_escaped_with(
+        &self,
+        b: &mut Buffer,
+        escaper: &dyn Escaper,
+    ) -> Result<(), RenderError> {
+        escaper.escape(self, b);
+        Ok(())
+    }
+}
+
+impl Render for Buffer {
+    #[inline]
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        b.push_str(self.as_str());
+        Ok(())
+    }
+
+    #[inline]
+    fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        escape::escape_to_buf(self.as_str(), b);
+        Ok(())
+    }
+
+    #[inline]
+    fn render_escaped_with(
+        &self,
+        b: &mut Buffer,
+        escaper: &dyn Escaper,
+    ) -> Result<(), RenderError> {
+        escaper.escape(self.as_str(), b);
+        Ok(())
+    }
 }
 
 impl Render for char {
@@ -114,8 +370,20 @@ impl Render for char {
         }
         Ok(())
     }
+
+    #[inline]
+    fn render_escaped_with(
+        &self,
+        b: &mut Buffer,
+        escaper: &dyn Escaper,
+    ) -> Result<(), RenderError> {
+        let mut tmp = [0; 4];
+        escaper.escape(self.encode_utf8(&mut tmp), b);
+        Ok(())
+    }
 }
 
+#[cfg(feature = "std")]
 impl Render for PathBuf {
     #[inline]
     fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
@@ -129,8 +397,19 @@ impl Render for PathBuf {
         escape::escape_to_buf(&*self.to_string_lossy(), b);
         Ok(())
     }
+
+    #[inline]
+    fn render_escaped_with(
+        &self,
+        b: &mut Buffer,
+        escaper: &dyn Escaper,
+    ) -> Result<(), RenderError> {
+        escaper.escape(&self.to_string_lossy(), b);
+        Ok(())
+    }
 }
 
+#[cfg(feature = "std")]
 impl Render for Path {
     #[inline]
     fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
@@ -144,31 +423,134 @@ impl Render for Path {
         escape::escape_to_buf(&*self.to_string_lossy(), b);
         Ok(())
     }
+
+    #[inline]
+    fn render_escaped_with(
+        &self,
+        b: &mut Buffer,
+        escaper: &dyn Escaper,
+    ) -> Result<(), RenderError> {
+        escaper.escape(&self.to_string_lossy(), b);
+        Ok(())
+    }
+}
+
+impl<'a> Render for fmt::Arguments<'a> {
+    #[inline]
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        fmt::write(b, *self)?;
+        Ok(())
+    }
+
+    #[inline]
+    fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        fmt::write(&mut EscapeWriter(b), *self)?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl Render for OsString {
+    #[inline]
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        // TODO: speed up on Windows using OsStrExt
+        b.push_str(&self.to_string_lossy());
+        Ok(())
+    }
+
+    #[inline]
+    fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        escape::escape_to_buf(&self.to_string_lossy(), b);
+        Ok(())
+    }
+
+    #[inline]
+    fn render_escaped_with(
+        &self,
+        b: &mut Buffer,
+        escaper: &dyn Escaper,
+    ) -> Result<(), RenderError> {
+        escaper.escape(&self.to_string_lossy(), b);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl Render for OsStr {
+    #[inline]
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        // TODO: speed up on Windows using OsStrExt
+        b.push_str(&self.to_string_lossy());
+        Ok(())
+    }
+
+    #[inline]
+    fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        escape::escape_to_buf(&self.to_string_lossy(), b);
+        Ok(())
+    }
+
+    #[inline]
+    fn render_escaped_with(
+        &self,
+        b: &mut Buffer,
+        escaper: &dyn Escaper,
+    ) -> Result<(), RenderError> {
+        escaper.escape(&self.to_string_lossy(), b);
+        Ok(())
+    }
+}
+
+/// render the bytes as UTF-8 string
+///
+/// An error is returned if the bytes are not valid UTF-8.
+impl Render for [u8] {
+    #[inline]
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        b.write_bytes(self)?;
+        Ok(())
+    }
+
+    #[inline]
+    fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        escape::escape_to_buf(core::str::from_utf8(self)?, b);
+        Ok(())
+    }
+
+    #[inline]
+    fn render_escaped_with(
+        &self,
+        b: &mut Buffer,
+        escaper: &dyn Escaper,
+    ) -> Result<(), RenderError> {
+        escaper.escape(core::str::from_utf8(self)?, b);
+        Ok(())
+    }
 }
 
-// impl Render for [u8] {
-//     #[inline]
-//     fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
-//         b.write_bytes(self);
-//         Ok(())
-//     }
-// }
-//
-// impl<'a> Render for &'a [u8] {
-//     #[inline]
-//     fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
-//         b.write_bytes(self);
-//         Ok(())
-//     }
-// }
-//
-// impl Render for Vec<u8> {
-//     #[inline]
-//     fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
-//         b.write_bytes(&**self);
-//         Ok(())
-//     }
-// }
+/// render the bytes as UTF-8 string
+///
+/// An error is returned if the bytes are not valid UTF-8.
+impl Render for Vec<u8> {
+    #[inline]
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        (**self).render(b)
+    }
+
+    #[inline]
+    fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        (**self).render_escaped(b)
+    }
+
+    #[inline]
+    fn render_escaped_with(
+        &self,
+        b: &mut Buffer,
+        escaper: &dyn Escaper,
+    ) -> Result<(), RenderError> {
+        (**self).render_escaped_with(b, escaper)
+    }
+}
 
 impl Render for bool {
     #[inline]
@@ -189,17 +571,24 @@ macro_rules! render_int {
         $(
             impl Render for $int {
                 #[cfg_attr(feature = "perf-inline", inline)]
+                #[cfg(not(feature = "safe"))]
                 fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
                     use itoap::Integer;
 
                     b.reserve(Self::MAX_LEN);
 
                     unsafe {
-                        let ptr = b.as_mut_ptr().add(b.len());
-                        let l = itoap::write_to_ptr(ptr, *self);
-                        b.advance(l);
+                        let spare = b.spare_capacity_mut();
+                        let l = itoap::write_to_ptr(spare.as_mut_ptr() as *mut u8, *self);
+                        b.advance_checked(l);
                     }
-                    debug_assert!(b.len() <= b.capacity());
+                    Ok(())
+                }
+
+                #[cfg_attr(feature = "perf-inline", inline)]
+                #[cfg(feature = "safe")]
+                fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+                    fmt::write(b, format_args!("{}", self))?;
                     Ok(())
                 }
 
@@ -219,13 +608,18 @@ impl Render for f32 {
     #[cfg_attr(feature = "perf-inline", inline)]
     fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
         if likely!(self.is_finite()) {
-            unsafe {
+            #[cfg(not(feature = "safe"))]
+            {
                 b.reserve(16);
-                let ptr = b.as_mut_ptr().add(b.len());
-                let l = ryu::raw::format32(*self, ptr);
-                b.advance(l);
-                debug_assert!(b.len() <= b.capacity());
+                unsafe {
+                    let spare = b.spare_capacity_mut();
+                    let l = ryu::raw::format32(*self, spare.as_mut_ptr() as *mut u8);
+                    b.advance_checked(l);
+                }
             }
+
+            #[cfg(feature = "safe")]
+            b.push_str(ryu::Buffer::new().format_finite(*self));
         } else if self.is_nan() {
             b.push_str("NaN");
         } else if *self > 0.0 {
@@ -248,13 +642,18 @@ impl Render for f64 {
     #[cfg_attr(feature = "perf-inline", inline)]
     fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
         if likely!(self.is_finite()) {
-            unsafe {
+            #[cfg(not(feature = "safe"))]
+            {
                 b.reserve(24);
-                let ptr = b.as_mut_ptr().add(b.len());
-                let l = ryu::raw::format64(*self, ptr);
-                b.advance(l);
-                debug_assert!(b.len() <= b.capacity());
+                unsafe {
+                    let spare = b.spare_capacity_mut();
+                    let l = ryu::raw::format64(*self, spare.as_mut_ptr() as *mut u8);
+                    b.advance_checked(l);
+                }
             }
+
+            #[cfg(feature = "safe")]
+            b.push_str(ryu::Buffer::new().format_finite(*self));
         } else if self.is_nan() {
             b.push_str("NaN");
         } else if *self > 0.0 {
@@ -273,46 +672,223 @@ impl Render for f64 {
     }
 }
 
-macro_rules! render_deref {
-    (
-        $(#[doc = $doc:tt])*
-        [$($bounds:tt)+] $($desc:tt)+
-    ) => {
-        $(#[doc = $doc])*
-        impl <$($bounds)+> Render for $($desc)+ {
-            #[inline]
-            fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
-                (**self).render(b)
-            }
+#[cfg(feature = "std")]
+impl Render for Ipv4Addr {
+    #[inline]
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        let [o1, o2, o3, o4] = self.octets();
+        o1.render(b)?;
+        b.push('.');
+        o2.render(b)?;
+        b.push('.');
+        o3.render(b)?;
+        b.push('.');
+        o4.render(b)
+    }
 
-            #[inline]
-            fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
-                (**self).render_escaped(b)
-            }
-        }
-    };
+    #[inline]
+    fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        // push_str without escape
+        self.render(b)
+    }
 }
 
-render_deref!(['a, T: Render + ?Sized] &'a T);
-render_deref!(['a, T: Render + ?Sized] &'a mut T);
-render_deref!([T: Render + ?Sized] Box<T>);
-render_deref!([T: Render + ?Sized] Rc<T>);
-render_deref!([T: Render + ?Sized] Arc<T>);
-render_deref!(['a, T: Render + ToOwned + ?Sized] Cow<'a, T>);
-render_deref!(['a, T: Render + ?Sized] Ref<'a, T>);
-render_deref!(['a, T: Render + ?Sized] RefMut<'a, T>);
-render_deref!(['a, T: Render + ?Sized] MutexGuard<'a, T>);
-render_deref!(['a, T: Render + ?Sized] RwLockReadGuard<'a, T>);
-render_deref!(['a, T: Render + ?Sized] RwLockWriteGuard<'a, T>);
+/// render in the canonical text representation defined in RFC 5952
+/// (e.g. `2001:db8::1`)
+#[cfg(feature = "std")]
+impl Render for Ipv6Addr {
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        let segments = self.segments();
 
-macro_rules! render_nonzero {
-    ($($type:ty,)*) => {
-        $(
-            impl Render for $type {
-                #[inline]
-                fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
-                    self.get().render(b)
-                }
+        // IPv4-mapped address (e.g. `::ffff:192.0.2.1`)
+        if let [0, 0, 0, 0, 0, 0xffff, hi, lo] = segments {
+            b.push_str("::ffff:");
+            let [o1, o2] = hi.to_be_bytes();
+            let [o3, o4] = lo.to_be_bytes();
+            return Ipv4Addr::new(o1, o2, o3, o4).render(b);
+        }
+
+        // find the longest run of zero segments to be replaced with `::`
+        let (mut zeros_start, mut zeros_len) = (0, 0);
+        let (mut start, mut len) = (0, 0);
+        for (i, &segment) in segments.iter().enumerate() {
+            if segment == 0 {
+                if len == 0 {
+                    start = i;
+                }
+                len += 1;
+                if len > zeros_len {
+                    zeros_start = start;
+                    zeros_len = len;
+                }
+            } else {
+                len = 0;
+            }
+        }
+
+        if zeros_len > 1 {
+            render_ipv6_segments(&segments[..zeros_start], b);
+            b.push_str("::");
+            render_ipv6_segments(&segments[zeros_start + zeros_len..], b);
+        } else {
+            render_ipv6_segments(&segments, b);
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        // push_str without escape
+        self.render(b)
+    }
+}
+
+/// render the colon-separated hexadecimal segments without leading zeros
+#[cfg(feature = "std")]
+fn render_ipv6_segments(segments: &[u16], b: &mut Buffer) {
+    const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+    for (i, &segment) in segments.iter().enumerate() {
+        if i > 0 {
+            b.push(':');
+        }
+
+        let digits = [
+            HEX_DIGITS[(segment >> 12) as usize],
+            HEX_DIGITS[(segment >> 8 & 0xf) as usize],
+            HEX_DIGITS[(segment >> 4 & 0xf) as usize],
+            HEX_DIGITS[(segment & 0xf) as usize],
+        ];
+        let skip = (segment.leading_zeros() as usize / 4).min(3);
+        b.push_str(utils::ascii_to_str(&digits[skip..]));
+    }
+}
+
+#[cfg(feature = "std")]
+impl Render for IpAddr {
+    #[inline]
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        match *self {
+            IpAddr::V4(ref a) => a.render(b),
+            IpAddr::V6(ref a) => a.render(b),
+        }
+    }
+
+    #[inline]
+    fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        // push_str without escape
+        self.render(b)
+    }
+}
+
+#[cfg(feature = "std")]
+impl Render for SocketAddrV4 {
+    #[inline]
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        self.ip().render(b)?;
+        b.push(':');
+        self.port().render(b)
+    }
+
+    #[inline]
+    fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        // push_str without escape
+        self.render(b)
+    }
+}
+
+/// render in `[address]:port` format, with the scope ID if it is not zero
+/// (e.g. `[fe80::1%2]:8080`)
+#[cfg(feature = "std")]
+impl Render for SocketAddrV6 {
+    #[inline]
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        b.push('[');
+        self.ip().render(b)?;
+        if self.scope_id() != 0 {
+            b.push('%');
+            self.scope_id().render(b)?;
+        }
+        b.push_str("]:");
+        self.port().render(b)
+    }
+
+    #[inline]
+    fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        // push_str without escape
+        self.render(b)
+    }
+}
+
+#[cfg(feature = "std")]
+impl Render for SocketAddr {
+    #[inline]
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        match *self {
+            SocketAddr::V4(ref a) => a.render(b),
+            SocketAddr::V6(ref a) => a.render(b),
+        }
+    }
+
+    #[inline]
+    fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        // push_str without escape
+        self.render(b)
+    }
+}
+
+macro_rules! render_deref {
+    (
+        $(#[doc = $doc:tt])*
+        [$($bounds:tt)+] $($desc:tt)+
+    ) => {
+        $(#[doc = $doc])*
+        impl <$($bounds)+> Render for $($desc)+ {
+            #[inline]
+            fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+                (**self).render(b)
+            }
+
+            #[inline]
+            fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+                (**self).render_escaped(b)
+            }
+
+            #[inline]
+            fn render_escaped_with(
+                &self,
+                b: &mut Buffer,
+                escaper: &dyn Escaper,
+            ) -> Result<(), RenderError> {
+                (**self).render_escaped_with(b, escaper)
+            }
+        }
+    };
+}
+
+render_deref!(['a, T: Render + ?Sized] &'a T);
+render_deref!(['a, T: Render + ?Sized] &'a mut T);
+render_deref!([T: Render + ?Sized] Box<T>);
+render_deref!([T: Render + ?Sized] Rc<T>);
+render_deref!([T: Render + ?Sized] Arc<T>);
+render_deref!(['a, T: Render + ToOwned + ?Sized] Cow<'a, T>);
+render_deref!(['a, T: Render + ?Sized] Ref<'a, T>);
+render_deref!(['a, T: Render + ?Sized] RefMut<'a, T>);
+#[cfg(feature = "std")]
+render_deref!(['a, T: Render + ?Sized] MutexGuard<'a, T>);
+#[cfg(feature = "std")]
+render_deref!(['a, T: Render + ?Sized] RwLockReadGuard<'a, T>);
+#[cfg(feature = "std")]
+render_deref!(['a, T: Render + ?Sized] RwLockWriteGuard<'a, T>);
+
+macro_rules! render_nonzero {
+    ($($type:ty,)*) => {
+        $(
+            impl Render for $type {
+                #[inline]
+                fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+                    self.get().render(b)
+                }
 
                 #[inline]
                 fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
@@ -348,11 +924,606 @@ impl<T: Render> Render for Wrapping<T> {
     fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
         self.0.render_escaped(b)
     }
+
+    #[inline]
+    fn render_escaped_with(
+        &self,
+        b: &mut Buffer,
+        escaper: &dyn Escaper,
+    ) -> Result<(), RenderError> {
+        self.0.render_escaped_with(b, escaper)
+    }
+}
+
+macro_rules! render_atomic {
+    ($($atomic:ty),*) => {
+        $(
+            /// render the value loaded with `Ordering::Relaxed`
+            impl Render for $atomic {
+                #[inline]
+                fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+                    self.load(Ordering::Relaxed).render(b)
+                }
+
+                #[inline]
+                fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+                    // push_str without escape
+                    self.render(b)
+                }
+            }
+        )*
+    }
+}
+
+render_atomic!(
+    AtomicBool,
+    AtomicI8,
+    AtomicI16,
+    AtomicI32,
+    AtomicI64,
+    AtomicIsize,
+    AtomicU8,
+    AtomicU16,
+    AtomicU32,
+    AtomicU64,
+    AtomicUsize
+);
+
+impl<T: Render + Copy> Render for Cell<T> {
+    #[inline]
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        self.get().render(b)
+    }
+
+    #[inline]
+    fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        self.get().render_escaped(b)
+    }
+}
+
+/// render the borrowed value, or returns an error if the value is currently
+/// mutably borrowed
+impl<T: Render + ?Sized> Render for RefCell<T> {
+    #[inline]
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        match self.try_borrow() {
+            Ok(v) => v.render(b),
+            Err(_) => Err(RenderError::new("RefCell is already mutably borrowed")),
+        }
+    }
+
+    #[inline]
+    fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        match self.try_borrow() {
+            Ok(v) => v.render_escaped(b),
+            Err(_) => Err(RenderError::new("RefCell is already mutably borrowed")),
+        }
+    }
+}
+
+impl<T: Render> Render for Option<T> {
+    /// render the inner value, or nothing if the value is `None`
+    #[inline]
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        match *self {
+            Some(ref v) => v.render(b),
+            None => Ok(()),
+        }
+    }
+
+    #[inline]
+    fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        match *self {
+            Some(ref v) => v.render_escaped(b),
+            None => Ok(()),
+        }
+    }
+
+    #[inline]
+    fn render_escaped_with(
+        &self,
+        b: &mut Buffer,
+        escaper: &dyn Escaper,
+    ) -> Result<(), RenderError> {
+        match *self {
+            Some(ref v) => v.render_escaped_with(b, escaper),
+            None => Ok(()),
+        }
+    }
+}
+
+impl RenderOnce for String {
+    #[inline]
+    fn render_once(self, b: &mut Buffer) -> Result<(), RenderError> {
+        if b.is_empty() {
+            // reuse the allocation instead of copying if possible
+            b.append(Buffer::from(self));
+        } else {
+            b.push_str(&self);
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn render_once_escaped(self, b: &mut Buffer) -> Result<(), RenderError> {
+        escape::escape_to_buf(&self, b);
+        Ok(())
+    }
+}
+
+impl RenderOnce for Buffer {
+    #[inline]
+    fn render_once(self, b: &mut Buffer) -> Result<(), RenderError> {
+        // reuses the allocation if `b` is empty
+        b.append(self);
+        Ok(())
+    }
+
+    #[inline]
+    fn render_once_escaped(self, b: &mut Buffer) -> Result<(), RenderError> {
+        escape::escape_to_buf(self.as_str(), b);
+        Ok(())
+    }
+}
+
+/// render the result of other template, or propagate the error
+impl RenderOnce for Result<String, RenderError> {
+    #[inline]
+    fn render_once(self, b: &mut Buffer) -> Result<(), RenderError> {
+        self?.render_once(b)
+    }
+
+    #[inline]
+    fn render_once_escaped(self, b: &mut Buffer) -> Result<(), RenderError> {
+        self?.render_once_escaped(b)
+    }
+}
+
+/// render the number of seconds with sub-second precision (e.g. `1.5`)
+#[cfg(feature = "std-time")]
+impl Render for Duration {
+    #[inline]
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        self.as_secs().render(b)?;
+        render_subsec_nanos(self.subsec_nanos(), b);
+        Ok(())
+    }
+
+    #[inline]
+    fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        // escape string
+        self.render(b)
+    }
+}
+
+/// render the timestamp in RFC 3339 format (e.g. `2020-09-13T12:26:40.5Z`)
+#[cfg(feature = "std-time")]
+impl Render for SystemTime {
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        let (secs, nanos) = match self.duration_since(UNIX_EPOCH) {
+            Ok(d) => (d.as_secs() as i64, d.subsec_nanos()),
+            Err(e) => {
+                let d = e.duration();
+                if d.subsec_nanos() == 0 {
+                    (-(d.as_secs() as i64), 0)
+                } else {
+                    (-(d.as_secs() as i64) - 1, 1_000_000_000 - d.subsec_nanos())
+                }
+            }
+        };
+
+        let (year, month, day) = civil_from_days(secs.div_euclid(86400));
+        let secs_of_day = secs.rem_euclid(86400) as u32;
+
+        render_date(year, month, day, b)?;
+        b.push('T');
+        render_time(
+            (secs_of_day / 3600) as u8,
+            (secs_of_day / 60 % 60) as u8,
+            (secs_of_day % 60) as u8,
+            nanos,
+            b,
+        );
+        b.push('Z');
+        Ok(())
+    }
+
+    #[inline]
+    fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        // escape string
+        self.render(b)
+    }
+}
+
+/// render the date in `YYYY-MM-DD` format
+#[cfg(any(feature = "std-time", feature = "time"))]
+pub(crate) fn render_date(
+    year: i64,
+    month: u8,
+    day: u8,
+    b: &mut Buffer,
+) -> Result<(), RenderError> {
+    if (0..10000).contains(&year) {
+        let year = year as u32;
+        render_2digits((year / 100) as u8, b);
+        render_2digits((year % 100) as u8, b);
+    } else {
+        year.render(b)?;
+    }
+    b.push('-');
+    render_2digits(month, b);
+    b.push('-');
+    render_2digits(day, b);
+    Ok(())
+}
+
+/// render the time in `HH:MM:SS[.fraction]` format
+#[cfg(any(feature = "std-time", feature = "time"))]
+pub(crate) fn render_time(hour: u8, minute: u8, second: u8, nanos: u32, b: &mut Buffer) {
+    render_2digits(hour, b);
+    b.push(':');
+    render_2digits(minute, b);
+    b.push(':');
+    render_2digits(second, b);
+    render_subsec_nanos(nanos, b);
+}
+
+/// render the zero-padded number less than 100
+#[cfg(any(feature = "std-time", feature = "time"))]
+#[inline]
+fn render_2digits(n: u8, b: &mut Buffer) {
+    debug_assert!(n < 100);
+    let digits = [b'0' + n / 10, b'0' + n % 10];
+    b.push_str(utils::ascii_to_str(&digits));
+}
+
+/// render the fractional part of seconds without trailing zeros
+#[cfg(any(feature = "std-time", feature = "time"))]
+fn render_subsec_nanos(nanos: u32, b: &mut Buffer) {
+    debug_assert!(nanos < 1_000_000_000);
+    if nanos == 0 {
+        return;
+    }
+
+    let mut digits = [b'0'; 10];
+    digits[0] = b'.';
+
+    let mut n = nanos;
+    for d in digits[1..].iter_mut().rev() {
+        *d = b'0' + (n % 10) as u8;
+        n /= 10;
+    }
+
+    let mut end = digits.len();
+    while digits[end - 1] == b'0' {
+        end -= 1;
+    }
+
+    b.push_str(utils::ascii_to_str(&digits[..end]));
+}
+
+/// Convert the number of days since 1970-01-01 into (year, month, day)
+///
+/// Algorithm: <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>
+#[cfg(feature = "std-time")]
+fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+/// Wrapper type to render the trusted contents without HTML escaping
+///
+/// Contents wrapped with this type is not escaped even if it is rendered inside
+/// `<%= %>` tag. Make sure that the contents never contains untrusted inputs.
+///
+/// # Examples
+///
+/// ```
+/// use sailfish::runtime::{Buffer, Raw, Render};
+///
+/// let mut b = Buffer::new();
+/// Raw("<b>bold</b>").render_escaped(&mut b).unwrap();
+/// assert_eq!(b.as_str(), "<b>bold</b>");
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Raw<T>(pub T);
+
+impl<T: Render> Render for Raw<T> {
+    #[inline]
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        self.0.render(b)
+    }
+
+    #[inline]
+    fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        self.0.render(b)
+    }
+
+    #[inline]
+    fn render_escaped_with(
+        &self,
+        b: &mut Buffer,
+        _escaper: &dyn Escaper,
+    ) -> Result<(), RenderError> {
+        self.0.render(b)
+    }
+}
+
+impl<T: RenderOnce> RenderOnce for Raw<T> {
+    #[inline]
+    fn render_once(self, b: &mut Buffer) -> Result<(), RenderError> {
+        self.0.render_once(b)
+    }
+
+    #[inline]
+    fn render_once_escaped(self, b: &mut Buffer) -> Result<(), RenderError> {
+        self.0.render_once(b)
+    }
+}
+
+/// Render every item of the iterator separated by the given string
+///
+/// Both items and separators are escaped if it is rendered inside `<%= %>` tag.
+/// `Render` is implemented for cloneable iterators (e.g. `&Vec<T>` or
+/// `slice.iter().map(...)`), and `RenderOnce` is implemented for any iterators.
+///
+/// # Examples
+///
+/// ```
+/// use sailfish::runtime::{Buffer, Join, Render};
+///
+/// let items = vec!["<a>", "b"];
+/// let mut b = Buffer::new();
+/// Join(&items, ", ").render_escaped(&mut b).unwrap();
+/// assert_eq!(b.as_str(), "&lt;a&gt;, b");
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct Join<'a, I>(pub I, pub &'a str);
+
+impl<'a, I> Render for Join<'a, I>
+where
+    I: IntoIterator + Clone,
+    I::Item: Render,
+{
+    #[inline]
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        self.clone().render_once(b)
+    }
+
+    #[inline]
+    fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        self.clone().render_once_escaped(b)
+    }
+}
+
+impl<'a, I> RenderOnce for Join<'a, I>
+where
+    I: IntoIterator,
+    I::Item: Render,
+{
+    fn render_once(self, b: &mut Buffer) -> Result<(), RenderError> {
+        let mut iter = self.0.into_iter();
+        if let Some(item) = iter.next() {
+            item.render(b)?;
+            for item in iter {
+                b.push_str(self.1);
+                item.render(b)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn render_once_escaped(self, b: &mut Buffer) -> Result<(), RenderError> {
+        let mut iter = self.0.into_iter();
+        if let Some(item) = iter.next() {
+            item.render_escaped(b)?;
+            for item in iter {
+                escape::escape_to_buf(self.1, b);
+                item.render_escaped(b)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Class name which is rendered only if the condition is `true`
+///
+/// See [`classes!`](../macro.classes.html) macro.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Class<'a>(pub &'a str, pub bool);
+
+impl<'a> From<&'a str> for Class<'a> {
+    #[inline]
+    fn from(name: &'a str) -> Self {
+        Class(name, true)
+    }
+}
+
+impl<'a> From<(&'a str, bool)> for Class<'a> {
+    #[inline]
+    fn from((name, cond): (&'a str, bool)) -> Self {
+        Class(name, cond)
+    }
+}
+
+/// Space-separated list of class names
+///
+/// Class names whose condition is `false` are skipped. This type is usually
+/// constructed with [`classes!`](../macro.classes.html) macro.
+///
+/// # Examples
+///
+/// ```
+/// use sailfish::runtime::{Buffer, Class, Classes, Render};
+///
+/// let mut b = Buffer::new();
+/// Classes(&[Class("btn", true), Class("active", false), Class("a&b", true)])
+///     .render_escaped(&mut b)
+///     .unwrap();
+/// assert_eq!(b.as_str(), "btn a&amp;b");
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct Classes<'a>(pub &'a [Class<'a>]);
+
+impl<'a> Classes<'a> {
+    #[inline]
+    fn names(&self) -> impl Iterator<Item = &'a str> {
+        self.0.iter().filter(|c| c.1).map(|c| c.0)
+    }
+}
+
+impl<'a> Render for Classes<'a> {
+    #[inline]
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        Join(self.names(), " ").render_once(b)
+    }
+
+    #[inline]
+    fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        Join(self.names(), " ").render_once_escaped(b)
+    }
+}
+
+/// Render every item of the iterator with HTML escaping, separated by `sep`
+///
+/// This is a shorthand for `Join(iter, sep).render_once_escaped(b)`, which is
+/// useful inside the custom `Render` implementations.
+#[inline]
+pub fn render_iter<I>(b: &mut Buffer, iter: I, sep: &str) -> Result<(), RenderError>
+where
+    I: IntoIterator,
+    I::Item: Render,
+{
+    Join(iter, sep).render_once_escaped(b)
+}
+
+/// Boxed closure which renders the contents into buffer
+///
+/// This is useful to store the ad-hoc rendering logic alongside other values as
+/// `Box<dyn Render>`. The closure is responsible for escaping, so the output is
+/// never escaped even if it is rendered inside `<%= %>` tag.
+///
+/// # Examples
+///
+/// ```
+/// use sailfish::runtime::{Buffer, DynRender, Render};
+///
+/// let items: Vec<Box<dyn Render>> = vec![
+///     Box::new(1),
+///     Box::new(DynRender::new(|b| {
+///         b.push_str("<br>");
+///         Ok(())
+///     })),
+///     Box::new("<"),
+/// ];
+///
+/// let mut b = Buffer::new();
+/// for item in &items {
+///     item.render_escaped(&mut b).unwrap();
+/// }
+/// assert_eq!(b.as_str(), "1<br>&lt;");
+/// ```
+pub struct DynRender<'a>(Box<RenderFn<'a>>);
+
+type RenderFn<'a> = dyn Fn(&mut Buffer) -> Result<(), RenderError> + 'a;
+
+impl<'a> DynRender<'a> {
+    /// Create a new renderer from closure
+    #[inline]
+    pub fn new<F>(f: F) -> Self
+    where
+        F: Fn(&mut Buffer) -> Result<(), RenderError> + 'a,
+    {
+        DynRender(Box::new(f))
+    }
+}
+
+impl<'a> Render for DynRender<'a> {
+    #[inline]
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        (self.0)(b)
+    }
+
+    #[inline]
+    fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        (self.0)(b)
+    }
+}
+
+impl<'a> fmt::Debug for DynRender<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("DynRender { .. }")
+    }
+}
+
+/// Renderer created by [`from_fn`](fn.from_fn.html)
+#[derive(Clone, Copy)]
+pub struct FromFn<F>(F);
+
+impl<F> Render for FromFn<F>
+where
+    F: Fn(&mut Buffer) -> Result<(), RenderError>,
+{
+    #[inline]
+    fn render(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        (self.0)(b)
+    }
+
+    #[inline]
+    fn render_escaped(&self, b: &mut Buffer) -> Result<(), RenderError> {
+        (self.0)(b)
+    }
+}
+
+impl<F> fmt::Debug for FromFn<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("FromFn { .. }")
+    }
+}
+
+/// Create a renderer from closure
+///
+/// Unlike [`DynRender`](struct.DynRender.html), the closure is not boxed. The
+/// closure is responsible for escaping, so the output is never escaped even if it
+/// is rendered inside `<%= %>` tag.
+///
+/// # Examples
+///
+/// ```
+/// use sailfish::runtime::{from_fn, Buffer, Render};
+///
+/// let active = true;
+/// let class = from_fn(|b| {
+///     if active {
+///         b.push_str(" class=\"active\"");
+///     }
+///     Ok(())
+/// });
+///
+/// let mut b = Buffer::new();
+/// class.render_escaped(&mut b).unwrap();
+/// assert_eq!(b.as_str(), " class=\"active\"");
+/// ```
+#[inline]
+pub fn from_fn<F>(f: F) -> FromFn<F>
+where
+    F: Fn(&mut Buffer) -> Result<(), RenderError>,
+{
+    FromFn(f)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use alloc::string::ToString;
 
     #[test]
     fn receiver_coercion() {
@@ -390,18 +1561,60 @@ mod tests {
 
     #[test]
     fn deref_coercion() {
-        use std::path::PathBuf;
-        use std::rc::Rc;
-
         let mut b = Buffer::new();
         Render::render(&String::from("a"), &mut b).unwrap();
-        Render::render(&&PathBuf::from("b"), &mut b).unwrap();
+        Render::render(&&Box::new("b"), &mut b).unwrap();
         Render::render_escaped(&Rc::new(4u32), &mut b).unwrap();
         Render::render_escaped(&Rc::new(2.3f32), &mut b).unwrap();
 
         assert_eq!(b.as_str(), "ab42.3");
     }
 
+    #[test]
+    fn arguments() {
+        fn render_all() -> Result<Buffer, RenderError> {
+            let mut b = Buffer::new();
+            crate::render!(b, format_args!("<{:>4}>", 1));
+            crate::render_escaped!(b, format_args!("<{:.2}>", 0.5));
+            crate::render_once_escaped!(b, format_args!("{}", "&"));
+            Ok(b)
+        }
+
+        let b = render_all().unwrap();
+        assert_eq!(b.as_str(), "<   1>&lt;0.50&gt;&amp;");
+    }
+
+    #[test]
+    fn buffer() {
+        let mut b = Buffer::new();
+        let inner = Buffer::from("<p>");
+        Render::render(&inner, &mut b).unwrap();
+        Render::render_escaped(&inner, &mut b).unwrap();
+        assert_eq!(b.as_str(), "<p>&lt;p&gt;");
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn os_str() {
+        let mut b = Buffer::new();
+        Render::render(&OsString::from("<a>"), &mut b).unwrap();
+        Render::render_escaped(&OsString::from("<b>"), &mut b).unwrap();
+        Render::render_escaped(OsStr::new("&c"), &mut b).unwrap();
+        assert_eq!(b.as_str(), "<a>&lt;b&gt;&amp;c");
+    }
+
+    #[test]
+    fn bytes() {
+        let mut b = Buffer::new();
+        Render::render(&b"<a>"[..], &mut b).unwrap();
+        Render::render_escaped(&b"<b>".to_vec(), &mut b).unwrap();
+        assert_eq!(b.as_str(), "<a>&lt;b&gt;");
+
+        let err = Render::render(&vec![b'c', 0xff], &mut b).unwrap_err();
+        assert!(err.to_string().starts_with("invalid UTF-8 sequence"));
+        assert_eq!(b.as_str(), "<a>&lt;b&gt;");
+    }
+
     #[test]
     fn float() {
         let mut b = Buffer::new();
@@ -419,4 +1632,275 @@ mod tests {
         Render::render_escaped(&std::f32::NAN, &mut b).unwrap();
         assert_eq!(b.as_str(), "0.0inf-infNaN");
     }
+
+    #[test]
+    fn display_fallback() {
+        struct Tag;
+
+        impl fmt::Display for Tag {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("<tag>")
+            }
+        }
+
+        fn render_all() -> Result<Buffer, RenderError> {
+            let mut b = Buffer::new();
+            crate::render!(b, Tag);
+            crate::render_escaped!(b, Tag);
+            crate::render!(b, '\t'.escape_default());
+
+            // types which implement both `Render` and `Display` use `Render`
+            crate::render_escaped!(b, '<');
+            crate::render_escaped!(b, &"<");
+            crate::render!(b, 1);
+            Ok(b)
+        }
+
+        let b = render_all().unwrap();
+        assert_eq!(b.as_str(), "<tag>&lt;tag&gt;\\t&lt;&lt;1");
+    }
+
+    #[test]
+    fn render_once() {
+        fn render_all() -> Result<Buffer, RenderError> {
+            let mut b = Buffer::new();
+            crate::render_once!(b, String::from("<a>"));
+            crate::render_once_escaped!(b, String::from("<b>"));
+            crate::render_once_escaped!(b, Ok::<_, RenderError>(String::from("<c>")));
+            crate::render_once!(b, Buffer::from("<d>"));
+
+            // fallback to `Render` and `Display`
+            crate::render_once_escaped!(b, std::cmp::max(1, 2));
+            crate::render_once_escaped!(b, Some("<e>"));
+            crate::render_once_escaped!(b, '\n'.escape_default());
+            Ok(b)
+        }
+
+        let b = render_all().unwrap();
+        assert_eq!(b.as_str(), "<a>&lt;b&gt;&lt;c&gt;<d>2&lt;e&gt;\\n");
+
+        // move the allocation into empty buffer
+        let s = String::from("apple");
+        let ptr = s.as_ptr();
+        let mut b = Buffer::new();
+        s.render_once(&mut b).unwrap();
+        assert_eq!(b.as_str().as_ptr(), ptr);
+        assert_eq!(b.as_str(), "apple");
+
+        let err = Err::<String, _>(RenderError::new("custom error"));
+        assert!(err.render_once(&mut b).is_err());
+
+        // the settings of the target buffer are kept
+        let mut b = Buffer::with_limit(4);
+        Buffer::from("apple pie").render_once(&mut b).unwrap();
+        assert_eq!(b.limit(), 4);
+        assert!(b.check_limit().is_err());
+    }
+
+    #[test]
+    fn raw() {
+        let mut b = Buffer::new();
+        Render::render_escaped(&Raw("<br>"), &mut b).unwrap();
+        Render::render_escaped(&Raw(Some('&')), &mut b).unwrap();
+        RenderOnce::render_once_escaped(Raw(String::from("<hr>")), &mut b).unwrap();
+        assert_eq!(b.as_str(), "<br>&<hr>");
+    }
+
+    #[cfg(feature = "std-time")]
+    #[test]
+    fn time() {
+        let mut b = Buffer::new();
+        Render::render(&Duration::from_secs(3), &mut b).unwrap();
+        b.push(' ');
+        Render::render(&Duration::from_millis(1500), &mut b).unwrap();
+        b.push(' ');
+        Render::render(&Duration::new(0, 1), &mut b).unwrap();
+        assert_eq!(b.as_str(), "3 1.5 0.000000001");
+        b.clear();
+
+        Render::render(&UNIX_EPOCH, &mut b).unwrap();
+        b.push(' ');
+        let t = UNIX_EPOCH + Duration::from_millis(1_600_000_000_500);
+        Render::render_escaped(&t, &mut b).unwrap();
+        b.push(' ');
+        let t = UNIX_EPOCH - Duration::from_millis(1500);
+        Render::render(&t, &mut b).unwrap();
+        b.push(' ');
+        let t = UNIX_EPOCH + Duration::from_secs(951_782_400);
+        Render::render(&t, &mut b).unwrap();
+        assert_eq!(
+            b.as_str(),
+            "1970-01-01T00:00:00Z 2020-09-13T12:26:40.5Z 1969-12-31T23:59:58.5Z \
+             2000-02-29T00:00:00Z"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn net() {
+        let addrs: Vec<IpAddr> = vec![
+            Ipv4Addr::new(0, 0, 0, 0).into(),
+            Ipv4Addr::new(192, 168, 10, 255).into(),
+            Ipv6Addr::UNSPECIFIED.into(),
+            Ipv6Addr::LOCALHOST.into(),
+            Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1).into(),
+            Ipv6Addr::new(0x2001, 0xdb8, 0, 1, 0, 0, 0, 0).into(),
+            Ipv6Addr::new(0x2001, 0, 0, 1, 0, 0, 1, 0).into(),
+            Ipv6Addr::new(0x2001, 0xdb8, 0, 1, 1, 1, 1, 1).into(),
+            Ipv6Addr::new(0xfe80, 0xabc, 0x1234, 0xffff, 0x10, 0x1, 0xa, 0xf00).into(),
+            Ipv6Addr::new(0, 0, 0, 0, 0, 0xffff, 0xc000, 0x0201).into(),
+            Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0xc000, 0x0201).into(),
+        ];
+
+        let mut b = Buffer::new();
+        for addr in &addrs {
+            addr.render(&mut b).unwrap();
+            assert_eq!(b.as_str(), addr.to_string());
+            b.clear();
+        }
+
+        let addrs: Vec<SocketAddr> = vec![
+            "127.0.0.1:8080".parse().unwrap(),
+            "[::1]:443".parse().unwrap(),
+            SocketAddrV6::new(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1), 80, 0, 2)
+                .into(),
+        ];
+        for addr in &addrs {
+            addr.render_escaped(&mut b).unwrap();
+            assert_eq!(b.as_str(), addr.to_string());
+            b.clear();
+        }
+    }
+
+    #[test]
+    fn join() {
+        fn render_all() -> Result<Buffer, RenderError> {
+            let v = vec![1, 2, 3];
+            let mut b = Buffer::new();
+            crate::render!(b, Join(&v, "<>"));
+            b.push(' ');
+            crate::render_escaped!(b, Join(v.iter().map(|n| n * 2), "<>"));
+            b.push(' ');
+            crate::render_once_escaped!(b, Join(vec!["<a>", "b"], ","));
+            b.push(' ');
+            crate::render_escaped!(b, Join(Vec::<i32>::new(), ","));
+            render_iter(&mut b, Some('&'), ",")?;
+            Ok(b)
+        }
+
+        let b = render_all().unwrap();
+        assert_eq!(b.as_str(), "1<>2<>3 2&lt;&gt;4&lt;&gt;6 &lt;a&gt;,b &amp;");
+    }
+
+    #[test]
+    fn trait_object() {
+        fn render_all(values: &[Box<dyn Render>]) -> Result<Buffer, RenderError> {
+            let mut b = Buffer::new();
+            for v in values {
+                crate::render_escaped!(b, v);
+            }
+            let r: &dyn Render = &values[0];
+            crate::render!(b, r);
+            Ok(b)
+        }
+
+        let prefix = String::from("<i>");
+        let values: Vec<Box<dyn Render>> = vec![
+            Box::new("<a>"),
+            Box::new(Some(1)),
+            Box::new(Raw("<b>")),
+            Box::new(DynRender::new(move |b| {
+                b.push_str(&prefix);
+                escape::escape_to_buf("&", b);
+                Ok(())
+            })),
+        ];
+
+        let b = render_all(&values).unwrap();
+        assert_eq!(b.as_str(), "&lt;a&gt;1<b><i>&amp;<a>");
+    }
+
+    #[test]
+    fn closure() {
+        fn render_list(items: &[&str]) -> Result<Buffer, RenderError> {
+            let list = from_fn(|b| {
+                for item in items {
+                    b.push_str("<li>");
+                    item.render_escaped(b)?;
+                    b.push_str("</li>");
+                }
+                Ok(())
+            });
+
+            let mut b = Buffer::new();
+            crate::render_escaped!(b, list);
+            Ok(b)
+        }
+
+        fn render_error() -> Result<Buffer, RenderError> {
+            let mut b = Buffer::new();
+            crate::render_once_escaped!(b, from_fn(|_| Err(RenderError::new("fail"))));
+            Ok(b)
+        }
+
+        let b = render_list(&["<a>", "b"]).unwrap();
+        assert_eq!(b.as_str(), "<li>&lt;a&gt;</li><li>b</li>");
+        assert_eq!(render_error().unwrap_err().to_string(), "fail");
+    }
+
+    #[test]
+    fn interior_mutability() {
+        let counter = AtomicU64::new(0);
+        counter.fetch_add(42, Ordering::SeqCst);
+        let flag = AtomicBool::new(true);
+        let cell = Cell::new('<');
+        let refcell = RefCell::new(String::from("<a>"));
+
+        let mut b = Buffer::new();
+        Render::render(&counter, &mut b).unwrap();
+        Render::render_escaped(&flag, &mut b).unwrap();
+        Render::render_escaped(&cell, &mut b).unwrap();
+        Render::render_escaped(&refcell, &mut b).unwrap();
+        assert_eq!(b.as_str(), "42true&lt;&lt;a&gt;");
+
+        let _guard = refcell.borrow_mut();
+        assert!(Render::render(&refcell, &mut b).is_err());
+    }
+
+    #[test]
+    fn custom_escaper() {
+        use std::rc::Rc;
+
+        // escape for CSV field
+        let csv = |feed: &str, buf: &mut Buffer| {
+            buf.push('"');
+            buf.push_str(&feed.replace('"', "\"\""));
+            buf.push('"');
+        };
+
+        let mut b = Buffer::new();
+        "a\"b".render_escaped_with(&mut b, &csv).unwrap();
+        b.push(',');
+        Rc::new(Some(String::from("c,d")))
+            .render_escaped_with(&mut b, &csv)
+            .unwrap();
+        b.push(',');
+        1.5f64.render_escaped_with(&mut b, &csv).unwrap();
+        b.push(',');
+        Raw("\"").render_escaped_with(&mut b, &csv).unwrap();
+        b.push(',');
+        '<'.render_escaped_with(&mut b, &escape::HtmlEscaper)
+            .unwrap();
+        assert_eq!(b.as_str(), "\"a\"\"b\",\"c,d\",\"1.5\",\",&lt;");
+    }
+
+    #[test]
+    fn option() {
+        let mut b = Buffer::new();
+        Render::render_escaped(&Some("<a>"), &mut b).unwrap();
+        Render::render_escaped(&None::<&str>, &mut b).unwrap();
+        Render::render(&Some(Some(1)), &mut b).unwrap();
+        Render::render(&Some(None::<i32>), &mut b).unwrap();
+        assert_eq!(b.as_str(), "&lt;a&gt;1");
+    }
 }
@@ -0,0 +1,99 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+static BUFFERS: AtomicUsize = AtomicUsize::new(0);
+static REALLOCS: AtomicUsize = AtomicUsize::new(0);
+static MAX_PEAK_LEN: AtomicUsize = AtomicUsize::new(0);
+static TOTAL_PEAK_LEN: AtomicUsize = AtomicUsize::new(0);
+
+/// Counters of how a single buffer grew
+///
+/// Use [`Buffer::stats`](struct.Buffer.html#method.stats) to get the counters of
+/// the buffer.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BufferStats {
+    /// Number of reallocations
+    pub reallocs: usize,
+    /// Maximum length of the contents in bytes
+    pub peak_len: usize,
+}
+
+/// Counters aggregated from all buffers dropped since the last reset
+///
+/// Buffers which were never written are not counted.
+///
+/// # Examples
+///
+/// ```
+/// use sailfish::runtime::{global_buffer_stats, reset_global_buffer_stats, Buffer};
+///
+/// reset_global_buffer_stats();
+/// let mut buf = Buffer::with_capacity(4);
+/// buf.push_str("<table></table>");
+/// drop(buf);
+///
+/// let stats = global_buffer_stats();
+/// assert!(stats.buffers >= 1);
+/// assert!(stats.max_peak_len >= 15);
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GlobalBufferStats {
+    /// Number of buffers
+    pub buffers: usize,
+    /// Total number of reallocations
+    pub reallocs: usize,
+    /// Maximum of the peak lengths in bytes
+    pub max_peak_len: usize,
+    /// Sum of the peak lengths in bytes
+    pub total_peak_len: usize,
+}
+
+impl GlobalBufferStats {
+    /// Average of the peak lengths in bytes
+    ///
+    /// This is a good starting point for the `with_capacity` hint.
+    #[inline]
+    pub fn mean_peak_len(&self) -> usize {
+        self.total_peak_len.checked_div(self.buffers).unwrap_or(0)
+    }
+}
+
+/// Get the counters aggregated from all buffers dropped since the last reset
+pub fn global_buffer_stats() -> GlobalBufferStats {
+    GlobalBufferStats {
+        buffers: BUFFERS.load(Ordering::Relaxed),
+        reallocs: REALLOCS.load(Ordering::Relaxed),
+        max_peak_len: MAX_PEAK_LEN.load(Ordering::Relaxed),
+        total_peak_len: TOTAL_PEAK_LEN.load(Ordering::Relaxed),
+    }
+}
+
+/// Reset the global counters (e.g. before starting the benchmark)
+pub fn reset_global_buffer_stats() {
+    BUFFERS.store(0, Ordering::Relaxed);
+    REALLOCS.store(0, Ordering::Relaxed);
+    MAX_PEAK_LEN.store(0, Ordering::Relaxed);
+    TOTAL_PEAK_LEN.store(0, Ordering::Relaxed);
+}
+
+/// Add the counters of the buffer which is being dropped to the global counters
+pub(crate) fn record(stats: &BufferStats) {
+    if stats.peak_len == 0 && stats.reallocs == 0 {
+        return;
+    }
+
+    BUFFERS.fetch_add(1, Ordering::Relaxed);
+    REALLOCS.fetch_add(stats.reallocs, Ordering::Relaxed);
+    let mut current = MAX_PEAK_LEN.load(Ordering::Relaxed);
+    while stats.peak_len > current {
+        match MAX_PEAK_LEN.compare_exchange_weak(
+            current,
+            stats.peak_len,
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+        ) {
+            Ok(_) => break,
+            Err(actual) => current = actual,
+        }
+    }
+    TOTAL_PEAK_LEN.fetch_add(stats.peak_len, Ordering::Relaxed);
+}
@@ -1,9 +1,10 @@
-use std::ptr;
+#[cfg(not(feature = "safe"))]
+use core::ptr;
 
 #[cfg(sailfish_nightly)]
 macro_rules! likely {
     ($val:expr) => {
-        std::intrinsics::likely($val)
+        core::intrinsics::likely($val)
     };
 }
 
@@ -17,7 +18,7 @@ macro_rules! likely {
 #[cfg(sailfish_nightly)]
 macro_rules! unlikely {
     ($val:expr) => {
-        std::intrinsics::unlikely($val)
+        core::intrinsics::unlikely($val)
     };
 }
 
@@ -28,7 +29,24 @@ macro_rules! unlikely {
     };
 }
 
+/// Convert the ASCII bytes (e.g. digits) into `str`
+///
+/// The bytes are validated only if the `safe` feature is enabled.
+#[cfg(not(feature = "safe"))]
+#[inline(always)]
+pub(crate) fn ascii_to_str(bytes: &[u8]) -> &str {
+    debug_assert!(bytes.is_ascii());
+    unsafe { core::str::from_utf8_unchecked(bytes) }
+}
+
+#[cfg(feature = "safe")]
+#[inline(always)]
+pub(crate) fn ascii_to_str(bytes: &[u8]) -> &str {
+    core::str::from_utf8(bytes).unwrap()
+}
+
 /// memcpy implementation based on glibc (https://github.molgen.mpg.de/git-mirror/glibc/blob/master/sysdeps/x86_64/multiarch/memcpy-avx-unaligned.S)
+#[cfg(not(feature = "safe"))]
 #[allow(clippy::cast_ptr_alignment)]
 pub unsafe fn memcpy_16(src: *const u8, dst: *mut u8, len: usize) {
     debug_assert!(len <= 16);